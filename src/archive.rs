@@ -0,0 +1,756 @@
+//! Whole backup packed into a single file, so it can be kept in cold storage
+//! or moved between machines. The archive can be compressed and encrypted
+//! with external programs, like snapshots are signed with them.
+//!
+//! An archive starts with a header followed by entries of the backup folder
+//! in the order of a walk sorted by file names. Every entry is stored as its
+//! type, relative path, mode, modification time and data (content of a file
+//! or target of a symlink). The last entry is followed by a SHA-256 hash of
+//! everything before it.
+
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace};
+use walkdir::WalkDir;
+
+const HEADER: &[u8] = b"mizeria archive 1\n";
+const END: u8 = 0;
+const DIRECTORY: u8 = b'd';
+const FILE: u8 = b'f';
+const SYMLINK: u8 = b'l';
+
+/// External program used to compress an archive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            "xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        }
+    }
+
+    fn magic(&self) -> &'static [u8] {
+        match self {
+            Self::Gzip => &[0x1f, 0x8b],
+            Self::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Self::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        }
+    }
+
+    fn detect(start: &[u8]) -> Option<Self> {
+        [Self::Gzip, Self::Zstd, Self::Xz]
+            .iter()
+            .copied()
+            .find(|c| start.starts_with(c.magic()))
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+#[derive(Default)]
+pub struct ArchiveOptions {
+    pub compression: Option<Compression>,
+    /// Key ID of a GPG recipient the archive is encrypted for.
+    pub recipient: Option<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct ArchiveReport {
+    pub entries: usize,
+    /// Total size of stored files in bytes, before compression.
+    pub size: u64,
+}
+
+/// Packs everything in the backup folder into a single file. The archive
+/// is written next to `output` and renamed when it is complete.
+pub fn create(
+    backup: &Path,
+    output: &Path,
+    options: &ArchiveOptions,
+) -> Result<ArchiveReport, String> {
+    let mut filters = vec![];
+    if let Some(compression) = options.compression {
+        filters.push(Filter::Compress(compression));
+    }
+    if let Some(recipient) = &options.recipient {
+        filters.push(Filter::Encrypt(recipient.clone()));
+    }
+
+    let partial = partial_path(output);
+    let file = File::create(&partial)
+        .map_err(|e| format!("Cannot create '{}' ({})", partial.display(), e))?;
+    let result = write_through(&filters, file, |writer| write_archive(backup, writer));
+    let synced = result.and_then(|report| {
+        File::open(&partial)
+            .and_then(|file| file.sync_all())
+            .map_err(|e| e.to_string())?;
+        Ok(report)
+    });
+    let report = match synced {
+        Ok(report) => report,
+        Err(e) => {
+            fs::remove_file(&partial).ok();
+            return Err(e);
+        }
+    };
+    fs::rename(&partial, output)
+        .map_err(|e| format!("Cannot create '{}' ({})", output.display(), e))?;
+    Ok(report)
+}
+
+/// Unpacks an archive into a new backup folder. Compressed and encrypted
+/// archives are recognized by their content. Nothing is left in `backup`
+/// unless the whole archive was unpacked and its hash matches.
+pub fn extract(archive: &Path, backup: &Path) -> Result<ArchiveReport, String> {
+    if backup.exists() && !is_empty_dir(backup) {
+        return Err(format!(
+            "Folder '{}' already exists and isn't empty",
+            backup.display()
+        ));
+    }
+    let partial = partial_path(backup);
+    if partial.exists() {
+        debug!("Removing unfinished extraction: {}", partial.display());
+        fs::remove_dir_all(&partial)
+            .map_err(|e| format!("Cannot remove '{}' ({})", partial.display(), e))?;
+    }
+
+    let file =
+        File::open(archive).map_err(|e| format!("Cannot open '{}' ({})", archive.display(), e))?;
+    let result = read_through(file, |reader| read_archive(reader, &partial));
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            fs::remove_dir_all(&partial).ok();
+            return Err(e);
+        }
+    };
+    if backup.exists() {
+        fs::remove_dir(backup).map_err(|e| e.to_string())?;
+    }
+    fs::rename(&partial, backup)
+        .map_err(|e| format!("Cannot create '{}' ({})", backup.display(), e))?;
+    Ok(report)
+}
+
+/// Unpacks a tar archive made by another tool into `target`. Like in
+/// `extract`, compressed and encrypted archives are recognized by their
+/// content.
+pub fn unpack_tar(archive: &Path, target: &Path) -> Result<(), String> {
+    let file =
+        File::open(archive).map_err(|e| format!("Cannot open '{}' ({})", archive.display(), e))?;
+    read_through(file, |reader| tar::Archive::new(reader).unpack(target))
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    path.read_dir()
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(false)
+}
+
+fn write_archive(backup: &Path, writer: &mut dyn Write) -> io::Result<ArchiveReport> {
+    let mut writer = HashingWriter::new(writer);
+    let mut report = ArchiveReport::default();
+    writer.write_all(HEADER)?;
+    let walk = WalkDir::new(backup)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name();
+    for entry in walk {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(backup).unwrap();
+        trace!("Archiving: {}", relative.display());
+        let metadata = entry.metadata()?;
+        let kind = if metadata.is_dir() {
+            DIRECTORY
+        } else if metadata.file_type().is_symlink() {
+            SYMLINK
+        } else {
+            FILE
+        };
+        writer.write_all(&[kind])?;
+        write_bytes(&mut writer, archived_path(relative)?.as_bytes())?;
+        writer.write_all(&mode_of(&metadata).to_le_bytes())?;
+        writer.write_all(&nanos_since_epoch(metadata.modified()?).to_le_bytes())?;
+        match kind {
+            FILE => {
+                let length = metadata.len();
+                writer.write_all(&length.to_le_bytes())?;
+                let copied = io::copy(&mut File::open(entry.path())?.take(length), &mut writer)?;
+                if copied != length {
+                    return Err(invalid_data("File changed while archiving"));
+                }
+                report.size += length;
+            }
+            SYMLINK => {
+                let target = fs::read_link(entry.path())?;
+                write_bytes(&mut writer, utf8_path(&target)?.as_bytes())?;
+            }
+            _ => writer.write_all(&0u64.to_le_bytes())?,
+        }
+        report.entries += 1;
+    }
+    writer.write_all(&[END])?;
+    let hash = writer.hasher.finalize_reset();
+    writer.inner.write_all(&hash)?;
+    writer.inner.flush()?;
+    Ok(report)
+}
+
+fn read_archive(reader: &mut dyn Read, target: &Path) -> io::Result<ArchiveReport> {
+    let mut reader = HashingReader::new(reader);
+    let mut report = ArchiveReport::default();
+    let mut header = vec![];
+    (&mut reader)
+        .take(HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+    if header != HEADER {
+        return Err(invalid_data("Not a mizeria archive"));
+    }
+    fs::create_dir(target)?;
+
+    // modes of folders are set at the end, so read-only folders can be filled
+    let mut folders = vec![];
+    loop {
+        let [kind] = read_array(&mut reader)?;
+        if kind == END {
+            break;
+        }
+        let relative = read_path(&mut reader)?;
+        let mode = u32::from_le_bytes(read_array(&mut reader)?);
+        let modified =
+            UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(read_array(&mut reader)?));
+        let path = target.join(&relative);
+        trace!("Extracting: {}", relative.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match kind {
+            DIRECTORY => {
+                read_array::<8>(&mut reader)?;
+                fs::create_dir_all(&path)?;
+                folders.push((path, mode));
+            }
+            FILE => {
+                let length = u64::from_le_bytes(read_array(&mut reader)?);
+                let mut file = File::create(&path)?;
+                let copied = io::copy(&mut (&mut reader).take(length), &mut file)?;
+                if copied != length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                file.set_modified(modified)?;
+                set_mode(&path, mode)?;
+                report.size += length;
+            }
+            SYMLINK => {
+                let target = read_string(&mut reader)?;
+                symlink(Path::new(&target), &path)?;
+            }
+            _ => return Err(invalid_data("Unknown type of entry")),
+        }
+        report.entries += 1;
+    }
+
+    let expected = reader.hasher.finalize_reset();
+    let hash: [u8; 32] = read_array(&mut reader.inner)?;
+    if hash[..] != expected[..] {
+        return Err(invalid_data(
+            "Hash of the archive doesn't match, it is corrupted",
+        ));
+    }
+    for (folder, mode) in folders.iter().rev() {
+        set_mode(folder, *mode)?;
+    }
+    Ok(report)
+}
+
+/// Relative paths are stored with '/' separators, so archives can be moved
+/// between platforms.
+fn archived_path(relative: &Path) -> io::Result<String> {
+    let parts = relative
+        .iter()
+        .map(|part| part.to_str().ok_or_else(|| not_utf8(relative)))
+        .collect::<io::Result<Vec<&str>>>()?;
+    Ok(parts.join("/"))
+}
+
+fn utf8_path(path: &Path) -> io::Result<&str> {
+    path.to_str().ok_or_else(|| not_utf8(path))
+}
+
+fn not_utf8(path: &Path) -> io::Error {
+    invalid_data(&format!("Path '{}' is not valid UTF-8", path.display()))
+}
+
+/// Only plain relative paths are extracted, so an archive cannot write
+/// outside of the target folder.
+fn read_path(reader: &mut impl Read) -> io::Result<PathBuf> {
+    let path = PathBuf::from(read_string(reader)?);
+    let is_plain = path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !is_plain {
+        return Err(invalid_data(&format!(
+            "Invalid path in archive: '{}'",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = u64::from_le_bytes(read_array(reader)?);
+    let mut bytes = vec![];
+    reader.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array)?;
+    Ok(array)
+}
+
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Other platforms don't have modes, so defaults of the target are used.
+#[cfg(not(unix))]
+fn mode_of(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if mode == 0 {
+        return Ok(());
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Extracting symlinks is not supported on this platform.",
+    ))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// External program the archive is passed through.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Filter {
+    Compress(Compression),
+    Decompress(Compression),
+    Encrypt(String),
+    Decrypt,
+}
+
+impl Filter {
+    fn command(&self) -> Command {
+        let mut command;
+        match self {
+            Self::Compress(compression) => {
+                command = Command::new(compression.program());
+                command.args(["-c", "-q"]);
+            }
+            Self::Decompress(compression) => {
+                command = Command::new(compression.program());
+                command.args(["-d", "-c", "-q"]);
+            }
+            Self::Encrypt(recipient) => {
+                command = Command::new("gpg");
+                command.args(["--batch", "--yes", "--encrypt", "--recipient"]);
+                command.arg(recipient);
+            }
+            Self::Decrypt => {
+                command = Command::new("gpg");
+                command.args(["--batch", "--quiet", "--decrypt"]);
+            }
+        }
+        command
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Compress(compression) | Self::Decompress(compression) => compression.program(),
+            Self::Encrypt(_) | Self::Decrypt => "gpg",
+        }
+    }
+
+    fn spawn(&self, stdin: Stdio, stdout: Stdio) -> Result<RunningFilter, String> {
+        debug!("Running {:?}", self);
+        let child = self
+            .command()
+            .stdin(stdin)
+            .stdout(stdout)
+            .spawn()
+            .map_err(|e| format!("Cannot run {} ({})", self.program(), e))?;
+        Ok(RunningFilter {
+            program: self.program(),
+            child,
+            feeder: None,
+        })
+    }
+}
+
+struct RunningFilter {
+    program: &'static str,
+    child: Child,
+    /// Thread that writes the input of the program.
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl RunningFilter {
+    fn finish(mut self) -> Result<(), String> {
+        let status = self
+            .child
+            .wait()
+            .map_err(|e| format!("{} failed ({})", self.program, e))?;
+        let fed = self.feeder.take().map(|feeder| feeder.join());
+        if !status.success() {
+            return Err(format!("{} failed ({})", self.program, status));
+        }
+        match fed {
+            Some(Ok(Err(e))) => Err(format!("{} failed ({})", self.program, e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn kill(mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Writes to `file` through every filter, in order.
+fn write_through<T>(
+    filters: &[Filter],
+    file: File,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> Result<T, String> {
+    if filters.is_empty() {
+        let mut writer = BufWriter::new(&file);
+        let result = write(&mut writer).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        return Ok(result);
+    }
+
+    let mut running: Vec<RunningFilter> = vec![];
+    let mut file = Some(file);
+    for (i, filter) in filters.iter().enumerate() {
+        let stdin = match running.last_mut() {
+            Some(previous) => Stdio::from(previous.child.stdout.take().unwrap()),
+            None => Stdio::piped(),
+        };
+        let stdout = if i + 1 == filters.len() {
+            Stdio::from(file.take().unwrap())
+        } else {
+            Stdio::piped()
+        };
+        match filter.spawn(stdin, stdout) {
+            Ok(filter) => running.push(filter),
+            Err(e) => {
+                running.into_iter().for_each(RunningFilter::kill);
+                return Err(e);
+            }
+        }
+    }
+
+    let stdin = running[0].child.stdin.take().unwrap();
+    let mut writer = BufWriter::new(stdin);
+    let result = write(&mut writer).and_then(|result| writer.flush().map(|_| result));
+    drop(writer);
+    let mut failure = result.as_ref().err().map(|e| e.to_string());
+    for filter in running {
+        if let Err(e) = filter.finish() {
+            failure.get_or_insert(e);
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(result.unwrap()),
+    }
+}
+
+/// Reads an archive from `file`, decrypting and decompressing it first
+/// if its content starts like a compressed or encrypted file.
+fn read_through<T>(
+    file: File,
+    read: impl FnOnce(&mut dyn Read) -> io::Result<T>,
+) -> Result<T, String> {
+    let mut running: Vec<RunningFilter> = vec![];
+    let mut stream: Box<dyn Read + Send> = Box::new(file);
+    loop {
+        let start = match peek(&mut stream) {
+            Ok(start) => start,
+            Err(e) => return fail(running, e.to_string()),
+        };
+        stream = Box::new(Cursor::new(start.clone()).chain(stream));
+        if start.is_empty() {
+            return fail(running, "Archive is empty".into());
+        }
+        let filter = match Compression::detect(&start) {
+            Some(compression) => Filter::Decompress(compression),
+            None if running.is_empty() && is_encrypted(&start) => Filter::Decrypt,
+            None => break,
+        };
+        if running.iter().any(|r| r.program == filter.program()) {
+            break;
+        }
+        let mut filter = match filter.spawn(Stdio::piped(), Stdio::piped()) {
+            Ok(filter) => filter,
+            Err(e) => return fail(running, e),
+        };
+        let mut stdin = filter.child.stdin.take().unwrap();
+        filter.feeder = Some(thread::spawn(move || {
+            let copied = io::copy(&mut stream, &mut stdin);
+            // broken pipe means the program has already failed
+            match copied {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+                copied => copied,
+            }
+        }));
+        let stdout: ChildStdout = filter.child.stdout.take().unwrap();
+        stream = Box::new(stdout);
+        running.push(filter);
+    }
+
+    let result = read(&mut BufReader::new(stream));
+    match result {
+        Ok(result) => {
+            for filter in running {
+                filter.finish()?;
+            }
+            Ok(result)
+        }
+        Err(e) => {
+            let mut failure = None;
+            for filter in running {
+                if let Err(e) = filter.finish() {
+                    failure.get_or_insert(e);
+                }
+            }
+            // output of a failed program ends early, the program tells why
+            match failure {
+                Some(failure) if e.kind() == io::ErrorKind::UnexpectedEof => Err(failure),
+                _ if e.kind() == io::ErrorKind::UnexpectedEof => Err("Archive is truncated".into()),
+                _ => Err(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Binary OpenPGP packets have the highest bit of the first byte set.
+/// Armored ones start with a "-----BEGIN PGP" line.
+fn is_encrypted(start: &[u8]) -> bool {
+    start[0] & 0x80 != 0 || start.starts_with(b"-----")
+}
+
+fn fail<T>(running: Vec<RunningFilter>, error: String) -> Result<T, String> {
+    running.into_iter().for_each(RunningFilter::kill);
+    Err(error)
+}
+
+/// Reads as many bytes as it takes to recognize the format of a stream.
+fn peek(stream: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut start = vec![];
+    stream.take(HEADER.len() as u64).read_to_end(&mut start)?;
+    Ok(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_backup(root: &Path) -> PathBuf {
+        let backup = root.join("backup");
+        let snapshot = backup.join("2021-07-15_18.34");
+        fs::create_dir_all(snapshot.join("files").join("folder")).unwrap();
+        fs::write(snapshot.join("index.txt"), b"2021-07-15_18.34 /folder\n").unwrap();
+        fs::write(snapshot.join("files").join("folder").join("a.txt"), b"a").unwrap();
+        fs::write(backup.join("verified.txt"), b"").unwrap();
+        backup
+    }
+
+    #[test]
+    fn create_and_extract_archive() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = create_backup(root.path());
+        let archive = root.path().join("backup.mza");
+        let extracted = root.path().join("extracted");
+
+        let created = create(&backup, &archive, &ArchiveOptions::default()).unwrap();
+        let report = extract(&archive, &extracted).unwrap();
+
+        assert_eq!(created.entries, 6);
+        assert_eq!(report.entries, 6);
+        assert_eq!(report.size, created.size);
+        let a = Path::new("2021-07-15_18.34/files/folder/a.txt");
+        assert_eq!(fs::read(extracted.join(a)).unwrap(), b"a");
+        assert_eq!(
+            fs::metadata(extracted.join(a)).unwrap().modified().unwrap(),
+            fs::metadata(backup.join(a)).unwrap().modified().unwrap()
+        );
+        assert!(extracted.join("verified.txt").is_file());
+        assert!(!partial_path(&archive).exists());
+        assert!(!partial_path(&extracted).exists());
+    }
+
+    #[test]
+    fn corrupted_archive_is_not_extracted() {
+        let root = tempfile::tempdir().unwrap();
+        let backup = create_backup(root.path());
+        let archive = root.path().join("backup.mza");
+        let extracted = root.path().join("extracted");
+        create(&backup, &archive, &ArchiveOptions::default()).unwrap();
+        let mut content = fs::read(&archive).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&archive, content).unwrap();
+
+        let error = extract(&archive, &extracted).unwrap_err();
+
+        assert!(error.contains("corrupted"), "{}", error);
+        assert!(!extracted.exists());
+        assert!(!partial_path(&extracted).exists());
+    }
+
+    #[test]
+    fn paths_outside_of_target_are_rejected() {
+        for path in ["../escape", "/etc/passwd", ""] {
+            let mut stored = vec![];
+            write_bytes(&mut stored, path.as_bytes()).unwrap();
+            assert!(read_path(&mut stored.as_slice()).is_err(), "{}", path);
+        }
+        let mut stored = vec![];
+        write_bytes(&mut stored, b"snapshot/files/a.txt").unwrap();
+        assert_eq!(
+            read_path(&mut stored.as_slice()).unwrap(),
+            Path::new("snapshot/files/a.txt")
+        );
+    }
+
+    #[test]
+    fn recognize_compressed_archives() {
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 8]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::detect(HEADER), None);
+    }
+}
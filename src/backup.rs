@@ -1,70 +1,1234 @@
-use log::{debug, warn};
-use snapshot::{Snapshot, SnapshotPreview};
-use snapshot_utils::{load_all_snapshot_previews, load_all_snapshots};
+use import::FolderClock;
+use snapshot::{ChainLink, Snapshot, SnapshotPreview, Unpacked};
+use snapshot_utils::{
+    find_unfinished_snapshot, load_all_snapshot_previews, load_all_snapshots,
+    load_all_snapshots_without_index, load_foreign_entries,
+};
 use std::{
+    collections::HashMap,
     ffi::OsStr,
+    fs,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tracing::{debug, field, info, info_span, warn, Span};
+use walkdir::WalkDir;
 
-use crate::result::{IntegrityCheckError, IntegrityCheckResult};
+use crate::environment::{CopyOptions, Environment};
+use crate::find::Pattern;
+use crate::messages::t;
+use crate::parallel;
+use crate::progress::{Progress, ProgressStyle};
+use crate::result::{BackupReport, IntegrityCheckError, IntegrityReport, Interrupted};
+use fingerprints::Fingerprints;
+use trash::Trash;
+use verification::VerificationLog;
 
+mod destination;
+mod fingerprints;
+mod import;
+mod lock;
+mod permissions;
+mod prune;
+mod settings;
+mod signing;
 mod snapshot;
 mod snapshot_utils;
+mod trash;
+mod usage;
+mod verification;
+
+pub use destination::{Destination, Filesystem, LimitsPolicy};
+pub use lock::{Lock, LockState};
+pub use permissions::PermissionsPolicy;
+pub use settings::Settings;
+pub use signing::{SignaturePolicy, SigningKey, SigningTool, VerifyingKey};
+pub use snapshot::sha256_file;
+pub use snapshot::{
+    Change, Encryption, EntryInfo, EntryKind, Estimate, FileCompression, LargeFilesPolicy,
+    ParitySummary, Period, RestoreOptions, SnapshotFormat, SpecialFilesPolicy, ARCHIVE_FILE_NAME,
+    ERRORS_FILE_NAME, PARTIAL_SUFFIX,
+};
+pub use trash::Removal;
+pub use verification::{parse_duration, VerificationBudget};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+pub struct BackupConfig {
+    pub incremental: bool,
+    pub limits_policy: LimitsPolicy,
+    pub signing_key: Option<SigningKey>,
+    pub permissions: PermissionsPolicy,
+    /// Enables bounded-memory mode, in bytes.
+    pub memory_limit: Option<u64>,
+    pub copy_options: CopyOptions,
+    /// Entries nested deeper in an input than this are not backed up.
+    pub max_depth: Option<usize>,
+    /// Entries matching any of these patterns are not backed up.
+    pub exclude: Vec<Pattern>,
+    /// Files larger than this are not copied, in bytes.
+    pub max_file_size: Option<u64>,
+    pub large_files: LargeFilesPolicy,
+    /// Files larger than this are stored as chunks, in bytes.
+    pub chunk_threshold: Option<u64>,
+    /// Files copied into a snapshot are compressed.
+    pub compression: Option<FileCompression>,
+    /// Files copied into a snapshot are encrypted to the recipients.
+    pub encryption: Option<Encryption>,
+    pub format: SnapshotFormat,
+    /// Named pipes, sockets and device nodes.
+    pub special_files: SpecialFilesPolicy,
+    /// Record permissions, owner and modification time in the index.
+    pub preserve_attributes: bool,
+    /// Report progress of copying files.
+    pub progress: Option<ProgressStyle>,
+    /// Remove the snapshot if the backup is interrupted, instead of
+    /// keeping the files copied so far.
+    pub remove_partial: bool,
+    /// Continue an unfinished snapshot instead of creating a new one.
+    pub resume: bool,
+    /// Take over a lock of the backup folder whose process isn't running
+    /// anymore or can't be checked.
+    pub break_lock: bool,
+    /// Read back copied files and compare them with their sources.
+    pub verify: bool,
+    /// Compare files with the base snapshot by their checksums instead
+    /// of their modification times.
+    pub checksum: bool,
+    /// Stop at the first entry that can't be backed up, instead of
+    /// backing up the rest and reporting all failures at the end.
+    pub fail_fast: bool,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            incremental: true,
+            limits_policy: LimitsPolicy::Adapt,
+            signing_key: None,
+            permissions: PermissionsPolicy::default(),
+            memory_limit: None,
+            copy_options: CopyOptions::default(),
+            max_depth: None,
+            exclude: vec![],
+            max_file_size: None,
+            large_files: LargeFilesPolicy::Record,
+            chunk_threshold: None,
+            compression: None,
+            encryption: None,
+            format: SnapshotFormat::Loose,
+            special_files: SpecialFilesPolicy::Skip,
+            preserve_attributes: true,
+            progress: None,
+            remove_partial: false,
+            resume: false,
+            break_lock: false,
+            verify: false,
+            checksum: false,
+            fail_fast: false,
+        }
+    }
+}
+
+/// File or folder in the backup folder that isn't a snapshot.
+pub struct ForeignEntry {
+    pub path: PathBuf,
+    pub size: u64, // in bytes
+}
+
+impl ForeignEntry {
+    fn new(path: PathBuf) -> Self {
+        let size = WalkDir::new(&path)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(std::result::Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.metadata().ok())
+            .map(|m| m.len())
+            .sum();
+        Self { path, size }
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into()
+    }
+
+    /// A folder named like a snapshot is most likely a damaged snapshot
+    /// and may still contain backed up files.
+    pub fn is_damaged_snapshot(&self) -> bool {
+        self.path.is_dir() && Snapshot::has_valid_name(self.name())
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        if self.is_damaged_snapshot() {
+            return Err(format!("'{}' looks like a damaged snapshot", self.name()).into());
+        }
+        let metadata = self.path.symlink_metadata()?;
+        if metadata.is_dir() {
+            fs::remove_dir_all(&self.path)?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        debug!("Removed foreign entry: {}", self.path.display());
+        Ok(())
+    }
+}
+
+pub enum FingerprintStatus {
+    Unchanged,
+    Changed,
+    /// Snapshot was fingerprinted, but it doesn't exist anymore.
+    Removed,
+    NotRecorded,
+}
+
+/// Outcome of pruning old snapshots.
+#[derive(Default)]
+pub struct PruneReport {
+    /// Snapshots that were removed or moved to the trash.
+    pub removed: Vec<String>,
+    /// Snapshots that were kept, because a newer kept snapshot refers
+    /// to their data, with the name of that snapshot.
+    pub kept: Vec<(String, String)>,
+    /// Snapshots removed from the trash after the retention time.
+    pub expired: Vec<String>,
+}
+
+/// Outcome of squashing snapshots.
+#[derive(Default)]
+pub struct SquashReport {
+    /// Name of the snapshot that was made full.
+    pub snapshot: String,
+    /// Entries copied into the squashed snapshot.
+    pub copied: usize,
+    /// Entries of newer snapshots pointed to the squashed snapshot.
+    pub redirected: usize,
+    /// Squashed snapshots that no other snapshot references anymore.
+    pub unreferenced: Vec<String>,
+}
+
+/// Outcome of repairing corrupted files of a snapshot.
+#[derive(Default)]
+pub struct RepairReport {
+    /// Original paths of files repaired with parity data of the snapshot.
+    pub from_parity: Vec<PathBuf>,
+    /// Original paths of repaired files with snapshots their copies came from.
+    pub repaired: Vec<(PathBuf, String)>,
+    /// Corrupted files without an intact copy in any other snapshot.
+    pub unrepaired: Vec<PathBuf>,
+}
+
+/// Outcome of importing dated folders of another backup.
+#[derive(Default)]
+pub struct ImportReport {
+    /// Imported folders with reports of snapshots made from them.
+    pub imported: Vec<(PathBuf, BackupReport)>,
+    /// Entries that weren't imported with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
 pub struct Backup {
     location: PathBuf,
     snapshots: Vec<SnapshotPreview>,
+    settings: Settings,
+    environment: Environment,
+    /// Commands that change the backup take over a lock of a process that
+    /// isn't running anymore.
+    break_lock: bool,
 }
 
 impl Backup {
     pub fn open(path: &Path) -> Result<Backup> {
+        Self::open_with_environment(path, Environment::default())
+    }
+
+    pub fn open_with_environment(path: &Path, environment: Environment) -> Result<Backup> {
         if !path.exists() {
             return Err("Folder with backup doesn't exist or isn't accessible".into());
         }
 
+        let settings = Settings::load(path)?;
         let snapshots = load_all_snapshot_previews(path);
 
         Ok(Backup {
             location: path.to_owned(),
             snapshots,
+            settings,
+            environment,
+            break_lock: false,
         })
     }
 
+    /// A lock of the backup folder held by a process that isn't running
+    /// anymore, or can't be checked, is taken over by commands that change
+    /// the backup. Backups and imports are configured by their config.
+    pub fn set_break_lock(&mut self, break_lock: bool) {
+        self.break_lock = break_lock;
+    }
+
+    /// Keeps other processes from changing the backup until it's dropped,
+    /// e.g. a prune from removing the base snapshot of a running backup.
+    fn lock(&self) -> Result<Lock> {
+        Ok(Lock::acquire(&self.location, self.break_lock)?)
+    }
+
+    pub fn snapshots(&self) -> &[SnapshotPreview] {
+        &self.snapshots
+    }
+
+    /// Defaults declared in mizeria.toml in the backup folder.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn is_append_only(&self) -> bool {
+        fingerprints::is_append_only(&self.location)
+    }
+
+    /// Once enabled, the mode cannot be disabled with mizeria.
+    pub fn enable_append_only(&self) -> Result<()> {
+        if !self.is_append_only() {
+            fingerprints::enable_append_only(&self.location)?;
+            debug!("Append-only mode enabled");
+        }
+        Ok(())
+    }
+
+    /// Compares fingerprints recorded at creation of snapshots
+    /// with their current state.
+    pub fn verify_fingerprints(&self) -> Result<Vec<(String, FingerprintStatus)>> {
+        let fingerprints = Fingerprints::open(&self.location)?;
+        let mut statuses = vec![];
+        for snapshot in &self.snapshots {
+            let name = snapshot.name();
+            let status = match fingerprints.get(&name) {
+                Some(recorded) => {
+                    let current = Snapshot::fingerprint(&self.location.join(&name))?;
+                    if current == recorded {
+                        FingerprintStatus::Unchanged
+                    } else {
+                        FingerprintStatus::Changed
+                    }
+                }
+                None => FingerprintStatus::NotRecorded,
+            };
+            statuses.push((name, status));
+        }
+        for name in fingerprints.snapshots() {
+            if !self.snapshots.iter().any(|s| s.name() == *name) {
+                statuses.push((name.clone(), FingerprintStatus::Removed));
+            }
+        }
+        Ok(statuses)
+    }
+
+    /// Removes snapshots older than the `keep_last` newest ones. Snapshots
+    /// whose data is still referenced by a kept snapshot are not removed.
+    /// Snapshots that were in the trash longer than the retention time are
+    /// removed for good before pruning.
+    pub fn prune(&mut self, keep_last: usize, removal: Removal) -> Result<PruneReport> {
+        if self.is_append_only() {
+            return Err("Backup is in append-only mode, snapshots cannot be removed".into());
+        }
+        let _lock = self.lock()?;
+        let mut report = PruneReport::default();
+        let now = self.environment.clock.now();
+        let mut trash = Trash::open(&self.location, now)?;
+        if let Removal::Trash(retention) = removal {
+            report.expired = trash.remove_expired(now, retention)?;
+        }
+        let mut needed_by: HashMap<String, String> = HashMap::new();
+        let candidates = self.snapshots.len().saturating_sub(keep_last);
+
+        // References always point to older snapshots, so walking from
+        // the newest one is enough to know every dependency in time.
+        for (position, preview) in self.snapshots.iter().enumerate().rev() {
+            let name = preview.name();
+            let location = self.location.join(&name);
+            let keep = position >= candidates || needed_by.contains_key(&name);
+            if keep {
+                if let Some(dependent) = needed_by.get(&name) {
+                    if position < candidates {
+                        report.kept.push((name.clone(), dependent.clone()));
+                    }
+                }
+                let snapshot = Snapshot::open(&location)
+                    .ok_or_else(|| format!("Cannot open snapshot {}", name))?;
+                for referenced in snapshot.referenced_snapshots() {
+                    needed_by.entry(referenced).or_insert_with(|| name.clone());
+                }
+                continue;
+            }
+
+            let link = Snapshot::chain_link(&location)?;
+            match removal {
+                Removal::Trash(_) => trash.put(&location, &name, now),
+                Removal::Delete => prune::remove_snapshot(&location, false),
+                Removal::Shred => prune::remove_snapshot(&location, true),
+            }
+            .map_err(|e| format!("Cannot remove snapshot {} ({})", name, e))?;
+            prune::record_pruned(&self.location, &link)?;
+            report.removed.push(name);
+        }
+
+        self.snapshots
+            .retain(|s| !report.removed.iter().any(|name| *name == s.name()));
+        report.removed.reverse();
+        report.kept.reverse();
+        Ok(report)
+    }
+
+    /// Removes a single snapshot. Snapshots referencing its data are
+    /// returned. Unless `force` is set, a snapshot referenced by newer
+    /// snapshots is not removed, as that would break their incremental chain.
+    pub fn delete_snapshot(&mut self, name: &str, force: bool) -> Result<Vec<String>> {
+        if self.is_append_only() {
+            return Err("Backup is in append-only mode, snapshots cannot be removed".into());
+        }
+        let _lock = self.lock()?;
+        let position = self
+            .snapshots
+            .iter()
+            .position(|s| s.name() == name)
+            .ok_or_else(|| format!("Snapshot {} doesn't exist", name))?;
+
+        // References always point to older snapshots.
+        let mut dependents = vec![];
+        for preview in &self.snapshots[position + 1..] {
+            let snapshot = Snapshot::open(&self.location.join(preview.name()))
+                .ok_or_else(|| format!("Cannot open snapshot {}", preview.name()))?;
+            if snapshot.referenced_snapshots().contains(name) {
+                dependents.push(preview.name());
+            }
+        }
+        if !dependents.is_empty() && !force {
+            return Err(format!(
+                "Snapshot {} is referenced by newer snapshots: {}",
+                name,
+                dependents.join(", ")
+            )
+            .into());
+        }
+
+        self.remove_snapshot(name)?;
+        Ok(dependents)
+    }
+
+    /// Rewrites snapshot `to` into a full snapshot, so snapshots from `from`
+    /// up to `to` are no longer needed to restore it. Newer snapshots that
+    /// reference the same data in these snapshots are pointed to `to`.
+    /// With `remove_originals`, snapshots that are no longer referenced are
+    /// removed.
+    pub fn squash(&mut self, from: &str, to: &str, remove_originals: bool) -> Result<SquashReport> {
+        if self.is_append_only() {
+            return Err("Backup is in append-only mode, snapshots cannot be rewritten".into());
+        }
+        let _lock = self.lock()?;
+        let from = self.resolve_snapshot(from)?;
+        let to = self.resolve_snapshot(to)?;
+        let first = self.snapshots.iter().position(|s| s.name() == from);
+        let last = self.snapshots.iter().position(|s| s.name() == to);
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) if first < last => (first, last),
+            _ => return Err(format!("Snapshot {} is not older than {}", from, to).into()),
+        };
+        // TO and every newer snapshot are rewritten, so they would no longer
+        // match their signatures or recorded fingerprints.
+        let fingerprints = Fingerprints::open(&self.location)?;
+        for preview in &self.snapshots[last..] {
+            let name = preview.name();
+            if Snapshot::is_signed(&self.location.join(&name)) {
+                return Err(format!(
+                    "Snapshot {} is signed and would have to be rewritten, so {} cannot be squashed",
+                    name, to
+                )
+                .into());
+            }
+            if fingerprints.get(&name).is_some() {
+                return Err(format!(
+                    "Snapshot {} is fingerprinted and would have to be rewritten, so {} cannot be squashed",
+                    name, to
+                )
+                .into());
+            }
+        }
+        let originals: Vec<String> = self.snapshots[first..last]
+            .iter()
+            .map(|s| s.name())
+            .collect();
+
+        let mut report = SquashReport {
+            snapshot: to.clone(),
+            ..SquashReport::default()
+        };
+        let mut snapshot = Self::get_snapshot(&self.location.join(&to))?;
+        let (squashed, copied) = snapshot.make_full()?;
+        report.copied = copied;
+
+        // Every rewritten snapshot changes the hash of all newer ones.
+        let mut referenced = snapshot.referenced_snapshots();
+        let mut previous = Snapshot::chain_link(&self.location.join(&to))?;
+        for preview in &self.snapshots[last + 1..] {
+            let location = self.location.join(preview.name());
+            let mut snapshot = Self::get_snapshot(&location)?;
+            report.redirected += snapshot.redirect_entries(&squashed, &originals)?;
+            snapshot.relink(previous)?;
+            referenced.extend(snapshot.referenced_snapshots());
+            previous = Snapshot::chain_link(&location)?;
+        }
+
+        report.unreferenced = originals
+            .into_iter()
+            .filter(|name| !referenced.contains(name))
+            .collect();
+        if remove_originals {
+            for name in &report.unreferenced {
+                self.remove_snapshot(name)?;
+            }
+        }
+        Ok(report)
+    }
+
+    fn remove_snapshot(&mut self, name: &str) -> Result<()> {
+        let location = self.location.join(name);
+        let link = Snapshot::chain_link(&location)?;
+        prune::remove_snapshot(&location, false)
+            .map_err(|e| format!("Cannot remove snapshot {} ({})", name, e))?;
+        prune::record_pruned(&self.location, &link)?;
+        self.snapshots.retain(|s| s.name() != name);
+        Ok(())
+    }
+
+    /// Snapshots in the trash with the time they were moved there.
+    pub fn trashed_snapshots(&self) -> Result<Vec<(String, time::OffsetDateTime)>> {
+        let trash = Trash::open(&self.location, self.environment.clock.now())?;
+        Ok(trash
+            .snapshots()
+            .map(|(name, time)| (name.clone(), *time))
+            .collect())
+    }
+
+    /// Moves a pruned snapshot from the trash back into the backup.
+    pub fn undelete(&mut self, name: &str) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut trash = Trash::open(&self.location, self.environment.clock.now())?;
+        trash.restore(name, &self.location)?;
+        self.snapshots = load_all_snapshot_previews(&self.location);
+        Ok(())
+    }
+
     pub fn get_all_snapshots(path: &Path) -> Vec<Snapshot> {
         load_all_snapshots(path)
     }
 
+    /// Snapshots of the backup whose indexes are read only when scanned,
+    /// so searching a large backup doesn't keep every index in memory.
+    pub fn get_all_snapshots_without_index(path: &Path) -> Vec<Snapshot> {
+        load_all_snapshots_without_index(path)
+    }
+
+    pub fn get_snapshot(path: &Path) -> Result<Snapshot> {
+        Snapshot::open(path)
+            .ok_or_else(|| format!("Cannot open snapshot: '{}'", path.display()).into())
+    }
+
+    /// Resolves a snapshot name, or a reference to the latest snapshot:
+    /// "latest", or "latest~N" for the N-th snapshot before it.
+    pub fn resolve_snapshot(&self, reference: &str) -> Result<String> {
+        let offset = match reference.strip_prefix("latest") {
+            Some("") => 0,
+            Some(offset) => offset
+                .strip_prefix('~')
+                .and_then(|n| n.parse::<usize>().ok())
+                .ok_or_else(|| format!("Invalid snapshot reference: '{}'", reference))?,
+            None if self.snapshots.iter().any(|s| s.name() == reference) => {
+                return Ok(reference.to_string())
+            }
+            None => return Err(format!("Snapshot {} doesn't exist", reference).into()),
+        };
+        if self.snapshots.is_empty() {
+            return Err("Backup has no snapshots".into());
+        }
+        let snapshot = self.snapshots.iter().rev().nth(offset).ok_or_else(|| {
+            format!(
+                "Snapshot {} doesn't exist, the backup has {} snapshot(s)",
+                reference,
+                self.snapshots.len()
+            )
+        })?;
+        Ok(snapshot.name())
+    }
+
+    /// Resolves a path to a snapshot whose name can be a reference like
+    /// "latest~1", e.g. "backup/latest~1". A path without a folder refers
+    /// to the current folder. Existing paths are never resolved.
+    pub fn resolve_snapshot_path(path: &Path) -> Result<PathBuf> {
+        let reference = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with("latest") && !path.exists() => name,
+            _ => return Ok(path.to_owned()),
+        };
+        let backup = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        let backup = Self::open(backup)?;
+        let name = backup.resolve_snapshot(reference)?;
+        debug!("Resolved {} to snapshot {}", path.display(), name);
+        Ok(path.with_file_name(name))
+    }
+
+    /// Finds where the data of `path` is stored. The latest snapshot
+    /// is searched unless a snapshot name or reference is given.
+    pub fn locate(&self, path: &Path, snapshot_name: Option<&str>) -> Result<(String, EntryInfo)> {
+        let name = self.resolve_snapshot(snapshot_name.unwrap_or("latest"))?;
+        let snapshot = Self::get_snapshot(&self.location.join(name))?;
+        let entry = snapshot.find_entry(path).ok_or_else(|| {
+            format!(
+                "Path '{}' is not indexed in snapshot {}",
+                path.display(),
+                snapshot.name()
+            )
+        })?;
+        Ok((snapshot.name(), entry))
+    }
+
+    /// Newest snapshot made at or before `moment`, given as a snapshot name,
+    /// e.g. "2021-07-15_18.34", or as a date meaning the end of that day.
+    pub fn snapshot_at(&self, moment: &str) -> Result<Snapshot> {
+        let moment = if Snapshot::has_valid_name(moment) {
+            moment.to_string()
+        } else if Snapshot::has_valid_name(format!("{}_23.59", moment)) {
+            format!("{}_23.59", moment)
+        } else {
+            return Err(format!("Invalid point in time: '{}'", moment).into());
+        };
+        let snapshot = self
+            .snapshots
+            .iter()
+            .filter(|s| s.name() <= moment)
+            .max()
+            .ok_or_else(|| format!("No snapshot was made at or before {}", moment))?;
+        debug!("Snapshot {} was the latest at {}", snapshot.name(), moment);
+        Self::get_snapshot(&self.location.join(snapshot.name()))
+    }
+
     pub fn get_all_snapshot_previews(path: &Path) -> Vec<SnapshotPreview> {
         load_all_snapshot_previews(path)
     }
 
-    pub fn check_integrity(&self, snapshot_name: &OsStr) -> IntegrityCheckResult {
+    pub fn get_foreign_entries(path: &Path) -> Vec<ForeignEntry> {
+        load_foreign_entries(path)
+            .into_iter()
+            .map(ForeignEntry::new)
+            .collect()
+    }
+
+    /// Signatures are checked only if the snapshot is otherwise intact.
+    /// With `only_new` the previous snapshots are trusted: the hash chain is
+    /// checked only up to the previous snapshot, so the check takes time
+    /// proportional to the data added by this snapshot.
+    pub fn check_integrity(
+        &self,
+        snapshot_name: &OsStr,
+        key: Option<&VerifyingKey>,
+        only_new: bool,
+    ) -> IntegrityReport {
         debug!("Integrity check start");
         let snapshot_path = self.location.join(snapshot_name);
-        Snapshot::check_integrity(&snapshot_path)
+        let mut report = Snapshot::check_integrity(&snapshot_path);
+        if report.passed(false) {
+            let max_links = if only_new { Some(1) } else { None };
+            Snapshot::check_chain(&snapshot_path, max_links, &mut report);
+            Snapshot::check_signatures(&snapshot_path, key, &mut report);
+        }
+        report
+    }
+
+    /// Verifies signatures and the checksums manifest of the snapshot at
+    /// `path`, without checking its files or the hash chain.
+    pub fn check_signatures(path: &Path, key: Option<&VerifyingKey>) -> IntegrityReport {
+        let mut report = IntegrityReport::new();
+        Snapshot::check_signatures(path, key, &mut report);
+        report
+    }
+
+    /// Checks snapshots that weren't verified for the longest time, until
+    /// the budget is spent. Snapshots are checked with `only_new`, so every
+    /// link of the hash chain is covered over successive runs. Time of
+    /// every passed check is recorded in the backup folder.
+    pub fn verify_rolling(
+        &self,
+        budget: VerificationBudget,
+    ) -> Result<Vec<(String, IntegrityReport)>> {
+        let mut log = VerificationLog::open(&self.location)?;
+        let names: Vec<String> = self.snapshots.iter().map(|s| s.name()).collect();
+        let mut queue = names.clone();
+        // Never verified snapshots go first, then the oldest verified
+        queue.sort_by_key(|name| log.last_verified(name));
+
+        let results = match budget {
+            VerificationBudget::Time(limit) => {
+                let started = Instant::now();
+                let mut results = vec![];
+                for name in queue {
+                    if started.elapsed() >= limit {
+                        break;
+                    }
+                    let report = self.verify_snapshot(&name);
+                    results.push((name, report));
+                }
+                results
+            }
+            VerificationBudget::Snapshots(_) | VerificationBudget::All => {
+                if let VerificationBudget::Snapshots(limit) = budget {
+                    queue.truncate(limit);
+                }
+                // Snapshots are checked in parallel when the budget doesn't depend on time
+                parallel::map(queue, |name| {
+                    let report = self.verify_snapshot(&name);
+                    (name, report)
+                })
+            }
+        };
+        for (name, report) in &results {
+            if report.passed(false) {
+                log.record(name, time::OffsetDateTime::now_utc());
+            }
+        }
+
+        log.retain(&names);
+        log.save()?;
+        Ok(results)
+    }
+
+    /// Verifies content of files stored in every snapshot against checksums
+    /// in its index, skipping snapshots scrubbed within `since`. Time of
+    /// every passed scrub is recorded in the backup folder.
+    pub fn scrub(&self, since: Option<Duration>) -> Result<Vec<(String, IntegrityReport)>> {
+        let mut log = VerificationLog::open_scrubbed(&self.location)?;
+        let now = time::OffsetDateTime::now_utc();
+        let names: Vec<String> = self.snapshots.iter().map(|s| s.name()).collect();
+        let due = |name: &&String| match (log.last_verified(name), since) {
+            (Some(scrubbed), Some(since)) => now - scrubbed >= since,
+            _ => true,
+        };
+        let queue: Vec<&String> = names.iter().filter(due).collect();
+        debug!("Scrubbing {} of {} snapshot(s)", queue.len(), names.len());
+
+        let mut results = vec![];
+        for name in queue {
+            let mut report = IntegrityReport::new();
+            match Snapshot::open(&self.location.join(name)) {
+                Some(snapshot) => snapshot.verify_content(&mut report),
+                None => report.push(IntegrityCheckError::SnapshotDoesntExist),
+            }
+            if report.passed(false) {
+                log.record(name, now);
+            }
+            results.push((name.clone(), report));
+        }
+
+        log.retain(&names);
+        log.save()?;
+        Ok(results)
     }
 
-    pub fn add_snapshot(&mut self, files: &[PathBuf], incremental: bool) -> Result<String> {
+    /// Replaces files of a snapshot that don't match their checksums with
+    /// intact copies of the same entries, i.e. with the same path and
+    /// checksum, stored in other snapshots. Newer snapshots are searched
+    /// first.
+    pub fn repair(&self, name: &str) -> Result<RepairReport> {
+        if self.is_append_only() {
+            return Err("Backup is in append-only mode, snapshots cannot be changed".into());
+        }
+        let _lock = self.lock()?;
+        let name = self.resolve_snapshot(name)?;
+        let snapshot = Self::get_snapshot(&self.location.join(&name))?;
+        let (mut remaining, _) = snapshot.find_corrupted_files();
+        debug!("Found {} corrupted file(s) in {}", remaining.len(), name);
+
+        let mut report = RepairReport::default();
+        if !remaining.is_empty() && snapshot.has_parity() {
+            let blocks = snapshot
+                .repair_with_parity()
+                .map_err(|e| format!("Cannot repair {} with parity data ({})", name, e))?;
+            if blocks > 0 {
+                let (corrupted, _) = snapshot.find_corrupted_files();
+                report.from_parity = remaining
+                    .iter()
+                    .filter(|file| !corrupted.iter().any(|c| c.path == file.path))
+                    .map(|file| file.path.clone())
+                    .collect();
+                remaining = corrupted;
+            }
+        }
+        for other in self.snapshots.iter().rev().filter(|s| s.name() != name) {
+            // Files of an archived snapshot can be repaired only with parity data.
+            if remaining.is_empty() || snapshot.is_archived() {
+                break;
+            }
+            let other = match Snapshot::open(&self.location.join(other.name())) {
+                Some(other) => other,
+                None => continue,
+            };
+            let mut unrepaired = vec![];
+            for file in remaining {
+                let copy = match other.find_intact_copy(&file) {
+                    Some(copy) => copy,
+                    None => {
+                        unrepaired.push(file);
+                        continue;
+                    }
+                };
+                snapshot
+                    .repair_file(&file, &copy)
+                    .map_err(|e| format!("Cannot repair '{}' ({})", file.data.display(), e))?;
+                debug!("Repaired {} from {}", file.path.display(), other.name());
+                report.repaired.push((file.path, other.name()));
+            }
+            remaining = unrepaired;
+        }
+        report.unrepaired = remaining.into_iter().map(|file| file.path).collect();
+        Ok(report)
+    }
+
+    /// Adds Reed-Solomon parity data taking about `percent` of the size of
+    /// files stored in the snapshot.
+    pub fn add_parity(&self, name: &str, percent: u8) -> Result<ParitySummary> {
+        if self.is_append_only() {
+            return Err("Backup is in append-only mode, snapshots cannot be changed".into());
+        }
+        let _lock = self.lock()?;
+        let name = self.resolve_snapshot(name)?;
+        let snapshot = Self::get_snapshot(&self.location.join(&name))?;
+        Ok(snapshot.add_parity(percent)?)
+    }
+
+    fn verify_snapshot(&self, name: &str) -> IntegrityReport {
+        debug!("Verifying snapshot: {}", name);
+        self.check_integrity(OsStr::new(name), None, true)
+    }
+
+    pub fn add_snapshot(
+        &mut self,
+        files: &[PathBuf],
+        config: &BackupConfig,
+    ) -> Result<BackupReport> {
         debug!("Started backup process");
-        // TODO: pass self.latest_snapshot() to Snapshot::create
-        //       because currently snapshot has to load all snapshots
-        //       to find the latest one.
-        let mut new_snapshot = Snapshot::create(self.location.as_path())?;
+        let started = Instant::now();
+        let mut filteres_files = Self::validate_input_paths(files)?;
+        let mut warnings: Vec<String> = files
+            .iter()
+            .filter(|f| !filteres_files.contains(f))
+            .map(|f| format!("Skipped input path: \"{}\"", f.display()))
+            .collect();
+        let _lock = Lock::acquire(&self.location, config.break_lock)?;
+        let destination = self.probe_destination(config.limits_policy)?;
+        let previous = self.previous_chain_link(&mut warnings);
+        let mut new_snapshot = match config.resume {
+            true => self.resume_or_create_snapshot()?,
+            false => Snapshot::create(self.location.as_path(), &self.environment)?,
+        };
+        let _span = snapshot_span(&new_snapshot).entered();
+        self.restrict_new_snapshot(&new_snapshot, config, &mut warnings);
 
-        self.set_incremental_snapshot(&mut new_snapshot, incremental);
-        let filteres_files = Self::validate_input_paths(files);
+        new_snapshot.set_destination_limits(&destination);
+        new_snapshot.set_copy_options(config.copy_options);
+        new_snapshot.set_max_depth(config.max_depth);
+        new_snapshot.set_exclude(config.exclude.clone());
+        new_snapshot.set_size_limit(config.max_file_size, config.large_files);
+        new_snapshot.set_special_files(config.special_files);
+        new_snapshot.set_chunking(config.chunk_threshold);
+        new_snapshot.set_compression(config.compression);
+        new_snapshot.set_encryption(config.encryption.clone());
+        new_snapshot.set_preserve_attributes(config.preserve_attributes);
+        new_snapshot.set_fail_fast(config.fail_fast);
+        new_snapshot.set_checksum(config.checksum);
+        if let Some(limit) = config.memory_limit {
+            new_snapshot.set_memory_limit(limit)?;
+            // Entries are compared with the sorted base index in order
+            filteres_files.sort_by_cached_key(|path| path.canonicalize().ok());
+        }
+        self.set_incremental_snapshot(&mut new_snapshot, config.incremental);
+        if let Some(style) = config.progress {
+            new_snapshot.set_progress(Progress::start(style, &filteres_files, config.max_depth));
+        }
 
         for path in filteres_files {
+            if new_snapshot.is_interrupted() || new_snapshot.has_failed_fast() {
+                break;
+            }
             new_snapshot.add_files_to_snapshot(path);
         }
+        new_snapshot.finish_progress();
+        if new_snapshot.has_failed_fast() {
+            // Files copied so far are reused when the snapshot is resumed.
+            return Err(format!(
+                "Backup stopped at the first error: {}. Unfinished snapshot {} can be continued with --resume",
+                new_snapshot.failures()[0].1,
+                new_snapshot.name()
+            )
+            .into());
+        }
+        let interrupted = new_snapshot.is_interrupted();
+        if interrupted && config.remove_partial {
+            let name = new_snapshot.name();
+            let location = new_snapshot.location().to_owned();
+            drop(new_snapshot);
+            fs::remove_dir_all(location)
+                .map_err(|e| format!("Cannot remove unfinished snapshot {} ({})", name, e))?;
+            return Err(Interrupted(format!(
+                "Backup was interrupted, unfinished snapshot {} was removed",
+                name
+            ))
+            .into());
+        }
+        // Entries copied so far are indexed, so the snapshot is a valid base.
+        let mut report =
+            self.finish_snapshot(new_snapshot, files, started, previous, config, warnings)?;
+        report.interrupted = interrupted;
+        Ok(report)
+    }
+
+    /// Imports dated folders in `root`, made by another backup tool, as
+    /// snapshots from the oldest. Every folder is a copy of `original`.
+    /// Files that didn't change since the previous folder are stored only
+    /// once. Folders that aren't newer than the latest snapshot are skipped.
+    pub fn import_dated_folders(
+        &mut self,
+        root: &Path,
+        original: &Path,
+        config: &BackupConfig,
+    ) -> Result<ImportReport> {
+        if !original.is_absolute() {
+            return Err("Original path of imported folders must be absolute".into());
+        }
+        let (folders, skipped) = import::find_dated_folders(root)
+            .map_err(|e| format!("Cannot read folder '{}' ({})", root.display(), e))?;
+        let mut report = ImportReport {
+            skipped: skipped
+                .into_iter()
+                .map(|path| (path, t!("no date in its name")))
+                .collect(),
+            ..Default::default()
+        };
+        let destination = self.probe_destination(config.limits_policy)?;
+        let _lock = Lock::acquire(&self.location, config.break_lock)?;
+
+        let mut previous_folder: Option<PathBuf> = None;
+        for folder in folders {
+            let name = import::snapshot_name(folder.time);
+            if let Some(latest) = self.latest_snapshot().filter(|l| l.name() >= name) {
+                let reason = t!("not newer than snapshot {}", latest.name());
+                report.skipped.push((folder.path, reason));
+                continue;
+            }
+            debug!("Importing folder: {}", folder.path.display());
+            let snapshot_report = self.import_snapshot(
+                &folder,
+                original,
+                previous_folder.as_deref(),
+                &destination,
+                config,
+            )?;
+            report.imported.push((folder.path.clone(), snapshot_report));
+            previous_folder = Some(folder.path);
+        }
+        Ok(report)
+    }
+
+    /// Imports a tar archive made by another tool, e.g. an old tarball of
+    /// `original`, as a full snapshot. The snapshot is named after the date
+    /// in the name of the archive or, without one, its modification time,
+    /// and has to be newer than the latest snapshot.
+    pub fn import_archive(
+        &mut self,
+        archive: &Path,
+        original: &Path,
+        config: &BackupConfig,
+    ) -> Result<BackupReport> {
+        if !original.is_absolute() {
+            return Err("Original path of the imported archive must be absolute".into());
+        }
+        let time = import::archive_time(archive)
+            .map_err(|e| format!("Cannot open '{}' ({})", archive.display(), e))?;
+        let name = import::snapshot_name(time);
+        if let Some(latest) = self.latest_snapshot().filter(|l| l.name() >= name) {
+            return Err(format!(
+                "Archive made at {} isn't newer than snapshot {}",
+                name,
+                latest.name()
+            )
+            .into());
+        }
+        let destination = self.probe_destination(config.limits_policy)?;
+        let _lock = Lock::acquire(&self.location, config.break_lock)?;
+
+        let unpacked = Unpacked::empty(&name)
+            .map_err(|e| format!("Cannot create a temporary folder ({})", e))?;
+        // The temporary folder is private, the archived one doesn't have to be.
+        let path = unpacked.folder().join("archive");
+        debug!("Unpacking {} into {}", archive.display(), path.display());
+        fs::create_dir(&path).map_err(|e| format!("Cannot create a temporary folder ({})", e))?;
+        crate::archive::unpack_tar(archive, &path)
+            .map_err(|e| format!("Cannot unpack '{}' ({})", archive.display(), e))?;
+        let folder = import::DatedFolder { path, time };
+        self.import_snapshot(&folder, original, None, &destination, config)
+    }
+
+    /// Creates a snapshot from a copy of `original` made by another tool.
+    fn import_snapshot(
+        &mut self,
+        folder: &import::DatedFolder,
+        original: &Path,
+        previous_folder: Option<&Path>,
+        destination: &Destination,
+        config: &BackupConfig,
+    ) -> Result<BackupReport> {
+        let started = Instant::now();
+        let mut warnings = vec![];
+        let previous = self.previous_chain_link(&mut warnings);
+        let environment = Environment {
+            clock: Arc::new(FolderClock(folder.time)),
+            fs: self.environment.fs.clone(),
+            interrupted: self.environment.interrupted.clone(),
+        };
+        let mut new_snapshot = Snapshot::create(self.location.as_path(), &environment)?;
+        let _span = snapshot_span(&new_snapshot).entered();
+        self.restrict_new_snapshot(&new_snapshot, config, &mut warnings);
+
+        new_snapshot.set_destination_limits(destination);
+        new_snapshot.set_copy_options(config.copy_options);
+        // Only a snapshot imported in this run is known to match the previous folder
+        if previous_folder.is_some() {
+            self.set_incremental_snapshot(&mut new_snapshot, config.incremental);
+        }
+        new_snapshot.import_folder(&folder.path, original, previous_folder);
+        let inputs = [original.to_owned()];
+        self.finish_snapshot(new_snapshot, &inputs, started, previous, config, warnings)
+    }
+
+    /// Continues the newest unfinished snapshot, unless a snapshot was made
+    /// after it. Then, or when there is none, a new snapshot is created.
+    fn resume_or_create_snapshot(&self) -> Result<Snapshot> {
+        let latest = self.latest_snapshot().map(|s| s.name());
+        match find_unfinished_snapshot(&self.location) {
+            Some((name, location)) if latest.as_ref().is_none_or(|latest| name > *latest) => {
+                info!("Resuming unfinished snapshot: {}", name);
+                return Ok(Snapshot::resume(&location, &self.environment)?);
+            }
+            Some((name, _)) => warn!(
+                "Unfinished snapshot {} is older than the latest snapshot, it can't be resumed",
+                name
+            ),
+            None => info!("No unfinished snapshot to resume"),
+        }
+        Ok(Snapshot::create(
+            self.location.as_path(),
+            &self.environment,
+        )?)
+    }
+
+    fn previous_chain_link(&self, warnings: &mut Vec<String>) -> Option<ChainLink> {
+        let latest = self.latest_snapshot()?;
+        Snapshot::chain_link(&self.location.join(latest.name()))
+            .map_err(|e| {
+                warnings.push(format!(
+                    "Failed to link snapshot {} in the hash chain ({})",
+                    latest.name(),
+                    e
+                ))
+            })
+            .ok()
+    }
+
+    /// Nothing inside is accessible by others while the snapshot is being made.
+    fn restrict_new_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        config: &BackupConfig,
+        warnings: &mut Vec<String>,
+    ) {
+        if let Err(e) = permissions::restrict(snapshot.location(), config.permissions) {
+            warnings.push(format!(
+                "Failed to restrict permissions of snapshot ({})",
+                e
+            ));
+        }
+    }
+
+    /// Saves the index and metadata of a new snapshot, signs it and
+    /// records its fingerprint as configured.
+    fn finish_snapshot(
+        &mut self,
+        mut new_snapshot: Snapshot,
+        inputs: &[PathBuf],
+        started: Instant,
+        previous: Option<ChainLink>,
+        config: &BackupConfig,
+        mut warnings: Vec<String>,
+    ) -> Result<BackupReport> {
+        let snapshot_location = self.location.join(new_snapshot.name());
         new_snapshot.save_index()?;
+        let readback = match config.verify {
+            true => Some(new_snapshot.read_back_copies().map_err(|e| {
+                format!(
+                    "Cannot verify copies of snapshot {} ({})",
+                    new_snapshot.name(),
+                    e
+                )
+            })?),
+            false => None,
+        };
+        if config.format == SnapshotFormat::Archive {
+            new_snapshot.pack_files().map_err(|e| {
+                format!(
+                    "Cannot pack files of snapshot {} ({})",
+                    new_snapshot.name(),
+                    e
+                )
+            })?;
+        }
+        if let Err(e) = new_snapshot.save_metadata(inputs, started.elapsed(), previous) {
+            warnings.push(format!("Failed to save metadata of snapshot ({})", e));
+        }
+        if let Err(e) = new_snapshot.save_errors() {
+            warnings.push(format!("Failed to save errors of snapshot ({})", e));
+        }
+        // The index has checksums of the original files, encrypted data is
+        // verified by checksums in the manifest. Signing writes it anyway.
+        if config.encryption.is_some() && config.signing_key.is_none() {
+            new_snapshot.save_checksums().map_err(|e| {
+                format!(
+                    "Cannot save checksums of snapshot {} ({})",
+                    new_snapshot.name(),
+                    e
+                )
+            })?;
+        }
+        new_snapshot.commit().map_err(|e| {
+            format!(
+                "Cannot rename unfinished snapshot {} ({})",
+                new_snapshot.name(),
+                e
+            )
+        })?;
+        new_snapshot.save_files_cache();
+        if let Some(signing_key) = &config.signing_key {
+            Self::sign_snapshot(&new_snapshot, signing_key).map_err(|e| {
+                format!(
+                    "Snapshot {} was created, but it couldn't be signed: {}",
+                    new_snapshot.name(),
+                    e
+                )
+            })?;
+        }
+
+        if let Err(e) = permissions::restrict_all(&snapshot_location, config.permissions) {
+            warnings.push(format!(
+                "Failed to restrict permissions of snapshot's content ({})",
+                e
+            ));
+        }
+
+        if self.is_append_only() {
+            self.record_fingerprint(&new_snapshot)?;
+        }
 
         debug!("Finished backup process");
         self.snapshots.push(new_snapshot.to_preview());
 
-        Ok(new_snapshot.name())
+        warnings.extend_from_slice(new_snapshot.warnings());
+        let (verified, mismatched) = match readback {
+            Some(readback) => {
+                for path in readback.changed {
+                    warnings.push(format!(
+                        "Not verified: \"{}\" (changed after it was copied)",
+                        path.display()
+                    ));
+                }
+                (Some(readback.verified), readback.mismatched)
+            }
+            None => (None, vec![]),
+        };
+        Ok(BackupReport {
+            snapshot: new_snapshot.name(),
+            warnings,
+            not_copied: new_snapshot.not_copied().to_vec(),
+            failures: new_snapshot.failures().to_vec(),
+            interrupted: false,
+            verified,
+            mismatched,
+            stats: new_snapshot.stats(started.elapsed()),
+        })
+    }
+
+    fn record_fingerprint(&self, snapshot: &Snapshot) -> Result<()> {
+        let location = self.location.join(snapshot.name());
+        let error = |e: String| {
+            format!(
+                "Snapshot {} was created, but its fingerprint couldn't be recorded: {}",
+                snapshot.name(),
+                e
+            )
+        };
+        let fingerprint = Snapshot::fingerprint(&location).map_err(|e| error(e.to_string()))?;
+        Fingerprints::open(&self.location)
+            .and_then(|mut f| {
+                f.append(&snapshot.name(), &fingerprint)
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(error)?;
+        debug!(
+            "Recorded fingerprint of {}: {}",
+            snapshot.name(),
+            fingerprint
+        );
+        Ok(())
+    }
+
+    /// Signs the index and a manifest with checksums of stored files.
+    fn sign_snapshot(snapshot: &Snapshot, signing_key: &SigningKey) -> Result<()> {
+        let manifest = snapshot.save_checksums()?;
+        signing_key.sign(snapshot.index_path())?;
+        signing_key.sign(&manifest)?;
+        debug!("Snapshot signed with {}", signing_key.tool);
+        Ok(())
+    }
+
+    fn probe_destination(&self, policy: LimitsPolicy) -> Result<Destination> {
+        let destination = Destination::probe(&self.location).map_err(|e| {
+            format!(
+                "Cannot write to backup folder '{}' ({})",
+                self.location.display(),
+                e
+            )
+        })?;
+
+        let limitations = destination.limitations();
+        if limitations.is_empty() {
+            return Ok(destination);
+        }
+        if policy == LimitsPolicy::Abort {
+            return Err(format!(
+                "Backup folder is on {}: {}. Use --on-fs-limits=adapt to skip what cannot be stored",
+                destination.filesystem,
+                limitations.join(", ")
+            )
+            .into());
+        }
+        for limitation in limitations {
+            warn!(
+                "Backup folder is on {}: {}",
+                destination.filesystem, limitation
+            );
+        }
+        Ok(destination)
     }
 
     fn set_incremental_snapshot(&self, snapshot: &mut Snapshot, incremental: bool) {
@@ -77,16 +1241,40 @@ impl Backup {
         }
     }
 
-    fn latest_snapshot(&self) -> Option<&SnapshotPreview> {
+    /// What a backup of `files` would copy into a new snapshot, based on
+    /// the latest snapshot unless it's a full backup. Nothing is written.
+    pub fn estimate(
+        &self,
+        files: &[PathBuf],
+        incremental: bool,
+        max_depth: Option<usize>,
+    ) -> Result<Estimate> {
+        let files = Self::validate_input_paths(files)?;
+        let base = self.latest_snapshot().filter(|_| incremental);
+        Ok(Snapshot::estimate(&files, base, max_depth))
+    }
+
+    pub fn latest_snapshot(&self) -> Option<&SnapshotPreview> {
         self.snapshots.last()
     }
 
-    fn validate_input_paths(paths: &[PathBuf]) -> Vec<&PathBuf> {
+    fn validate_input_paths(paths: &[PathBuf]) -> Result<Vec<&PathBuf>> {
         let existent_paths = Self::remove_nonexistent_paths(paths);
-        let paths_without_duplicates = Self::remove_duplicated_paths(existent_paths);
+        let paths_without_duplicates = Self::remove_duplicated_paths(existent_paths)?;
         Self::remove_overlapping_paths(paths_without_duplicates)
     }
 
+    fn canonicalize(path: &Path) -> Result<PathBuf> {
+        path.canonicalize().map_err(|e| {
+            format!(
+                "Cannot determine an absolute path for: '{}' ({})",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
     fn remove_nonexistent_paths(paths: &[PathBuf]) -> Vec<&PathBuf> {
         let mut filtered = vec![];
         for path in paths {
@@ -99,39 +1287,43 @@ impl Backup {
         filtered
     }
 
-    fn remove_duplicated_paths(paths: Vec<&PathBuf>) -> Vec<&PathBuf> {
-        let mut filtered: Vec<&PathBuf> = vec![];
+    fn remove_duplicated_paths(paths: Vec<&PathBuf>) -> Result<Vec<&PathBuf>> {
+        let mut filtered: Vec<(&PathBuf, PathBuf)> = vec![];
 
         for path in paths {
-            let absolute_path = path.canonicalize().unwrap();
+            let absolute_path = Self::canonicalize(path)?;
             let duplicate = filtered
                 .iter()
-                .find(|p| p.canonicalize().unwrap() == absolute_path);
+                .find(|(_, p_abs)| *p_abs == absolute_path)
+                .map(|(p, _)| p);
             match duplicate {
                 Some(duplicate) => warn!(
                     "Path \"{}\" is the same as {}",
                     path.display(),
                     duplicate.display()
                 ),
-                None => filtered.push(path),
+                None => filtered.push((path, absolute_path)),
             }
         }
-        filtered
+        Ok(filtered.into_iter().map(|(p, _)| p).collect())
     }
 
-    fn remove_overlapping_paths(paths: Vec<&PathBuf>) -> Vec<&PathBuf> {
+    fn remove_overlapping_paths(paths: Vec<&PathBuf>) -> Result<Vec<&PathBuf>> {
+        let mut absolute_paths = vec![];
+        for path in &paths {
+            absolute_paths.push(Self::canonicalize(path)?);
+        }
+
         let mut filtered = vec![];
 
-        for path in &paths {
-            let absolute_path = path.canonicalize().unwrap();
-            let prefix_path = paths.iter().find(|p| {
-                let p_abs = p.canonicalize().unwrap();
-                let paths_are_different = absolute_path != p_abs;
-                let path_has_prefix = absolute_path.starts_with(&p_abs);
+        for (path, absolute_path) in paths.iter().zip(&absolute_paths) {
+            let prefix_path = paths.iter().zip(&absolute_paths).find(|(_, p_abs)| {
+                let paths_are_different = absolute_path != *p_abs;
+                let path_has_prefix = absolute_path.starts_with(p_abs);
                 path_has_prefix && paths_are_different
             });
             match prefix_path {
-                Some(prefix) => warn!(
+                Some((prefix, _)) => warn!(
                     "Path \"{}\" includes \"{}\". Child path will be ignored",
                     prefix.display(),
                     path.display()
@@ -140,15 +1332,60 @@ impl Backup {
             }
         }
 
-        filtered
+        Ok(filtered)
     }
 }
 
+/// Span of logs made while the snapshot is created. Numbers of entries are
+/// recorded in it when the snapshot is complete.
+fn snapshot_span(snapshot: &Snapshot) -> Span {
+    info_span!(
+        "snapshot",
+        name = %snapshot.name(),
+        indexed = field::Empty,
+        copied = field::Empty
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::create_dir_all;
 
     use super::*;
+    use crate::environment::RealFileSystem;
+
+    #[test]
+    fn resolve_references_to_latest_snapshots() {
+        let tempdir = tempfile::tempdir().unwrap();
+        for name in ["2021-07-14_10.00", "2021-07-15_10.00", "2021-07-16_10.00"] {
+            create_dir_all(tempdir.path().join(name).join("files")).unwrap();
+            fs::write(tempdir.path().join(name).join("index.txt"), "").unwrap();
+        }
+        let backup = Backup::open(tempdir.path()).unwrap();
+
+        let resolve = |reference| {
+            backup
+                .resolve_snapshot(reference)
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(resolve("latest").unwrap(), "2021-07-16_10.00");
+        assert_eq!(resolve("latest~0").unwrap(), "2021-07-16_10.00");
+        assert_eq!(resolve("latest~2").unwrap(), "2021-07-14_10.00");
+        assert_eq!(resolve("2021-07-15_10.00").unwrap(), "2021-07-15_10.00");
+        assert_eq!(
+            resolve("latest~3").unwrap_err(),
+            "Snapshot latest~3 doesn't exist, the backup has 3 snapshot(s)"
+        );
+        assert_eq!(
+            resolve("latest-1").unwrap_err(),
+            "Invalid snapshot reference: 'latest-1'"
+        );
+        assert_eq!(
+            resolve("2021-07-17_10.00").unwrap_err(),
+            "Snapshot 2021-07-17_10.00 doesn't exist"
+        );
+    }
 
     #[test]
     fn remove_nonexistent_paths() {
@@ -173,7 +1410,7 @@ mod tests {
         let path_4 = path_4.path().to_owned();
         let paths = vec![&path_1, &path_2, &path_3, &path_4];
 
-        let result = Backup::remove_duplicated_paths(paths);
+        let result = Backup::remove_duplicated_paths(paths).unwrap();
 
         assert_eq!(result.len(), 3);
         assert_eq!(result[0], &path_1);
@@ -191,7 +1428,7 @@ mod tests {
         let path_3 = path_3.path().to_owned();
         let paths = vec![&path_1, &path_2, &path_3];
 
-        let result = Backup::remove_duplicated_paths(paths);
+        let result = Backup::remove_duplicated_paths(paths).unwrap();
 
         assert_eq!(result.len(), 3);
         assert_eq!(result[0], &path_1);
@@ -209,7 +1446,7 @@ mod tests {
         create_dir_all(&path_1).unwrap();
         let paths = vec![&path_1, &path_2];
 
-        let filtered = Backup::remove_overlapping_paths(paths);
+        let filtered = Backup::remove_overlapping_paths(paths).unwrap();
 
         assert_eq!(filtered.len(), 2);
         assert_eq!(filtered[0], &path_1);
@@ -229,10 +1466,256 @@ mod tests {
         create_dir_all(&path_4).unwrap();
         let paths = vec![&path_1, &path_3, &path_4, &path_2];
 
-        let filtered = Backup::remove_overlapping_paths(paths);
+        let filtered = Backup::remove_overlapping_paths(paths).unwrap();
 
         assert_eq!(filtered.len(), 2);
         assert_eq!(filtered[0], &path_1);
         assert_eq!(filtered[1], &path_4);
     }
+
+    #[test]
+    fn remove_duplicated_paths_fails_for_nonexistent_path() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let existent = tempdir.path().to_owned();
+        let nonexistent = existent.join("foobar");
+        let paths = vec![&existent, &nonexistent];
+
+        let result = Backup::remove_duplicated_paths(paths);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn remove_overlapping_paths_fails_for_nonexistent_path() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let existent = tempdir.path().to_owned();
+        let nonexistent = existent.join("foobar");
+        let paths = vec![&existent, &nonexistent];
+
+        let result = Backup::remove_overlapping_paths(paths);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn interrupted_backup_keeps_or_removes_snapshot() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("file.txt"), b"file").unwrap();
+        let inputs = [input.path().to_owned()];
+        let environment = Environment::default();
+        environment
+            .interrupted
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let mut backup = Backup::open_with_environment(backup_dir.path(), environment).unwrap();
+
+        let report = backup.add_snapshot(&inputs, &Default::default()).unwrap();
+        assert!(report.interrupted);
+        let snapshot = Backup::get_snapshot(&backup_dir.path().join(&report.snapshot)).unwrap();
+        assert_eq!(snapshot.metadata().unwrap().indexed, 0);
+
+        let config = BackupConfig {
+            remove_partial: true,
+            ..Default::default()
+        };
+        fs::remove_dir_all(backup_dir.path().join(&report.snapshot)).unwrap();
+        let error = backup.add_snapshot(&inputs, &config).err().unwrap();
+        assert!(error.is::<Interrupted>());
+        assert!(load_all_snapshot_previews(backup_dir.path()).is_empty());
+    }
+
+    /// Fails to copy files whose names start with "bad".
+    struct BadFilesFileSystem;
+    impl crate::environment::FileSystem for BadFilesFileSystem {
+        fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+            RealFileSystem.create_dir_all(path)
+        }
+        fn copy_file(&self, from: &Path, to: &Path) -> std::io::Result<u64> {
+            match from
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("bad")
+            {
+                true => Err(std::io::Error::other("bad sector")),
+                false => RealFileSystem.copy_file(from, to),
+            }
+        }
+        fn read_link(&self, path: &Path) -> std::io::Result<PathBuf> {
+            RealFileSystem.read_link(path)
+        }
+        fn symlink(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+            RealFileSystem.symlink(original, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+            RealFileSystem.hard_link(original, link)
+        }
+    }
+
+    #[test]
+    fn failures_are_listed_or_stop_the_backup() {
+        let input = tempfile::tempdir().unwrap();
+        for name in ["bad1.txt", "bad2.txt", "good.txt"] {
+            fs::write(input.path().join(name), name).unwrap();
+        }
+        let inputs = [input.path().to_owned()];
+        let environment = || Environment {
+            fs: Arc::new(BadFilesFileSystem),
+            ..Environment::default()
+        };
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let mut backup = Backup::open_with_environment(backup_dir.path(), environment()).unwrap();
+        let report = backup.add_snapshot(&inputs, &Default::default()).unwrap();
+        assert_eq!(report.failures.len(), 2);
+        let errors = backup_dir
+            .path()
+            .join(&report.snapshot)
+            .join(ERRORS_FILE_NAME);
+        assert_eq!(fs::read_to_string(errors).unwrap().lines().count(), 2);
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let mut backup = Backup::open_with_environment(backup_dir.path(), environment()).unwrap();
+        let config = BackupConfig {
+            fail_fast: true,
+            ..Default::default()
+        };
+        let error = backup.add_snapshot(&inputs, &config).err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("Backup stopped at the first error"),
+            "{}",
+            error
+        );
+        assert!(find_unfinished_snapshot(backup_dir.path()).is_some());
+    }
+
+    struct TomorrowClock;
+
+    impl crate::environment::Clock for TomorrowClock {
+        fn now(&self) -> time::OffsetDateTime {
+            time::OffsetDateTime::now_utc() + time::Duration::days(1)
+        }
+    }
+
+    #[test]
+    fn report_has_stats_of_backup() {
+        struct InTwoDays;
+        impl crate::environment::Clock for InTwoDays {
+            fn now(&self) -> time::OffsetDateTime {
+                time::OffsetDateTime::now_utc() + time::Duration::days(2)
+            }
+        }
+        let backup_dir = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("file.txt"), b"file").unwrap();
+        let inputs = [input.path().join("file.txt")];
+        let backup_at = |clock: Arc<dyn crate::environment::Clock>| {
+            let environment = Environment {
+                clock,
+                ..Environment::default()
+            };
+            let mut backup = Backup::open_with_environment(backup_dir.path(), environment).unwrap();
+            backup
+                .add_snapshot(&inputs, &Default::default())
+                .unwrap()
+                .stats
+        };
+
+        let stats = backup_at(Arc::new(TomorrowClock));
+        assert_eq!(
+            (stats.scanned, stats.copied, stats.reused, stats.written),
+            (1, 1, 0, 4)
+        );
+
+        let stats = backup_at(Arc::new(InTwoDays));
+        assert_eq!(
+            (stats.scanned, stats.copied, stats.reused, stats.written),
+            (1, 0, 1, 0)
+        );
+        assert_eq!(stats.errors, 0);
+    }
+
+    #[test]
+    fn prune_keeps_snapshots_referenced_by_kept_ones() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("file.txt"), b"file").unwrap();
+        let inputs = [input.path().join("file.txt")];
+        let full = BackupConfig {
+            incremental: false,
+            ..Default::default()
+        };
+
+        // file is older than snapshots, so the incremental one refers to it
+        let environment = Environment {
+            clock: std::sync::Arc::new(TomorrowClock),
+            ..Default::default()
+        };
+        let mut backup = Backup::open_with_environment(backup_dir.path(), environment).unwrap();
+        backup.add_snapshot(&inputs, &full).unwrap();
+        backup.add_snapshot(&inputs, &Default::default()).unwrap();
+        backup.add_snapshot(&inputs, &full).unwrap();
+        let names: Vec<String> = backup.snapshots().iter().map(|s| s.name()).collect();
+
+        let report = backup.prune(2, Removal::Delete).unwrap();
+        assert!(report.removed.is_empty());
+        assert_eq!(report.kept, vec![(names[0].clone(), names[1].clone())]);
+
+        let report = backup.prune(1, Removal::Shred).unwrap();
+        assert_eq!(report.removed, vec![names[0].clone(), names[1].clone()]);
+        assert_eq!(backup.snapshots().len(), 1);
+        assert!(backup
+            .check_integrity(OsStr::new(&names[2]), None, false)
+            .passed(false));
+    }
+
+    #[test]
+    fn prune_fails_in_append_only_mode() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let mut backup = Backup::open(backup_dir.path()).unwrap();
+        backup.enable_append_only().unwrap();
+
+        assert!(backup.prune(0, Removal::Delete).is_err());
+    }
+
+    #[test]
+    fn locked_backup_is_not_changed() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let inputs = tempfile::tempdir().unwrap();
+        let mut backup = Backup::open(backup_dir.path()).unwrap();
+        let inputs = [inputs.path().to_owned()];
+        backup.add_snapshot(&inputs, &Default::default()).unwrap();
+        backup.add_snapshot(&inputs, &Default::default()).unwrap();
+        let names: Vec<String> = backup.snapshots().iter().map(|s| s.name()).collect();
+
+        // held by this process, as if a backup was running
+        let lock = Lock::acquire(backup_dir.path(), false).unwrap();
+        backup.set_break_lock(true);
+        let error = backup.prune(1, Removal::Delete).err().unwrap();
+        assert!(error.to_string().contains("locked"), "{}", error);
+        assert!(backup.delete_snapshot(&names[1], true).is_err());
+        assert!(backup.squash(&names[0], &names[1], true).is_err());
+        assert!(backup.repair(&names[0]).is_err());
+        assert_eq!(backup.snapshots().len(), 2);
+
+        drop(lock);
+        backup.delete_snapshot(&names[1], false).unwrap();
+        assert_eq!(backup.snapshots().len(), 1);
+    }
+
+    #[test]
+    fn repair_fails_in_append_only_mode() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let inputs = tempfile::tempdir().unwrap();
+        let mut backup = Backup::open(backup_dir.path()).unwrap();
+        backup
+            .add_snapshot(&[inputs.path().to_owned()], &Default::default())
+            .unwrap();
+        backup.enable_append_only().unwrap();
+
+        let error = backup.repair("latest").err().unwrap();
+        assert!(error.to_string().contains("append-only"), "{}", error);
+    }
 }
@@ -0,0 +1,261 @@
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use tracing::{debug, trace};
+
+const PROBE_FILE_NAME: &str = ".mizeria-probe";
+const PROBE_LINK_NAME: &str = ".mizeria-probe-link";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filesystem {
+    Fat,
+    ExFat,
+    NetworkShare,
+    Other,
+    Unknown,
+}
+
+impl Filesystem {
+    /// The largest file that can be stored on this filesystem.
+    pub fn max_file_size(&self) -> Option<u64> {
+        match self {
+            Self::Fat => Some(u32::MAX as u64),
+            _ => None,
+        }
+    }
+
+    /// FAT family stores modification time with a 2 second resolution.
+    pub fn has_coarse_timestamps(&self) -> bool {
+        matches!(self, Self::Fat | Self::ExFat)
+    }
+}
+
+impl Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fat => write!(f, "FAT"),
+            Self::ExFat => write!(f, "exFAT"),
+            Self::NetworkShare => write!(f, "network share"),
+            Self::Other => write!(f, "local filesystem"),
+            Self::Unknown => write!(f, "unknown filesystem"),
+        }
+    }
+}
+
+/// What to do when the backup folder cannot store everything.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LimitsPolicy {
+    /// Skip entries that cannot be stored and report them as warnings.
+    Adapt,
+    /// Don't start the backup at all.
+    Abort,
+}
+
+/// Capabilities of the folder where snapshots are stored.
+pub struct Destination {
+    pub filesystem: Filesystem,
+    pub supports_symlinks: bool,
+    pub free_space: Option<u64>,      // in bytes
+    pub max_name_length: Option<u64>, // in bytes
+}
+
+impl Destination {
+    /// Probes the destination by writing a small file into it.
+    /// Returns an error if the destination isn't writable.
+    pub fn probe(root: &Path) -> io::Result<Destination> {
+        debug!("Probing destination: {}", root.display());
+        Self::probe_writable(root)?;
+
+        let destination = Destination {
+            filesystem: Self::detect_filesystem(root),
+            supports_symlinks: Self::probe_symlinks(root),
+            free_space: Self::get_free_space(root),
+            max_name_length: Self::get_max_name_length(root),
+        };
+        trace!(
+            "Destination: {}, symlinks: {}, free space: {:?}, max name length: {:?}",
+            destination.filesystem,
+            destination.supports_symlinks,
+            destination.free_space,
+            destination.max_name_length
+        );
+        Ok(destination)
+    }
+
+    /// Human readable descriptions of what this destination cannot do.
+    pub fn limitations(&self) -> Vec<String> {
+        let mut limitations = vec![];
+        if !self.supports_symlinks {
+            limitations.push("symlinks are not supported".to_string());
+        }
+        if let Some(max_size) = self.filesystem.max_file_size() {
+            limitations.push(format!(
+                "files larger than {} bytes cannot be stored",
+                max_size
+            ));
+        }
+        if self.filesystem.has_coarse_timestamps() {
+            limitations.push("modification times have 2 second resolution".to_string());
+        }
+        limitations
+    }
+
+    fn probe_writable(root: &Path) -> io::Result<()> {
+        let probe = root.join(PROBE_FILE_NAME);
+        let result = File::create(&probe).and_then(|mut file| file.write_all(b"mizeria"));
+        fs::remove_file(&probe).ok();
+        result
+    }
+
+    #[cfg(unix)]
+    fn probe_symlinks(root: &Path) -> bool {
+        let link = root.join(PROBE_LINK_NAME);
+        let result = std::os::unix::fs::symlink(PROBE_FILE_NAME, &link);
+        fs::remove_file(&link).ok();
+        result.is_ok()
+    }
+
+    #[cfg(not(unix))]
+    fn probe_symlinks(_root: &Path) -> bool {
+        false
+    }
+
+    #[cfg(unix)]
+    fn statvfs(root: &Path) -> Option<libc::statvfs> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(root.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+        (result == 0).then_some(stat)
+    }
+
+    #[cfg(unix)]
+    #[allow(clippy::unnecessary_cast)] // field types differ between platforms
+    fn get_free_space(root: &Path) -> Option<u64> {
+        let stat = Self::statvfs(root)?;
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+
+    #[cfg(not(unix))]
+    fn get_free_space(_root: &Path) -> Option<u64> {
+        None
+    }
+
+    #[cfg(unix)]
+    #[allow(clippy::unnecessary_cast)] // field types differ between platforms
+    fn get_max_name_length(root: &Path) -> Option<u64> {
+        let stat = Self::statvfs(root)?;
+        Some(stat.f_namemax as u64)
+    }
+
+    #[cfg(not(unix))]
+    fn get_max_name_length(_root: &Path) -> Option<u64> {
+        Some(255)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn detect_filesystem(root: &Path) -> Filesystem {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        const MSDOS_SUPER_MAGIC: u32 = 0x4d44;
+        const EXFAT_SUPER_MAGIC: u32 = 0x2011_bab0;
+        const NFS_SUPER_MAGIC: u32 = 0x6969;
+        const SMB_SUPER_MAGIC: u32 = 0x517b;
+        const CIFS_SUPER_MAGIC: u32 = 0xff53_4d42;
+        const SMB2_SUPER_MAGIC: u32 = 0xfe53_4d42;
+
+        let path = match CString::new(root.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return Filesystem::Unknown,
+        };
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+            return Filesystem::Unknown;
+        }
+
+        match stat.f_type as u32 {
+            MSDOS_SUPER_MAGIC => Filesystem::Fat,
+            EXFAT_SUPER_MAGIC => Filesystem::ExFat,
+            NFS_SUPER_MAGIC | SMB_SUPER_MAGIC | CIFS_SUPER_MAGIC | SMB2_SUPER_MAGIC => {
+                Filesystem::NetworkShare
+            }
+            _ => Filesystem::Other,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn detect_filesystem(root: &Path) -> Filesystem {
+        use std::ffi::{CStr, CString};
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = match CString::new(root.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return Filesystem::Unknown,
+        };
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+            return Filesystem::Unknown;
+        }
+
+        let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+        match name.to_string_lossy().as_ref() {
+            "msdos" => Filesystem::Fat,
+            "exfat" => Filesystem::ExFat,
+            "smbfs" | "nfs" | "afpfs" | "webdav" => Filesystem::NetworkShare,
+            _ => Filesystem::Other,
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn detect_filesystem(_root: &Path) -> Filesystem {
+        Filesystem::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_writable_destination() {
+        let root = tempfile::tempdir().unwrap();
+        let destination = Destination::probe(root.path()).unwrap();
+
+        assert!(destination.max_name_length.is_some());
+        // probe files are cleaned up
+        assert_eq!(root.path().read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn probe_nonexistent_destination() {
+        let root = tempfile::tempdir().unwrap();
+        let result = Destination::probe(root.path().join("nonexistent").as_path());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fat_destination_has_limitations() {
+        let destination = Destination {
+            filesystem: Filesystem::Fat,
+            supports_symlinks: false,
+            free_space: None,
+            max_name_length: Some(255),
+        };
+
+        assert_eq!(destination.limitations().len(), 3);
+    }
+
+    #[test]
+    fn fat_has_file_size_limit() {
+        assert_eq!(Filesystem::Fat.max_file_size(), Some(4294967295));
+        assert_eq!(Filesystem::ExFat.max_file_size(), None);
+        assert!(Filesystem::ExFat.has_coarse_timestamps());
+        assert!(!Filesystem::Other.has_coarse_timestamps());
+    }
+}
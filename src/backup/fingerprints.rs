@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const FINGERPRINTS_FILE_NAME: &str = "fingerprints.txt";
+pub const APPEND_ONLY_FILE_NAME: &str = ".append-only";
+
+/// Fingerprints of snapshots recorded when they were created. Stored in
+/// the backup folder as `<snapshot> <fingerprint>` lines. New lines are
+/// only appended, existing ones are never rewritten.
+pub struct Fingerprints {
+    location: PathBuf,
+    entries: BTreeMap<String, String>,
+}
+
+impl Fingerprints {
+    pub fn open(backup: &Path) -> Result<Self, String> {
+        let location = backup.join(FINGERPRINTS_FILE_NAME);
+        let mut entries = BTreeMap::new();
+        if location.exists() {
+            let content = fs::read_to_string(&location)
+                .map_err(|e| format!("Cannot read {} ({})", FINGERPRINTS_FILE_NAME, e))?;
+            for (line_num, line) in content.lines().enumerate() {
+                let (snapshot, fingerprint) = line.split_once(' ').ok_or(format!(
+                    "Invalid line {} in {}",
+                    line_num + 1,
+                    FINGERPRINTS_FILE_NAME
+                ))?;
+                entries.insert(snapshot.to_string(), fingerprint.to_string());
+            }
+        }
+        Ok(Self { location, entries })
+    }
+
+    pub fn get(&self, snapshot: &str) -> Option<&str> {
+        self.entries.get(snapshot).map(String::as_str)
+    }
+
+    pub fn snapshots(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    pub fn append(&mut self, snapshot: &str, fingerprint: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.location)?;
+        writeln!(file, "{} {}", snapshot, fingerprint)?;
+        file.sync_all()?;
+        self.entries
+            .insert(snapshot.to_string(), fingerprint.to_string());
+        Ok(())
+    }
+}
+
+/// In append-only mode existing snapshots are never modified or removed
+/// by mizeria, and every new snapshot is fingerprinted.
+pub fn is_append_only(backup: &Path) -> bool {
+    backup.join(APPEND_ONLY_FILE_NAME).exists()
+}
+
+pub fn enable_append_only(backup: &Path) -> io::Result<()> {
+    fs::write(
+        backup.join(APPEND_ONLY_FILE_NAME),
+        "Snapshots in this backup are never modified by mizeria.\n",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_open_fingerprints() {
+        let backup = tempfile::tempdir().unwrap();
+        let mut fingerprints = Fingerprints::open(backup.path()).unwrap();
+        assert!(fingerprints.get("2021-07-15_18.34").is_none());
+
+        fingerprints.append("2021-07-15_18.34", "abc").unwrap();
+        fingerprints.append("2021-07-16_18.34", "def").unwrap();
+
+        let fingerprints = Fingerprints::open(backup.path()).unwrap();
+        assert_eq!(fingerprints.get("2021-07-15_18.34"), Some("abc"));
+        assert_eq!(fingerprints.get("2021-07-16_18.34"), Some("def"));
+        assert_eq!(fingerprints.snapshots().count(), 2);
+    }
+
+    #[test]
+    fn open_invalid_fingerprints() {
+        let backup = tempfile::tempdir().unwrap();
+        fs::write(backup.path().join(FINGERPRINTS_FILE_NAME), "invalid\n").unwrap();
+
+        assert!(Fingerprints::open(backup.path()).is_err());
+    }
+
+    #[test]
+    fn enable_append_only_mode() {
+        let backup = tempfile::tempdir().unwrap();
+        assert!(!is_append_only(backup.path()));
+
+        enable_append_only(backup.path()).unwrap();
+
+        assert!(is_append_only(backup.path()));
+    }
+}
@@ -0,0 +1,212 @@
+//! Import of backups made by other tools as a series of dated folders,
+//! e.g. by `rsync --link-dest` or robocopy. Every folder is a full copy
+//! of the backed up folder made at the time in its name.
+
+use std::convert::TryFrom;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::environment::Clock;
+
+/// Folder of another backup with the time it was made.
+pub struct DatedFolder {
+    pub path: PathBuf,
+    pub time: PrimitiveDateTime,
+}
+
+/// Dated folders in `root` sorted from the oldest. Entries without a date
+/// in their name are returned separately.
+pub fn find_dated_folders(root: &Path) -> io::Result<(Vec<DatedFolder>, Vec<PathBuf>)> {
+    let mut folders = vec![];
+    let mut skipped = vec![];
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        let time = path
+            .file_name()
+            .and_then(|name| parse_folder_time(&name.to_string_lossy()));
+        match time {
+            Some(time) if path.is_dir() => folders.push(DatedFolder { path, time }),
+            _ => skipped.push(path),
+        }
+    }
+    folders.sort_by(|a, b| a.time.cmp(&b.time).then(a.path.cmp(&b.path)));
+    skipped.sort();
+    Ok((folders, skipped))
+}
+
+/// Finds a date like "2021-07-15" or "20210715" in a folder name, followed
+/// by an optional time like "18.34", "18:34" or "1834". Folders without
+/// time were made at midnight.
+pub fn parse_folder_time(name: &str) -> Option<PrimitiveDateTime> {
+    let regex = Regex::new(r"(\d{4})-?(\d{2})-?(\d{2})(?:[_T \-]?(\d{2})[.:\-]?(\d{2}))?").unwrap();
+    let captures = regex.captures(name)?;
+    let number = |i: usize| captures.get(i).and_then(|m| m.as_str().parse::<u8>().ok());
+    let year = captures[1].parse().ok()?;
+    let month = Month::try_from(number(2)?).ok()?;
+    let date = Date::from_calendar_date(year, month, number(3)?).ok()?;
+    let time = match (number(4), number(5)) {
+        (Some(hour), Some(minute)) => Time::from_hms(hour, minute, 0).ok()?,
+        _ => Time::MIDNIGHT,
+    };
+    Some(PrimitiveDateTime::new(date, time))
+}
+
+/// Time an archive was made at: the date in its name or, without one,
+/// its modification time in the local time zone.
+pub fn archive_time(archive: &Path) -> io::Result<PrimitiveDateTime> {
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(time) = parse_folder_time(&name) {
+        return Ok(time);
+    }
+    let modified = OffsetDateTime::from(fs::metadata(archive)?.modified()?);
+    let modified = match UtcOffset::current_local_offset() {
+        Ok(offset) => modified.to_offset(offset),
+        Err(_) => modified,
+    };
+    Ok(PrimitiveDateTime::new(modified.date(), modified.time()))
+}
+
+/// Name of the snapshot imported from a folder made at `time`.
+pub fn snapshot_name(time: PrimitiveDateTime) -> String {
+    format!(
+        "{}-{:02}-{:02}_{:02}.{:02}",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute()
+    )
+}
+
+/// Clock stopped at the time a folder was made, so the snapshot imported
+/// from it is named after that time.
+pub struct FolderClock(pub PrimitiveDateTime);
+
+impl Clock for FolderClock {
+    fn now(&self) -> OffsetDateTime {
+        self.0.assume_utc()
+    }
+}
+
+/// An entry didn't change since the previous folder if it has the same
+/// type and modification time, and the same size or target of a symlink.
+pub fn is_unchanged(entry: &Path, metadata: &Metadata, previous: &Path) -> bool {
+    let previous_metadata = match previous.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    if metadata.file_type() != previous_metadata.file_type() {
+        return false;
+    }
+    match (metadata.modified(), previous_metadata.modified()) {
+        (Ok(modified), Ok(previous_modified)) if modified == previous_modified => (),
+        _ => return false,
+    }
+    if metadata.is_file() {
+        metadata.len() == previous_metadata.len()
+    } else if metadata.file_type().is_symlink() {
+        matches!((fs::read_link(entry), fs::read_link(previous)), (Ok(a), Ok(b)) if a == b)
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn time(name: &str) -> Option<String> {
+        parse_folder_time(name).map(|t| t.to_string())
+    }
+
+    #[test]
+    fn parse_time_from_folder_names() {
+        assert_eq!(time("2021-07-15").unwrap(), "2021-07-15 0:00:00.0");
+        assert_eq!(
+            time("daily.2021-07-15_18.34").unwrap(),
+            "2021-07-15 18:34:00.0"
+        );
+        assert_eq!(
+            time("backup-20210715-1834").unwrap(),
+            "2021-07-15 18:34:00.0"
+        );
+        assert_eq!(
+            time("2021-07-15T18:34:56").unwrap(),
+            "2021-07-15 18:34:00.0"
+        );
+        assert_eq!(time("2021-13-15"), None);
+        assert_eq!(time("latest"), None);
+    }
+
+    #[test]
+    fn find_dated_folders_from_the_oldest() {
+        let root = tempfile::tempdir().unwrap();
+        for name in ["2021-07-16", "2021-07-15_18.34", "latest"] {
+            fs::create_dir(root.path().join(name)).unwrap();
+        }
+        fs::write(root.path().join("2021-07-17.log"), b"").unwrap();
+
+        let (folders, skipped) = find_dated_folders(root.path()).unwrap();
+
+        let names: Vec<_> = folders
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["2021-07-15_18.34", "2021-07-16"]);
+        assert_eq!(
+            skipped,
+            [
+                root.path().join("2021-07-17.log"),
+                root.path().join("latest")
+            ]
+        );
+    }
+
+    #[test]
+    fn archive_time_from_name_or_modification_time() {
+        let root = tempfile::tempdir().unwrap();
+        let dated = root.path().join("home-2021-07-15_18.34.tar.gz");
+        let undated = root.path().join("home.tar");
+        fs::write(&undated, b"").unwrap();
+
+        assert_eq!(
+            archive_time(&dated).unwrap().to_string(),
+            "2021-07-15 18:34:00.0"
+        );
+        assert!(archive_time(&undated).unwrap().year() >= 2021);
+        assert!(archive_time(&root.path().join("missing.tar")).is_err());
+    }
+
+    #[test]
+    fn unchanged_file_has_same_size_and_modification_time() {
+        let root = tempfile::tempdir().unwrap();
+        let previous = root.path().join("previous.txt");
+        let current = root.path().join("current.txt");
+        fs::write(&previous, b"data").unwrap();
+        fs::copy(&previous, &current).unwrap();
+        let modified = fs::metadata(&previous).unwrap().modified().unwrap();
+        File::options()
+            .write(true)
+            .open(&current)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let metadata = fs::symlink_metadata(&current).unwrap();
+        assert!(is_unchanged(&current, &metadata, &previous));
+
+        fs::write(&current, b"changed").unwrap();
+        let metadata = fs::symlink_metadata(&current).unwrap();
+        assert!(!is_unchanged(&current, &metadata, &previous));
+        assert!(!is_unchanged(
+            &current,
+            &metadata,
+            &root.path().join("missing.txt")
+        ));
+    }
+}
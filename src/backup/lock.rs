@@ -0,0 +1,305 @@
+//! Lock file that keeps two processes from creating snapshots in the same
+//! backup folder at once. It records the process holding it, so a lock left
+//! behind by a process that died can be recognized and taken over.
+
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+
+use super::snapshot::get_host_name;
+
+/// Stored in the backup folder as `key: value` lines, like metadata.txt.
+pub const LOCK_FILE_NAME: &str = "mizeria.lock";
+/// The holder is written right after the lock file is created, so a lock
+/// file that can't be read yet may belong to a process still writing it.
+const LOCK_WRITE_TIME: Duration = Duration::from_secs(5);
+
+/// Process holding the lock, as recorded in the lock file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    pub started: Option<OffsetDateTime>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LockState {
+    /// The process is running on this host.
+    Held,
+    /// The process isn't running on this host anymore.
+    Stale,
+    /// The process runs on another host, so it can't be checked.
+    Unknown,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            host: get_host_name(),
+            started: Some(OffsetDateTime::now_utc()),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let (mut pid, mut host, mut started) = (None, None, None);
+        for (key, value) in text.lines().filter_map(|line| line.split_once(": ")) {
+            match key {
+                "pid" => pid = value.parse().ok(),
+                "host" => host = Some(value.to_string()),
+                "started" => started = OffsetDateTime::parse(value, &Rfc3339).ok(),
+                _ => (),
+            }
+        }
+        Some(Self {
+            pid: pid?,
+            host: host?,
+            started,
+        })
+    }
+
+    pub fn state(&self) -> LockState {
+        if self.host != get_host_name() {
+            return LockState::Unknown;
+        }
+        match is_running(self.pid) {
+            Some(true) => LockState::Held,
+            Some(false) => LockState::Stale,
+            None => LockState::Unknown,
+        }
+    }
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "process {} on {}", self.pid, self.host)?;
+        match self
+            .started
+            .and_then(|started| started.format(&Rfc3339).ok())
+        {
+            Some(started) => write!(f, " since {}", started),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lock of a backup folder, released when dropped.
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    /// Takes the lock of the backup folder. A lock held by a process that
+    /// isn't running anymore, or can't be checked, is taken over only with
+    /// `break_lock`. A lock of a process running on this host never is.
+    pub fn acquire(backup: &Path, break_lock: bool) -> Result<Self, String> {
+        let path = backup.join(LOCK_FILE_NAME);
+        // Another process may take the lock between breaking and retrying.
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    let lock = Self { path };
+                    write_holder(file, &LockHolder::current())
+                        .map_err(|e| format!("Cannot write lock file ({})", e))?;
+                    debug!("Locked backup folder: {}", backup.display());
+                    return Ok(lock);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(format!("Cannot create lock file ({})", e)),
+            }
+
+            let holder = Self::holder(backup);
+            if holder.is_none() && is_being_written(&path) {
+                return Err("Backup folder is being locked by another process".into());
+            }
+            match (&holder, holder.as_ref().map(LockHolder::state)) {
+                (Some(holder), Some(LockState::Held)) => {
+                    return Err(format!("Backup folder is locked by {}", holder))
+                }
+                (Some(holder), Some(LockState::Stale)) if !break_lock => {
+                    return Err(format!(
+                        "Backup folder is locked by {}, which isn't running anymore. Take the lock over with --break-lock",
+                        holder
+                    ))
+                }
+                (Some(holder), _) if !break_lock => {
+                    return Err(format!(
+                        "Backup folder is locked by {}. If it isn't running, take the lock over with --break-lock",
+                        holder
+                    ))
+                }
+                (None, _) if !break_lock => {
+                    return Err("Backup folder is locked, but the lock file can't be read. Take the lock over with --break-lock".into())
+                }
+                _ => (),
+            }
+            match holder {
+                Some(holder) => warn!("Breaking lock of {}", holder),
+                None => warn!("Breaking unreadable lock"),
+            }
+            Self::remove(backup).map_err(|e| format!("Cannot remove lock file ({})", e))?;
+        }
+        Err("Backup folder is locked by another process".into())
+    }
+
+    /// Process holding the lock, if the folder is locked and the lock file
+    /// can be read.
+    pub fn holder(backup: &Path) -> Option<LockHolder> {
+        LockHolder::parse(&fs::read_to_string(backup.join(LOCK_FILE_NAME)).ok()?)
+    }
+
+    pub fn is_locked(backup: &Path) -> bool {
+        backup.join(LOCK_FILE_NAME).exists()
+    }
+
+    /// Removes the lock of the backup folder, whoever holds it.
+    pub fn remove(backup: &Path) -> io::Result<()> {
+        match fs::remove_file(backup.join(LOCK_FILE_NAME)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Cannot remove lock file {} ({})", self.path.display(), e);
+        }
+    }
+}
+
+fn is_being_written(path: &Path) -> bool {
+    match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        // A modification time in the future counts as recent.
+        Ok(modified) => modified.elapsed().map_or(true, |age| age < LOCK_WRITE_TIME),
+        Err(_) => false,
+    }
+}
+
+fn write_holder(mut file: fs::File, holder: &LockHolder) -> io::Result<()> {
+    writeln!(file, "pid: {}", holder.pid)?;
+    writeln!(file, "host: {}", holder.host)?;
+    if let Some(started) = holder.started.and_then(|s| s.format(&Rfc3339).ok()) {
+        writeln!(file, "started: {}", started)?;
+    }
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> Option<bool> {
+    use std::convert::TryFrom;
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 only checks whether the process exists.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    match io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        // It exists, but belongs to another user.
+        Some(libc::EPERM) => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_lock(backup: &Path, holder: &LockHolder) {
+        let file = fs::File::create(backup.join(LOCK_FILE_NAME)).unwrap();
+        write_holder(file, holder).unwrap();
+    }
+
+    #[test]
+    fn lock_is_released_when_dropped() {
+        let backup = tempfile::tempdir().unwrap();
+
+        let lock = Lock::acquire(backup.path(), false).unwrap();
+        let holder = Lock::holder(backup.path()).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert_eq!(holder.state(), LockState::Held);
+        assert!(Lock::acquire(backup.path(), true).is_err());
+
+        drop(lock);
+        assert!(!Lock::is_locked(backup.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_lock_is_taken_over_only_when_broken() {
+        let backup = tempfile::tempdir().unwrap();
+        let mut finished = std::process::Command::new("true").spawn().unwrap();
+        finished.wait().unwrap();
+        let holder = LockHolder {
+            pid: finished.id(),
+            ..LockHolder::current()
+        };
+        write_lock(backup.path(), &holder);
+
+        assert_eq!(holder.state(), LockState::Stale);
+        let error = Lock::acquire(backup.path(), false).err().unwrap();
+        assert!(error.contains("isn't running anymore"), "{}", error);
+
+        let _lock = Lock::acquire(backup.path(), true).unwrap();
+        assert_eq!(Lock::holder(backup.path()).unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn lock_of_another_host_cannot_be_checked() {
+        let backup = tempfile::tempdir().unwrap();
+        let holder = LockHolder {
+            host: format!("{}-other", get_host_name()),
+            ..LockHolder::current()
+        };
+        write_lock(backup.path(), &holder);
+
+        assert_eq!(holder.state(), LockState::Unknown);
+        assert!(Lock::acquire(backup.path(), false).is_err());
+        assert!(Lock::acquire(backup.path(), true).is_ok());
+    }
+
+    #[test]
+    fn lock_file_being_written_is_not_broken() {
+        let backup = tempfile::tempdir().unwrap();
+        let path = backup.path().join(LOCK_FILE_NAME);
+        let file = fs::File::create(&path).unwrap();
+
+        let error = Lock::acquire(backup.path(), true).err().unwrap();
+        assert!(error.contains("being locked"), "{}", error);
+
+        let written = std::time::SystemTime::now() - 2 * LOCK_WRITE_TIME;
+        file.set_modified(written).unwrap();
+        drop(file);
+        let error = Lock::acquire(backup.path(), false).err().unwrap();
+        assert!(error.contains("can't be read"), "{}", error);
+        let _lock = Lock::acquire(backup.path(), true).unwrap();
+        assert_eq!(Lock::holder(backup.path()).unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn parse_lock_file() {
+        let holder = LockHolder::parse(
+            "pid: 1234\nhost: my-computer\nstarted: 2021-07-15T18:34:00Z\nnew: key\n",
+        )
+        .unwrap();
+
+        assert_eq!(holder.pid, 1234);
+        assert_eq!(
+            holder.to_string(),
+            "process 1234 on my-computer since 2021-07-15T18:34:00Z"
+        );
+        assert_eq!(LockHolder::parse("host: my-computer\n"), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::path::Path;
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// Permissions of everything that is written into a snapshot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PermissionsPolicy {
+    /// Keep defaults of the destination and permissions of copied files.
+    Inherit,
+    /// Remove permission bits that are set in the mask, like umask does.
+    Umask(u32),
+}
+
+impl Default for PermissionsPolicy {
+    /// Snapshots are accessible only by their owner.
+    fn default() -> Self {
+        Self::Umask(0o077)
+    }
+}
+
+impl PermissionsPolicy {
+    /// Parses an octal mask like "077" or "027". Masks that remove
+    /// permissions of the owner are rejected, because mizeria couldn't
+    /// read its own snapshots.
+    pub fn parse_umask(mask: &str) -> Option<Self> {
+        let mask = u32::from_str_radix(mask, 8).ok()?;
+        if mask > 0o777 || mask & 0o700 != 0 {
+            return None;
+        }
+        Some(Self::Umask(mask))
+    }
+}
+
+/// Applies the policy to a single file or folder.
+pub fn restrict(path: &Path, policy: PermissionsPolicy) -> io::Result<()> {
+    match policy {
+        PermissionsPolicy::Inherit => Ok(()),
+        PermissionsPolicy::Umask(mask) => apply_mask(path, mask),
+    }
+}
+
+/// Applies the policy to a folder and everything inside of it.
+/// Symlinks are not followed.
+pub fn restrict_all(path: &Path, policy: PermissionsPolicy) -> io::Result<()> {
+    let mask = match policy {
+        PermissionsPolicy::Inherit => return Ok(()),
+        PermissionsPolicy::Umask(mask) => mask,
+    };
+    debug!("Restricting permissions with mask {:03o}", mask);
+    for entry in WalkDir::new(path).follow_links(false) {
+        let entry = entry?;
+        if !entry.path_is_symlink() {
+            apply_mask(entry.path(), mask)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn apply_mask(path: &Path, mask: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::symlink_metadata(path)?.permissions();
+    let mode = permissions.mode();
+    if mode & mask != 0 {
+        permissions.set_mode(mode & !mask);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Other platforms use ACLs inherited from the backup folder.
+#[cfg(not(unix))]
+fn apply_mask(_path: &Path, _mask: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid_and_invalid_umask() {
+        assert_eq!(
+            PermissionsPolicy::parse_umask("077"),
+            Some(PermissionsPolicy::Umask(0o077))
+        );
+        assert_eq!(
+            PermissionsPolicy::parse_umask("0027"),
+            Some(PermissionsPolicy::Umask(0o027))
+        );
+        assert_eq!(PermissionsPolicy::parse_umask("277"), None);
+        assert_eq!(PermissionsPolicy::parse_umask("1077"), None);
+        assert_eq!(PermissionsPolicy::parse_umask("088"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restrict_all_entries_in_folder() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("folder");
+        let file = folder.join("file.txt");
+        fs::create_dir(&folder).unwrap();
+        fs::write(&file, b"private").unwrap();
+        fs::set_permissions(&folder, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+        restrict_all(root.path(), PermissionsPolicy::default()).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&folder), 0o700);
+        assert_eq!(mode(&file), 0o600);
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use tracing::{debug, trace};
+use walkdir::WalkDir;
+
+use super::snapshot::ChainLink;
+
+/// Log of snapshots removed by prune. Stored in the backup folder as
+/// `<snapshot> <chain hash>` lines, so the hash chain can end at a snapshot
+/// that was pruned on purpose.
+pub const PRUNED_FILE_NAME: &str = "pruned.txt";
+
+pub fn record_pruned(backup: &Path, link: &ChainLink) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(backup.join(PRUNED_FILE_NAME))?;
+    writeln!(file, "{} {}", link.snapshot, link.hash)?;
+    file.sync_all()
+}
+
+pub fn is_pruned(backup: &Path, link: &ChainLink) -> bool {
+    let file = match File::open(backup.join(PRUNED_FILE_NAME)) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .any(|line| line.split_once(' ') == Some((&link.snapshot, &link.hash)))
+}
+
+/// Removes a snapshot folder. With `shred` the content of every file is
+/// overwritten with zeros and flushed to the disk before it is unlinked.
+pub fn remove_snapshot(location: &Path, shred: bool) -> io::Result<()> {
+    if shred {
+        for entry in WalkDir::new(location).follow_links(false) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                shred_file(entry.path())?;
+            }
+        }
+    }
+    fs::remove_dir_all(location)?;
+    debug!("Removed snapshot: {}", location.display());
+    Ok(())
+}
+
+fn shred_file(path: &Path) -> io::Result<()> {
+    trace!("Shredding: {}", path.display());
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let zeros = [0; 64 * 1024];
+    while remaining > 0 {
+        let length = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..length])?;
+        remaining -= length as u64;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_find_pruned_snapshot() {
+        let backup = tempfile::tempdir().unwrap();
+        let link = ChainLink {
+            snapshot: "2021-07-15_18.34".into(),
+            hash: "abc".into(),
+        };
+        assert!(!is_pruned(backup.path(), &link));
+
+        record_pruned(backup.path(), &link).unwrap();
+
+        assert!(is_pruned(backup.path(), &link));
+        let other_hash = ChainLink {
+            hash: "def".into(),
+            ..link
+        };
+        assert!(!is_pruned(backup.path(), &other_hash));
+    }
+
+    #[test]
+    fn shred_file_overwrites_content() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("secret.txt");
+        fs::write(&file, vec![b'x'; 100_000]).unwrap();
+
+        shred_file(&file).unwrap();
+
+        let content = fs::read(&file).unwrap();
+        assert_eq!(content.len(), 100_000);
+        assert!(content.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn remove_snapshot_with_shred() {
+        let root = tempfile::tempdir().unwrap();
+        let snapshot = root.path().join("2021-07-15_18.34");
+        fs::create_dir_all(snapshot.join("files").join("folder")).unwrap();
+        fs::write(snapshot.join("files").join("folder").join("a.txt"), b"a").unwrap();
+
+        remove_snapshot(&snapshot, true).unwrap();
+
+        assert!(!snapshot.exists());
+    }
+}
@@ -0,0 +1,157 @@
+//! Defaults of a backup declared in mizeria.toml in the backup folder, so
+//! `mizeria backup <BACKUP>` needs no further arguments. Options given on
+//! the command line take precedence.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::FileCompression;
+use crate::find::Pattern;
+
+pub const SETTINGS_FILE_NAME: &str = "mizeria.toml";
+
+/// The file as written by the user, before it's validated.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SettingsFile {
+    inputs: Vec<PathBuf>,
+    exclude: Vec<String>,
+    compress: Option<String>,
+    incremental: Option<bool>,
+    keep_last: Option<usize>,
+    pre_hook: Option<String>,
+    post_hook: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Backed up when no inputs are given.
+    pub inputs: Vec<PathBuf>,
+    /// Entries that are never backed up, in addition to those excluded on
+    /// the command line.
+    pub exclude: Vec<Pattern>,
+    pub compression: Option<FileCompression>,
+    /// Snapshots are full when it's false.
+    pub incremental: bool,
+    /// Number of the newest snapshots kept by prune.
+    pub keep_last: Option<usize>,
+    /// Commands run before and after a backup, unless given on the
+    /// command line.
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            inputs: vec![],
+            exclude: vec![],
+            compression: None,
+            incremental: true,
+            keep_last: None,
+            pre_hook: None,
+            post_hook: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads and validates the settings of the backup. A backup without
+    /// mizeria.toml has default settings.
+    pub fn load(backup: &Path) -> Result<Self, String> {
+        let text = match fs::read_to_string(backup.join(SETTINGS_FILE_NAME)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Cannot read {} ({})", SETTINGS_FILE_NAME, e)),
+        };
+        Self::parse(&text).map_err(|e| format!("Invalid {}: {}", SETTINGS_FILE_NAME, e))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let file: SettingsFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        if let Some(input) = file.inputs.iter().find(|input| !input.is_absolute()) {
+            return Err(format!(
+                "input '{}' isn't an absolute path",
+                input.display()
+            ));
+        }
+        let compression = match &file.compress {
+            Some(value) => Some(FileCompression::parse(value).ok_or_else(|| {
+                format!(
+                    "compress = '{}', expected zstd or zstd:LEVEL with LEVEL from 1 to 22",
+                    value
+                )
+            })?),
+            None => None,
+        };
+        let exclude = file
+            .exclude
+            .iter()
+            .map(|pattern| Pattern::glob(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            inputs: file.inputs,
+            exclude,
+            compression,
+            incremental: file.incremental.unwrap_or(true),
+            keep_last: file.keep_last,
+            pre_hook: file.pre_hook,
+            post_hook: file.post_hook,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings_file() {
+        let settings = Settings::parse(
+            "inputs = [\"/home/user/documents\"]\n\
+             exclude = [\"*.tmp\", \"/home/user/documents/cache\"]\n\
+             compress = \"zstd:9\"\n\
+             incremental = false\n\
+             keep-last = 30\n\
+             pre-hook = \"pg_dump app > /var/backups/app.sql\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(settings.inputs, vec![PathBuf::from("/home/user/documents")]);
+        assert!(settings.exclude[0].matches(Path::new("/home/user/a.tmp")));
+        assert!(settings.exclude[1].matches(Path::new("/home/user/documents/cache")));
+        assert!(!settings.exclude[1].matches(Path::new("/home/user/documents/cache2")));
+        assert_eq!(settings.compression, FileCompression::parse("zstd:9"));
+        assert!(!settings.incremental);
+        assert_eq!(settings.keep_last, Some(30));
+        assert!(settings.pre_hook.unwrap().starts_with("pg_dump"));
+        assert!(settings.post_hook.is_none());
+
+        let defaults = Settings::parse("").unwrap();
+        assert!(defaults.inputs.is_empty());
+        assert!(defaults.incremental);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let error = |text| Settings::parse(text).err().unwrap();
+
+        assert!(error("keep-last = -1").contains("keep-last"));
+        assert!(error("exclude = [\"*.tmp\"]\nretention = 3").contains("retention"));
+        assert!(error("compress = \"gzip\"").contains("compress"));
+        assert!(error("inputs = [\"documents\"]").contains("absolute"));
+    }
+
+    #[test]
+    fn backup_without_settings_file_has_defaults() {
+        let backup = tempfile::tempdir().unwrap();
+        assert!(Settings::load(backup.path()).unwrap().inputs.is_empty());
+
+        fs::write(backup.path().join(SETTINGS_FILE_NAME), "inputs = ").unwrap();
+        let error = Settings::load(backup.path()).err().unwrap();
+        assert!(error.starts_with("Invalid mizeria.toml"), "{}", error);
+    }
+}
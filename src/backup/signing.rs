@@ -0,0 +1,241 @@
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tracing::debug;
+
+/// External program used to sign snapshots.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SigningTool {
+    Minisign,
+    Gpg,
+}
+
+impl SigningTool {
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Minisign => "minisign",
+            Self::Gpg => "gpg",
+        }
+    }
+
+    fn signature_extension(&self) -> &'static str {
+        match self {
+            Self::Minisign => "minisig",
+            Self::Gpg => "sig",
+        }
+    }
+
+    pub fn all() -> [SigningTool; 2] {
+        [Self::Minisign, Self::Gpg]
+    }
+}
+
+impl Display for SigningTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+/// Key used to sign snapshots. For minisign it is a path to a secret key
+/// file, for GPG it is an identifier of a key in the keyring.
+#[derive(Clone, Debug)]
+pub struct SigningKey {
+    pub tool: SigningTool,
+    pub key: String,
+}
+
+impl SigningKey {
+    /// Creates a detached signature next to `file` and returns its path.
+    pub fn sign(&self, file: &Path) -> Result<PathBuf, String> {
+        let signature = self.signature_path(file);
+        debug!("Signing {} with {}", file.display(), self.tool);
+
+        let status = Command::new(self.tool.program())
+            .args(self.sign_args(file, &signature))
+            .status()
+            .map_err(|e| format!("Cannot run {} ({})", self.tool, e))?;
+        if !status.success() {
+            return Err(format!(
+                "{} failed to sign '{}' ({})",
+                self.tool,
+                file.display(),
+                status
+            ));
+        }
+        Ok(signature)
+    }
+
+    pub fn signature_path(&self, file: &Path) -> PathBuf {
+        signature_path(self.tool, file)
+    }
+
+    fn sign_args(&self, file: &Path, signature: &Path) -> Vec<OsString> {
+        let args: Vec<&std::ffi::OsStr> = match self.tool {
+            SigningTool::Minisign => vec![
+                "-S".as_ref(),
+                "-s".as_ref(),
+                self.key.as_ref(),
+                "-m".as_ref(),
+                file.as_os_str(),
+                "-x".as_ref(),
+                signature.as_os_str(),
+            ],
+            SigningTool::Gpg => vec![
+                "--batch".as_ref(),
+                "--yes".as_ref(),
+                "--local-user".as_ref(),
+                self.key.as_ref(),
+                "--detach-sign".as_ref(),
+                "--output".as_ref(),
+                signature.as_os_str(),
+                file.as_os_str(),
+            ],
+        };
+        args.into_iter().map(OsString::from).collect()
+    }
+}
+
+/// What to do when a signature doesn't match.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SignaturePolicy {
+    Fail,
+    Warn,
+}
+
+/// Public key used to verify signed snapshots. For minisign it is a path
+/// to a public key file, for GPG it is a path to a keyring with public keys.
+#[derive(Clone, Debug)]
+pub struct VerifyingKey {
+    pub tool: SigningTool,
+    pub key: String,
+    pub policy: SignaturePolicy,
+}
+
+impl VerifyingKey {
+    /// Returns `Ok(false)` if the signature doesn't match or is missing.
+    pub fn verify(&self, file: &Path) -> Result<bool, String> {
+        let signature = signature_path(self.tool, file);
+        if !signature.exists() {
+            return Ok(false);
+        }
+        debug!("Verifying {} with {}", file.display(), self.tool);
+
+        let (program, args) = self.verify_args(file, &signature)?;
+        let status = Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| format!("Cannot run {} ({})", program, e))?;
+        Ok(status.success())
+    }
+
+    fn verify_args(
+        &self,
+        file: &Path,
+        signature: &Path,
+    ) -> Result<(&'static str, Vec<OsString>), String> {
+        let args: Vec<OsString> = match self.tool {
+            SigningTool::Minisign => vec![
+                "-V".into(),
+                "-q".into(),
+                "-p".into(),
+                self.key.clone().into(),
+                "-m".into(),
+                file.into(),
+                "-x".into(),
+                signature.into(),
+            ],
+            SigningTool::Gpg => {
+                // gpgv looks for relative keyrings in its home directory
+                let keyring = Path::new(&self.key)
+                    .canonicalize()
+                    .map_err(|e| format!("Cannot open keyring '{}' ({})", self.key, e))?;
+                vec![
+                    "--keyring".into(),
+                    keyring.into(),
+                    signature.into(),
+                    file.into(),
+                ]
+            }
+        };
+        let program = match self.tool {
+            SigningTool::Minisign => "minisign",
+            SigningTool::Gpg => "gpgv",
+        };
+        Ok((program, args))
+    }
+}
+
+pub fn signature_path(tool: SigningTool, file: &Path) -> PathBuf {
+    let mut signature = file.as_os_str().to_owned();
+    signature.push(".");
+    signature.push(tool.signature_extension());
+    PathBuf::from(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_stored_next_to_file() {
+        let minisign = SigningKey {
+            tool: SigningTool::Minisign,
+            key: "key".into(),
+        };
+        let gpg = SigningKey {
+            tool: SigningTool::Gpg,
+            key: "key".into(),
+        };
+        let file = Path::new("backup/2021-07-15_18.34/index.txt");
+
+        assert_eq!(
+            minisign.signature_path(file),
+            Path::new("backup/2021-07-15_18.34/index.txt.minisig")
+        );
+        assert_eq!(
+            gpg.signature_path(file),
+            Path::new("backup/2021-07-15_18.34/index.txt.sig")
+        );
+    }
+
+    #[test]
+    fn sign_args_contain_key_and_files() {
+        let key = SigningKey {
+            tool: SigningTool::Gpg,
+            key: "ABCD1234".into(),
+        };
+        let args = key.sign_args(Path::new("index.txt"), Path::new("index.txt.sig"));
+
+        assert_eq!(
+            args,
+            vec![
+                "--batch",
+                "--yes",
+                "--local-user",
+                "ABCD1234",
+                "--detach-sign",
+                "--output",
+                "index.txt.sig",
+                "index.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_signature_is_invalid() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("index.txt");
+        std::fs::write(&file, b"").unwrap();
+        let key = VerifyingKey {
+            tool: SigningTool::Minisign,
+            key: "minisign.pub".into(),
+            policy: SignaturePolicy::Fail,
+        };
+
+        assert_eq!(key.verify(&file), Ok(false));
+    }
+}
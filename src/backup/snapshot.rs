@@ -1,56 +1,214 @@
+mod archived;
+mod attributes;
+mod chain;
+mod checksums;
+mod chunks;
+mod compression;
+mod diff;
+mod du;
+mod encryption;
+mod estimate;
 mod files;
+mod files_cache;
+mod hardlinks;
 mod index;
+mod index_cache;
+mod metadata;
+mod parity;
+mod readback;
+mod repair;
+mod restore;
+mod special;
+mod spill;
+mod squash;
 mod timestamp;
 
-use files::Files;
-use index::{Index, IndexPreview};
-use log::{debug, error, info, trace, warn};
+pub use archived::{SnapshotFormat, Unpacked, ARCHIVE_FILE_NAME};
+use attributes::Attributes;
+pub use checksums::sha256_file;
+use chunks::{ChunkLists, ChunkReader, ChunkRef, ChunkStore};
+pub use compression::FileCompression;
+pub use diff::Change;
+pub use encryption::Encryption;
+use encryption::{Decryption, EncryptionTool, GpgHome};
+pub use estimate::Estimate;
+use files::{Files, SourceEntry};
+pub use files_cache::FILES_CACHE_FILE_NAME;
+use files_cache::{FilesCache, Lookup, Stat};
+use hardlinks::Hardlinks;
+use index::{Content, Index, IndexEntry, IndexPreview};
+use index_cache::{hash_path, CachedIndex, PathHash};
+pub(super) use metadata::get_host_name;
+use metadata::get_user_name;
+pub use metadata::{ChainLink, Metadata};
+pub use parity::ParitySummary;
+pub use restore::RestoreOptions;
+use special::Special;
+pub use special::SpecialFilesPolicy;
+use spill::{IndexWriter, SortedIndex};
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug, Display};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use std::{fs, io};
+pub use timestamp::Period;
 use timestamp::Timestamp;
+use tracing::{debug, error, info, info_span, trace, warn, Span};
 use walkdir::WalkDir;
 
-use crate::result::IntegrityCheckResult;
+use crate::environment::{Clock, CopyOptions, Environment};
+use crate::find::Pattern;
+use crate::progress::{Event, Progress, SkipReason};
+use crate::result::{BackupStats, IntegrityCheckResult, IntegrityReport, Severity};
 
+use super::destination::Destination;
+use super::import::is_unchanged;
+use super::prune::is_pruned;
+use super::signing::{signature_path, SignaturePolicy, SigningTool, VerifyingKey};
 use super::snapshot_utils::get_latest_snapshot_preview;
 use super::IntegrityCheckError;
 
+/// Suffix of the folder of a snapshot that is still being created.
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Lists entries of a snapshot that couldn't be backed up, one per line.
+pub const ERRORS_FILE_NAME: &str = "errors.txt";
+
+/// How many times a file that changes while it is copied is copied, before
+/// it is flagged as unstable.
+const COPY_ATTEMPTS: usize = 3;
+
 pub struct Snapshot {
     location: PathBuf,
     timestamp: Timestamp,
     index: Index,
+    /// Replaces `index` in bounded-memory mode.
+    index_writer: Option<IndexWriter>,
     files: Files,
     config: SnapshotConfig,
+    warnings: Vec<String>,
+    /// Files larger than the size limit, with their sizes.
+    not_copied: Vec<(PathBuf, u64)>,
+    /// Files that kept changing while they were copied.
+    unstable: Vec<PathBuf>,
+    /// Entries that couldn't be backed up, with the reason.
+    failures: Vec<(PathBuf, String)>,
+    hardlinks: Hardlinks,
+    /// Parsed metadata.txt, if the snapshot has one.
+    metadata: Option<Metadata>,
+    /// Created when the first file is chunked.
+    chunk_store: Option<ChunkStore>,
+    /// Chunk lists of files chunked in this or other snapshots, read from
+    /// their indexes.
+    chunk_lists: RefCell<ChunkLists>,
+    /// How files stored in this or other snapshots are transformed.
+    encodings: RefCell<BTreeMap<Timestamp, Encoding>>,
+    /// Copies of files of archived snapshots, unpacked when first read.
+    unpacked: RefCell<BTreeMap<Timestamp, Option<Unpacked>>>,
+    /// Drawn while files are added to the snapshot.
+    progress: Option<Progress>,
+    /// Adding files stops when it's set.
+    interrupted: Arc<AtomicBool>,
+    /// Entries stored before a resumed snapshot stopped, by path.
+    resumed: Option<HashMap<PathBuf, IndexEntry>>,
+    /// Metadata of indexed sources, by hashes of their index paths. Saved
+    /// to the files cache when the snapshot is committed.
+    stats: HashMap<PathHash, Stat, ahash::RandomState>,
 }
 
 impl Snapshot {
-    pub fn create(root: &Path) -> Result<Snapshot, String> {
+    pub fn create(root: &Path, environment: &Environment) -> Result<Snapshot, String> {
         if !root.is_dir() {
             return Err("Folder with backup does not exist or is not accessible".into());
         }
 
-        let timestamp = get_timestamp_for_new_snapshot(root);
+        let timestamp = get_timestamp_for_new_snapshot(root, environment.clock.as_ref());
 
-        let location = root.join(timestamp.to_string());
+        // Renamed by `commit`, so an unfinished snapshot is never loaded.
+        let location = root.join(format!("{}{}", timestamp, PARTIAL_SUFFIX));
         fs::create_dir(&location).or(Err("Cannot create directory for a snapshot"))?;
 
-        let index = Index::new(location.join("index.txt"));
-        let files = Files::new(location.join("files"));
-
         debug!("Created new snapshot: {}", timestamp);
+        Self::unfinished(location, timestamp, environment)
+    }
+
+    /// Continues an unfinished snapshot left in `<timestamp>.partial` folder.
+    /// Files copied before it stopped are reused when they match the source.
+    pub fn resume(location: &Path, environment: &Environment) -> Result<Snapshot, String> {
+        let timestamp = location
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_suffix(PARTIAL_SUFFIX))
+            .and_then(Timestamp::parse_from)
+            .ok_or(format!(
+                "Not an unfinished snapshot: \"{}\"",
+                location.display()
+            ))?;
+        let mut snapshot = Self::unfinished(location.to_owned(), timestamp, environment)?;
+        let entries = Index::read_unfinished(&snapshot.index.location);
+        debug!(
+            "Resumed snapshot {} with {} indexed entries",
+            snapshot.timestamp,
+            entries.len()
+        );
+        snapshot.resumed = Some(
+            entries
+                .into_iter()
+                .filter(|e| e.timestamp == snapshot.timestamp)
+                .map(|e| (e.path.clone(), e))
+                .collect(),
+        );
+        Ok(snapshot)
+    }
+
+    fn unfinished(
+        location: PathBuf,
+        timestamp: Timestamp,
+        environment: &Environment,
+    ) -> Result<Snapshot, String> {
+        let index = Index::new(location.join("index.txt"));
+        let files = Files::new(location.join("files"), environment.fs.clone())?;
         Ok(Snapshot {
             location,
             timestamp,
             index,
+            index_writer: None,
             files,
             config: SnapshotConfig::default(),
+            warnings: vec![],
+            not_copied: vec![],
+            unstable: vec![],
+            failures: vec![],
+            hardlinks: Hardlinks::default(),
+            metadata: None,
+            chunk_store: None,
+            chunk_lists: RefCell::default(),
+            encodings: RefCell::default(),
+            unpacked: RefCell::default(),
+            progress: None,
+            interrupted: environment.interrupted.clone(),
+            resumed: None,
+            stats: HashMap::default(),
         })
     }
 
     pub fn open(location: &Path) -> Option<Snapshot> {
-        match Self::try_to_open(location) {
+        match Self::try_to_open(location, true) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                warn!("Failed to open snapshot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Opens the snapshot without reading its index into memory. Entries
+    /// can be read from the disk one at a time with `scan_stored_entries`.
+    pub fn open_without_index(location: &Path) -> Option<Snapshot> {
+        match Self::try_to_open(location, false) {
             Ok(snapshot) => Some(snapshot),
             Err(e) => {
                 warn!("Failed to open snapshot: {}", e);
@@ -59,22 +217,41 @@ impl Snapshot {
         }
     }
 
-    fn try_to_open(location: &Path) -> Result<Snapshot, String> {
+    fn try_to_open(location: &Path, read_index: bool) -> Result<Snapshot, String> {
         let snapshot_name = location
             .file_name()
             .ok_or("Invalid snapshot name")?
             .to_string_lossy();
         let timestamp = Timestamp::parse_from(&snapshot_name)
             .ok_or(format!("Invalid snapshot name: \"{}\"", snapshot_name))?;
-        let index = Index::open(location.join("index.txt"))?;
+        let index = match read_index {
+            true => Index::open(location.join("index.txt"))?,
+            false => Index::new(location.join("index.txt")),
+        };
         let files = Files::open(location.join("files"))?;
+        let metadata = read_metadata(location);
 
         Ok(Snapshot {
             location: location.to_owned(),
             timestamp,
             index,
+            index_writer: None,
             files,
             config: SnapshotConfig::default(),
+            warnings: vec![],
+            not_copied: vec![],
+            unstable: vec![],
+            failures: vec![],
+            hardlinks: Hardlinks::default(),
+            metadata,
+            chunk_store: None,
+            chunk_lists: RefCell::default(),
+            encodings: RefCell::default(),
+            unpacked: RefCell::default(),
+            progress: None,
+            interrupted: Arc::default(),
+            resumed: None,
+            stats: HashMap::default(),
         })
     }
 
@@ -86,34 +263,186 @@ impl Snapshot {
         Timestamp::is_valid(name.as_ref())
     }
 
+    /// Files larger than `limit` will not be copied. What happens to
+    /// them depends on the `policy`.
+    pub fn set_size_limit(&mut self, limit: Option<u64>, policy: LargeFilesPolicy) {
+        self.config.size_limit = limit;
+        self.config.large_files = policy;
+    }
+
+    /// Named pipes, sockets and device nodes are handled according
+    /// to the `policy`.
+    pub fn set_special_files(&mut self, policy: SpecialFilesPolicy) {
+        self.config.special_files = policy;
+    }
+
+    /// Permissions, owner and modification time of entries are recorded
+    /// in the index unless disabled.
+    pub fn set_preserve_attributes(&mut self, preserve: bool) {
+        self.config.preserve_attributes = preserve;
+    }
+
+    /// Files are compared with the base snapshot by their checksums
+    /// instead of their modification times.
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.config.checksum = checksum;
+    }
+
+    /// Stop adding files after the first entry that can't be backed up.
+    pub fn set_fail_fast(&mut self, fail_fast: bool) {
+        self.config.fail_fast = fail_fast;
+    }
+
+    /// Files larger than `threshold` are split into chunks and only chunks
+    /// that aren't stored in the backup yet are stored.
+    pub fn set_chunking(&mut self, threshold: Option<u64>) {
+        self.config.chunk_threshold = threshold;
+    }
+
+    /// Files copied into the snapshot are compressed.
+    pub fn set_compression(&mut self, compression: Option<FileCompression>) {
+        self.config.compression = compression;
+    }
+
+    /// Files copied into the snapshot are encrypted to the recipients.
+    pub fn set_encryption(&mut self, encryption: Option<Encryption>) {
+        self.config.encryption = encryption;
+    }
+
+    /// Private key used to decrypt files: an age identity file, or GPG
+    /// secret keys imported into a temporary home. Without it, GPG finds
+    /// the private key in its keyring.
+    pub fn set_key_file(&mut self, key_file: Option<PathBuf>) -> Result<(), String> {
+        self.config.gpg_home = match &key_file {
+            Some(key_file) if self.has_gpg_encrypted_data() => {
+                let home = GpgHome::import(key_file).map_err(|e| {
+                    format!("Cannot import key file '{}' ({})", key_file.display(), e)
+                })?;
+                Some(Arc::new(home))
+            }
+            _ => None,
+        };
+        self.config.key_file = key_file;
+        Ok(())
+    }
+
+    /// Any file of the snapshot, or of snapshots it refers to, is encrypted
+    /// with GPG, so reading it may need the passphrase of the private key.
+    pub fn has_gpg_encrypted_data(&self) -> bool {
+        let timestamps: BTreeSet<&Timestamp> =
+            self.index.entries.iter().map(|e| &e.timestamp).collect();
+        timestamps
+            .into_iter()
+            .any(|t| self.encoding_of(t).encryption == Some(EncryptionTool::Gpg))
+    }
+
+    /// Passphrase of the GPG private key used to decrypt files, instead of
+    /// asking for it.
+    pub fn set_passphrase(&mut self, passphrase: Option<String>) {
+        self.config.passphrase = passphrase;
+    }
+
+    /// Limits how deep into inputs entries are backed up. Inputs
+    /// themselves have depth 0.
+    pub fn set_max_depth(&mut self, depth: Option<usize>) {
+        self.config.max_depth = depth;
+    }
+
+    /// Entries matching any of the patterns are not backed up. Folders
+    /// are skipped with all their content.
+    pub fn set_exclude(&mut self, patterns: Vec<Pattern>) {
+        self.config.exclude = patterns;
+    }
+
+    /// Switches to bounded-memory mode. Index is written to the disk while
+    /// entries are added, and the base index is streamed instead of loaded.
+    /// Should be called before the base snapshot is set.
+    pub fn set_memory_limit(&mut self, limit: u64) -> io::Result<()> {
+        debug!("Bounded-memory mode with limit of {} bytes", limit);
+        self.index_writer = Some(IndexWriter::create(
+            &self.index.location,
+            self.timestamp.clone(),
+        )?);
+        self.config.memory_limit = Some(limit);
+        Ok(())
+    }
+
     pub fn set_base_snapshot(&mut self, base_snapshot: Option<&SnapshotPreview>) {
         let base_index = match base_snapshot {
-            Some(snapshot) => Self::get_base_snapshot_index(snapshot),
+            Some(snapshot) => self.get_base_snapshot_index(snapshot),
             None => None,
         };
 
+        if let (Some(snapshot), None) = (base_snapshot, &base_index) {
+            self.warnings.push(format!(
+                "Base snapshot {} couldn't be used. Full snapshot was performed",
+                snapshot.timestamp
+            ));
+        }
+
         let base_snapshot_str = match base_index {
             Some(_) => base_snapshot.unwrap().timestamp.to_string(),
             None => String::from("None"),
         };
         debug!("Base snapshot set to: {}", base_snapshot_str);
 
+        // The cache is as large as the index, which isn't kept in memory
+        // in bounded-memory mode.
+        if let (Some(snapshot), Some(_), None) =
+            (base_snapshot, &base_index, self.config.memory_limit)
+        {
+            let backup = snapshot.location.parent().unwrap_or(Path::new("."));
+            self.config.files_cache =
+                files_cache::load(backup, &snapshot.timestamp.to_string(), &snapshot.index);
+        }
         self.config.base_index = base_index;
     }
 
-    fn get_base_snapshot_index(base_snapshot: &SnapshotPreview) -> Option<IndexPreview> {
-        match Snapshot::check_integrity(base_snapshot.location.as_path()) {
-            Ok(_) => debug!("Base snapshot integrity check passed"),
-            Err(e) => {
-                error!("Base snapshot integrity check failed: {}", e);
-                info!("Incremental backup will not be performed");
-                return None;
+    fn get_base_snapshot_index(&self, base_snapshot: &SnapshotPreview) -> Option<BaseIndex> {
+        let sorted = self
+            .config
+            .memory_limit
+            .map(|limit| (limit, self.sort_location()));
+        Self::open_base_index(base_snapshot, sorted)
+    }
+
+    /// Index of the base snapshot, if the snapshot is intact. With `sorted`
+    /// the index is sorted on the disk in the given folder, in chunks of at
+    /// most the given size.
+    fn open_base_index(
+        base_snapshot: &SnapshotPreview,
+        sorted: Option<(u64, PathBuf)>,
+    ) -> Option<BaseIndex> {
+        let report = Snapshot::check_integrity(base_snapshot.location.as_path());
+        for finding in report.findings() {
+            match finding.severity() {
+                Severity::Error => error!("Base snapshot integrity check failed: {}", finding),
+                Severity::Warning => warn!("Base snapshot integrity check: {}", finding),
+                Severity::Info => info!("Base snapshot integrity check: {}", finding),
             }
         }
+        if report.passed(false) {
+            debug!("Base snapshot integrity check passed");
+        } else {
+            info!("Incremental backup will not be performed");
+            return None;
+        }
 
-        let index_preview = IndexPreview::open(base_snapshot.index.as_path());
-        match index_preview {
-            Ok(index_preview) => Some(index_preview),
+        let base_index = match sorted {
+            Some((limit, location)) => spill::sort_index(&base_snapshot.index, &location, limit)
+                .and_then(|sorted| SortedIndex::open(&sorted))
+                .map(BaseIndex::Sorted)
+                .map_err(|e| e.to_string()),
+            None => match index_cache::load(&base_snapshot.location, &base_snapshot.index) {
+                Some(cached) => {
+                    debug!("Using cached base index");
+                    Ok(BaseIndex::Cached(cached))
+                }
+                None => IndexPreview::open(base_snapshot.index.as_path()).map(BaseIndex::InMemory),
+            },
+        };
+        match base_index {
+            Ok(base_index) => Some(base_index),
             Err(e) => {
                 warn!("Failed to load base index with cause: {}", e);
                 None
@@ -125,127 +454,1054 @@ impl Snapshot {
         self.timestamp.to_string()
     }
 
-    pub fn save_index(&self) -> io::Result<()> {
-        self.index.save()
+    pub fn period(&self, period: Period) -> String {
+        self.timestamp.get_period(period)
+    }
+
+    /// Size of files stored in this snapshot in bytes. It's read from
+    /// metadata if possible, because otherwise all stored files are walked.
+    pub fn size(&self) -> u64 {
+        match self.metadata() {
+            Some(metadata) => metadata.size,
+            None => self.files.size(),
+        }
+    }
+
+    /// Problems encountered while creating this snapshot that didn't
+    /// stop the backup, e.g. files that couldn't be copied.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Files that weren't copied because they exceed the size limit.
+    pub fn not_copied(&self) -> &[(PathBuf, u64)] {
+        &self.not_copied
+    }
+
+    /// Entries that couldn't be backed up, with the reason.
+    pub fn failures(&self) -> &[(PathBuf, String)] {
+        &self.failures
+    }
+
+    /// Whether adding files stopped at the first failure.
+    pub fn has_failed_fast(&self) -> bool {
+        self.config.fail_fast && !self.failures.is_empty()
+    }
+
+    /// Reports an entry that couldn't be backed up.
+    fn report_failure(&mut self, path: &Path, message: String) {
+        self.failures.push((path.to_owned(), message.clone()));
+        self.report_problem(message);
+    }
+
+    fn report_problem(&mut self, message: String) {
+        self.emit(Event::Error { message: &message });
+        error!("{}", message);
+        self.warnings.push(message);
+    }
+
+    /// Folder for temporary files of bounded-memory mode.
+    fn sort_location(&self) -> PathBuf {
+        self.location.join(".sort")
+    }
+
+    pub fn save_index(&mut self) -> io::Result<()> {
+        let sort_location = self.sort_location();
+        match &mut self.index_writer {
+            Some(writer) => {
+                // Sorted base index may be read from the temporary folder
+                self.config.base_index = None;
+                if sort_location.exists() {
+                    fs::remove_dir_all(sort_location)?;
+                }
+                writer.flush()
+            }
+            None => {
+                self.index.save()?;
+                let cached =
+                    index_cache::save(&self.location, &self.index.location, &self.index.entries);
+                if let Err(e) = cached {
+                    debug!("Failed to cache index ({})", e);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn indexed_count(&self) -> usize {
+        match &self.index_writer {
+            Some(writer) => writer.indexed,
+            None => self.index.entries.len(),
+        }
+    }
+
+    fn copied_count(&self) -> usize {
+        match &self.index_writer {
+            Some(writer) => writer.copied,
+            None => self
+                .index
+                .entries
+                .iter()
+                .filter(|e| e.timestamp == self.timestamp)
+                .count(),
+        }
+    }
+
+    /// Numbers of this snapshot as it was created, in `duration`.
+    pub fn stats(&self, duration: Duration) -> BackupStats {
+        let (scanned, copied) = (self.indexed_count(), self.copied_count());
+        BackupStats {
+            scanned,
+            copied,
+            reused: scanned - copied,
+            written: self.files.size(),
+            errors: self.failures.len(),
+            duration,
+        }
+    }
+
+    /// Saves messages of entries that couldn't be backed up in errors.txt,
+    /// if there are any, so they can be reviewed after the log is gone.
+    pub fn save_errors(&self) -> io::Result<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        let lines: String = self
+            .failures
+            .iter()
+            .map(|(_, message)| format!("{}\n", message))
+            .collect();
+        fs::write(self.location.join(ERRORS_FILE_NAME), lines)
+    }
+
+    /// Records how this snapshot was made. Should be called when
+    /// the snapshot is complete.
+    pub fn save_metadata(
+        &mut self,
+        inputs: &[PathBuf],
+        duration: Duration,
+        previous: Option<ChainLink>,
+    ) -> io::Result<()> {
+        let metadata = Metadata {
+            version: env!("CARGO_PKG_VERSION").into(),
+            host: get_host_name(),
+            user: get_user_name(),
+            incremental: self.copied_count() < self.indexed_count(),
+            duration,
+            inputs: inputs.to_vec(),
+            indexed: self.indexed_count(),
+            copied: self.copied_count(),
+            size: self.files.size(),
+            compression: self.config.compression,
+            encryption: self.config.encryption.clone(),
+            warnings: self.warnings.len(),
+            not_copied: match self.config.large_files {
+                LargeFilesPolicy::Record => self.not_copied.clone(),
+                LargeFilesPolicy::Skip => vec![],
+            },
+            unstable: self.unstable.clone(),
+            previous,
+        };
+        // Counts are known only now, the span of the backup gets them here.
+        Span::current()
+            .record("indexed", metadata.indexed)
+            .record("copied", metadata.copied);
+        metadata.save(&self.location.join("metadata.txt"))?;
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+
+    /// Packs files stored in this snapshot into a single archive. Should be
+    /// called when all entries are copied.
+    pub fn pack_files(&mut self) -> io::Result<()> {
+        archived::pack(&self.location)
+    }
+
+    pub fn is_archived(&self) -> bool {
+        archived::is_archived(&self.location)
+    }
+
+    /// Writes checksums of files stored in this snapshot.
+    /// Returns the path of the manifest.
+    pub fn save_checksums(&self) -> io::Result<PathBuf> {
+        checksums::save_manifest(&self.location)
+    }
+
+    /// Writes checksums of files stored in this snapshot into `output`,
+    /// e.g. to verify a copy of the snapshot with `sha256sum -c`.
+    /// Returns the number of files.
+    pub fn export_checksums(&self, output: &Path) -> io::Result<usize> {
+        checksums::write_manifest(&self.location, output)
+    }
+
+    /// Checksum of the whole snapshot folder. See [`checksums::fingerprint`].
+    pub fn fingerprint(location: &Path) -> io::Result<String> {
+        checksums::fingerprint(location)
+    }
+
+    /// Link to a complete snapshot, recorded in metadata of the next one.
+    pub fn chain_link(location: &Path) -> io::Result<ChainLink> {
+        let snapshot = location
+            .file_name()
+            .map(|name| name.to_string_lossy().into())
+            .unwrap_or_default();
+        let hash = checksums::chain_hash(location)?;
+        Ok(ChainLink { snapshot, hash })
+    }
+
+    pub fn index_path(&self) -> &Path {
+        &self.index.location
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    /// Gives a created snapshot its final name. Should be called when its
+    /// index is saved, until then it's in a folder with `.partial` suffix.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let location = self.location.with_file_name(self.name());
+        fs::rename(&self.location, &location)?;
+        debug!("Committed snapshot: {}", self.timestamp);
+        self.index.location = location.join("index.txt");
+        self.files.relocate(location.join("files"));
+        self.location = location;
+        Ok(())
+    }
+
+    /// Saves metadata of the backed up entries, so the next backup finds
+    /// unchanged ones quickly. Should be called when it's committed.
+    pub fn save_files_cache(&self) {
+        if self.index_writer.is_some() || self.stats.is_empty() {
+            return;
+        }
+        let backup = self.location.parent().unwrap_or(Path::new("."));
+        let saved = files_cache::save(
+            backup,
+            &self.name(),
+            &self.index.location,
+            &self.index.entries,
+            &self.stats,
+        );
+        if let Err(e) = saved {
+            debug!("Failed to save files cache ({})", e);
+        }
+    }
+
+    /// Snapshots made by older versions have no metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
     }
 
     pub fn add_files_to_snapshot(&mut self, path: &Path) {
-        for entry in WalkDir::new(path).follow_links(false) {
+        let _span = info_span!("input", path = %path.display()).entered();
+        let walked_location = match path.canonicalize() {
+            Ok(location) => location,
+            Err(e) => {
+                let message = format!("Failed to copy: \"{}\" ({})", path.display(), e);
+                return self.report_failure(path, message);
+            }
+        };
+        let mut walk = WalkDir::new(path).follow_links(false);
+        if self.config.memory_limit.is_some() {
+            // The same order as of the sorted base index
+            walk = walk.sort_by_file_name();
+        }
+        if let Some(depth) = self.config.max_depth {
+            walk = walk.max_depth(depth);
+        }
+        let exclude = self.config.exclude.clone();
+        let included = |entry: &walkdir::DirEntry| {
+            let location = match entry.path().strip_prefix(path) {
+                Ok(relative) if entry.depth() > 0 => walked_location.join(relative),
+                _ => walked_location.clone(),
+            };
+            let excluded = exclude.iter().any(|pattern| pattern.matches(&location));
+            if excluded {
+                trace!("Excluded: {}", location.display());
+            }
+            !excluded
+        };
+        for entry in walk.into_iter().filter_entry(included) {
+            if self.is_interrupted() || self.has_failed_fast() {
+                debug!("Stopped adding files of {}", path.display());
+                return;
+            }
+            let failed = |entry_path: &Path, e: &dyn std::fmt::Display| {
+                let message = format!("Failed to copy: \"{}\" ({})", entry_path.display(), e);
+                (entry_path.to_owned(), message)
+            };
             let entry = match entry {
-                Ok(entry) => entry,
-                Err(e) => {
-                    error!("{}", e);
-                    continue;
+                Ok(entry) if entry.depth() == 0 => {
+                    SourceEntry::read(entry.path()).map_err(|e| failed(entry.path(), &e))
+                }
+                Ok(entry) => {
+                    let entry_path = entry.path().to_owned();
+                    SourceEntry::from_walk(entry, path, &walked_location)
+                        .map_err(|e| failed(&entry_path, &e))
                 }
+                Err(e) => Err((e.path().unwrap_or(path).to_owned(), e.to_string())),
             };
+            match entry {
+                Ok(entry) => {
+                    self.add_entry(&entry);
+                    let size = match entry.metadata.is_file() {
+                        true => entry.metadata.len(),
+                        false => 0,
+                    };
+                    self.advance_progress(size);
+                }
+                Err((entry_path, message)) => {
+                    self.report_failure(&entry_path, message);
+                    self.advance_progress(0);
+                }
+            }
+        }
+    }
 
-            let entry = entry.path();
+    /// Whether the user asked to stop while files were added. Entries
+    /// added so far are consistent, the rest of the inputs is missing.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(atomic::Ordering::Relaxed)
+    }
 
-            match self.is_entry_already_backed_up(entry) {
-                Some(prev_timestamp) => self.index_entry(prev_timestamp, entry),
-                None => self.copy_and_index_entry(entry),
+    fn add_entry(&mut self, entry: &SourceEntry) {
+        let recreatable = self.config.special_files == SpecialFilesPolicy::RecreateOnRestore;
+        if let Some(special) = Special::of(&entry.metadata, recreatable) {
+            return self.index_special_entry(special, entry);
+        }
+        match self.is_entry_already_backed_up(entry) {
+            Some((prev_timestamp, content)) => {
+                if entry.metadata.is_file() {
+                    self.emit(Event::FileSkipped {
+                        path: &entry.path,
+                        reason: SkipReason::Unchanged,
+                    });
+                }
+                self.index_entry(prev_timestamp, content, vec![], entry)
             }
+            None => self.copy_and_index_entry(entry),
         }
     }
 
-    fn is_entry_already_backed_up(&self, entry: &Path) -> Option<Timestamp> {
-        let margin = time::Duration::minutes(1);
-        let prev_timestamp = self.config.base_index.as_ref()?.find(entry)?;
-        let prev_timestamp_with_margin = prev_timestamp.clone() - margin;
+    fn is_entry_already_backed_up(
+        &mut self,
+        entry: &SourceEntry,
+    ) -> Option<(Timestamp, Option<Content>)> {
+        if self.config.checksum && entry.metadata.is_file() {
+            return find_same_content(self.config.base_index.as_mut()?, entry);
+        }
+        if let Some(cache) = &self.config.files_cache {
+            match cache.find(&entry.index_path().ok()?, &entry.metadata) {
+                Lookup::Unchanged(timestamp, content) => {
+                    return Some((timestamp.clone(), content.cloned()))
+                }
+                Lookup::Changed => return None,
+                Lookup::Unknown => (),
+            }
+        }
+        find_unchanged(self.config.base_index.as_mut()?, entry)
+    }
 
-        let metadata = entry.symlink_metadata().ok()?;
-        let modif_system_time = metadata.modified().ok()?;
-        let create_system_time = metadata.created().ok()?;
-        let modif_timestamp = Timestamp::from(modif_system_time);
-        let create_timestamp = Timestamp::from(create_system_time);
+    /// Shows progress of adding files until [`Self::finish_progress`].
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
 
-        let file_has_changed = modif_timestamp > prev_timestamp_with_margin
-            || create_timestamp > prev_timestamp_with_margin;
-        trace!(
-            "Entry \"{}\" (modif: {}) found in snapshot: {}, has_changed={}",
-            entry.display(),
-            modif_timestamp,
-            prev_timestamp,
-            file_has_changed
-        );
-        if file_has_changed {
-            None
-        } else {
-            Some(prev_timestamp.clone())
+    pub fn finish_progress(&mut self) {
+        self.progress = None;
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(progress) = &mut self.progress {
+            progress.emit(event);
+        }
+    }
+
+    fn advance_progress(&mut self, size: u64) {
+        if let Some(progress) = &mut self.progress {
+            progress.advance(size, self.files.size());
+        }
+    }
+
+    pub fn set_copy_options(&mut self, options: CopyOptions) {
+        debug!("Copying files with {:?}", options);
+        self.files.set_copy_options(options);
+    }
+
+    /// Entries that the destination cannot store will be skipped
+    /// instead of failing one by one during copying.
+    pub fn set_destination_limits(&mut self, destination: &Destination) {
+        self.config.skip_symlinks = !destination.supports_symlinks;
+        self.config.max_file_size = destination.filesystem.max_file_size();
+    }
+
+    fn check_destination_limits(&self, entry: &SourceEntry) -> Result<(), String> {
+        let metadata = &entry.metadata;
+        if self.config.skip_symlinks && metadata.file_type().is_symlink() {
+            return Err(format!(
+                "Skipped: \"{}\" (backup folder doesn't support symlinks)",
+                entry.path.display()
+            ));
+        }
+        match self.config.max_file_size {
+            Some(max_size) if metadata.is_file() && metadata.len() > max_size => Err(format!(
+                "Skipped: \"{}\" ({} bytes exceeds maximum file size of backup folder)",
+                entry.path.display(),
+                metadata.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the entry is a file larger than the size limit.
+    fn exceeds_size_limit(&mut self, entry: &SourceEntry) -> bool {
+        let size = entry.metadata.len();
+        match self.config.size_limit {
+            Some(limit) if entry.metadata.is_file() && size > limit => (),
+            _ => return false,
+        }
+        match self.config.large_files {
+            LargeFilesPolicy::Record => self.report_problem(format!(
+                "Not copied: \"{}\" ({} bytes exceeds maximum file size)",
+                entry.path.display(),
+                size
+            )),
+            LargeFilesPolicy::Skip => debug!(
+                "Skipped: \"{}\" ({} bytes exceeds maximum file size)",
+                entry.path.display(),
+                size
+            ),
+        }
+        self.emit(Event::FileSkipped {
+            path: &entry.path,
+            reason: SkipReason::TooLarge,
+        });
+        let path = entry.index_path().unwrap_or_else(|_| entry.path.clone());
+        self.not_copied.push((path, size));
+        true
+    }
+
+    fn copy_and_index_entry(&mut self, entry: &SourceEntry) {
+        if self.exceeds_size_limit(entry) {
+            return;
+        }
+        if self.should_be_chunked(entry) {
+            return self.chunk_and_index_entry(entry);
+        }
+        if let Err(message) = self.check_destination_limits(entry) {
+            return self.report_failure(&entry.path, message);
+        }
+        if let Some(content) = self.reuse_resumed_copy(entry) {
+            return self.index_entry(self.timestamp.clone(), content, vec![], entry);
+        }
+        self.copy_stable_and_index_entry(entry, COPY_ATTEMPTS)
+    }
+
+    /// Copies the entry again if it changed while it was copied, so the
+    /// snapshot doesn't keep a torn copy. A file that keeps changing, e.g. a
+    /// log being written, is indexed with its last copy and flagged as
+    /// unstable.
+    fn copy_stable_and_index_entry(&mut self, entry: &SourceEntry, attempts: usize) {
+        // Linking again would link the stale copy to itself.
+        let linked = self.hardlinks.stored_copy(&entry.metadata).is_some();
+        let size = self.files.size();
+        let content = match self.copy_entry(entry) {
+            Ok(content) => content,
+            Err(message) => return self.report_failure(&entry.path, message),
+        };
+        match entry.changed() {
+            Some(changed) if attempts > 1 && !linked => {
+                debug!(
+                    "Changed while copied, copying again: \"{}\"",
+                    entry.path.display()
+                );
+                // The stale copy may be read-only, like its source.
+                let data = Files::to_snapshot_path_unchecked(
+                    &self.location.join("files"),
+                    &entry.location,
+                );
+                if let Err(e) = fs::remove_file(&data) {
+                    debug!("Cannot remove \"{}\" ({})", data.display(), e);
+                }
+                self.files.set_size(size);
+                self.hardlinks.forget_stored(&entry.metadata);
+                return self.copy_stable_and_index_entry(&changed, attempts - 1);
+            }
+            Some(_) => {
+                self.report_problem(format!(
+                    "Changed while copied: \"{}\"",
+                    entry.path.display()
+                ));
+                self.unstable.push(entry.path.clone());
+            }
+            None => (),
+        }
+        self.emit_copied(entry);
+        self.index_entry(self.timestamp.clone(), content, vec![], entry)
+    }
+
+    /// Returns the content of a file copied before the snapshot was resumed,
+    /// if the copy matches the source. Otherwise, whatever was stored at its
+    /// path is removed, so the entry can be copied again.
+    fn reuse_resumed_copy(&mut self, entry: &SourceEntry) -> Option<Option<Content>> {
+        let resumed = self.resumed.as_ref()?;
+        let data = Files::to_snapshot_path_unchecked(&self.location.join("files"), &entry.location);
+        let stored = data.symlink_metadata().ok()?;
+        if entry.metadata.is_dir() && stored.is_dir() {
+            return None;
+        }
+        let modified = Attributes::of(&entry.metadata).modified;
+        let transformed = self.config.compression.is_some() || self.config.encryption.is_some();
+        let content = match resumed.get(&entry.location) {
+            // Index of a resumed snapshot has the checksum of the original
+            Some(indexed)
+                if indexed.chunks.is_empty() && indexed.attributes.modified == modified =>
+            {
+                indexed
+                    .content
+                    .clone()
+                    .filter(|c| c.size == entry.metadata.len())
+            }
+            // Copies keep modification times of originals
+            None if !transformed && stored.modified().ok() == modified => Content::of_file(&data)
+                .ok()
+                .filter(|c| c.size == entry.metadata.len()),
+            _ => None,
+        };
+        match content {
+            Some(content) if entry.metadata.is_file() && stored.is_file() => {
+                debug!("Reused: \"{}\"", data.display());
+                self.files.add_size(stored.len());
+                self.hardlinks.set_stored(&entry.metadata, &data, &content);
+                Some(Some(content))
+            }
+            _ => {
+                let removed = match stored.is_dir() {
+                    true => fs::remove_dir_all(&data),
+                    false => fs::remove_file(&data),
+                };
+                if let Err(e) = removed {
+                    debug!("Cannot remove \"{}\" ({})", data.display(), e);
+                }
+                None
+            }
         }
     }
 
-    fn copy_and_index_entry(&mut self, entry: &Path) {
-        if self.copy_entry(entry).is_ok() {
-            self.index_entry(self.timestamp.clone(), entry);
+    fn emit_copied(&mut self, entry: &SourceEntry) {
+        if entry.metadata.is_file() {
+            self.emit(Event::FileCopied {
+                path: &entry.path,
+                size: entry.metadata.len(),
+            });
         }
     }
 
-    fn copy_entry(&mut self, entry: &Path) -> Result<(), ()> {
+    fn should_be_chunked(&self, entry: &SourceEntry) -> bool {
+        match self.config.chunk_threshold {
+            Some(threshold) => entry.metadata.is_file() && entry.metadata.len() > threshold,
+            None => false,
+        }
+    }
+
+    /// Chunks are small, so limits of the destination don't apply to them.
+    fn chunk_and_index_entry(&mut self, entry: &SourceEntry) {
+        let (location, timestamp) = (&self.location, &self.timestamp);
+        let store = self
+            .chunk_store
+            .get_or_insert_with(|| ChunkStore::open(location, timestamp));
+        match store.store_file(&entry.path) {
+            Ok((content, chunks, stored)) => {
+                debug!(
+                    "Chunked: \"{}\" ({} chunks, {} bytes stored)",
+                    entry.path.display(),
+                    chunks.len(),
+                    stored
+                );
+                self.files.add_size(stored);
+                self.chunk_lists.borrow_mut().remove(&self.timestamp);
+                self.emit_copied(entry);
+                self.index_entry(self.timestamp.clone(), Some(content), chunks, entry)
+            }
+            Err(e) => self.report_failure(
+                &entry.path,
+                format!("Failed to copy: \"{}\" ({})", entry.path.display(), e),
+            ),
+        }
+    }
+
+    /// Returns the content of the copied entry, if it is a file.
+    fn copy_entry(&mut self, entry: &SourceEntry) -> Result<Option<Content>, String> {
+        if let Some((data, content)) = self.hardlinks.stored_copy(&entry.metadata) {
+            let content = content.clone();
+            match self.files.link_entry(entry, data) {
+                Ok(destination) => {
+                    debug!(
+                        "Linked: \"{}\" -> \"{}\"",
+                        entry.path.display(),
+                        destination.display()
+                    );
+                    return Ok(Some(content));
+                }
+                Err(e) => debug!("Failed to link \"{}\" ({})", entry.path.display(), e),
+            }
+        }
+        if let (Some(compression), true) = (self.config.compression, entry.metadata.is_file()) {
+            let (destination, content) = self
+                .files
+                .compress_entry(entry, compression)
+                .map_err(|e| format!("Failed to copy: \"{}\" ({})", entry.path.display(), e))?;
+            debug!(
+                "Compressed: \"{}\" -> \"{}\"",
+                entry.path.display(),
+                destination.display()
+            );
+            self.encrypt_stored(entry, &destination)?;
+            self.hardlinks
+                .set_stored(&entry.metadata, &destination, &content);
+            return Ok(Some(content));
+        }
         let destination = self.files.copy_entry(entry);
         match destination {
             Ok(destination) => {
                 debug!(
                     "Copied: \"{}\" -> \"{}\"",
-                    entry.display(),
+                    entry.path.display(),
                     destination.display()
                 );
+                if !entry.metadata.is_file() {
+                    return Ok(None);
+                }
+                match Content::of_file(&destination) {
+                    Ok(content) => {
+                        self.encrypt_stored(entry, &destination)?;
+                        self.hardlinks
+                            .set_stored(&entry.metadata, &destination, &content);
+                        Ok(Some(content))
+                    }
+                    Err(e) => {
+                        self.report_problem(format!(
+                            "Failed to compute checksum: \"{}\" ({})",
+                            destination.display(),
+                            e
+                        ));
+                        Ok(None)
+                    }
+                }
+            }
+            Err(e) => Err(format!(
+                "Failed to copy: \"{}\" ({})",
+                entry.path.display(),
+                e
+            )),
+        }
+    }
+
+    /// Files of an encrypted snapshot are encrypted after they are stored.
+    /// A file that can't be encrypted is removed, so no plain copy is left
+    /// in the snapshot.
+    fn encrypt_stored(&self, entry: &SourceEntry, data: &Path) -> Result<(), String> {
+        let encryption = match &self.config.encryption {
+            Some(encryption) => encryption,
+            None => return Ok(()),
+        };
+        let encrypted = data.metadata().and_then(|metadata| {
+            let modified = metadata.modified()?;
+            encryption.encrypt_in_place(data)?;
+            fs::File::options()
+                .write(true)
+                .open(data)?
+                .set_modified(modified)
+        });
+        encrypted.map_err(|e| {
+            fs::remove_file(data).ok();
+            format!("Failed to encrypt: \"{}\" ({})", entry.path.display(), e)
+        })
+    }
+
+    /// Special files have no data, so they are indexed as stored in this
+    /// snapshot every time.
+    fn index_special_entry(&mut self, special: Special, entry: &SourceEntry) {
+        if self.config.special_files == SpecialFilesPolicy::Skip {
+            debug!("Skipped special file: \"{}\"", entry.path.display());
+            return;
+        }
+        match entry.index_path() {
+            Ok(path) => {
+                let index_entry = IndexEntry {
+                    timestamp: self.timestamp.clone(),
+                    path,
+                    content: None,
+                    link: None,
+                    special: Some(special),
+                    chunks: vec![],
+                    attributes: self.attributes_of(entry),
+                };
+                self.push_index_entry(index_entry, &entry.path)
+            }
+            Err(e) => self.report_failure(
+                &entry.path,
+                format!("Failed to index: \"{}\" ({})", entry.path.display(), e),
+            ),
+        }
+    }
+
+    fn index_entry(
+        &mut self,
+        timestamp: Timestamp,
+        content: Option<Content>,
+        chunks: Vec<ChunkRef>,
+        entry: &SourceEntry,
+    ) {
+        match entry.index_path() {
+            Ok(path) => {
+                if let (None, Some(stat)) = (&self.index_writer, Stat::of(&entry.metadata)) {
+                    self.stats.insert(hash_path(&path), stat);
+                }
+                let index_entry = IndexEntry {
+                    timestamp,
+                    path,
+                    content,
+                    link: self.hardlinks.group(&entry.metadata),
+                    special: None,
+                    chunks,
+                    attributes: self.attributes_of(entry),
+                };
+                self.push_index_entry(index_entry, &entry.path)
+            }
+            Err(e) => self.report_failure(
+                &entry.path,
+                format!("Failed to index: \"{}\" ({})", entry.path.display(), e),
+            ),
+        }
+    }
+
+    fn attributes_of(&self, entry: &SourceEntry) -> Attributes {
+        match self.config.preserve_attributes {
+            true => Attributes::of(&entry.metadata),
+            false => Attributes::default(),
+        }
+    }
+
+    fn push_index_entry(&mut self, index_entry: IndexEntry, entry: &Path) {
+        trace!(
+            "Indexed: {} {}",
+            index_entry.timestamp,
+            index_entry.path.display()
+        );
+        let written = match &mut self.index_writer {
+            Some(writer) => writer.push(&index_entry),
+            None => {
+                self.index.push(index_entry);
                 Ok(())
             }
-            Err(e) => {
-                error!("Failed to copy: \"{}\" ({})", entry.display(), e);
-                Err(())
+        };
+        if let Err(e) = written {
+            self.report_failure(
+                entry,
+                format!("Failed to index: \"{}\" ({})", entry.display(), e),
+            )
+        }
+    }
+
+    /// Adds entries of `folder`, a copy of `original` made by another
+    /// backup tool. Entries are indexed by their original paths. Entries
+    /// that are the same as in `previous_folder`, from which the base
+    /// snapshot was imported, are not copied again.
+    pub fn import_folder(
+        &mut self,
+        folder: &Path,
+        original: &Path,
+        previous_folder: Option<&Path>,
+    ) {
+        // The root of the filesystem has no folder of its own in the snapshot.
+        let min_depth = match original.parent() {
+            Some(_) => 0,
+            None => 1,
+        };
+        let walk = WalkDir::new(folder)
+            .min_depth(min_depth)
+            .follow_links(false);
+        for entry in walk.sort_by_file_name() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.report_failure(e.path().unwrap_or(folder), e.to_string());
+                    continue;
+                }
+            };
+            let relative = entry.path().strip_prefix(folder).unwrap_or(Path::new(""));
+            let location = match entry.depth() {
+                0 => original.to_owned(),
+                _ => original.join(relative),
+            };
+            let previous = previous_folder.map(|previous| previous.join(relative));
+            let entry = match entry.metadata() {
+                Ok(metadata) => SourceEntry {
+                    path: entry.into_path(),
+                    metadata,
+                    location,
+                },
+                Err(e) => {
+                    let message = format!("Failed to copy: \"{}\" ({})", entry.path().display(), e);
+                    self.report_failure(entry.path(), message);
+                    continue;
+                }
+            };
+
+            let unchanged = previous
+                .filter(|previous| is_unchanged(&entry.path, &entry.metadata, previous))
+                .and_then(|_| self.config.base_index.as_mut()?.find(&entry.location));
+            if let Some((timestamp, content, _)) = unchanged {
+                let index_entry = IndexEntry {
+                    timestamp,
+                    path: entry.location.clone(),
+                    content,
+                    link: None,
+                    special: None,
+                    chunks: vec![],
+                    attributes: self.attributes_of(&entry),
+                };
+                self.push_index_entry(index_entry, &entry.path);
+                continue;
+            }
+            if let Err(message) = self.check_destination_limits(&entry) {
+                self.report_failure(&entry.path, message);
+                continue;
+            }
+            match self.copy_entry(&entry) {
+                Ok(content) => {
+                    let index_entry = IndexEntry {
+                        timestamp: self.timestamp.clone(),
+                        path: entry.location.clone(),
+                        content,
+                        link: None,
+                        special: None,
+                        chunks: vec![],
+                        attributes: self.attributes_of(&entry),
+                    };
+                    self.push_index_entry(index_entry, &entry.path)
+                }
+                Err(message) => self.report_failure(&entry.path, message),
+            }
+        }
+    }
+}
+
+fn read_metadata(location: &Path) -> Option<Metadata> {
+    let path = location.join("metadata.txt");
+    if !path.exists() {
+        return None;
+    }
+    match Metadata::open(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            warn!(
+                "Failed to read metadata of snapshot {}: {}",
+                location.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+fn get_timestamp_for_new_snapshot(root: &Path, clock: &dyn Clock) -> Timestamp {
+    let mut current_timestamp = Timestamp::now_from(clock);
+    debug!("Current timestamp: {}", current_timestamp);
+    let timestamp_of_latest_snapshot = get_latest_snapshot_preview(root).map(|s| s.timestamp);
+
+    // If there is a snapshot from the future, then set current_timestamp to its timestamp + 1 minute.
+    if let Some(timestamp_of_latest_snapshot) = timestamp_of_latest_snapshot {
+        debug!("Latest snapshot: {}", timestamp_of_latest_snapshot);
+        if current_timestamp < timestamp_of_latest_snapshot {
+            current_timestamp = timestamp_of_latest_snapshot.get_next();
+            warn!(
+                "Found snapshot from the future: {}. New snapshot will be created with timestamp: {}",
+                timestamp_of_latest_snapshot,
+                current_timestamp
+            );
+        }
+    }
+
+    // Another backup may be creating a snapshot with the same name.
+    loop {
+        let name = current_timestamp.to_string();
+        let partial = root.join(format!("{}{}", name, PARTIAL_SUFFIX));
+        if !root.join(name).exists() && !partial.exists() {
+            break;
+        }
+        current_timestamp = current_timestamp.get_next();
+    }
+
+    current_timestamp
+}
+
+// -------------------------------------
+// Integrity check
+// -------------------------------------
+impl Snapshot {
+    /// Verifies signatures of a signed snapshot and checksums of its files.
+    /// Nothing is reported for unsigned snapshots, unless a key was given.
+    /// Walks the hash chain back from the snapshot at `location` until
+    /// a snapshot without a link to the previous one, e.g. the first one,
+    /// or until `max_links` links were checked.
+    pub fn check_chain(location: &Path, max_links: Option<usize>, report: &mut IntegrityReport) {
+        let root = location.parent().unwrap_or(location);
+        let mut current = location.to_owned();
+        let mut links = 0;
+        while let Some(previous) = Metadata::open(&current.join("metadata.txt"))
+            .ok()
+            .and_then(|metadata| metadata.previous)
+        {
+            if max_links.is_some_and(|max_links| links >= max_links) {
+                break;
+            }
+            links += 1;
+            let current_name = current.file_name().unwrap_or_default().to_string_lossy();
+            // Snapshots are linked only to older ones, so the walk always ends
+            if *previous.snapshot >= *current_name {
+                break;
+            }
+
+            let previous_location = root.join(&previous.snapshot);
+            if !previous_location.is_dir() && is_pruned(root, &previous) {
+                trace!("Chain ends at pruned snapshot {}", previous.snapshot);
+                break;
+            }
+            if !previous_location.is_dir() {
+                report.push(IntegrityCheckError::ChainedSnapshotMissing(
+                    previous.snapshot,
+                ));
+                break;
+            }
+            match checksums::chain_hash(&previous_location) {
+                Ok(hash) if hash == previous.hash => {
+                    trace!("Link to snapshot {} is valid", previous.snapshot)
+                }
+                _ => report.push(IntegrityCheckError::ChainedSnapshotAltered(
+                    previous.snapshot,
+                )),
+            }
+            current = previous_location;
+        }
+    }
+
+    /// Signed snapshots have a manifest of checksums, which is signed.
+    pub fn is_signed(location: &Path) -> bool {
+        location.join(checksums::MANIFEST_FILE_NAME).exists()
+    }
+
+    pub fn check_signatures(
+        location: &Path,
+        key: Option<&VerifyingKey>,
+        report: &mut IntegrityReport,
+    ) {
+        let index = location.join("index.txt");
+        let manifest = location.join(checksums::MANIFEST_FILE_NAME);
+        let key = match key {
+            Some(key) => key,
+            None => {
+                let is_signed = SigningTool::all()
+                    .iter()
+                    .any(|tool| signature_path(*tool, &index).exists());
+                if is_signed {
+                    report.push(IntegrityCheckError::SignatureNotVerified);
+                }
+                return;
+            }
+        };
+        let severity = match key.policy {
+            SignaturePolicy::Fail => Severity::Error,
+            SignaturePolicy::Warn => Severity::Warning,
+        };
+
+        for file in [&index, &manifest] {
+            match key.verify(file) {
+                Ok(true) => debug!("Signature of {} is valid", file.display()),
+                Ok(false) => {
+                    report.push(IntegrityCheckError::InvalidSignature(
+                        file.to_owned(),
+                        severity,
+                    ));
+                    return;
+                }
+                Err(e) => {
+                    report.push(IntegrityCheckError::UnexpectedError(e));
+                    return;
+                }
             }
         }
-    }
-
-    fn index_entry(&mut self, timestamp: Timestamp, entry: &Path) {
-        let absolute_path = entry.canonicalize();
 
-        match absolute_path {
-            Ok(absolute_path) => {
-                trace!("Indexed: {} {}", timestamp, absolute_path.display());
-                self.index.push(timestamp, absolute_path);
+        match checksums::verify_manifest(location) {
+            Ok(mismatched) => {
+                for path in mismatched {
+                    report.push(IntegrityCheckError::ChecksumMismatch(location.join(path)));
+                }
             }
-            Err(e) => error!("Failed to index: \"{}\" ({})", entry.display(), e),
+            Err(e) => report.push(IntegrityCheckError::UnexpectedError(format!(
+                "Cannot read {} ({})",
+                checksums::MANIFEST_FILE_NAME,
+                e
+            ))),
         }
     }
-}
 
-fn get_timestamp_for_new_snapshot(root: &Path) -> Timestamp {
-    let mut current_timestamp = Timestamp::now();
-    debug!("Current timestamp: {}", current_timestamp);
-    let timestamp_of_latest_snapshot = get_latest_snapshot_preview(root).map(|s| s.timestamp);
-
-    // If there is a snapshot from the future, then set current_timestamp to its timestamp + 1 minute.
-    if let Some(timestamp_of_latest_snapshot) = timestamp_of_latest_snapshot {
-        debug!("Latest snapshot: {}", timestamp_of_latest_snapshot);
-        if current_timestamp < timestamp_of_latest_snapshot {
-            current_timestamp = timestamp_of_latest_snapshot.get_next();
-            warn!(
-                "Found snapshot from the future: {}. New snapshot will be created with timestamp: {}",
-                timestamp_of_latest_snapshot,
-                current_timestamp
-            );
+    /// Hashes files stored in this snapshot again and compares them with
+    /// checksums recorded in the index. This is the only check that detects
+    /// files corrupted on the backup drive without any change of their size
+    /// or modification time.
+    pub fn verify_content(&self, report: &mut IntegrityReport) {
+        // Encrypted data is compared with the checksums manifest instead,
+        // which doesn't need the private key.
+        if self.is_encrypted(&self.timestamp) {
+            match checksums::verify_manifest(&self.location) {
+                Ok(mismatched) => {
+                    for path in mismatched {
+                        report.push(IntegrityCheckError::ContentMismatch(
+                            self.location.join(path),
+                        ));
+                    }
+                }
+                Err(e) => report.push(IntegrityCheckError::UnexpectedError(format!(
+                    "Cannot read {} ({})",
+                    checksums::MANIFEST_FILE_NAME,
+                    e
+                ))),
+            }
+        }
+        let (corrupted, not_recorded) = self.find_corrupted_files();
+        for file in corrupted {
+            report.push(IntegrityCheckError::ContentMismatch(file.data));
+        }
+        for chunk in self.find_corrupted_chunks() {
+            report.push(IntegrityCheckError::ContentMismatch(chunk));
+        }
+        if not_recorded > 0 {
+            report.push(IntegrityCheckError::ContentNotRecorded(not_recorded));
+        }
+        if self.has_parity() {
+            match self.check_parity() {
+                Ok(0) => (),
+                Ok(damaged) => report.push(IntegrityCheckError::ParityDamaged(damaged)),
+                Err(e) => report.push(IntegrityCheckError::UnexpectedError(format!(
+                    "Cannot read parity data ({})",
+                    e
+                ))),
+            }
         }
     }
 
-    loop {
-        let location = root.join(current_timestamp.to_string());
-        if !location.exists() {
-            break;
+    pub fn check_integrity(location: &Path) -> IntegrityReport {
+        let mut report = IntegrityReport::new();
+        if let Err(error) = Self::check_integrity_into(location, &mut report) {
+            report.push(error);
         }
-        current_timestamp = current_timestamp.get_next();
+        report
     }
 
-    current_timestamp
-}
-
-// -------------------------------------
-// Integrity check
-// -------------------------------------
-impl Snapshot {
-    pub fn check_integrity(location: &Path) -> IntegrityCheckResult {
+    fn check_integrity_into(location: &Path, report: &mut IntegrityReport) -> IntegrityCheckResult {
         if !location.exists() {
             return Err(IntegrityCheckError::SnapshotDoesntExist);
         }
@@ -278,20 +1534,329 @@ impl Snapshot {
             .entries
             .iter()
             .filter(|e| e.timestamp.to_string() == snapshot_name)
+            .filter(|e| e.special.is_none() && e.chunks.is_empty())
             .map(|e| &e.path);
 
-        Files::check_integrity(location.join("files"), entries_from_this_snapshot)?;
+        if archived::is_archived(location) {
+            let stored = archived::list(location).map_err(|e| {
+                IntegrityCheckError::UnexpectedError(format!(
+                    "Cannot read {} ({})",
+                    archived::ARCHIVE_FILE_NAME,
+                    e
+                ))
+            })?;
+            let files = location.join("files");
+            Files::check_stored_entries(&files, stored, entries_from_this_snapshot, report)?;
+        } else {
+            Files::check_integrity(location.join("files"), entries_from_this_snapshot, report)?;
+        }
         info!("Files integrity check passed");
 
         Ok(())
     }
 }
 
+// -------------------------------------
+// Browsing
+// -------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// Named pipe, socket or device node, which has no data.
+    Special,
+    Missing,
+}
+
+impl Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Directory => write!(f, "dir"),
+            Self::Symlink => write!(f, "link"),
+            Self::Special => write!(f, "special"),
+            Self::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// Indexed entry together with the location of its data.
+pub struct EntryInfo {
+    pub path: PathBuf,
+    pub stored_in: String,
+    pub data: PathBuf,
+    pub kind: EntryKind,
+    pub size: u64, // in bytes
+    pub modified: Option<SystemTime>,
+    pub special: Option<Special>,
+    /// Chunks that make up the data of a chunked file, in order.
+    pub chunks: Vec<PathBuf>,
+    /// Attributes of the original entry, if they were recorded.
+    pub attributes: Attributes,
+    /// Data is stored compressed.
+    pub compressed: bool,
+    /// Data is stored encrypted and is decrypted with this.
+    pub decryption: Option<Decryption>,
+}
+
+impl EntryInfo {
+    /// Opens data of a file, joining its chunks or decrypting and
+    /// decompressing it.
+    pub fn open_data(&self) -> io::Result<Box<dyn io::Read>> {
+        if !self.chunks.is_empty() {
+            return Ok(Box::new(ChunkReader::new(&self.chunks)));
+        }
+        compression::open_stored(&self.data, self.compressed, self.decryption.as_ref())
+    }
+
+    /// Data has to be read with [`Self::open_data`] instead of copied.
+    pub fn is_transformed(&self) -> bool {
+        !self.chunks.is_empty() || self.compressed || self.decryption.is_some()
+    }
+
+    /// Adds what the index records about the entry.
+    fn indexed_as(mut self, entry: &IndexEntry) -> Self {
+        self.attributes = entry.attributes.clone();
+        if entry.special.is_some() {
+            self.kind = EntryKind::Special;
+            self.special = entry.special.clone();
+        }
+        // Compressed or encrypted data differs in size from the file.
+        let transformed = self.compressed || self.decryption.is_some();
+        if let (true, Some(content)) = (transformed, &entry.content) {
+            self.size = content.size;
+        }
+        self
+    }
+}
+
+impl Snapshot {
+    /// Entries indexed directly under `parent`. When `parent` is `None`,
+    /// entries that were given as inputs of the backup are returned.
+    /// Returns `None` if `parent` isn't indexed in this snapshot.
+    pub fn list_entries(&self, parent: Option<&Path>) -> Option<Vec<EntryInfo>> {
+        let indexed: std::collections::HashSet<&Path> = self
+            .index
+            .entries
+            .iter()
+            .map(|e| e.path.as_path())
+            .collect();
+        if parent.is_some_and(|p| !indexed.contains(p)) {
+            return None;
+        }
+
+        let entries = self
+            .index
+            .entries
+            .iter()
+            .filter(|e| match parent {
+                Some(parent) => e.path.parent() == Some(parent),
+                None => !e.path.parent().is_some_and(|p| indexed.contains(p)),
+            })
+            .map(|e| self.entry_info(e))
+            .collect();
+        Some(entries)
+    }
+
+    /// All indexed entries, including those stored in earlier snapshots.
+    pub fn entries(&self) -> Vec<EntryInfo> {
+        self.index
+            .entries
+            .iter()
+            .map(|e| self.entry_info(e))
+            .collect()
+    }
+
+    /// Names of other snapshots that store data of entries indexed here.
+    pub fn referenced_snapshots(&self) -> BTreeSet<String> {
+        self.index
+            .entries
+            .iter()
+            .flat_map(|e| std::iter::once(&e.timestamp).chain(e.chunks.iter().map(|c| &c.snapshot)))
+            .filter(|timestamp| **timestamp != self.timestamp)
+            .map(|timestamp| timestamp.to_string())
+            .collect()
+    }
+
+    /// Entries whose data was copied into this snapshot.
+    pub fn stored_entries(&self) -> Vec<EntryInfo> {
+        self.index
+            .entries
+            .iter()
+            .filter(|e| e.timestamp == self.timestamp)
+            .map(|e| self.entry_info(e))
+            .collect()
+    }
+
+    /// Reads the index from the disk line by line and passes entries whose
+    /// data was copied into this snapshot to `found`, if their paths are
+    /// `selected`. Data of entries that aren't selected is never looked up.
+    pub fn scan_stored_entries(
+        &self,
+        selected: impl Fn(&Path) -> bool,
+        mut found: impl FnMut(EntryInfo),
+    ) -> Result<(), String> {
+        for entry in Index::stream(&self.index.location)? {
+            let entry = entry?;
+            if entry.timestamp == self.timestamp && selected(&entry.path) {
+                found(self.entry_info(&entry));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds an indexed entry and resolves where its data is stored.
+    pub fn find_entry(&self, path: &Path) -> Option<EntryInfo> {
+        self.index
+            .entries
+            .iter()
+            .find(|e| e.path == path)
+            .map(|e| self.entry_info(e))
+    }
+
+    fn entry_info(&self, entry: &IndexEntry) -> EntryInfo {
+        self.get_entry_info(&entry.timestamp, &entry.path)
+            .indexed_as(entry)
+    }
+
+    /// How files stored in snapshot `timestamp` are transformed,
+    /// according to its metadata.
+    fn encoding_of(&self, timestamp: &Timestamp) -> Encoding {
+        if *timestamp == self.timestamp {
+            let metadata = self.metadata.as_ref();
+            let encryption = self
+                .config
+                .encryption
+                .as_ref()
+                .or_else(|| metadata?.encryption.as_ref());
+            return Encoding {
+                compressed: self.config.compression.is_some()
+                    || metadata.is_some_and(|m| m.compression.is_some()),
+                encryption: encryption.map(|e| e.tool),
+            };
+        }
+        *self
+            .encodings
+            .borrow_mut()
+            .entry(timestamp.clone())
+            .or_insert_with(|| {
+                let metadata = read_metadata(&self.location_of(timestamp));
+                Encoding {
+                    compressed: metadata.as_ref().is_some_and(|m| m.compression.is_some()),
+                    encryption: metadata.and_then(|m| Some(m.encryption?.tool)),
+                }
+            })
+    }
+
+    fn is_compressed(&self, timestamp: &Timestamp) -> bool {
+        self.encoding_of(timestamp).compressed
+    }
+
+    fn is_encrypted(&self, timestamp: &Timestamp) -> bool {
+        self.encoding_of(timestamp).encryption.is_some()
+    }
+
+    /// Folder of snapshot `timestamp` in the same backup. This snapshot
+    /// may not have its final name yet.
+    fn location_of(&self, timestamp: &Timestamp) -> PathBuf {
+        if *timestamp == self.timestamp {
+            return self.location.clone();
+        }
+        let backup_root = self.location.parent().unwrap_or_else(|| Path::new("."));
+        backup_root.join(timestamp.to_string())
+    }
+
+    /// Folder with files stored in snapshot `timestamp`. Files of an
+    /// archived snapshot are unpacked first.
+    fn files_root(&self, timestamp: &Timestamp) -> PathBuf {
+        let snapshot = self.location_of(timestamp);
+        if !archived::is_archived(&snapshot) {
+            return snapshot.join("files");
+        }
+        self.unpacked
+            .borrow_mut()
+            .entry(timestamp.clone())
+            .or_insert_with(|| match Unpacked::new(&snapshot) {
+                Ok(unpacked) => Some(unpacked),
+                Err(e) => {
+                    warn!("Cannot unpack files of snapshot {} ({})", timestamp, e);
+                    None
+                }
+            })
+            .as_ref()
+            .map_or_else(|| snapshot.join("files"), Unpacked::files)
+    }
+
+    fn get_entry_info(&self, timestamp: &Timestamp, path: &Path) -> EntryInfo {
+        let files_root = self.files_root(timestamp);
+        let data = Files::to_snapshot_path_unchecked(&files_root, path);
+
+        let metadata = data.symlink_metadata();
+        let (kind, size) = match &metadata {
+            Ok(meta) if meta.file_type().is_symlink() => (EntryKind::Symlink, meta.len()),
+            Ok(meta) if meta.is_dir() => (EntryKind::Directory, 0),
+            Ok(meta) => (EntryKind::File, meta.len()),
+            Err(_) => (EntryKind::Missing, 0),
+        };
+        let modified = metadata.and_then(|meta| meta.modified()).ok();
+        let chunked = match kind {
+            EntryKind::Missing => self.chunks_of(timestamp, path),
+            _ => None,
+        };
+        let (kind, size, chunks) = match chunked {
+            Some((chunks, size)) => (EntryKind::File, size, chunks),
+            None => (kind, size, vec![]),
+        };
+        let encoding = match kind == EntryKind::File && chunks.is_empty() {
+            true => self.encoding_of(timestamp),
+            false => Encoding::default(),
+        };
+        let decryption = encoding.encryption.map(|tool| Decryption {
+            tool,
+            key_file: self.config.key_file.clone(),
+            gpg_home: self.config.gpg_home.clone(),
+            passphrase: self.config.passphrase.clone(),
+        });
+
+        EntryInfo {
+            path: path.to_owned(),
+            stored_in: timestamp.to_string(),
+            data,
+            kind,
+            size,
+            modified,
+            special: None,
+            chunks,
+            attributes: Attributes::default(),
+            compressed: encoding.compressed,
+            decryption,
+        }
+    }
+}
+
+/// How files stored in a snapshot are transformed.
+#[derive(Clone, Copy, Default)]
+struct Encoding {
+    compressed: bool,
+    encryption: Option<EncryptionTool>,
+}
+
 impl Display for Snapshot {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Snapshot: {}", self.timestamp)?;
         writeln!(f, "  Index: {} entries", self.index.entries.len())?;
-        writeln!(f, "  Files: {} bytes", self.files.size())?;
+        writeln!(f, "  Files: {} bytes", self.size())?;
+        if let Some(metadata) = &self.metadata {
+            writeln!(f, "  Type: {}", metadata.kind())?;
+            writeln!(f, "  Made by: {}@{}", metadata.user, metadata.host)?;
+            let inputs: Vec<_> = metadata
+                .inputs
+                .iter()
+                .map(|i| i.display().to_string())
+                .collect();
+            writeln!(f, "  Inputs: {}", inputs.join(", "))?;
+        }
         Ok(())
     }
 }
@@ -319,12 +1884,147 @@ impl Debug for Snapshot {
 }
 
 struct SnapshotConfig {
-    base_index: Option<IndexPreview>,
+    base_index: Option<BaseIndex>,
+    /// Metadata of entries of the base snapshot.
+    files_cache: Option<FilesCache>,
+    skip_symlinks: bool,
+    max_file_size: Option<u64>,
+    memory_limit: Option<u64>, // in bytes
+    max_depth: Option<usize>,
+    exclude: Vec<Pattern>,
+    size_limit: Option<u64>, // in bytes
+    large_files: LargeFilesPolicy,
+    chunk_threshold: Option<u64>, // in bytes
+    compression: Option<FileCompression>,
+    encryption: Option<Encryption>,
+    key_file: Option<PathBuf>,
+    gpg_home: Option<Arc<GpgHome>>,
+    passphrase: Option<String>,
+    special_files: SpecialFilesPolicy,
+    preserve_attributes: bool,
+    checksum: bool,
+    fail_fast: bool,
+}
+
+/// What to do with files larger than the size limit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LargeFilesPolicy {
+    /// List them in metadata.txt and report them as warnings.
+    Record,
+    /// Leave them out of the snapshot without a trace.
+    Skip,
 }
 
 impl SnapshotConfig {
     fn default() -> Self {
-        Self { base_index: None }
+        Self {
+            base_index: None,
+            files_cache: None,
+            skip_symlinks: false,
+            max_file_size: None,
+            memory_limit: None,
+            max_depth: None,
+            exclude: vec![],
+            size_limit: None,
+            large_files: LargeFilesPolicy::Record,
+            chunk_threshold: None,
+            compression: None,
+            encryption: None,
+            key_file: None,
+            gpg_home: None,
+            passphrase: None,
+            special_files: SpecialFilesPolicy::Skip,
+            preserve_attributes: true,
+            checksum: false,
+            fail_fast: false,
+        }
+    }
+}
+
+/// Timestamp of the snapshot that stores `entry` and its content, if the
+/// entry didn't change since the base snapshot was made.
+/// Entries with the modification time recorded in the base index are
+/// unchanged when it and the size of a file are the same. Other entries,
+/// e.g. indexed by older versions or with `--no-preserve`, are compared
+/// with the time of the snapshot that stores them.
+fn find_unchanged(
+    base_index: &mut BaseIndex,
+    entry: &SourceEntry,
+) -> Option<(Timestamp, Option<Content>)> {
+    let (prev_timestamp, content, prev_modified) = base_index.find(&entry.index_path().ok()?)?;
+    let modif_system_time = entry.metadata.modified().ok()?;
+
+    let file_has_changed = match prev_modified {
+        Some(prev_modified) => {
+            let size_changed = match (&content, entry.metadata.is_file()) {
+                (Some(content), true) => content.size != entry.metadata.len(),
+                _ => false,
+            };
+            prev_modified != modif_system_time || size_changed
+        }
+        None => {
+            let margin = time::Duration::minutes(1);
+            let prev_timestamp_with_margin = prev_timestamp.clone() - margin;
+            let create_timestamp = Timestamp::from(entry.metadata.created().ok()?);
+            Timestamp::from(modif_system_time) > prev_timestamp_with_margin
+                || create_timestamp > prev_timestamp_with_margin
+        }
+    };
+    trace!(
+        "Entry \"{}\" (modif: {}) found in snapshot: {}, has_changed={}",
+        entry.path.display(),
+        Timestamp::from(modif_system_time),
+        prev_timestamp,
+        file_has_changed
+    );
+    if file_has_changed {
+        None
+    } else {
+        Some((prev_timestamp, content))
+    }
+}
+
+/// Files indexed without a checksum, e.g. by older versions, are copied.
+fn find_same_content(
+    base_index: &mut BaseIndex,
+    entry: &SourceEntry,
+) -> Option<(Timestamp, Option<Content>)> {
+    let (prev_timestamp, content, _) = base_index.find(&entry.index_path().ok()?)?;
+    let prev_content = content?;
+    if prev_content.size != entry.metadata.len() {
+        return None;
+    }
+    let current = Content::of_file(&entry.path).ok()?;
+    trace!(
+        "Entry \"{}\" found in snapshot: {}, same_content={}",
+        entry.path.display(),
+        prev_timestamp,
+        current == prev_content
+    );
+    match current == prev_content {
+        true => Some((prev_timestamp, Some(prev_content))),
+        false => None,
+    }
+}
+
+enum BaseIndex {
+    InMemory(IndexPreview),
+    /// Index of the base snapshot cached by the previous backup.
+    Cached(CachedIndex),
+    /// Used in bounded-memory mode.
+    Sorted(SortedIndex),
+}
+
+impl BaseIndex {
+    /// Entry must be an absolute path, as stored in the index. Returns
+    /// also its content and the modification time of its source.
+    fn find(&mut self, entry: &Path) -> Option<(Timestamp, Option<Content>, Option<SystemTime>)> {
+        let found = match self {
+            Self::InMemory(index) => index.find_with_content(entry),
+            Self::Cached(index) => index.find(entry),
+            Self::Sorted(index) => return index.find(entry),
+        };
+        found.map(|(timestamp, content, modified)| (timestamp.clone(), content.cloned(), modified))
     }
 }
 #[derive(Clone)]
@@ -343,7 +2043,8 @@ impl SnapshotPreview {
         let files = location.join("files");
 
         index.exists().then_some(())?;
-        files.exists().then_some(())?;
+        // Files of an archived snapshot are packed into a single file.
+        (files.exists() || archived::is_archived(location)).then_some(())?;
 
         Some(SnapshotPreview {
             location: location.to_owned(),
@@ -352,6 +2053,14 @@ impl SnapshotPreview {
             files,
         })
     }
+
+    pub fn name(&self) -> String {
+        self.timestamp.to_string()
+    }
+
+    pub fn is_from_the_future(&self) -> bool {
+        self.timestamp > Timestamp::now()
+    }
 }
 
 impl Display for SnapshotPreview {
@@ -385,12 +2094,13 @@ impl Ord for SnapshotPreview {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::environment::{FileSystem, RealFileSystem};
     use std::fs;
-    use tempfile;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn create_snapshot_in_nonexistent_folder() {
-        let result = Snapshot::create(Path::new("nonexistent"));
+        let result = Snapshot::create(Path::new("nonexistent"), &Environment::default());
 
         assert!(result.is_err());
         assert_eq!(
@@ -402,13 +2112,347 @@ mod tests {
     #[test]
     fn backup_invalid_path() {
         let root = tempfile::tempdir().unwrap();
-        let mut snapshot = Snapshot::create(root.path()).unwrap();
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
 
         snapshot.add_files_to_snapshot(Path::new("incorrect path"));
         let result = snapshot.save_index();
         assert!(result.is_ok());
+        assert_eq!(snapshot.warnings().len(), 1);
 
         let index_content = fs::read_to_string(snapshot.index.location).unwrap();
         assert!(index_content.is_empty());
     }
+
+    #[test]
+    fn skip_files_exceeding_destination_limits() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let big_file = files.path().join("big_file.txt");
+        fs::write(&big_file, b"hello world").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.config.max_file_size = Some(5);
+        snapshot.add_files_to_snapshot(files.path());
+
+        assert_eq!(snapshot.warnings().len(), 1);
+        assert_eq!(snapshot.index.entries.len(), 1); // only the folder
+        assert!(snapshot.warnings()[0].contains("big_file.txt"));
+    }
+
+    struct FixedClock(time::OffsetDateTime);
+    impl Clock for FixedClock {
+        fn now(&self) -> time::OffsetDateTime {
+            self.0
+        }
+    }
+
+    struct FailingFileSystem;
+    impl crate::environment::FileSystem for FailingFileSystem {
+        fn create_dir_all(&self, _: &Path) -> io::Result<()> {
+            Err(io::Error::other("disk is full"))
+        }
+        fn copy_file(&self, _: &Path, _: &Path) -> io::Result<u64> {
+            Err(io::Error::other("disk is full"))
+        }
+        fn read_link(&self, _: &Path) -> io::Result<PathBuf> {
+            Err(io::Error::other("disk is full"))
+        }
+        fn symlink(&self, _: &Path, _: &Path) -> io::Result<()> {
+            Err(io::Error::other("disk is full"))
+        }
+        fn hard_link(&self, _: &Path, _: &Path) -> io::Result<()> {
+            Err(io::Error::other("disk is full"))
+        }
+    }
+
+    #[test]
+    fn snapshot_is_loaded_only_when_committed() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        fs::write(files.path().join("file.txt"), b"hello world").unwrap();
+        let environment = Environment {
+            clock: std::sync::Arc::new(FixedClock(
+                time::OffsetDateTime::from_unix_timestamp(1893553440).unwrap(),
+            )),
+            ..Environment::default()
+        };
+
+        let mut snapshot = Snapshot::create(root.path(), &environment).unwrap();
+        snapshot.add_files_to_snapshot(files.path());
+        snapshot.save_index().unwrap();
+        assert_eq!(
+            snapshot.location(),
+            root.path().join("2030-01-02_03.04.partial")
+        );
+        assert!(get_latest_snapshot_preview(root.path()).is_none());
+        // The name is taken until the unfinished snapshot is committed
+        let other = Snapshot::create(root.path(), &environment).unwrap();
+        assert_eq!(other.name(), "2030-01-02_03.05");
+
+        snapshot.commit().unwrap();
+        assert!(!root.path().join("2030-01-02_03.04.partial").exists());
+        let latest = get_latest_snapshot_preview(root.path()).unwrap();
+        assert_eq!(latest.name(), "2030-01-02_03.04");
+        let file = files.path().canonicalize().unwrap().join("file.txt");
+        assert_eq!(snapshot.find_entry(&file).unwrap().kind, EntryKind::File);
+    }
+
+    #[test]
+    fn resumed_snapshot_reuses_matching_copies() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let files = files.path().canonicalize().unwrap();
+        fs::write(files.join("same.txt"), b"same").unwrap();
+        fs::write(files.join("changed.txt"), b"old").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.add_files_to_snapshot(&files);
+        let location = snapshot.location().to_owned();
+        drop(snapshot); // stopped before the index was saved
+        fs::write(files.join("changed.txt"), b"new data").unwrap();
+
+        let mut snapshot = Snapshot::resume(&location, &Environment::default()).unwrap();
+        snapshot.add_files_to_snapshot(&files);
+        snapshot.save_index().unwrap();
+        snapshot.commit().unwrap();
+
+        assert!(snapshot.warnings().is_empty());
+        assert_eq!(snapshot.index.entries.len(), 3);
+        let changed = snapshot.find_entry(&files.join("changed.txt")).unwrap();
+        assert_eq!(fs::read(changed.data).unwrap(), b"new data");
+        assert!(Snapshot::resume(&root.path().join("backup"), &Environment::default()).is_err());
+    }
+
+    #[test]
+    fn create_snapshot_with_skewed_clock() {
+        let root = tempfile::tempdir().unwrap();
+        let environment = Environment {
+            clock: std::sync::Arc::new(FixedClock(
+                time::OffsetDateTime::from_unix_timestamp(1893553440).unwrap(),
+            )),
+            ..Environment::default()
+        };
+
+        let snapshot = Snapshot::create(root.path(), &environment).unwrap();
+
+        assert_eq!(snapshot.name(), "2030-01-02_03.04");
+    }
+
+    #[test]
+    fn incremental_snapshot_in_bounded_memory_mode() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        for name in ["b.txt", "a.txt", "c.txt"] {
+            fs::write(files.path().join(name), name).unwrap();
+        }
+        let environment = Environment {
+            clock: std::sync::Arc::new(FixedClock(
+                time::OffsetDateTime::from_unix_timestamp(1893553440).unwrap(),
+            )),
+            ..Environment::default()
+        };
+        let mut base = Snapshot::create(root.path(), &environment).unwrap();
+        base.add_files_to_snapshot(files.path());
+        base.save_index().unwrap();
+        base.commit().unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &environment).unwrap();
+        snapshot.set_memory_limit(1).unwrap(); // sort base index in many runs
+        snapshot.set_base_snapshot(Some(&base.to_preview()));
+        snapshot.add_files_to_snapshot(files.path());
+        snapshot.save_index().unwrap();
+        snapshot.commit().unwrap();
+
+        assert!(snapshot.warnings().is_empty());
+        assert!(!snapshot.sort_location().exists());
+        let snapshot = Snapshot::open(&snapshot.location).unwrap();
+        assert_eq!(snapshot.entries().len(), 4);
+        assert!(snapshot.stored_entries().is_empty());
+    }
+
+    #[test]
+    fn report_io_failures_during_copying() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        fs::write(files.path().join("file.txt"), b"hello world").unwrap();
+        let environment = Environment {
+            fs: std::sync::Arc::new(FailingFileSystem),
+            ..Environment::default()
+        };
+
+        let mut snapshot = Snapshot::create(root.path(), &environment).unwrap();
+        snapshot.add_files_to_snapshot(files.path());
+
+        assert_eq!(snapshot.warnings().len(), 2); // folder and file
+        assert!(snapshot.index.entries.is_empty());
+    }
+
+    /// Appends to files while they are copied, the given number of times.
+    struct ChangingFileSystem(AtomicUsize);
+    impl FileSystem for ChangingFileSystem {
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFileSystem.create_dir_all(path)
+        }
+        fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+            let size = RealFileSystem.copy_file(from, to)?;
+            let changes = &self.0;
+            if changes.load(Ordering::SeqCst) > 0 {
+                changes.fetch_sub(1, Ordering::SeqCst);
+                let mut file = fs::OpenOptions::new().append(true).open(from)?;
+                io::Write::write_all(&mut file, b" and more")?;
+            }
+            Ok(size)
+        }
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            RealFileSystem.read_link(path)
+        }
+        fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFileSystem.symlink(original, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFileSystem.hard_link(original, link)
+        }
+    }
+
+    #[test]
+    fn file_changed_while_copied_is_copied_again() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let file = files.path().canonicalize().unwrap().join("app.log");
+        fs::write(&file, b"hello").unwrap();
+        let environment = Environment {
+            fs: std::sync::Arc::new(ChangingFileSystem(1.into())),
+            ..Environment::default()
+        };
+
+        let mut snapshot = Snapshot::create(root.path(), &environment).unwrap();
+        snapshot.add_files_to_snapshot(&file);
+
+        assert!(snapshot.warnings().is_empty());
+        assert!(snapshot.unstable.is_empty());
+        let entry = &snapshot.index.entries[0];
+        assert_eq!(entry.content, Some(Content::of_file(&file).unwrap()));
+        assert_eq!(snapshot.files.size(), "hello and more".len() as u64);
+    }
+
+    #[test]
+    fn file_changing_while_copied_is_flagged_as_unstable() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let file = files.path().canonicalize().unwrap().join("app.log");
+        fs::write(&file, b"hello").unwrap();
+        let environment = Environment {
+            fs: std::sync::Arc::new(ChangingFileSystem(usize::MAX.into())),
+            ..Environment::default()
+        };
+
+        let mut snapshot = Snapshot::create(root.path(), &environment).unwrap();
+        snapshot.add_files_to_snapshot(&file);
+
+        assert_eq!(snapshot.unstable, vec![file.clone()]);
+        assert_eq!(
+            snapshot.warnings(),
+            [format!("Changed while copied: \"{}\"", file.display())]
+        );
+        assert_eq!(snapshot.index.entries.len(), 1);
+    }
+
+    #[test]
+    fn list_entries_of_incremental_snapshot() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let files = files.path().canonicalize().unwrap();
+        let old_file = files.join("old.txt");
+        let new_file = files.join("new.txt");
+        fs::write(&old_file, b"old").unwrap();
+        fs::write(&new_file, b"new data").unwrap();
+
+        // old.txt is stored in an older snapshot
+        let old_snapshot = root.path().join("2021-07-15_18.34");
+        let old_data = Files::to_snapshot_path_unchecked(&old_snapshot.join("files"), &old_file);
+        fs::create_dir_all(old_data.parent().unwrap()).unwrap();
+        fs::write(&old_data, b"old").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.copy_and_index_entry(&SourceEntry::read(&files).unwrap());
+        snapshot.copy_and_index_entry(&SourceEntry::read(&new_file).unwrap());
+        snapshot.index_entry(
+            Timestamp::parse_from("2021-07-15_18.34").unwrap(),
+            None,
+            vec![],
+            &SourceEntry::read(&old_file).unwrap(),
+        );
+
+        let roots = snapshot.list_entries(None).unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0].path, files);
+        assert_eq!(roots[0].kind, EntryKind::Directory);
+
+        let entries = snapshot.list_entries(Some(&files)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].kind, EntryKind::File);
+        assert_eq!(entries[0].size, 8);
+        assert_eq!(entries[0].stored_in, snapshot.name());
+        assert_eq!(entries[1].stored_in, "2021-07-15_18.34");
+        assert_eq!(entries[1].size, 3);
+        assert_eq!(entries[1].data, old_data);
+        assert!(snapshot.list_entries(Some(&old_data)).is_none());
+    }
+
+    #[test]
+    fn find_entry_stored_in_another_snapshot() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file.txt");
+        fs::write(&file, b"file").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.index_entry(
+            Timestamp::parse_from("2021-07-15_18.34").unwrap(),
+            None,
+            vec![],
+            &SourceEntry::read(&file).unwrap(),
+        );
+
+        let entry = snapshot.find_entry(&file).unwrap();
+        let expected_data =
+            Files::to_snapshot_path_unchecked(&root.path().join("2021-07-15_18.34/files"), &file);
+        assert_eq!(entry.stored_in, "2021-07-15_18.34");
+        assert_eq!(entry.data, expected_data);
+        assert_eq!(entry.kind, EntryKind::Missing);
+        assert!(snapshot
+            .find_entry(&root.path().join("other.txt"))
+            .is_none());
+    }
+
+    #[test]
+    fn save_and_read_metadata() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file.txt");
+        fs::write(&file, b"file").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        assert!(snapshot.metadata().is_none());
+        snapshot.copy_and_index_entry(&SourceEntry::read(&file).unwrap());
+        snapshot.index_entry(
+            Timestamp::parse_from("2021-07-15_18.34").unwrap(),
+            None,
+            vec![],
+            &SourceEntry::read(&file).unwrap(),
+        );
+        snapshot
+            .save_metadata(
+                std::slice::from_ref(&file),
+                Duration::from_millis(1500),
+                None,
+            )
+            .unwrap();
+
+        let metadata = snapshot.metadata().unwrap();
+        assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata.duration, Duration::from_millis(1500));
+        assert_eq!(metadata.inputs, vec![file]);
+        assert_eq!(metadata.indexed, 2);
+        assert_eq!(metadata.copied, 1);
+        assert_eq!(metadata.size, 4);
+    }
 }
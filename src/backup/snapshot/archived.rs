@@ -0,0 +1,157 @@
+//! Snapshots with files packed into a single `files.tar.zst` instead of a
+//! tree of loose files, which are slow to store and list on NTFS or network
+//! shares. The archive contains the `files` folder as it would be stored
+//! loose. Data of an archived snapshot is read from a copy of the folder
+//! unpacked into a temporary folder, removed when it's no longer needed.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::{debug, warn};
+
+pub const ARCHIVE_FILE_NAME: &str = "files.tar.zst";
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// How files of a new snapshot are stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapshotFormat {
+    /// Every file is stored as a file in the `files` folder.
+    Loose,
+    /// The `files` folder is packed into `files.tar.zst`.
+    Archive,
+}
+
+pub fn is_archived(snapshot: &Path) -> bool {
+    !snapshot.join("files").exists() && snapshot.join(ARCHIVE_FILE_NAME).is_file()
+}
+
+/// Packs the `files` folder of a snapshot into an archive and removes the
+/// folder. The archive is written under a temporary name first, so the
+/// folder is removed only when the archive is complete.
+pub fn pack(snapshot: &Path) -> io::Result<()> {
+    let archive = snapshot.join(ARCHIVE_FILE_NAME);
+    let temporary = archive.with_extension("zst.partial");
+    debug!(
+        "Packing files of {} into {}",
+        snapshot.display(),
+        ARCHIVE_FILE_NAME
+    );
+    let encoder = zstd::Encoder::new(BufWriter::new(File::create(&temporary)?), COMPRESSION_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    builder.append_dir_all("files", snapshot.join("files"))?;
+    builder.into_inner()?.finish()?.into_inner()?.sync_all()?;
+    fs::rename(&temporary, &archive)?;
+    fs::remove_dir_all(snapshot.join("files"))
+}
+
+/// Paths of entries in the archive of a snapshot, as they would be stored
+/// in the snapshot folder, together with whether they are folders.
+pub fn list(snapshot: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    let mut archive = open(snapshot)?;
+    let mut entries = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let is_dir = entry.header().entry_type().is_dir();
+        entries.push((snapshot.join(entry.path()?), is_dir));
+    }
+    Ok(entries)
+}
+
+fn open(snapshot: &Path) -> io::Result<tar::Archive<impl io::Read>> {
+    let file = File::open(snapshot.join(ARCHIVE_FILE_NAME))?;
+    Ok(tar::Archive::new(zstd::Decoder::new(BufReader::new(file))?))
+}
+
+/// Copy of the `files` folder of an archived snapshot, or of any other
+/// unpacked archive, in a temporary folder removed when dropped.
+pub struct Unpacked {
+    folder: PathBuf,
+}
+
+impl Unpacked {
+    pub fn new(snapshot: &Path) -> io::Result<Self> {
+        let name = snapshot.file_name().unwrap_or_default().to_string_lossy();
+        let unpacked = Self::empty(&name)?;
+        debug!(
+            "Unpacking {} into {}",
+            snapshot.display(),
+            unpacked.folder.display()
+        );
+        open(snapshot)?.unpack(&unpacked.folder)?;
+        Ok(unpacked)
+    }
+
+    /// Empty folder to unpack an archive into, with `name` in its name.
+    pub fn empty(name: &str) -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let folder = std::env::temp_dir().join(format!(
+            "mizeria-{}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        create_private_dir(&folder)?;
+        Ok(Self { folder })
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    pub fn files(&self) -> PathBuf {
+        self.folder.join("files")
+    }
+}
+
+impl Drop for Unpacked {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.folder) {
+            warn!("Cannot remove {} ({})", self.folder.display(), e);
+        }
+    }
+}
+
+/// Unpacked files are accessible only by the owner, like the snapshot.
+#[cfg(unix)]
+pub(super) fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+pub(super) fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_list_and_unpack_files() {
+        let root = tempfile::tempdir().unwrap();
+        let snapshot = root.path().join("2021-07-15_18.34");
+        fs::create_dir_all(snapshot.join("files").join("home")).unwrap();
+        fs::write(snapshot.join("files").join("home").join("a.txt"), "a").unwrap();
+
+        pack(&snapshot).unwrap();
+
+        assert!(is_archived(&snapshot));
+        let entries = list(&snapshot).unwrap();
+        assert!(entries.contains(&(snapshot.join("files").join("home"), true)));
+        assert!(entries.contains(&(snapshot.join("files").join("home").join("a.txt"), false)));
+
+        let unpacked = Unpacked::new(&snapshot).unwrap();
+        let folder = unpacked.files();
+        assert_eq!(
+            fs::read_to_string(folder.join("home").join("a.txt")).unwrap(),
+            "a"
+        );
+        drop(unpacked);
+        assert!(!folder.exists());
+    }
+}
@@ -0,0 +1,170 @@
+//! Permissions, owner and modification time of backed up entries. They are
+//! written in the index, because copies in a snapshot have permissions of
+//! the snapshot and folders are created anew, and applied again on restore.
+
+use std::fmt::Display;
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::EntryKind;
+
+/// Written in the index before the path as `mode:<octal>`,
+/// `owner:<uid>:<gid>` and `mtime:<seconds>.<nanoseconds>`. Each of them
+/// is optional: permissions and owner are recorded only on Unix.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Attributes {
+    pub mode: Option<u32>,
+    pub owner: Option<(u32, u32)>,
+    pub modified: Option<SystemTime>,
+}
+
+impl Attributes {
+    pub fn of(metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .filter(|time| time.duration_since(UNIX_EPOCH).is_ok());
+        Self {
+            modified,
+            ..Self::unix(metadata)
+        }
+    }
+
+    #[cfg(unix)]
+    fn unix(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            mode: Some(metadata.mode() & 0o7777),
+            owner: Some((metadata.uid(), metadata.gid())),
+            modified: None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn unix(_metadata: &Metadata) -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parses one field of an index line into these attributes. Returns
+    /// `None` if the field isn't one of them.
+    pub fn parse_field(&mut self, field: &str) -> Option<()> {
+        let (name, value) = field.split_once(':')?;
+        match name {
+            "mode" => self.mode = Some(u32::from_str_radix(value, 8).ok()?),
+            "owner" => {
+                let (uid, gid) = value.split_once(':')?;
+                self.owner = Some((uid.parse().ok()?, gid.parse().ok()?));
+            }
+            "mtime" => {
+                let (seconds, nanos) = value.split_once('.')?;
+                let since_epoch = Duration::new(seconds.parse().ok()?, nanos.parse().ok()?);
+                self.modified = Some(UNIX_EPOCH.checked_add(since_epoch)?);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Applies the attributes to a restored entry. Changing the owner
+    /// requires privileges, so it is skipped when it isn't permitted.
+    /// Special files would block when opened, so their modification time
+    /// isn't set.
+    pub fn apply(&self, path: &Path, kind: EntryKind) -> io::Result<()> {
+        #[cfg(unix)]
+        if let Some((uid, gid)) = self.owner {
+            if let Err(e) = std::os::unix::fs::lchown(path, Some(uid), Some(gid)) {
+                tracing::debug!("Cannot change owner of {} ({})", path.display(), e);
+            }
+        }
+        if kind == EntryKind::Symlink {
+            return Ok(());
+        }
+        match self.modified {
+            Some(modified) if kind != EntryKind::Special => {
+                set_modified(path, modified, kind == EntryKind::Directory)?
+            }
+            _ => (),
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_modified(path: &Path, modified: SystemTime, directory: bool) -> io::Result<()> {
+    let file = match directory {
+        true => File::open(path)?,
+        false => File::options().write(true).open(path)?,
+    };
+    file.set_modified(modified)
+}
+
+/// Folders can't be opened as files on other platforms.
+#[cfg(not(unix))]
+fn set_modified(path: &Path, modified: SystemTime, directory: bool) -> io::Result<()> {
+    if directory {
+        return Ok(());
+    }
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)
+}
+
+impl Display for Attributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut fields = vec![];
+        if let Some(mode) = self.mode {
+            fields.push(format!("mode:{:o}", mode));
+        }
+        if let Some((uid, gid)) = self.owner {
+            fields.push(format!("owner:{}:{}", uid, gid));
+        }
+        if let Some(modified) = self.modified {
+            let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+            fields.push(format!(
+                "mtime:{}.{:09}",
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            ));
+        }
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_attributes() {
+        let mut attributes = Attributes::default();
+        for field in ["mode:644", "owner:1000:100", "mtime:1626370440.000000500"] {
+            assert!(attributes.parse_field(field).is_some());
+        }
+        assert!(attributes.parse_field("link:1").is_none());
+        assert!(attributes.parse_field("mode:9").is_none());
+
+        assert_eq!(attributes.mode, Some(0o644));
+        assert_eq!(attributes.owner, Some((1000, 100)));
+        assert_eq!(
+            attributes.modified,
+            Some(UNIX_EPOCH + Duration::new(1626370440, 500))
+        );
+        assert_eq!(
+            attributes.to_string(),
+            "mode:644 owner:1000:100 mtime:1626370440.000000500"
+        );
+    }
+}
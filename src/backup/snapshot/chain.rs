@@ -0,0 +1,65 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use tracing::trace;
+
+use super::index::IndexPreview;
+use super::timestamp::Timestamp;
+use super::{EntryInfo, EntryKind, Snapshot};
+
+impl Snapshot {
+    /// All indexed entries with their data resolved across the incremental
+    /// chain. An entry referencing a snapshot that doesn't store its data,
+    /// e.g. because it was indexed there from an even older snapshot, is
+    /// followed through the index of that snapshot until the data is found.
+    pub fn resolve_entries(&self) -> Vec<EntryInfo> {
+        let mut indexes = BTreeMap::new();
+        self.index
+            .entries
+            .iter()
+            .map(|e| match e.special {
+                Some(_) => self.entry_info(e),
+                None => self
+                    .resolve_entry(&e.timestamp, &e.path, &mut indexes)
+                    .indexed_as(e),
+            })
+            .collect()
+    }
+
+    fn resolve_entry(
+        &self,
+        timestamp: &Timestamp,
+        path: &Path,
+        indexes: &mut BTreeMap<Timestamp, Option<IndexPreview>>,
+    ) -> EntryInfo {
+        let mut visited = BTreeSet::new();
+        let mut current = timestamp.clone();
+        loop {
+            let entry = self.get_entry_info(&current, path);
+            if entry.kind != EntryKind::Missing || !visited.insert(current.clone()) {
+                return entry;
+            }
+            let next = indexes
+                .entry(current.clone())
+                .or_insert_with(|| {
+                    let index = self.location_of(&current).join("index.txt");
+                    IndexPreview::open(&index).ok()
+                })
+                .as_ref()
+                .and_then(|index| index.find(path))
+                .cloned();
+            match next {
+                Some(next) if next != current => {
+                    trace!(
+                        "Data of {} is not in {}, following to {}",
+                        path.display(),
+                        current,
+                        next
+                    );
+                    current = next;
+                }
+                _ => return entry,
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tracing::trace;
+use walkdir::WalkDir;
+
+use super::archived::ARCHIVE_FILE_NAME;
+use super::chunks::CHUNKS_FOLDER_NAME;
+use crate::parallel;
+
+pub const MANIFEST_FILE_NAME: &str = "checksums.sha256";
+
+/// Writes checksums of all files stored in a snapshot into the snapshot.
+/// Returns the path of the manifest.
+pub fn save_manifest(snapshot: &Path) -> io::Result<PathBuf> {
+    let manifest = snapshot.join(MANIFEST_FILE_NAME);
+    write_manifest(snapshot, &manifest)?;
+    Ok(manifest)
+}
+
+/// Writes SHA-256 checksums of all files stored in a snapshot in the format
+/// of `sha256sum`, so they can be verified with `sha256sum -c` run inside
+/// the snapshot folder. Returns the number of files.
+pub fn write_manifest(snapshot: &Path, manifest: &Path) -> io::Result<usize> {
+    let mut writer = BufWriter::new(File::create(manifest)?);
+
+    let mut files = vec![];
+    // An archived snapshot has the archive instead of the files folder.
+    let stored = ["files", ARCHIVE_FILE_NAME, CHUNKS_FOLDER_NAME]
+        .iter()
+        .map(|name| snapshot.join(name))
+        .filter(|path| path.exists());
+    for folder in stored {
+        for entry in WalkDir::new(folder).follow_links(false).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    let checksums = parallel::map(files, |path| {
+        let checksum = sha256_file(&path);
+        (path, checksum)
+    });
+    let count = checksums.len();
+    for (path, checksum) in checksums {
+        let checksum = checksum?;
+        let relative_path = path.strip_prefix(snapshot).unwrap_or(&path);
+        trace!("Checksum of {}: {}", relative_path.display(), checksum);
+        writeln!(writer, "{}  {}", checksum, to_manifest_path(relative_path))?;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Compares checksums from the manifest with files stored in a snapshot.
+/// Returns paths of files that are missing or don't match, relative to
+/// the snapshot folder.
+pub fn verify_manifest(snapshot: &Path) -> io::Result<Vec<PathBuf>> {
+    let manifest = File::open(snapshot.join(MANIFEST_FILE_NAME))?;
+    let mut expected = vec![];
+    for line in BufReader::new(manifest).lines() {
+        let line = line?;
+        let (checksum, path) = line.split_once("  ").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid line in manifest")
+        })?;
+        expected.push((checksum.to_string(), PathBuf::from(path)));
+    }
+    let mismatched = parallel::map(expected, |(checksum, path)| {
+        match sha256_file(&snapshot.join(&path)) {
+            Ok(actual) if actual == checksum => None,
+            _ => Some(path),
+        }
+    });
+    Ok(mismatched.into_iter().flatten().collect())
+}
+
+/// Single checksum of everything in a snapshot folder: names, contents
+/// of files and targets of symlinks. Any change to the snapshot changes it.
+pub fn fingerprint(snapshot: &Path) -> io::Result<String> {
+    let entries = WalkDir::new(snapshot)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .collect::<walkdir::Result<Vec<_>>>()?;
+    let records = parallel::map(entries, |entry| -> io::Result<String> {
+        let relative_path = entry.path().strip_prefix(snapshot).unwrap_or(entry.path());
+        let mut record = to_manifest_path(relative_path);
+        record.push('\0');
+        if entry.file_type().is_file() {
+            record.push_str(&sha256_file(entry.path())?);
+        } else if entry.file_type().is_symlink() {
+            let target = std::fs::read_link(entry.path())?;
+            record.push_str(&target.to_string_lossy());
+        }
+        record.push('\n');
+        Ok(record)
+    });
+
+    let mut hasher = Sha256::new();
+    for record in records {
+        hasher.update(record?.as_bytes());
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Checksum of files that describe a snapshot: the index, metadata and
+/// the manifest if the snapshot is signed. Metadata of every snapshot holds
+/// this checksum of the previous one, so the snapshots form a hash chain.
+pub fn chain_hash(snapshot: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    for name in ["index.txt", "metadata.txt", MANIFEST_FILE_NAME] {
+        let path = snapshot.join(name);
+        if name != "index.txt" && !path.exists() {
+            continue;
+        }
+        hasher.update(name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(sha256_file(&path)?.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `sha256sum` expects forward slashes on every platform.
+pub(super) fn to_manifest_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sha256_of_file() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file.txt");
+        fs::write(&file, b"hello world").unwrap();
+
+        assert_eq!(
+            sha256_file(&file).unwrap(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+
+    #[test]
+    fn manifest_lists_stored_files() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let folder = snapshot.path().join("files").join("folder");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("b.txt"), b"hello world").unwrap();
+        fs::write(folder.join("a.txt"), b"").unwrap();
+
+        let manifest = save_manifest(snapshot.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(manifest).unwrap(),
+            concat!(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  files/folder/a.txt\n",
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  files/folder/b.txt\n"
+            )
+        );
+    }
+
+    #[test]
+    fn verify_manifest_finds_modified_files() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let folder = snapshot.path().join("files").join("folder");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("a.txt"), b"a").unwrap();
+        fs::write(folder.join("b.txt"), b"b").unwrap();
+        fs::write(folder.join("c.txt"), b"c").unwrap();
+        save_manifest(snapshot.path()).unwrap();
+
+        assert!(verify_manifest(snapshot.path()).unwrap().is_empty());
+
+        fs::write(folder.join("a.txt"), b"modified").unwrap();
+        fs::remove_file(folder.join("c.txt")).unwrap();
+
+        assert_eq!(
+            verify_manifest(snapshot.path()).unwrap(),
+            vec![
+                PathBuf::from("files/folder/a.txt"),
+                PathBuf::from("files/folder/c.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn fingerprint_changes_with_snapshot() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let folder = snapshot.path().join("files").join("folder");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(snapshot.path().join("index.txt"), b"index").unwrap();
+        fs::write(folder.join("a.txt"), b"a").unwrap();
+
+        let original = fingerprint(snapshot.path()).unwrap();
+        assert_eq!(fingerprint(snapshot.path()).unwrap(), original);
+
+        fs::write(folder.join("a.txt"), b"b").unwrap();
+        let modified = fingerprint(snapshot.path()).unwrap();
+        assert_ne!(modified, original);
+
+        fs::rename(folder.join("a.txt"), folder.join("b.txt")).unwrap();
+        assert_ne!(fingerprint(snapshot.path()).unwrap(), modified);
+    }
+
+    #[test]
+    fn chain_hash_covers_index_and_metadata() {
+        let snapshot = tempfile::tempdir().unwrap();
+        assert!(chain_hash(snapshot.path()).is_err());
+
+        fs::write(snapshot.path().join("index.txt"), b"index").unwrap();
+        let without_metadata = chain_hash(snapshot.path()).unwrap();
+
+        fs::write(snapshot.path().join("metadata.txt"), b"metadata").unwrap();
+        let with_metadata = chain_hash(snapshot.path()).unwrap();
+        assert_ne!(with_metadata, without_metadata);
+
+        fs::create_dir(snapshot.path().join("files")).unwrap();
+        fs::write(snapshot.path().join("files").join("a.txt"), b"a").unwrap();
+        assert_eq!(chain_hash(snapshot.path()).unwrap(), with_metadata);
+
+        fs::write(snapshot.path().join("index.txt"), b"modified").unwrap();
+        assert_ne!(chain_hash(snapshot.path()).unwrap(), with_metadata);
+    }
+}
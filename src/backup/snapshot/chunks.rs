@@ -0,0 +1,407 @@
+//! Content-defined chunking of large files. Such files are split into
+//! chunks at positions that depend on their content, so a change in one
+//! place of the file changes only the chunks around it. Every chunk is
+//! stored once in the `chunks` folder of the snapshot that first needed it,
+//! named by its SHA-256 checksum, and the index lists the chunks of a file.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace};
+use walkdir::WalkDir;
+
+use super::checksums::to_hex;
+use super::index::{Content, Index, IndexEntry};
+use super::timestamp::Timestamp;
+use super::Snapshot;
+use crate::parallel;
+
+pub const CHUNKS_FOLDER_NAME: &str = "chunks";
+
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// A chunk ends where the top 20 bits of the rolling hash are zero, which
+/// makes chunks about 1 MiB long on average.
+const BOUNDARY_BITS: u32 = 20;
+
+/// Random values for every byte, generated with SplitMix64, so chunk
+/// boundaries are the same in every version of mizeria.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits data read from `reader` into chunks and passes them to `chunk`.
+pub fn split(
+    mut reader: impl Read,
+    mut chunk: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buffer = vec![0; 64 * 1024];
+    let mut current = Vec::with_capacity(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut start = 0;
+        for (i, byte) in buffer[..read].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let length = current.len() + i + 1 - start;
+            let boundary = length >= MIN_CHUNK_SIZE && hash >> (64 - BOUNDARY_BITS) == 0;
+            if boundary || length == MAX_CHUNK_SIZE {
+                current.extend_from_slice(&buffer[start..=i]);
+                chunk(&current)?;
+                current.clear();
+                hash = 0;
+                start = i + 1;
+            }
+        }
+        current.extend_from_slice(&buffer[start..read]);
+    }
+    if !current.is_empty() {
+        chunk(&current)?;
+    }
+    Ok(())
+}
+
+/// Chunk of a file, written in the index as `<snapshot>/<checksum>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChunkRef {
+    /// Snapshot that stores the chunk.
+    pub snapshot: Timestamp,
+    pub sha256: [u8; 32],
+}
+
+impl ChunkRef {
+    fn parse(field: &str) -> Option<Self> {
+        let (snapshot, checksum) = field.split_once('/')?;
+        Some(Self {
+            snapshot: Timestamp::parse_from(snapshot)?,
+            sha256: parse_sha256(checksum)?,
+        })
+    }
+
+    /// Location of the chunk in the backup folder.
+    pub fn location(&self, backup: &Path) -> PathBuf {
+        chunk_location(&backup.join(self.snapshot.to_string()), &self.sha256)
+    }
+}
+
+impl Display for ChunkRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.snapshot, to_hex(&self.sha256))
+    }
+}
+
+/// Parses the `chunks:<chunk>,<chunk>...` field of an index line.
+pub fn parse_chunk_list(field: &str) -> Option<Vec<ChunkRef>> {
+    field
+        .strip_prefix("chunks:")?
+        .split(',')
+        .map(ChunkRef::parse)
+        .collect()
+}
+
+pub fn format_chunk_list(chunks: &[ChunkRef]) -> String {
+    let chunks: Vec<String> = chunks.iter().map(ChunkRef::to_string).collect();
+    format!("chunks:{}", chunks.join(","))
+}
+
+fn chunk_location(snapshot: &Path, sha256: &[u8; 32]) -> PathBuf {
+    let name = to_hex(sha256);
+    snapshot
+        .join(CHUNKS_FOLDER_NAME)
+        .join(&name[..2])
+        .join(name)
+}
+
+/// Chunks and sizes of chunked files by snapshot and path.
+pub type ChunkLists = BTreeMap<Timestamp, HashMap<PathBuf, (Vec<ChunkRef>, u64)>>;
+
+/// Chunks stored in all snapshots of a backup, so a new snapshot stores
+/// only chunks that aren't stored anywhere yet.
+pub struct ChunkStore {
+    snapshot: PathBuf,
+    timestamp: Timestamp,
+    known: HashMap<[u8; 32], Timestamp, ahash::RandomState>,
+}
+
+impl ChunkStore {
+    pub fn open(snapshot: &Path, timestamp: &Timestamp) -> Self {
+        let backup = snapshot.parent().unwrap_or_else(|| Path::new("."));
+        let mut known = HashMap::default();
+        for folder in fs::read_dir(backup).into_iter().flatten().flatten() {
+            let name = folder.file_name();
+            let stored_in = match name.to_str().and_then(Timestamp::parse_from) {
+                Some(stored_in) => stored_in,
+                None => continue,
+            };
+            for chunk in stored_chunks(&folder.path()) {
+                if let Some(sha256) = chunk_checksum(&chunk) {
+                    known.entry(sha256).or_insert_with(|| stored_in.clone());
+                }
+            }
+        }
+        debug!("Found {} chunks stored in the backup", known.len());
+        Self {
+            snapshot: snapshot.to_owned(),
+            timestamp: timestamp.clone(),
+            known,
+        }
+    }
+
+    /// Splits a file into chunks and stores those that aren't known yet.
+    /// Returns the content of the whole file, its chunks and the number of
+    /// bytes that were stored.
+    pub fn store_file(&mut self, path: &Path) -> io::Result<(Content, Vec<ChunkRef>, u64)> {
+        let mut hasher = Sha256::new();
+        let mut chunks = vec![];
+        let mut size = 0;
+        let mut stored = 0;
+        split(File::open(path)?, |chunk| {
+            hasher.update(chunk);
+            size += chunk.len() as u64;
+            let sha256: [u8; 32] = Sha256::digest(chunk).into();
+            let snapshot = match self.known.get(&sha256) {
+                Some(snapshot) => snapshot.clone(),
+                None => {
+                    self.write_chunk(&sha256, chunk)?;
+                    stored += chunk.len() as u64;
+                    self.known.insert(sha256, self.timestamp.clone());
+                    self.timestamp.clone()
+                }
+            };
+            chunks.push(ChunkRef { snapshot, sha256 });
+            Ok(())
+        })?;
+        let content = Content {
+            size,
+            sha256: hasher.finalize().into(),
+        };
+        Ok((content, chunks, stored))
+    }
+
+    /// Chunks are written under a temporary name first, so an interrupted
+    /// backup doesn't leave a partial chunk that looks complete.
+    fn write_chunk(&self, sha256: &[u8; 32], chunk: &[u8]) -> io::Result<()> {
+        let location = chunk_location(&self.snapshot, sha256);
+        trace!("Storing chunk {}", location.display());
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = location.with_extension("tmp");
+        fs::write(&temporary, chunk)?;
+        fs::rename(temporary, location)
+    }
+}
+
+fn stored_chunks(snapshot: &Path) -> Vec<PathBuf> {
+    WalkDir::new(snapshot.join(CHUNKS_FOLDER_NAME))
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Chunks are named by their checksums.
+fn chunk_checksum(chunk: &Path) -> Option<[u8; 32]> {
+    parse_sha256(chunk.file_name()?.to_str()?)
+}
+
+fn parse_sha256(checksum: &str) -> Option<[u8; 32]> {
+    if checksum.len() != 64 {
+        return None;
+    }
+    let mut sha256 = [0; 32];
+    for (i, byte) in sha256.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(checksum.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha256)
+}
+
+/// Reads chunks of a file one after another.
+pub struct ChunkReader {
+    chunks: VecDeque<PathBuf>,
+    current: Option<File>,
+}
+
+impl ChunkReader {
+    pub fn new(chunks: &[PathBuf]) -> Self {
+        Self {
+            chunks: chunks.iter().cloned().collect(),
+            current: None,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(current) = &mut self.current {
+                let read = current.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+            match self.chunks.pop_front() {
+                Some(chunk) => self.current = Some(File::open(chunk)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Locations of chunks and the size of a file that was chunked in
+    /// snapshot `timestamp`, according to its index.
+    pub(super) fn chunks_of(
+        &self,
+        timestamp: &Timestamp,
+        path: &Path,
+    ) -> Option<(Vec<PathBuf>, u64)> {
+        let backup = self.location.parent().unwrap_or_else(|| Path::new("."));
+        let collect = |entries: &[IndexEntry]| {
+            entries
+                .iter()
+                .filter(|e| !e.chunks.is_empty())
+                .map(|e| {
+                    let size = e.content.as_ref().map_or(0, |c| c.size);
+                    (e.path.clone(), (e.chunks.clone(), size))
+                })
+                .collect()
+        };
+        let mut lists = self.chunk_lists.borrow_mut();
+        let list = lists.entry(timestamp.clone()).or_insert_with(|| {
+            // A snapshot opened without its index reads it from the disk.
+            if *timestamp == self.timestamp && !self.index.entries.is_empty() {
+                return collect(&self.index.entries);
+            }
+            let index = backup.join(timestamp.to_string()).join("index.txt");
+            match Index::open(index) {
+                Ok(index) => collect(&index.entries),
+                Err(_) => HashMap::new(),
+            }
+        });
+        let (chunks, size) = list.get(path)?;
+        Some((chunks.iter().map(|c| c.location(backup)).collect(), *size))
+    }
+
+    /// Hashes chunks stored in this snapshot again and returns those that
+    /// don't match their names.
+    pub fn find_corrupted_chunks(&self) -> Vec<PathBuf> {
+        let chunks = stored_chunks(&self.location);
+        debug!("Verifying {} chunk(s)", chunks.len());
+        let corrupted = parallel::map(chunks, |chunk| {
+            let actual = Content::of_file(&chunk).ok().map(|c| c.sha256);
+            match actual.is_some() && actual == chunk_checksum(&chunk) {
+                true => None,
+                false => Some(chunk),
+            }
+        });
+        corrupted.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(size: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunk_sizes(data: &[u8]) -> Vec<usize> {
+        let mut sizes = vec![];
+        split(data, |chunk| {
+            sizes.push(chunk.len());
+            Ok(())
+        })
+        .unwrap();
+        sizes
+    }
+
+    #[test]
+    fn chunks_are_within_limits() {
+        let data = pseudo_random(10 * 1024 * 1024, 1);
+
+        let sizes = chunk_sizes(&data);
+
+        assert_eq!(sizes.iter().sum::<usize>(), data.len());
+        assert!(sizes.len() > 2);
+        let (last, rest) = sizes.split_last().unwrap();
+        assert!(rest
+            .iter()
+            .all(|size| (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(size)));
+        assert!(*last <= MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn change_in_the_middle_keeps_other_chunks() {
+        let data = pseudo_random(10 * 1024 * 1024, 2);
+        let mut changed = data.clone();
+        changed[5 * 1024 * 1024] ^= 0xff;
+
+        let checksums = |data: &[u8]| {
+            let mut checksums = vec![];
+            split(data, |chunk| {
+                checksums.push(Sha256::digest(chunk));
+                Ok(())
+            })
+            .unwrap();
+            checksums
+        };
+        let before = checksums(&data);
+        let after = checksums(&changed);
+
+        let kept = after.iter().filter(|c| before.contains(c)).count();
+        assert!(kept >= after.len() - 2);
+    }
+
+    #[test]
+    fn parse_and_display_chunk_list() {
+        let field = format!(
+            "chunks:2021-07-15_18.34/{},2021-07-16_18.34/{}",
+            "ab".repeat(32),
+            "01".repeat(32)
+        );
+
+        let chunks = parse_chunk_list(&field).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].sha256, [0xab; 32]);
+        assert_eq!(chunks[1].snapshot.to_string(), "2021-07-16_18.34");
+        assert_eq!(format_chunk_list(&chunks), field);
+        assert!(parse_chunk_list("chunks:2021-07-15_18.34/ab").is_none());
+        assert!(parse_chunk_list("link:1").is_none());
+    }
+}
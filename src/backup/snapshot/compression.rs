@@ -0,0 +1,144 @@
+//! Compression of files stored in a snapshot. Files of a compressed snapshot
+//! are stored in `files` under their original names, each as a zstd frame,
+//! and `metadata.txt` records the compression, so readers know that the
+//! data has to be decompressed.
+
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use super::encryption::{open_encrypted, Decryption};
+use super::index::Content;
+
+const DEFAULT_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileCompression {
+    pub level: i32,
+}
+
+impl FileCompression {
+    /// Parses `zstd` or `zstd:<level>`, with the level from 1 to 22.
+    pub fn parse(value: &str) -> Option<Self> {
+        let level = match value.split_once(':') {
+            Some(("zstd", level)) => level.parse().ok()?,
+            None if value == "zstd" => DEFAULT_LEVEL,
+            _ => return None,
+        };
+        (1..=22).contains(&level).then_some(Self { level })
+    }
+
+    /// Writes `source` compressed into `destination`. Returns the content of
+    /// the source and the number of bytes written.
+    pub fn compress_file(&self, source: &Path, destination: &Path) -> io::Result<(Content, u64)> {
+        let mut hasher = Sha256::new();
+        let mut encoder = zstd::Encoder::new(File::create(destination)?, self.level)?;
+        let size = io::copy(
+            &mut Tee {
+                reader: File::open(source)?,
+                hasher: &mut hasher,
+            },
+            &mut encoder,
+        )?;
+        encoder.finish()?;
+        let content = Content {
+            size,
+            sha256: hasher.finalize().into(),
+        };
+        Ok((content, fs::metadata(destination)?.len()))
+    }
+
+    /// Replaces an uncompressed file with its compressed version.
+    pub fn compress_in_place(&self, path: &Path) -> io::Result<()> {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".zst");
+        let temporary = path.with_file_name(name);
+        self.compress_file(path, &temporary)?;
+        fs::rename(temporary, path)
+    }
+}
+
+impl Display for FileCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "zstd:{}", self.level)
+    }
+}
+
+/// Opens data of a file stored in a snapshot, decrypting and
+/// decompressing it if needed.
+pub fn open_stored(
+    data: &Path,
+    compressed: bool,
+    decryption: Option<&Decryption>,
+) -> io::Result<Box<dyn Read>> {
+    let data = open_encrypted(data, decryption)?;
+    match compressed {
+        true => Ok(Box::new(zstd::Decoder::new(data)?)),
+        false => Ok(Box::new(BufReader::new(data))),
+    }
+}
+
+/// Content of the original file stored as `data`.
+pub fn content_of(data: &Path, compressed: bool) -> io::Result<Content> {
+    if !compressed {
+        return Content::of_file(data);
+    }
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut open_stored(data, true, None)?, &mut hasher)?;
+    Ok(Content {
+        size,
+        sha256: hasher.finalize().into(),
+    })
+}
+
+/// Hashes data while it is read.
+struct Tee<'a, R> {
+    reader: R,
+    hasher: &'a mut Sha256,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_compression() {
+        assert_eq!(
+            FileCompression::parse("zstd"),
+            Some(FileCompression { level: 3 })
+        );
+        assert_eq!(
+            FileCompression::parse("zstd:19"),
+            Some(FileCompression { level: 19 })
+        );
+        assert_eq!(FileCompression::parse("zstd:0"), None);
+        assert_eq!(FileCompression::parse("zstd:23"), None);
+        assert_eq!(FileCompression::parse("gzip"), None);
+    }
+
+    #[test]
+    fn compressed_file_has_content_of_original() {
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("notes.txt");
+        let stored = root.path().join("stored");
+        fs::write(&source, "hello world ".repeat(1000)).unwrap();
+
+        let compression = FileCompression { level: 3 };
+        let (content, size) = compression.compress_file(&source, &stored).unwrap();
+
+        assert_eq!(content, Content::of_file(&source).unwrap());
+        assert!(size < content.size / 10);
+        assert_eq!(content_of(&stored, true).unwrap(), content);
+    }
+}
@@ -0,0 +1,138 @@
+//! Differences between indexes of two snapshots. Files are compared by
+//! checksums recorded in the index when both snapshots have them, so no
+//! stored data has to be read.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::index::IndexEntry;
+use super::{EntryKind, Snapshot};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Removed => write!(f, "removed"),
+            Self::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct Difference {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+impl Snapshot {
+    /// Paths added, removed or modified in `newer` since this snapshot,
+    /// sorted by path. Files without checksums that are stored in different
+    /// snapshots are reported as modified, because they can't be compared.
+    pub fn diff(&self, newer: &Snapshot) -> Vec<Difference> {
+        let (old_entries, new_entries) = (entries_by_path(self), entries_by_path(newer));
+        let mut differences: Vec<Difference> = old_entries
+            .keys()
+            .filter(|path| !new_entries.contains_key(*path))
+            .map(|path| Difference {
+                path: path.to_path_buf(),
+                change: Change::Removed,
+            })
+            .collect();
+        for (path, new) in &new_entries {
+            let change = match old_entries.get(path) {
+                None => Change::Added,
+                Some(old) if !self.is_same_entry(old, newer, new) => Change::Modified,
+                Some(_) => continue,
+            };
+            differences.push(Difference {
+                path: path.to_path_buf(),
+                change,
+            });
+        }
+        differences.sort_by(|a, b| a.path.cmp(&b.path));
+        differences
+    }
+
+    fn is_same_entry(&self, old: &IndexEntry, newer: &Snapshot, new: &IndexEntry) -> bool {
+        if self.location.parent() == newer.location.parent() && old.timestamp == new.timestamp {
+            return true;
+        }
+        if let (Some(old), Some(new)) = (&old.content, &new.content) {
+            return old == new;
+        }
+        if old.special.is_some() || new.special.is_some() {
+            return old.special == new.special;
+        }
+        let (old, new) = (self.entry_info(old), newer.entry_info(new));
+        match (old.kind, new.kind) {
+            (EntryKind::Directory, EntryKind::Directory) => true,
+            (EntryKind::Symlink, EntryKind::Symlink) => {
+                let target = |data: &Path| fs::read_link(data).ok();
+                target(&old.data).is_some() && target(&old.data) == target(&new.data)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn entries_by_path(snapshot: &Snapshot) -> BTreeMap<&Path, &IndexEntry> {
+    snapshot
+        .index
+        .entries
+        .iter()
+        .map(|entry| (entry.path.as_path(), entry))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+
+    fn snapshot_of(files: &Path) -> (tempfile::TempDir, Snapshot) {
+        let root = tempfile::tempdir().unwrap();
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.add_files_to_snapshot(files);
+        snapshot.save_index().unwrap();
+        (root, snapshot)
+    }
+
+    #[test]
+    fn diff_lists_added_removed_and_modified_paths() {
+        let files = tempfile::tempdir().unwrap();
+        let files = files.path().canonicalize().unwrap();
+        fs::create_dir(files.join("docs")).unwrap();
+        fs::write(files.join("docs/same.txt"), "same").unwrap();
+        fs::write(files.join("docs/changed.txt"), "old").unwrap();
+        fs::write(files.join("removed.txt"), "removed").unwrap();
+        let (_older_root, older) = snapshot_of(&files);
+
+        fs::write(files.join("docs/changed.txt"), "new").unwrap();
+        fs::remove_file(files.join("removed.txt")).unwrap();
+        fs::write(files.join("docs/added.txt"), "added").unwrap();
+        let (_newer_root, newer) = snapshot_of(&files);
+
+        let difference = |path: &str, change| Difference {
+            path: files.join(path),
+            change,
+        };
+        assert_eq!(
+            older.diff(&newer),
+            vec![
+                difference("docs/added.txt", Change::Added),
+                difference("docs/changed.txt", Change::Modified),
+                difference("removed.txt", Change::Removed),
+            ]
+        );
+        assert!(newer.diff(&newer).is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+//! Sizes of folders stored in a snapshot, like `du`. Files are walked in the
+//! files folder and mapped back to their original paths through the index.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+use super::files::Files;
+use super::Snapshot;
+
+impl Snapshot {
+    /// Total size of files stored in this snapshot under every original
+    /// folder. With `under` only that folder and folders inside it are
+    /// included. Data stored in earlier snapshots isn't counted.
+    pub fn disk_usage(&self, under: Option<&Path>) -> BTreeMap<PathBuf, u64> {
+        let files_root = self.files_root(&self.timestamp);
+        let originals: HashMap<PathBuf, &Path> = self
+            .index
+            .entries
+            .iter()
+            .filter(|entry| entry.timestamp == self.timestamp)
+            .map(|entry| {
+                let data = Files::to_snapshot_path_unchecked(&files_root, &entry.path);
+                (data, entry.path.as_path())
+            })
+            .collect();
+
+        let mut folders = BTreeMap::new();
+        for entry in WalkDir::new(&files_root)
+            .min_depth(1)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(Result::ok)
+        {
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let original = match originals.get(entry.path()) {
+                Some(original) => *original,
+                None => {
+                    debug!("Stored file is not indexed: {}", entry.path().display());
+                    continue;
+                }
+            };
+            let size = entry.metadata().map(|meta| meta.len()).unwrap_or_default();
+            for folder in original.ancestors().skip(1) {
+                if under.is_none_or(|under| folder.starts_with(under)) {
+                    *folders.entry(folder.to_owned()).or_default() += size;
+                }
+            }
+        }
+        folders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use std::fs;
+
+    #[test]
+    fn sizes_of_folders_by_original_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let files = files.path().canonicalize().unwrap();
+        fs::create_dir_all(files.join("docs/old")).unwrap();
+        fs::write(files.join("docs/a.txt"), "aaaa").unwrap();
+        fs::write(files.join("docs/old/b.txt"), "bb").unwrap();
+        fs::write(files.join("c.txt"), "c").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.add_files_to_snapshot(&files);
+        snapshot.save_index().unwrap();
+
+        let folders = snapshot.disk_usage(None);
+        assert_eq!(folders[&files], 7);
+        assert_eq!(folders[&files.join("docs")], 6);
+        assert_eq!(folders[files.ancestors().last().unwrap()], 7);
+
+        let docs = files.join("docs");
+        let folders = snapshot.disk_usage(Some(&docs));
+        let expected: BTreeMap<PathBuf, u64> = vec![(docs.clone(), 6), (docs.join("old"), 2)]
+            .into_iter()
+            .collect();
+        assert_eq!(folders, expected);
+        assert!(snapshot.disk_usage(Some(&files.join("c.txt"))).is_empty());
+    }
+}
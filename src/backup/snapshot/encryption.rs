@@ -0,0 +1,432 @@
+//! Encryption of files stored in a snapshot to public keys of recipients,
+//! with age or GPG, so the machine that makes backups never holds the
+//! private key. Files are encrypted one by one, after compression, and keep
+//! their names. The index isn't encrypted, so incremental backups work
+//! without the private key; it is needed only to read the data back.
+
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use super::archived::create_private_dir;
+
+/// External program used to encrypt files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EncryptionTool {
+    Age,
+    Gpg,
+}
+
+impl EncryptionTool {
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Age => "age",
+            Self::Gpg => "gpg",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "age" => Some(Self::Age),
+            "gpg" => Some(Self::Gpg),
+            _ => None,
+        }
+    }
+}
+
+impl Display for EncryptionTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program())
+    }
+}
+
+/// Recipients whose public keys files are encrypted to. Any of them can
+/// decrypt the files with their private key.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Encryption {
+    pub tool: EncryptionTool,
+    pub recipients: Vec<String>,
+}
+
+impl Encryption {
+    /// Recipients starting with "age1" or "ssh-" are age public keys,
+    /// others are GPG key IDs. All recipients must use the same tool.
+    pub fn new(recipients: Vec<String>) -> Result<Self, String> {
+        let tool_of = |recipient: &str| match recipient.starts_with("age1")
+            || recipient.starts_with("ssh-")
+        {
+            true => EncryptionTool::Age,
+            false => EncryptionTool::Gpg,
+        };
+        let tool = match recipients.first() {
+            Some(recipient) => tool_of(recipient),
+            None => return Err("No recipients to encrypt files to".into()),
+        };
+        if recipients.iter().any(|r| r.is_empty() || r.contains(',')) {
+            return Err("Invalid recipient".into());
+        }
+        if recipients.iter().any(|r| tool_of(r) != tool) {
+            return Err("Files can't be encrypted to both age and GPG recipients".into());
+        }
+        Ok(Self { tool, recipients })
+    }
+
+    /// Parses `<tool>:<recipient>,...` as written in metadata.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (tool, recipients) = value.split_once(':')?;
+        let tool = EncryptionTool::parse(tool)?;
+        let recipients: Vec<String> = recipients.split(',').map(String::from).collect();
+        Self::new(recipients).ok().filter(|e| e.tool == tool)
+    }
+
+    /// Replaces a stored file with its encrypted version.
+    pub fn encrypt_in_place(&self, path: &Path) -> io::Result<()> {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".enc");
+        let temporary = path.with_file_name(name);
+        debug!("Encrypting {} with {}", path.display(), self.tool);
+        let status = Command::new(self.tool.program())
+            .args(self.encrypt_args(path, &temporary))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => fs::rename(&temporary, path),
+            result => {
+                fs::remove_file(&temporary).ok();
+                let reason = match result {
+                    Ok(status) => status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                Err(io::Error::other(format!(
+                    "{} failed ({})",
+                    self.tool, reason
+                )))
+            }
+        }
+    }
+
+    fn encrypt_args(&self, source: &Path, destination: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = match self.tool {
+            EncryptionTool::Age => vec!["--encrypt".into()],
+            // Keys of recipients don't have to be signed by the owner of the keyring.
+            EncryptionTool::Gpg => vec![
+                "--batch".into(),
+                "--yes".into(),
+                "--trust-model".into(),
+                "always".into(),
+                "--encrypt".into(),
+            ],
+        };
+        for recipient in &self.recipients {
+            args.push("--recipient".into());
+            args.push(recipient.into());
+        }
+        args.push("--output".into());
+        args.push(destination.into());
+        args.push(source.into());
+        args
+    }
+}
+
+impl Display for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.tool, self.recipients.join(","))
+    }
+}
+
+/// Private key used to read encrypted files. age reads it from the key
+/// file; GPG finds it in the key file imported into a temporary home, or
+/// in the keyring, and asks for its passphrase unless it is given up front.
+#[derive(Clone)]
+pub struct Decryption {
+    pub tool: EncryptionTool,
+    /// age identity file. GPG keys are read from `gpg_home` instead.
+    pub key_file: Option<PathBuf>,
+    pub gpg_home: Option<Arc<GpgHome>>,
+    /// Passphrase of the GPG private key, so unattended runs don't need
+    /// gpg-agent. age reads passphrases only from the terminal.
+    pub passphrase: Option<String>,
+}
+
+impl Decryption {
+    /// Decrypts `data` with the external program, reading its output.
+    pub fn open(&self, data: &Path) -> io::Result<Decrypted> {
+        let passphrase = self.gpg_passphrase();
+        let mut child = Command::new(self.tool.program())
+            .args(self.decrypt_args(data)?)
+            .stdin(match passphrase {
+                Some(_) => Stdio::piped(),
+                None => Stdio::inherit(),
+            })
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run {} ({})", self.tool, e)))?;
+        if let Some(passphrase) = passphrase {
+            // gpg reads the passphrase before any data, so it fits in the pipe.
+            let mut stdin = child.stdin.take().unwrap();
+            writeln!(stdin, "{}", passphrase)?;
+        }
+        let stdout = child.stdout.take().unwrap();
+        Ok(Decrypted {
+            tool: self.tool,
+            child,
+            stdout,
+        })
+    }
+
+    fn decrypt_args(&self, data: &Path) -> io::Result<Vec<OsString>> {
+        let mut args: Vec<OsString> = match (self.tool, &self.key_file) {
+            (EncryptionTool::Age, Some(key_file)) => {
+                vec!["--decrypt".into(), "--identity".into(), key_file.into()]
+            }
+            (EncryptionTool::Age, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "data is encrypted with age, a key file is needed to decrypt it",
+                ))
+            }
+            (EncryptionTool::Gpg, _) if self.gpg_passphrase().is_some() => vec![
+                "--batch".into(),
+                "--pinentry-mode".into(),
+                "loopback".into(),
+                "--passphrase-fd".into(),
+                "0".into(),
+                "--quiet".into(),
+                "--decrypt".into(),
+            ],
+            (EncryptionTool::Gpg, _) => vec!["--quiet".into(), "--decrypt".into()],
+        };
+        if let (EncryptionTool::Gpg, Some(home)) = (self.tool, &self.gpg_home) {
+            args.splice(0..0, ["--homedir".into(), home.folder.clone().into()]);
+        }
+        args.push(data.into());
+        Ok(args)
+    }
+
+    fn gpg_passphrase(&self) -> Option<&str> {
+        match self.tool {
+            EncryptionTool::Gpg => self.passphrase.as_deref(),
+            EncryptionTool::Age => None,
+        }
+    }
+}
+
+/// Temporary GPG home with private keys imported from a key file, so files
+/// can be decrypted without adding the keys to the keyring of the user.
+/// The home, and the gpg-agent started for it, are removed when dropped.
+pub struct GpgHome {
+    folder: PathBuf,
+}
+
+impl GpgHome {
+    /// Imports keys exported with `gpg --export-secret-keys`. They stay
+    /// protected by their passphrase.
+    pub fn import(key_file: &Path) -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let folder = std::env::temp_dir().join(format!(
+            "mizeria-{}-{}-gpg",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        create_private_dir(&folder)?;
+        let home = Self { folder };
+        debug!(
+            "Importing {} into {}",
+            key_file.display(),
+            home.folder.display()
+        );
+        let status = Command::new("gpg")
+            .arg("--batch")
+            .arg("--quiet")
+            .arg("--homedir")
+            .arg(&home.folder)
+            .arg("--import")
+            .arg(key_file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run gpg ({})", e)))?;
+        match status.success() {
+            true => Ok(home),
+            false => Err(io::Error::other(format!("gpg failed ({})", status))),
+        }
+    }
+}
+
+impl Drop for GpgHome {
+    fn drop(&mut self) {
+        Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&self.folder)
+            .arg("--kill")
+            .arg("gpg-agent")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok();
+        if let Err(e) = fs::remove_dir_all(&self.folder) {
+            warn!("Cannot remove {} ({})", self.folder.display(), e);
+        }
+    }
+}
+
+/// Decrypted data read from the output of the decrypting program. Reading
+/// fails at the end of the data if the program failed.
+pub struct Decrypted {
+    tool: EncryptionTool,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for Decrypted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "{} failed ({})",
+                    self.tool, status
+                )));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Decrypted {
+    fn drop(&mut self) {
+        // Does nothing if the program has already finished.
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Opens data of a stored file, decrypting it if needed.
+pub fn open_encrypted(data: &Path, decryption: Option<&Decryption>) -> io::Result<Box<dyn Read>> {
+    match decryption {
+        Some(decryption) => Ok(Box::new(decryption.open(data)?)),
+        None => Ok(Box::new(File::open(data)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_is_chosen_by_recipients() {
+        let age = Encryption::new(vec!["age1abc".into(), "ssh-ed25519 AAAA".into()]).unwrap();
+        assert_eq!(age.tool, EncryptionTool::Age);
+        let gpg = Encryption::new(vec!["ABCD1234".into()]).unwrap();
+        assert_eq!(gpg.tool, EncryptionTool::Gpg);
+
+        assert!(Encryption::new(vec![]).is_err());
+        assert!(Encryption::new(vec!["age1abc".into(), "ABCD1234".into()]).is_err());
+        assert_eq!(Encryption::parse(&gpg.to_string()), Some(gpg));
+        assert_eq!(Encryption::parse("age:ABCD1234"), None);
+    }
+
+    #[test]
+    fn encrypt_args_contain_all_recipients() {
+        let encryption = Encryption::new(vec!["ABCD1234".into(), "EF567890".into()]).unwrap();
+        let args = encryption.encrypt_args(Path::new("a.txt"), Path::new("a.txt.enc"));
+
+        assert_eq!(
+            args,
+            vec![
+                "--batch",
+                "--yes",
+                "--trust-model",
+                "always",
+                "--encrypt",
+                "--recipient",
+                "ABCD1234",
+                "--recipient",
+                "EF567890",
+                "--output",
+                "a.txt.enc",
+                "a.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn age_needs_key_file_to_decrypt() {
+        let mut decryption = Decryption {
+            tool: EncryptionTool::Age,
+            key_file: None,
+            gpg_home: None,
+            passphrase: Some("secret".into()),
+        };
+        assert!(decryption.decrypt_args(Path::new("a.txt")).is_err());
+
+        decryption.key_file = Some("key.txt".into());
+        assert_eq!(
+            decryption.decrypt_args(Path::new("a.txt")).unwrap(),
+            vec!["--decrypt", "--identity", "key.txt", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn gpg_reads_passphrase_from_stdin() {
+        let mut decryption = Decryption {
+            tool: EncryptionTool::Gpg,
+            key_file: None,
+            gpg_home: None,
+            passphrase: None,
+        };
+        assert_eq!(
+            decryption.decrypt_args(Path::new("a.txt")).unwrap(),
+            vec!["--quiet", "--decrypt", "a.txt"]
+        );
+
+        decryption.passphrase = Some("secret".into());
+        assert_eq!(
+            decryption.decrypt_args(Path::new("a.txt")).unwrap(),
+            vec![
+                "--batch",
+                "--pinentry-mode",
+                "loopback",
+                "--passphrase-fd",
+                "0",
+                "--quiet",
+                "--decrypt",
+                "a.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn gpg_reads_keys_from_temporary_home() {
+        let decryption = Decryption {
+            tool: EncryptionTool::Gpg,
+            key_file: Some("key.asc".into()),
+            gpg_home: Some(Arc::new(GpgHome {
+                folder: std::env::temp_dir().join("mizeria-test-gpg-home"),
+            })),
+            passphrase: None,
+        };
+        let home = std::env::temp_dir().join("mizeria-test-gpg-home");
+        assert_eq!(
+            decryption.decrypt_args(Path::new("a.txt")).unwrap(),
+            vec![
+                OsString::from("--homedir"),
+                home.into(),
+                "--quiet".into(),
+                "--decrypt".into(),
+                "a.txt".into()
+            ]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Estimate of what a new snapshot would copy. Inputs are compared with the
+//! base snapshot the same way as during a backup, but nothing is written.
+
+use std::path::Path;
+use std::time::Duration;
+
+use walkdir::WalkDir;
+
+use super::files::SourceEntry;
+use super::{find_unchanged, BaseIndex, Snapshot, SnapshotPreview};
+
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Estimate {
+    /// Snapshot whose files wouldn't be copied again.
+    pub base: Option<String>,
+    /// Entries that would be indexed.
+    pub entries: u64,
+    /// Files that would be copied.
+    pub copied: u64,
+    /// Size of files that would be copied, in bytes.
+    pub bytes: u64,
+    /// Files stored in earlier snapshots.
+    pub unchanged: u64,
+    /// Entries that can't be read, so they wouldn't be backed up.
+    pub unreadable: u64,
+}
+
+impl Estimate {
+    /// Time of copying the files at `throughput` bytes per second.
+    pub fn duration(&self, throughput: u64) -> Duration {
+        Duration::from_secs_f64(self.bytes as f64 / throughput.max(1) as f64)
+    }
+
+    fn add(&mut self, entry: &SourceEntry, base_index: Option<&mut BaseIndex>) {
+        self.entries += 1;
+        if !entry.metadata.is_file() {
+            return;
+        }
+        match base_index.and_then(|index| find_unchanged(index, entry)) {
+            Some(_) => self.unchanged += 1,
+            None => {
+                self.copied += 1;
+                self.bytes += entry.metadata.len();
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Walks `inputs` like [`Snapshot::add_files_to_snapshot`]. Without
+    /// a base snapshot, or when it isn't intact, every file is counted as
+    /// copied, like in a full backup.
+    pub fn estimate<P: AsRef<Path>>(
+        inputs: &[P],
+        base: Option<&SnapshotPreview>,
+        max_depth: Option<usize>,
+    ) -> Estimate {
+        let mut base_index = base.and_then(|base| Self::open_base_index(base, None));
+        let mut estimate = Estimate {
+            base: base
+                .filter(|_| base_index.is_some())
+                .map(|base| base.timestamp.to_string()),
+            ..Default::default()
+        };
+        for input in inputs {
+            let input = input.as_ref();
+            let walked_location = match input.canonicalize() {
+                Ok(location) => location,
+                Err(_) => {
+                    estimate.unreadable += 1;
+                    continue;
+                }
+            };
+            let mut walk = WalkDir::new(input).follow_links(false);
+            if let Some(depth) = max_depth {
+                walk = walk.max_depth(depth);
+            }
+            for entry in walk {
+                let entry = match entry {
+                    Ok(entry) if entry.depth() == 0 => SourceEntry::read(entry.path()).ok(),
+                    Ok(entry) => SourceEntry::from_walk(entry, input, &walked_location).ok(),
+                    Err(_) => None,
+                };
+                match entry {
+                    Some(entry) => estimate.add(&entry, base_index.as_mut()),
+                    None => estimate.unreadable += 1,
+                }
+            }
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::{Clock, Environment};
+    use std::fs;
+    use std::sync::Arc;
+
+    struct NextDay;
+    impl Clock for NextDay {
+        fn now(&self) -> time::OffsetDateTime {
+            time::OffsetDateTime::now_utc() + time::Duration::days(1)
+        }
+    }
+
+    #[test]
+    fn estimate_counts_only_changed_files() {
+        let root = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("a.txt"), "hello").unwrap();
+        fs::create_dir(input.path().join("folder")).unwrap();
+        fs::write(input.path().join("folder").join("b.txt"), "world!").unwrap();
+
+        let full = Snapshot::estimate(&[input.path()], None, None);
+        assert_eq!(
+            full,
+            Estimate {
+                base: None,
+                entries: 4,
+                copied: 2,
+                bytes: 11,
+                unchanged: 0,
+                unreadable: 0,
+            }
+        );
+
+        // Files are older than the snapshot taken a day later
+        let environment = Environment {
+            clock: Arc::new(NextDay),
+            ..Default::default()
+        };
+        let mut base = Snapshot::create(root.path(), &environment).unwrap();
+        base.add_files_to_snapshot(input.path());
+        base.save_index().unwrap();
+        base.commit().unwrap();
+        fs::write(input.path().join("c.txt"), "new").unwrap();
+
+        let incremental = Snapshot::estimate(&[input.path()], Some(&base.to_preview()), None);
+        assert_eq!(incremental.base, Some(base.name()));
+        assert_eq!(incremental.entries, 5);
+        assert_eq!((incremental.copied, incremental.bytes), (1, 3));
+        assert_eq!(incremental.unchanged, 2);
+        assert_eq!(incremental.duration(1), Duration::from_secs(3));
+    }
+}
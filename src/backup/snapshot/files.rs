@@ -1,44 +1,155 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::path::{Component, Components, Path, PathBuf, Prefix, PrefixComponent};
+use std::sync::{Arc, OnceLock};
 use std::{fs, io};
 
-use log::{debug, trace};
-use walkdir::WalkDir;
+use tracing::{debug, trace};
+use walkdir::{DirEntry, WalkDir};
 
-use crate::result::{IntegrityCheckError, IntegrityCheckResult};
+use super::archived::ARCHIVE_FILE_NAME;
+use super::compression::FileCompression;
+use super::index::Content;
+use crate::environment::{CopyOptions, FileSystem, RealFileSystem};
+use crate::result::{IntegrityCheckError, IntegrityCheckResult, IntegrityReport};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Entry to back up with its metadata, so that every entry is queried
+/// only once while it's compared with the base snapshot and copied.
+pub struct SourceEntry {
+    pub path: PathBuf,
+    pub metadata: fs::Metadata,
+    /// Absolute path of the entry. Unlike the canonical path, it isn't
+    /// resolved when the entry itself is a symlink.
+    pub location: PathBuf,
+}
+
+impl SourceEntry {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = path.symlink_metadata()?;
+        let location = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) if metadata.file_type().is_symlink() => {
+                fs::canonicalize(parent)?.join(name)
+            }
+            _ => fs::canonicalize(path)?,
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            metadata,
+            location,
+        })
+    }
+
+    /// The entry read again, if it is a file whose size or modification
+    /// time changed since it was read, e.g. while it was copied.
+    pub fn changed(&self) -> Option<Self> {
+        if !self.metadata.is_file() {
+            return None;
+        }
+        let metadata = self.path.symlink_metadata().ok()?;
+        let unchanged = metadata.len() == self.metadata.len()
+            && metadata.modified().ok() == self.metadata.modified().ok();
+        (!unchanged).then(|| Self {
+            path: self.path.clone(),
+            metadata,
+            location: self.location.clone(),
+        })
+    }
+
+    /// Entry found while walking `walked` folder, whose canonical path
+    /// is `walked_location`. Symlinks aren't followed by the walk, so the
+    /// location of the entry doesn't have to be canonicalized.
+    pub fn from_walk(
+        entry: DirEntry,
+        walked: &Path,
+        walked_location: &Path,
+    ) -> walkdir::Result<Self> {
+        let metadata = entry.metadata()?;
+        let location = match entry.path().strip_prefix(walked) {
+            Ok(relative) if entry.depth() > 0 => walked_location.join(relative),
+            _ => walked_location.to_owned(),
+        };
+        Ok(Self {
+            path: entry.into_path(),
+            metadata,
+            location,
+        })
+    }
+
+    /// Path of the entry in the index. Symlinks are indexed by paths
+    /// of their targets.
+    pub fn index_path(&self) -> io::Result<PathBuf> {
+        if self.metadata.file_type().is_symlink() {
+            fs::canonicalize(&self.path)
+        } else {
+            Ok(self.location.clone())
+        }
+    }
+}
+
 pub struct Files {
     root: PathBuf,
-    size: u64, // in bytes
+    /// In bytes. Computed on first use for opened snapshots.
+    size: OnceLock<u64>,
+    fs: Arc<dyn FileSystem>,
+    copy_options: CopyOptions,
 }
 
 impl Files {
-    pub fn new(location: PathBuf) -> Files {
+    pub fn new(location: PathBuf, fs: Arc<dyn FileSystem>) -> std::result::Result<Self, String> {
         if !location.exists() {
-            fs::create_dir(&location).expect("Error creating files dir");
+            fs::create_dir(&location).or(Err("Cannot create directory for files"))?;
         }
-        Files {
+        Ok(Files {
             root: location,
-            size: 0,
-        }
+            size: OnceLock::from(0),
+            fs,
+            copy_options: CopyOptions::default(),
+        })
+    }
+
+    /// Points to the folder after it was moved.
+    pub fn relocate(&mut self, location: PathBuf) {
+        self.root = location;
     }
 
+    /// Files of an archived snapshot are counted as the size of the archive.
     pub fn open(location: PathBuf) -> std::result::Result<Self, String> {
-        if !location.exists() {
-            return Err("Folder with files doesn't exist or isn't accessible".into());
-        }
-        let size = Self::get_size(location.as_path());
+        let size = match location.exists() {
+            true => OnceLock::new(),
+            false => match location.with_file_name(ARCHIVE_FILE_NAME).metadata() {
+                Ok(archive) => OnceLock::from(archive.len()),
+                Err(_) => return Err("Folder with files doesn't exist or isn't accessible".into()),
+            },
+        };
         Ok(Files {
             root: location,
             size,
+            fs: Arc::new(RealFileSystem),
+            copy_options: CopyOptions::default(),
         })
     }
 
+    pub fn set_copy_options(&mut self, options: CopyOptions) {
+        self.copy_options = options;
+    }
+
+    /// Counts data stored in the snapshot outside of the files folder.
+    pub fn add_size(&mut self, bytes: u64) {
+        if let Some(size) = self.size.get_mut() {
+            *size += bytes;
+        }
+    }
+
+    /// Brings back the size counted before a file was copied, when its
+    /// copy is replaced.
+    pub fn set_size(&mut self, bytes: u64) {
+        self.size = OnceLock::from(bytes);
+    }
+
     pub fn size(&self) -> u64 {
-        self.size
+        *self.size.get_or_init(|| Self::get_size(&self.root))
     }
 
     fn get_size(location: &Path) -> u64 {
@@ -60,28 +171,47 @@ impl Files {
     pub fn check_integrity<'a>(
         location: PathBuf,
         indexed_files: impl Iterator<Item = &'a PathBuf>,
+        report: &mut IntegrityReport,
     ) -> IntegrityCheckResult {
-        debug!("Building a map of indexed files");
-        let mut index_map = HashMap::new();
-        for indexed_file in indexed_files {
-            let local_path = Self::to_snapshot_path_unchecked(&location, indexed_file.as_path());
-            index_map.insert(local_path, indexed_file);
-        }
-
         if !location.exists() || !location.is_dir() {
             return Err(IntegrityCheckError::FilesFolderDoesntExist);
         }
 
         debug!("Traversing snapshot files has started");
-        for entry in WalkDir::new(location).min_depth(1).follow_links(false) {
+        let mut stored = vec![];
+        for entry in WalkDir::new(&location).min_depth(1).follow_links(false) {
             let entry = match entry {
                 Ok(entry) => entry,
                 Err(e) => return Err(IntegrityCheckError::UnexpectedError(format!("{}", e))),
             };
             trace!("Found file: {}", entry.path().display());
+            let is_dir = entry.path().is_dir();
+            stored.push((entry.into_path(), is_dir));
+        }
+        Self::check_stored_entries(&location, stored, indexed_files, report)
+    }
 
+    /// Compares entries stored under `location`, with whether they are
+    /// folders, with indexed entries. Used for entries listed in an archive.
+    pub fn check_stored_entries<'a>(
+        location: &Path,
+        stored: Vec<(PathBuf, bool)>,
+        indexed_files: impl Iterator<Item = &'a PathBuf>,
+        report: &mut IntegrityReport,
+    ) -> IntegrityCheckResult {
+        debug!("Building a map of indexed files");
+        let mut index_map = HashMap::new();
+        for indexed_file in indexed_files {
+            let local_path = Self::to_snapshot_path_unchecked(location, indexed_file.as_path());
+            index_map.insert(local_path, indexed_file);
+        }
+
+        for (entry, is_dir) in stored {
+            if entry == location {
+                continue;
+            }
             // Remove indexed entry so we know that it is present.
-            let entry = entry.path();
+            let entry = entry.as_path();
             let entry_was_indexed = index_map.remove(entry).is_some();
 
             // Don't raise an error when you don't find a folder like 'C\Program Files'
@@ -89,6 +219,14 @@ impl Files {
             // not explicitly indexed.
             let is_subpath_of_another_entry = index_map.iter().any(|(e, _)| e.starts_with(entry));
             if !entry_was_indexed && !is_subpath_of_another_entry {
+                // A stray directory holds no data by itself. Files inside it
+                // are reported separately.
+                if is_dir {
+                    report.push(IntegrityCheckError::DirectoryExistsButNotIndexed(
+                        entry.to_owned(),
+                    ));
+                    continue;
+                }
                 return Err(IntegrityCheckError::EntryExistsButNotIndexed(
                     entry.to_owned(),
                 ));
@@ -105,75 +243,88 @@ impl Files {
         Ok(())
     }
 
-    pub fn copy_entry(&mut self, entry: &Path) -> Result<PathBuf> {
-        let entry_meta = entry.symlink_metadata()?;
-        let entry_type = entry_meta.file_type();
+    pub fn copy_entry(&mut self, entry: &SourceEntry) -> Result<PathBuf> {
+        let entry_type = entry.metadata.file_type();
 
         let result = if entry_type.is_dir() {
-            self.copy_dir_entry(entry)
+            self.copy_dir_entry(&entry.location)
         } else if entry_type.is_file() {
-            self.copy_file_entry(entry)
+            self.copy_file_entry(&entry.path, &entry.location)
         } else if entry_type.is_symlink() {
-            #[cfg(windows)]
-            {
-                Err("Copying symlinks is not supported on Windows.".into())
-            }
-            #[cfg(unix)]
-            {
-                self.copy_link_entry(entry)
-            }
+            self.copy_link_entry(&entry.path, &entry.location)
         } else {
-            Err(format!("Unknown entry type: {}", &entry.display()).into())
+            Err(format!("Unknown entry type: {}", &entry.path.display()).into())
         };
 
-        if result.is_ok() {
-            self.size += entry_meta.len();
+        if let (Ok(_), Some(size)) = (&result, self.size.get_mut()) {
+            *size += entry.metadata.len();
         }
 
         result
     }
 
-    fn copy_dir_entry(&self, dir_to_copy: &Path) -> Result<PathBuf> {
-        let snapshot_entry = Files::to_snapshot_path(&self.root, dir_to_copy)?;
-        fs::create_dir_all(&snapshot_entry)?;
-        Ok(snapshot_entry)
+    /// Stores a file compressed. Returns its location in the snapshot
+    /// together with the content of the original file.
+    pub fn compress_entry(
+        &mut self,
+        entry: &SourceEntry,
+        compression: FileCompression,
+    ) -> Result<(PathBuf, Content)> {
+        let snapshot_entry = Files::to_snapshot_path_unchecked(&self.root, &entry.location);
+        let snapshot_entry_parent = snapshot_entry.parent().ok_or("no parent")?;
+        if !snapshot_entry_parent.exists() {
+            self.fs.create_dir_all(snapshot_entry_parent)?;
+        }
+        let (content, stored) = compression.compress_file(&entry.path, &snapshot_entry)?;
+        fs::File::options()
+            .write(true)
+            .open(&snapshot_entry)?
+            .set_modified(entry.metadata.modified()?)?;
+        self.add_size(stored);
+        Ok((snapshot_entry, content))
     }
 
-    fn copy_file_entry(&self, file_to_copy: &Path) -> Result<PathBuf> {
-        let snapshot_entry = Files::to_snapshot_path(&self.root, file_to_copy)?;
+    /// Stores a file as a hardlink to `data` of another link of the same
+    /// file that was already copied into the snapshot.
+    pub fn link_entry(&mut self, entry: &SourceEntry, data: &Path) -> Result<PathBuf> {
+        let snapshot_entry = Files::to_snapshot_path_unchecked(&self.root, &entry.location);
         let snapshot_entry_parent = snapshot_entry.parent().ok_or("no parent")?;
         if !snapshot_entry_parent.exists() {
-            fs::create_dir_all(snapshot_entry_parent)?;
+            self.fs.create_dir_all(snapshot_entry_parent)?;
         }
-        fs::copy(file_to_copy, &snapshot_entry)?;
+        self.fs.hard_link(data, &snapshot_entry)?;
         Ok(snapshot_entry)
     }
 
-    #[cfg(unix)]
-    fn copy_link_entry(&self, link_to_copy: &Path) -> Result<PathBuf> {
-        let link_parent = link_to_copy.parent().ok_or("no parent")?;
-        let link_file_name = link_to_copy.file_name().ok_or("invalid file name")?;
+    fn copy_dir_entry(&self, location: &Path) -> Result<PathBuf> {
+        let snapshot_entry = Files::to_snapshot_path_unchecked(&self.root, location);
+        self.fs.create_dir_all(&snapshot_entry)?;
+        Ok(snapshot_entry)
+    }
 
-        let snapshot_entry_parent = Files::to_snapshot_path(&self.root, link_parent)?;
-        let snapshot_entry = snapshot_entry_parent.join(link_file_name);
+    fn copy_file_entry(&self, file_to_copy: &Path, location: &Path) -> Result<PathBuf> {
+        let snapshot_entry = Files::to_snapshot_path_unchecked(&self.root, location);
+        let snapshot_entry_parent = snapshot_entry.parent().ok_or("no parent")?;
         if !snapshot_entry_parent.exists() {
-            fs::create_dir_all(snapshot_entry_parent)?;
+            self.fs.create_dir_all(snapshot_entry_parent)?;
         }
-        let source = link_to_copy.read_link()?;
-        std::os::unix::fs::symlink(source, &snapshot_entry)?;
+        self.fs
+            .copy_file_with(file_to_copy, &snapshot_entry, &self.copy_options)?;
         Ok(snapshot_entry)
     }
 
-    fn to_snapshot_path(root: &Path, entry: &Path) -> io::Result<PathBuf> {
-        // this entry must phisically exist
-        let absolute_entry = fs::canonicalize(entry)?;
-        Ok(Self::to_snapshot_path_unchecked(
-            root,
-            absolute_entry.as_path(),
-        ))
+    fn copy_link_entry(&self, link_to_copy: &Path, location: &Path) -> Result<PathBuf> {
+        let snapshot_entry = Files::to_snapshot_path_unchecked(&self.root, location);
+        let snapshot_entry_parent = snapshot_entry.parent().ok_or("no parent")?;
+        if !snapshot_entry_parent.exists() {
+            self.fs.create_dir_all(snapshot_entry_parent)?;
+        }
+        let source = self.fs.read_link(link_to_copy)?;
+        self.fs.symlink(&source, &snapshot_entry)?;
+        Ok(snapshot_entry)
     }
 
-    fn to_snapshot_path_unchecked(root: &Path, entry: &Path) -> PathBuf {
+    pub fn to_snapshot_path_unchecked(root: &Path, entry: &Path) -> PathBuf {
         let snapshot_relative_entry = Self::join_components_to_relative_path(entry.components());
         root.join(snapshot_relative_entry)
     }
@@ -215,19 +366,67 @@ impl Files {
 mod tests {
     use super::*;
 
+    #[test]
+    fn create_files_in_nonexistent_folder() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let location = tempdir.path().join("nonexistent").join("files");
+
+        let result = Files::new(location, Arc::new(RealFileSystem));
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "Cannot create directory for files");
+    }
+
     #[test]
     fn copy_files_from_invalid_path() {
         let tempdir = tempfile::tempdir().unwrap();
         let invalid_file = tempdir.path().join("foobar");
         let mut files = Files {
             root: PathBuf::new(),
-            size: 0,
+            size: OnceLock::from(0),
+            fs: Arc::new(RealFileSystem),
+            copy_options: CopyOptions::default(),
         };
 
-        let result = files.copy_entry(&invalid_file);
+        fs::write(&invalid_file, b"").unwrap();
+        let entry = SourceEntry::read(&invalid_file).unwrap();
+        fs::remove_file(&invalid_file).unwrap();
+
+        let result = files.copy_entry(&entry);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn size_is_computed_on_first_use() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let location = tempdir.path().join("files");
+        fs::create_dir(&location).unwrap();
+
+        let files = Files::open(location.clone()).unwrap();
+        fs::write(location.join("file.txt"), b"hello").unwrap();
+
+        assert_eq!(files.size(), 5);
+    }
+
+    #[test]
+    fn walked_entries_are_located_without_canonicalizing() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let folder = tempdir.path().join("folder");
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("file.txt"), b"").unwrap();
+        let walked_location = tempdir.path().canonicalize().unwrap();
+
+        for entry in WalkDir::new(tempdir.path()).min_depth(1) {
+            let entry = entry.unwrap();
+            let path = entry.path().to_owned();
+            let walked = SourceEntry::from_walk(entry, tempdir.path(), &walked_location).unwrap();
+            let read = SourceEntry::read(&path).unwrap();
+
+            assert_eq!(walked.location, read.location);
+            assert_eq!(walked.location, path.canonicalize().unwrap());
+            assert_eq!(walked.index_path().unwrap(), read.index_path().unwrap());
+        }
+    }
+
     #[test]
     #[cfg_attr(unix, ignore)]
     fn join_windows_verbatim_path() {
@@ -0,0 +1,293 @@
+//! Metadata of backed up files and folders kept in the backup folder,
+//! similar to the files cache of borg. An entry whose size, modification
+//! time, change time and inode are the same as when the latest snapshot
+//! was made is reused from the snapshot that stores it, without a lookup
+//! in the base index or comparing times with a margin. An entry whose
+//! metadata differs is copied, even if its modification time was set to
+//! the past. Entries are still read from the disk, as a folder doesn't
+//! change when a file in it does.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use tracing::debug;
+
+use super::index::{Content, IndexEntry};
+use super::index_cache::{cache_key, hash_path, invalid_data, read_array, PathHash};
+use super::timestamp::Timestamp;
+
+pub const FILES_CACHE_FILE_NAME: &str = "files-cache.bin";
+const HEADER: &[u8] = b"mizeria files cache 1\n";
+
+/// Metadata that changes when a file or folder is modified.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Stat {
+    size: u64,
+    /// In nanoseconds since the Unix epoch.
+    modified: i128,
+    changed: i128,
+    inode: u64,
+}
+
+impl Stat {
+    /// Only files and folders are cached. Symlinks are indexed by paths
+    /// of their targets, so they would share an entry with them.
+    #[cfg(unix)]
+    pub fn of(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        if !metadata.is_file() && !metadata.is_dir() {
+            return None;
+        }
+        let nanos = |seconds: i64, nanos: i64| seconds as i128 * 1_000_000_000 + nanos as i128;
+        Some(Self {
+            size: metadata.size(),
+            modified: nanos(metadata.mtime(), metadata.mtime_nsec()),
+            changed: nanos(metadata.ctime(), metadata.ctime_nsec()),
+            inode: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(metadata: &fs::Metadata) -> Option<Self> {
+        if !metadata.is_file() && !metadata.is_dir() {
+            return None;
+        }
+        let nanos = |time: std::time::SystemTime| {
+            let since_epoch = time.duration_since(std::time::UNIX_EPOCH).ok()?;
+            Some(since_epoch.as_nanos() as i128)
+        };
+        Some(Self {
+            size: metadata.len(),
+            modified: nanos(metadata.modified().ok()?)?,
+            changed: nanos(metadata.created().ok()?)?,
+            inode: 0,
+        })
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&self.modified.to_le_bytes())?;
+        writer.write_all(&self.changed.to_le_bytes())?;
+        writer.write_all(&self.inode.to_le_bytes())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(Self {
+            size: u64::from_le_bytes(read_array(reader)?),
+            modified: i128::from_le_bytes(read_array(reader)?),
+            changed: i128::from_le_bytes(read_array(reader)?),
+            inode: u64::from_le_bytes(read_array(reader)?),
+        })
+    }
+}
+
+pub enum Lookup<'a> {
+    /// Stored in the snapshot with the content.
+    Unchanged(&'a Timestamp, Option<&'a Content>),
+    Changed,
+    /// Not in the cache, e.g. a new file.
+    Unknown,
+}
+
+pub struct FilesCache {
+    timestamps: Vec<Timestamp>,
+    entries: HashMap<PathHash, (Stat, u32, Option<Content>), ahash::RandomState>,
+}
+
+impl FilesCache {
+    /// Entry must be an absolute path, as stored in the index.
+    pub fn find(&self, absolute_entry: &Path, metadata: &fs::Metadata) -> Lookup<'_> {
+        let (stat, timestamp, content) = match self.entries.get(&hash_path(absolute_entry)) {
+            Some(cached) => cached,
+            None => return Lookup::Unknown,
+        };
+        match self.timestamps.get(*timestamp as usize) {
+            Some(timestamp) if Stat::of(metadata).as_ref() == Some(stat) => {
+                Lookup::Unchanged(timestamp, content.as_ref())
+            }
+            Some(_) => Lookup::Changed,
+            None => Lookup::Unknown,
+        }
+    }
+}
+
+/// Writes metadata of `entries` indexed in `index` of the snapshot `name`
+/// to the cache in the backup folder. `stats` are metadata of the sources
+/// of entries, by hashes of their paths.
+pub fn save(
+    backup: &Path,
+    name: &str,
+    index: &Path,
+    entries: &[IndexEntry],
+    stats: &HashMap<PathHash, Stat, ahash::RandomState>,
+) -> io::Result<()> {
+    let mut positions: BTreeMap<&Timestamp, u32> = BTreeMap::new();
+    let mut timestamps: Vec<&Timestamp> = vec![];
+    let mut cached = Vec::with_capacity(stats.len());
+    for entry in entries
+        .iter()
+        .filter(|e| e.special.is_none() && e.chunks.is_empty())
+    {
+        let hash = hash_path(&entry.path);
+        let stat = match stats.get(&hash) {
+            Some(stat) => stat,
+            None => continue,
+        };
+        let position = *positions.entry(&entry.timestamp).or_insert_with(|| {
+            timestamps.push(&entry.timestamp);
+            timestamps.len() as u32 - 1
+        });
+        cached.push((hash, stat, position, &entry.content));
+    }
+
+    let temporary = backup.join(format!("{}.tmp", FILES_CACHE_FILE_NAME));
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writer.write_all(HEADER)?;
+    writeln!(writer, "{} {}", name, cache_key(index)?)?;
+    writer.write_all(&(timestamps.len() as u32).to_le_bytes())?;
+    for timestamp in timestamps {
+        let timestamp = timestamp.to_string();
+        writer.write_all(&[timestamp.len() as u8])?;
+        writer.write_all(timestamp.as_bytes())?;
+    }
+    writer.write_all(&(cached.len() as u64).to_le_bytes())?;
+    for (hash, stat, timestamp, content) in cached {
+        writer.write_all(&hash)?;
+        stat.write(&mut writer)?;
+        writer.write_all(&timestamp.to_le_bytes())?;
+        match content {
+            Some(content) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&content.size.to_le_bytes())?;
+                writer.write_all(&content.sha256)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temporary, backup.join(FILES_CACHE_FILE_NAME))
+}
+
+/// Returns the cache in the backup folder, if it was written for the
+/// snapshot `name` and its index didn't change since then.
+pub fn load(backup: &Path, name: &str, index: &Path) -> Option<FilesCache> {
+    let cache = backup.join(FILES_CACHE_FILE_NAME);
+    if !cache.exists() {
+        return None;
+    }
+    match read(&cache, name, index) {
+        Ok(Some(cached)) => Some(cached),
+        Ok(None) => {
+            debug!("Files cache isn't of snapshot {}", name);
+            None
+        }
+        Err(e) => {
+            debug!("Cannot read files cache ({})", e);
+            None
+        }
+    }
+}
+
+fn read(cache: &Path, name: &str, index: &Path) -> io::Result<Option<FilesCache>> {
+    let mut reader = BufReader::new(File::open(cache)?);
+    let expected_key = format!("{} {}\n", name, cache_key(index)?);
+    let mut header = vec![0; HEADER.len() + expected_key.len()];
+    reader.read_exact(&mut header)?;
+    if header[..HEADER.len()] != *HEADER || header[HEADER.len()..] != *expected_key.as_bytes() {
+        return Ok(None);
+    }
+
+    let mut timestamps = vec![];
+    for _ in 0..u32::from_le_bytes(read_array(&mut reader)?) {
+        let [length] = read_array(&mut reader)?;
+        let mut timestamp = vec![0; length as usize];
+        reader.read_exact(&mut timestamp)?;
+        let timestamp = String::from_utf8_lossy(&timestamp);
+        timestamps.push(Timestamp::parse_from(&timestamp).ok_or_else(|| invalid_data(&timestamp))?);
+    }
+
+    let count = u64::from_le_bytes(read_array(&mut reader)?);
+    let mut entries = HashMap::default();
+    for _ in 0..count {
+        let hash: PathHash = read_array(&mut reader)?;
+        let stat = Stat::read(&mut reader)?;
+        let timestamp = u32::from_le_bytes(read_array(&mut reader)?);
+        let content = match read_array(&mut reader)? {
+            [0] => None,
+            [1] => Some(Content {
+                size: u64::from_le_bytes(read_array(&mut reader)?),
+                sha256: read_array(&mut reader)?,
+            }),
+            _ => return Err(invalid_data("invalid content flag")),
+        };
+        entries.insert(hash, (stat, timestamp, content));
+    }
+    Ok(Some(FilesCache {
+        timestamps,
+        entries,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn changed_files_are_found_by_metadata() {
+        let backup = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let (notes, draft) = (
+            files.path().join("notes.txt"),
+            files.path().join("draft.txt"),
+        );
+        fs::write(&notes, "notes").unwrap();
+        fs::write(&draft, "draft").unwrap();
+        let index = backup.path().join("index.txt");
+        fs::write(&index, "index").unwrap();
+        let content = Content {
+            size: 5,
+            sha256: [7; 32],
+        };
+        let entry = |path: &Path| IndexEntry {
+            timestamp: Timestamp::parse_from("2021-07-15_18.34").unwrap(),
+            path: path.to_owned(),
+            content: Some(content.clone()),
+            link: None,
+            special: None,
+            chunks: vec![],
+            attributes: Default::default(),
+        };
+        let entries = vec![entry(&notes), entry(&draft), entry(Path::new("/new"))];
+        let stats = [&notes, &draft]
+            .iter()
+            .map(|path| {
+                (
+                    hash_path(path),
+                    Stat::of(&fs::metadata(path).unwrap()).unwrap(),
+                )
+            })
+            .collect();
+
+        save(backup.path(), "2021-07-15_18.34", &index, &entries, &stats).unwrap();
+        assert!(load(backup.path(), "2021-07-16_18.34", &index).is_none());
+        let cache = load(backup.path(), "2021-07-15_18.34", &index).unwrap();
+
+        fs::write(&draft, "draft, changed").unwrap();
+        let find = |path: &PathBuf| cache.find(path, &fs::metadata(path).unwrap());
+        match find(&notes) {
+            Lookup::Unchanged(timestamp, cached) => {
+                assert_eq!(timestamp.to_string(), "2021-07-15_18.34");
+                assert_eq!(cached, Some(&content));
+            }
+            _ => panic!("notes.txt didn't change"),
+        }
+        assert!(matches!(find(&draft), Lookup::Changed));
+        let other = files.path().join("other.txt");
+        fs::write(&other, "other").unwrap();
+        assert!(matches!(find(&other), Lookup::Unknown));
+    }
+}
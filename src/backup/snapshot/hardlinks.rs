@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+
+use super::index::Content;
+
+/// Files with more than one hardlink found while walking inputs. Links to
+/// the same file form a group, numbered from 1 in every snapshot.
+#[derive(Default)]
+pub struct Hardlinks {
+    groups: HashMap<(u64, u64), Group>,
+}
+
+struct Group {
+    id: u32,
+    /// Data of the first link copied into the snapshot.
+    stored: Option<(PathBuf, Content)>,
+}
+
+impl Hardlinks {
+    /// Link group of a file, if it has other hardlinks.
+    pub fn group(&mut self, metadata: &Metadata) -> Option<u32> {
+        let key = file_id(metadata)?;
+        Some(self.group_mut(key).id)
+    }
+
+    /// Data of another link of the same file that was already copied into
+    /// the snapshot, with its content.
+    pub fn stored_copy(&self, metadata: &Metadata) -> Option<(&Path, &Content)> {
+        let group = self.groups.get(&file_id(metadata)?)?;
+        group
+            .stored
+            .as_ref()
+            .map(|(data, content)| (data.as_path(), content))
+    }
+
+    /// Records where the data of a file with other hardlinks was copied.
+    pub fn set_stored(&mut self, metadata: &Metadata, data: &Path, content: &Content) {
+        if let Some(key) = file_id(metadata) {
+            let group = self.group_mut(key);
+            if group.stored.is_none() {
+                group.stored = Some((data.to_owned(), content.clone()));
+            }
+        }
+    }
+
+    /// Forgets the copied data of a file, when it is copied again.
+    pub fn forget_stored(&mut self, metadata: &Metadata) {
+        if let Some(group) = file_id(metadata).and_then(|key| self.groups.get_mut(&key)) {
+            group.stored = None;
+        }
+    }
+
+    fn group_mut(&mut self, key: (u64, u64)) -> &mut Group {
+        let id = self.groups.len() as u32 + 1;
+        self.groups.entry(key).or_insert(Group { id, stored: None })
+    }
+}
+
+/// Device and inode of a file that has more than one hardlink.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -1,13 +1,19 @@
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace};
 
 use crate::result::{IntegrityCheckError, IntegrityCheckResult};
 
+use super::attributes::Attributes;
+use super::chunks::{format_chunk_list, parse_chunk_list, ChunkRef};
+use super::special::Special;
 use super::timestamp::Timestamp;
 
 #[derive(Clone)]
@@ -41,8 +47,32 @@ impl Index {
         Ok(index)
     }
 
-    pub fn push(&mut self, timestamp: Timestamp, path: PathBuf) {
-        self.entries.push(IndexEntry { timestamp, path });
+    /// Reads entries of an index one at a time, so the whole index is never
+    /// kept in memory.
+    pub fn stream(path: &Path) -> Result<impl Iterator<Item = Result<IndexEntry, String>>, String> {
+        let file = File::open(path).or(Err("Cannot open index.txt"))?;
+        Ok(BufReader::new(file).lines().map(|line| {
+            let line = line.or(Err("Error while reading index.txt"))?;
+            IndexEntry::from_line(&line).map_err(|_| "index.txt is broken".into())
+        }))
+    }
+
+    /// Entries of an index that was being written when the backup stopped.
+    /// The last line may be incomplete, so only whole valid lines are read.
+    pub fn read_unfinished(path: &Path) -> Vec<IndexEntry> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_) => return vec![],
+        };
+        content
+            .split_inclusive('\n')
+            .take_while(|line| line.ends_with('\n'))
+            .map_while(|line| IndexEntry::from_line(line.trim_end_matches('\n')).ok())
+            .collect()
+    }
+
+    pub fn push(&mut self, entry: IndexEntry) {
+        self.entries.push(entry);
     }
 
     pub fn save(&self) -> io::Result<()> {
@@ -50,7 +80,7 @@ impl Index {
         let mut file = BufWriter::new(file);
         for index_entry in &self.entries {
             file.write_all(index_entry.to_string().as_bytes())?;
-            file.write_all(&[b'\n'])?;
+            file.write_all(b"\n")?;
         }
         file.flush()?;
         Ok(())
@@ -114,33 +144,168 @@ impl std::fmt::Debug for IndexEntryParseError {
 pub struct IndexEntry {
     pub timestamp: Timestamp,
     pub path: PathBuf,
+    /// Only files have content. Indexes made by older versions have none.
+    pub content: Option<Content>,
+    /// Files that were hardlinks of each other share a link group.
+    pub link: Option<u32>,
+    /// Named pipes, sockets and device nodes have no data in the snapshot.
+    pub special: Option<Special>,
+    /// Large files can be stored as chunks instead of a copy.
+    pub chunks: Vec<ChunkRef>,
+    pub attributes: Attributes,
 }
 
 impl IndexEntry {
     fn from_line(line: &str) -> Result<Self, IndexEntryParseError> {
-        let (timestamp_slice, path_slice) = line
-            .split_once(' ')
-            .ok_or(IndexEntryParseError::SyntaxError)?;
+        let line = split_line(line).ok_or(IndexEntryParseError::SyntaxError)?;
 
         let timestamp =
-            Timestamp::parse_from(timestamp_slice).ok_or(IndexEntryParseError::InvalidTimestamp)?;
-        let path = PathBuf::from(path_slice.trim());
+            Timestamp::parse_from(line.timestamp).ok_or(IndexEntryParseError::InvalidTimestamp)?;
+        let path = PathBuf::from(line.path.trim());
         if !path.is_absolute() {
             return Err(IndexEntryParseError::InvalidPath);
         }
 
-        Ok(Self { timestamp, path })
+        Ok(Self {
+            timestamp,
+            path,
+            content: line.content,
+            link: line.link,
+            special: line.special,
+            chunks: line.chunks,
+            attributes: line.attributes,
+        })
+    }
+}
+
+impl Display for IndexEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.timestamp)?;
+        if let Some(content) = &self.content {
+            write!(f, " {}", content)?;
+        }
+        if let Some(link) = self.link {
+            write!(f, " link:{}", link)?;
+        }
+        if let Some(special) = &self.special {
+            write!(f, " {}", special)?;
+        }
+        if !self.chunks.is_empty() {
+            write!(f, " {}", format_chunk_list(&self.chunks))?;
+        }
+        if !self.attributes.is_empty() {
+            write!(f, " {}", self.attributes)?;
+        }
+        write!(f, " {}", self.path.display())
+    }
+}
+
+/// Size and SHA-256 checksum of a file, written in the index between the
+/// timestamp and the path as `<size>:<checksum>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Content {
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+impl Content {
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)?;
+        Ok(Self {
+            size,
+            sha256: hasher.finalize().into(),
+        })
+    }
+
+    fn parse(field: &str) -> Option<Self> {
+        let (size, checksum) = field.split_once(':')?;
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_digit()) || checksum.len() != 64 {
+            return None;
+        }
+        let mut sha256 = [0; 32];
+        for (i, byte) in sha256.iter_mut().enumerate() {
+            let digits = checksum.get(i * 2..i * 2 + 2)?;
+            *byte = u8::from_str_radix(digits, 16).ok()?;
+        }
+        Some(Self {
+            size: size.parse().ok()?,
+            sha256,
+        })
     }
 }
 
-impl ToString for IndexEntry {
-    fn to_string(&self) -> String {
-        format!("{} {}", self.timestamp, self.path.display())
+impl Display for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.size)?;
+        self.sha256.iter().try_for_each(|b| write!(f, "{:02x}", b))
     }
 }
 
+/// Fields of a line of the index.
+pub struct IndexLine<'a> {
+    pub timestamp: &'a str,
+    pub content: Option<Content>,
+    pub link: Option<u32>,
+    pub special: Option<Special>,
+    pub chunks: Vec<ChunkRef>,
+    pub attributes: Attributes,
+    pub path: &'a str,
+}
+
+/// Splits a line of the index into a timestamp, optional fields and path.
+/// Paths in the index are absolute, so none of the content, `link:`,
+/// `special:`, `chunks:` or attribute fields is ever mistaken for the
+/// beginning of a path.
+pub fn split_line(line: &str) -> Option<IndexLine<'_>> {
+    let (timestamp, mut rest) = line.split_once(' ')?;
+    let content = rest
+        .split_once(' ')
+        .and_then(|(field, path)| Some((Content::parse(field)?, path)));
+    let content = content.map(|(content, path)| {
+        rest = path;
+        content
+    });
+    let link = rest.split_once(' ').and_then(|(field, path)| {
+        let group = field.strip_prefix("link:")?.parse().ok()?;
+        rest = path;
+        Some(group)
+    });
+    let special = rest.split_once(' ').and_then(|(field, path)| {
+        let special = Special::parse(field)?;
+        rest = path;
+        Some(special)
+    });
+    let chunks = rest
+        .split_once(' ')
+        .and_then(|(field, path)| {
+            let chunks = parse_chunk_list(field)?;
+            rest = path;
+            Some(chunks)
+        })
+        .unwrap_or_default();
+    let mut attributes = Attributes::default();
+    while let Some((field, path)) = rest.split_once(' ') {
+        if attributes.parse_field(field).is_none() {
+            break;
+        }
+        rest = path;
+    }
+    Some(IndexLine {
+        timestamp,
+        content,
+        link,
+        special,
+        chunks,
+        attributes,
+        path: rest,
+    })
+}
+
 pub struct IndexPreview {
-    inner: HashMap<PathBuf, Timestamp, ahash::RandomState>,
+    /// Timestamps, contents and modification times of sources, by paths.
+    inner: HashMap<PathBuf, (Timestamp, Option<Content>, Option<SystemTime>), ahash::RandomState>,
 }
 
 impl IndexPreview {
@@ -150,18 +315,30 @@ impl IndexPreview {
         let mut entries = HashMap::default();
         for line in file.lines() {
             let line = line.or(Err("Error while reading index.txt"))?;
-            let (timestamp_slice, path_slice) = line
-                .split_once(' ')
-                .ok_or("Index line has invalid format")?;
-            let timestamp = Timestamp::parse_from(timestamp_slice).ok_or("Invalid timestamp")?;
-            entries.insert(PathBuf::from(path_slice), timestamp);
+            let line = split_line(&line).ok_or("Index line has invalid format")?;
+            let timestamp = Timestamp::parse_from(line.timestamp).ok_or("Invalid timestamp")?;
+            entries.insert(
+                PathBuf::from(line.path),
+                (timestamp, line.content, line.attributes.modified),
+            );
         }
         Ok(Self { inner: entries })
     }
 
-    pub fn find(&self, entry: &Path) -> Option<&Timestamp> {
-        let absolute_entry = entry.canonicalize().ok()?;
-        self.inner.get(&absolute_entry)
+    pub fn find(&self, absolute_entry: &Path) -> Option<&Timestamp> {
+        self.inner
+            .get(absolute_entry)
+            .map(|(timestamp, _, _)| timestamp)
+    }
+
+    /// Also returns the content of the entry and the modification time
+    /// of its source, if they were recorded.
+    pub fn find_with_content(
+        &self,
+        absolute_entry: &Path,
+    ) -> Option<(&Timestamp, Option<&Content>, Option<SystemTime>)> {
+        let (timestamp, content, modified) = self.inner.get(absolute_entry)?;
+        Some((timestamp, content.as_ref(), *modified))
     }
 }
 
@@ -217,6 +394,60 @@ mod index_entry_tests {
         let index_entry = IndexEntry::from_line(valid_line.as_str()).unwrap();
         assert_eq!("2021-07-15_18.34", format!("{}", index_entry.timestamp));
         assert_eq!(valid_path, format!("{}", index_entry.path.display()));
+        assert!(index_entry.content.is_none());
+    }
+
+    #[test]
+    fn index_entry_with_content() {
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let line = format!("2021-07-15_18.34 5:{} /home/me/some file", checksum);
+
+        let index_entry = IndexEntry::from_line(&line).unwrap();
+
+        let content = index_entry.content.as_ref().unwrap();
+        assert_eq!(content.size, 5);
+        assert_eq!(content.sha256[..2], [0x2c, 0xf2]);
+        assert_eq!(index_entry.path, PathBuf::from("/home/me/some file"));
+        assert_eq!(index_entry.to_string(), line);
+    }
+
+    #[test]
+    fn index_entry_of_hardlink() {
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let line = format!("2021-07-15_18.34 5:{} link:2 /home/me/link:1", checksum);
+
+        let index_entry = IndexEntry::from_line(&line).unwrap();
+
+        assert!(index_entry.content.is_some());
+        assert_eq!(index_entry.link, Some(2));
+        assert_eq!(index_entry.path, PathBuf::from("/home/me/link:1"));
+        assert_eq!(index_entry.to_string(), line);
+    }
+
+    #[test]
+    fn index_entry_of_special_file() {
+        let line = "2021-07-15_18.34 special:fifo:644:0 /home/me/special:fifo";
+
+        let index_entry = IndexEntry::from_line(line).unwrap();
+
+        assert!(index_entry.content.is_none());
+        assert_eq!(index_entry.special.as_ref().unwrap().node, Some((0o644, 0)));
+        assert_eq!(index_entry.path, PathBuf::from("/home/me/special:fifo"));
+        assert_eq!(index_entry.to_string(), line);
+    }
+
+    #[test]
+    fn content_of_file() {
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("hello.txt");
+        std::fs::write(&file, "hello").unwrap();
+
+        let content = Content::of_file(&file).unwrap();
+
+        assert_eq!(
+            content.to_string(),
+            "5:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
     }
 
     #[test]
@@ -0,0 +1,263 @@
+//! Index of a snapshot cached in a compact binary form, so the next backup
+//! doesn't have to parse index.txt again. Paths are stored as hashes. The
+//! cache is used only while index.txt has the same size and modification
+//! time as when the cache was written.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use super::index::{Content, IndexEntry};
+use super::timestamp::Timestamp;
+
+pub const INDEX_CACHE_FILE_NAME: &str = "index-cache.bin";
+const HEADER: &[u8] = b"mizeria index cache 3\n";
+
+pub type PathHash = [u8; 16];
+
+pub struct CachedIndex {
+    timestamps: Vec<Timestamp>,
+    entries: HashMap<PathHash, (u32, Option<Content>, Option<SystemTime>), ahash::RandomState>,
+}
+
+impl CachedIndex {
+    /// Entry must be an absolute path, as stored in the index.
+    pub fn find(
+        &self,
+        absolute_entry: &Path,
+    ) -> Option<(&Timestamp, Option<&Content>, Option<SystemTime>)> {
+        let (timestamp, content, modified) = self.entries.get(&hash_path(absolute_entry))?;
+        Some((
+            self.timestamps.get(*timestamp as usize)?,
+            content.as_ref(),
+            *modified,
+        ))
+    }
+}
+
+/// Writes entries of the index to the cache in the snapshot folder.
+pub fn save(snapshot: &Path, index: &Path, entries: &[IndexEntry]) -> io::Result<()> {
+    let mut positions: BTreeMap<&Timestamp, u32> = BTreeMap::new();
+    let mut timestamps: Vec<&Timestamp> = vec![];
+    let mut hashed = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let position = *positions.entry(&entry.timestamp).or_insert_with(|| {
+            timestamps.push(&entry.timestamp);
+            timestamps.len() as u32 - 1
+        });
+        hashed.push((
+            hash_path(&entry.path),
+            position,
+            &entry.content,
+            entry.attributes.modified,
+        ));
+    }
+
+    let temporary = snapshot.join(format!("{}.tmp", INDEX_CACHE_FILE_NAME));
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writer.write_all(HEADER)?;
+    writeln!(writer, "{}", cache_key(index)?)?;
+    writer.write_all(&(timestamps.len() as u32).to_le_bytes())?;
+    for timestamp in timestamps {
+        let timestamp = timestamp.to_string();
+        writer.write_all(&[timestamp.len() as u8])?;
+        writer.write_all(timestamp.as_bytes())?;
+    }
+    writer.write_all(&(hashed.len() as u64).to_le_bytes())?;
+    for (hash, timestamp, content, modified) in hashed {
+        writer.write_all(&hash)?;
+        writer.write_all(&timestamp.to_le_bytes())?;
+        match content {
+            Some(content) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&content.size.to_le_bytes())?;
+                writer.write_all(&content.sha256)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        match modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) {
+            Some(since_epoch) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&since_epoch.as_secs().to_le_bytes())?;
+                writer.write_all(&since_epoch.subsec_nanos().to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temporary, snapshot.join(INDEX_CACHE_FILE_NAME))
+}
+
+/// Returns the cached index of a snapshot, unless the index was changed
+/// or the cache can't be read.
+pub fn load(snapshot: &Path, index: &Path) -> Option<CachedIndex> {
+    let cache = snapshot.join(INDEX_CACHE_FILE_NAME);
+    if !cache.exists() {
+        return None;
+    }
+    match read(&cache, index) {
+        Ok(Some(cached)) => Some(cached),
+        Ok(None) => {
+            debug!("Cached index is out of date");
+            None
+        }
+        Err(e) => {
+            debug!("Cannot read cached index ({})", e);
+            None
+        }
+    }
+}
+
+fn read(cache: &Path, index: &Path) -> io::Result<Option<CachedIndex>> {
+    let mut reader = BufReader::new(File::open(cache)?);
+    let expected_key = format!("{}\n", cache_key(index)?);
+    let mut header = vec![0; HEADER.len() + expected_key.len()];
+    reader.read_exact(&mut header)?;
+    if header[..HEADER.len()] != *HEADER || header[HEADER.len()..] != *expected_key.as_bytes() {
+        return Ok(None);
+    }
+
+    let mut timestamps = vec![];
+    for _ in 0..u32::from_le_bytes(read_array(&mut reader)?) {
+        let [length] = read_array(&mut reader)?;
+        let mut timestamp = vec![0; length as usize];
+        reader.read_exact(&mut timestamp)?;
+        let timestamp = String::from_utf8_lossy(&timestamp);
+        timestamps.push(Timestamp::parse_from(&timestamp).ok_or_else(|| invalid_data(&timestamp))?);
+    }
+
+    let count = u64::from_le_bytes(read_array(&mut reader)?);
+    let mut entries = HashMap::default();
+    for _ in 0..count {
+        let hash: PathHash = read_array(&mut reader)?;
+        let timestamp = u32::from_le_bytes(read_array(&mut reader)?);
+        if timestamp as usize >= timestamps.len() {
+            return Err(invalid_data("timestamp out of range"));
+        }
+        let content = match read_array(&mut reader)? {
+            [0] => None,
+            [1] => Some(Content {
+                size: u64::from_le_bytes(read_array(&mut reader)?),
+                sha256: read_array(&mut reader)?,
+            }),
+            _ => return Err(invalid_data("invalid content flag")),
+        };
+        let modified = match read_array(&mut reader)? {
+            [0] => None,
+            [1] => {
+                let seconds = u64::from_le_bytes(read_array(&mut reader)?);
+                let nanos = u32::from_le_bytes(read_array(&mut reader)?);
+                UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+            }
+            _ => return Err(invalid_data("invalid modification time flag")),
+        };
+        entries.insert(hash, (timestamp, content, modified));
+    }
+    Ok(Some(CachedIndex {
+        timestamps,
+        entries,
+    }))
+}
+
+/// Size and modification time of the index identify its content.
+pub fn cache_key(index: &Path) -> io::Result<String> {
+    let metadata = fs::metadata(index)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| invalid_data(&e.to_string()))?;
+    Ok(format!("{} {}", metadata.len(), modified.as_nanos()))
+}
+
+pub fn hash_path(path: &Path) -> PathHash {
+    let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+    let mut hash = PathHash::default();
+    hash.copy_from_slice(&digest[..std::mem::size_of::<PathHash>()]);
+    hash
+}
+
+pub fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array)?;
+    Ok(array)
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::snapshot::attributes::Attributes;
+    use std::path::PathBuf;
+
+    fn index_entry(timestamp: &str, path: &str) -> IndexEntry {
+        IndexEntry {
+            timestamp: Timestamp::parse_from(timestamp).unwrap(),
+            path: PathBuf::from(path),
+            content: None,
+            link: None,
+            special: None,
+            chunks: vec![],
+            attributes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn save_and_load_cached_index() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let index = snapshot.path().join("index.txt");
+        fs::write(&index, b"index").unwrap();
+        let content = Content {
+            size: 5,
+            sha256: [7; 32],
+        };
+        let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 123);
+        let entries = vec![
+            index_entry("2021-07-15_18.34", "/a"),
+            IndexEntry {
+                content: Some(content.clone()),
+                attributes: Attributes {
+                    modified: Some(modified),
+                    ..Default::default()
+                },
+                ..index_entry("2021-07-16_18.34", "/a/b")
+            },
+            index_entry("2021-07-15_18.34", "/a/c"),
+        ];
+
+        save(snapshot.path(), &index, &entries).unwrap();
+        let cached = load(snapshot.path(), &index).unwrap();
+
+        assert_eq!(cached.timestamps.len(), 2);
+        let (timestamp, cached_content, cached_modified) = cached.find(Path::new("/a/b")).unwrap();
+        assert_eq!(timestamp.to_string(), "2021-07-16_18.34");
+        assert_eq!(cached_content, Some(&content));
+        assert_eq!(cached_modified, Some(modified));
+        let (timestamp, cached_content, cached_modified) = cached.find(Path::new("/a/c")).unwrap();
+        assert_eq!(timestamp.to_string(), "2021-07-15_18.34");
+        assert!(cached_content.is_none());
+        assert!(cached_modified.is_none());
+        assert!(cached.find(Path::new("/a/d")).is_none());
+    }
+
+    #[test]
+    fn cache_of_changed_index_is_not_used() {
+        let snapshot = tempfile::tempdir().unwrap();
+        let index = snapshot.path().join("index.txt");
+        fs::write(&index, b"index").unwrap();
+        let entries = vec![index_entry("2021-07-15_18.34", "/a")];
+        save(snapshot.path(), &index, &entries).unwrap();
+
+        fs::write(&index, b"modified index").unwrap();
+
+        assert!(load(snapshot.path(), &index).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::compression::FileCompression;
+use super::encryption::Encryption;
+
+/// Information about how a snapshot was made. It is stored in `metadata.txt`
+/// as `key: value` lines. Unknown keys are ignored when reading.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Metadata {
+    pub version: String,
+    pub host: String,
+    pub user: String,
+    /// Whether some entries of the snapshot are stored in earlier snapshots.
+    pub incremental: bool,
+    pub duration: Duration,
+    pub inputs: Vec<PathBuf>,
+    pub indexed: usize,
+    pub copied: usize,
+    pub size: u64, // in bytes
+    /// Compression of files stored in the snapshot.
+    pub compression: Option<FileCompression>,
+    /// Recipients files stored in the snapshot are encrypted to.
+    pub encryption: Option<Encryption>,
+    pub warnings: usize,
+    /// Files larger than the size limit that were not copied, with sizes.
+    pub not_copied: Vec<(PathBuf, u64)>,
+    /// Files that kept changing while they were copied, so their copies may
+    /// be torn.
+    pub unstable: Vec<PathBuf>,
+    /// Link to the snapshot that was the latest one when this was made.
+    pub previous: Option<ChainLink>,
+}
+
+/// Name of a snapshot and its [`super::checksums::chain_hash`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChainLink {
+    pub snapshot: String,
+    pub hash: String,
+}
+
+impl Metadata {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).or(Err("Cannot open metadata.txt"))?;
+        let mut metadata = Metadata {
+            version: String::new(),
+            host: String::new(),
+            user: String::new(),
+            incremental: false,
+            duration: Duration::ZERO,
+            inputs: vec![],
+            indexed: 0,
+            copied: 0,
+            size: 0,
+            compression: None,
+            encryption: None,
+            warnings: 0,
+            not_copied: vec![],
+            unstable: vec![],
+            previous: None,
+        };
+
+        let mut incremental = None;
+        for line in BufReader::new(file).lines() {
+            let line = line.or(Err("Error while reading metadata.txt"))?;
+            let (key, value) = match line.split_once(": ") {
+                Some(key_value) => key_value,
+                None => continue,
+            };
+            let invalid = || format!("Invalid value of '{}' in metadata.txt", key);
+            match key {
+                "version" => metadata.version = value.into(),
+                "host" => metadata.host = value.into(),
+                "user" => metadata.user = value.into(),
+                "type" => match value {
+                    "full" => incremental = Some(false),
+                    "incremental" => incremental = Some(true),
+                    _ => return Err(invalid()),
+                },
+                "duration_ms" => {
+                    metadata.duration = Duration::from_millis(value.parse().map_err(|_| invalid())?)
+                }
+                "input" => metadata.inputs.push(value.into()),
+                "indexed" => metadata.indexed = value.parse().map_err(|_| invalid())?,
+                "copied" => metadata.copied = value.parse().map_err(|_| invalid())?,
+                "size" => metadata.size = value.parse().map_err(|_| invalid())?,
+                "compression" => {
+                    metadata.compression = Some(FileCompression::parse(value).ok_or_else(invalid)?)
+                }
+                "encryption" => {
+                    metadata.encryption = Some(Encryption::parse(value).ok_or_else(invalid)?)
+                }
+                "warnings" => metadata.warnings = value.parse().map_err(|_| invalid())?,
+                "not_copied" => {
+                    let (size, path) = value.split_once(' ').ok_or_else(invalid)?;
+                    let size = size.parse().map_err(|_| invalid())?;
+                    metadata.not_copied.push((path.into(), size));
+                }
+                "unstable" => metadata.unstable.push(value.into()),
+                "previous" => {
+                    let (snapshot, hash) = value.split_once(' ').ok_or_else(invalid)?;
+                    metadata.previous = Some(ChainLink {
+                        snapshot: snapshot.into(),
+                        hash: hash.into(),
+                    });
+                }
+                _ => (),
+            }
+        }
+        // Older versions didn't record the type of snapshot.
+        metadata.incremental = incremental.unwrap_or(metadata.copied < metadata.indexed);
+        Ok(metadata)
+    }
+
+    /// Type of the snapshot as recorded in metadata.txt.
+    pub fn kind(&self) -> &'static str {
+        if self.incremental {
+            "incremental"
+        } else {
+            "full"
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "{}", self)?;
+        file.flush()
+    }
+}
+
+impl Display for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(f, "host: {}", self.host)?;
+        writeln!(f, "user: {}", self.user)?;
+        writeln!(f, "type: {}", self.kind())?;
+        writeln!(f, "duration_ms: {}", self.duration.as_millis())?;
+        for input in &self.inputs {
+            writeln!(f, "input: {}", input.display())?;
+        }
+        writeln!(f, "indexed: {}", self.indexed)?;
+        writeln!(f, "copied: {}", self.copied)?;
+        writeln!(f, "size: {}", self.size)?;
+        if let Some(compression) = &self.compression {
+            writeln!(f, "compression: {}", compression)?;
+        }
+        if let Some(encryption) = &self.encryption {
+            writeln!(f, "encryption: {}", encryption)?;
+        }
+        writeln!(f, "warnings: {}", self.warnings)?;
+        for (path, size) in &self.not_copied {
+            writeln!(f, "not_copied: {} {}", size, path.display())?;
+        }
+        for path in &self.unstable {
+            writeln!(f, "unstable: {}", path.display())?;
+        }
+        if let Some(previous) = &self.previous {
+            writeln!(f, "previous: {} {}", previous.snapshot, previous.hash)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub fn get_host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return String::from("unknown");
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into()
+}
+
+#[cfg(not(unix))]
+pub fn get_host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| String::from("unknown"))
+}
+
+#[cfg(unix)]
+pub fn get_user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+#[cfg(not(unix))]
+pub fn get_user_name() -> String {
+    std::env::var("USERNAME").unwrap_or_else(|_| String::from("unknown"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_open_metadata() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("metadata.txt");
+        let metadata = Metadata {
+            version: "1.0.0".into(),
+            host: "my-computer".into(),
+            user: "kyriet".into(),
+            incremental: true,
+            duration: Duration::from_millis(1234),
+            inputs: vec!["/home/user".into(), "relative/path".into()],
+            indexed: 10,
+            copied: 3,
+            size: 512,
+            compression: Some(FileCompression { level: 19 }),
+            encryption: Some(Encryption::new(vec!["age1abc".into(), "age1def".into()]).unwrap()),
+            warnings: 1,
+            not_copied: vec![("/home/user/disk.img".into(), 4096)],
+            unstable: vec!["/home/user/app.log".into()],
+            previous: Some(ChainLink {
+                snapshot: "2021-07-15_18.34".into(),
+                hash: "abc".into(),
+            }),
+        };
+
+        metadata.save(&path).unwrap();
+
+        assert_eq!(Metadata::open(&path).unwrap(), metadata);
+    }
+
+    #[test]
+    fn open_metadata_with_unknown_keys() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("metadata.txt");
+        std::fs::write(&path, "version: 2.0.0\nsomething: new\nindexed: 5\n").unwrap();
+
+        let metadata = Metadata::open(&path).unwrap();
+
+        assert_eq!(metadata.version, "2.0.0");
+        assert_eq!(metadata.indexed, 5);
+    }
+
+    #[test]
+    fn type_of_snapshot_is_derived_when_not_recorded() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("metadata.txt");
+        std::fs::write(&path, "indexed: 5\ncopied: 2\n").unwrap();
+        assert!(Metadata::open(&path).unwrap().incremental);
+
+        std::fs::write(&path, "indexed: 5\ncopied: 5\n").unwrap();
+        assert!(!Metadata::open(&path).unwrap().incremental);
+    }
+
+    #[test]
+    fn open_metadata_with_invalid_value() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("metadata.txt");
+        std::fs::write(&path, "indexed: many\n").unwrap();
+
+        assert!(Metadata::open(&path).is_err());
+    }
+
+    #[test]
+    fn host_name_is_not_empty() {
+        assert!(!get_host_name().is_empty());
+    }
+}
@@ -0,0 +1,463 @@
+//! Reed-Solomon parity data of a snapshot. Stored files are read as a
+//! single stream in the order of their paths. The stream is split into
+//! blocks, which are grouped into stripes of up to 128 blocks. Every
+//! stripe gets parity blocks and survives as many damaged blocks as it
+//! has parity blocks. Damaged blocks are found by their checksums recorded
+//! in the manifest.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace, warn};
+use walkdir::WalkDir;
+
+use super::archived::ARCHIVE_FILE_NAME;
+use super::checksums::{to_hex, to_manifest_path};
+use super::Snapshot;
+
+pub const PARITY_FOLDER_NAME: &str = "parity";
+const MANIFEST_FILE_NAME: &str = "manifest.txt";
+const BLOCKS_FILE_NAME: &str = "parity.bin";
+const HEADER: &str = "mizeria parity 1";
+const STRIPE_BLOCKS: usize = 128;
+const MIN_BLOCK_SIZE: u64 = 512;
+const MAX_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Size of parity data added to a snapshot.
+pub struct ParitySummary {
+    pub data_blocks: usize,
+    pub parity_blocks: usize,
+    pub size: u64, // in bytes
+}
+
+struct Manifest {
+    block_size: usize,
+    percent: u8,
+    /// Stored files with their sizes, relative to the snapshot folder.
+    files: Vec<(String, u64)>,
+    data: Vec<String>,
+    parity: Vec<String>,
+}
+
+impl Manifest {
+    fn stripes(&self) -> Vec<(Range<usize>, Range<usize>)> {
+        let mut stripes = vec![];
+        let mut parity_start = 0;
+        for data_start in (0..self.data.len()).step_by(STRIPE_BLOCKS) {
+            let data_end = self.data.len().min(data_start + STRIPE_BLOCKS);
+            let parity_end = parity_start + parity_count(data_end - data_start, self.percent);
+            stripes.push((data_start..data_end, parity_start..parity_end));
+            parity_start = parity_end;
+        }
+        stripes
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "block-size {}", self.block_size)?;
+        writeln!(writer, "percent {}", self.percent)?;
+        for (file, size) in &self.files {
+            writeln!(writer, "file {} {}", size, file)?;
+        }
+        for checksum in &self.data {
+            writeln!(writer, "data {}", checksum)?;
+        }
+        for checksum in &self.parity {
+            writeln!(writer, "parity {}", checksum)?;
+        }
+        writer.flush()
+    }
+
+    fn open(path: &Path) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid line in parity manifest: {}", line),
+            )
+        };
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        match lines.next() {
+            Some(Ok(header)) if header == HEADER => (),
+            _ => return Err(invalid("header")),
+        }
+        let mut manifest = Self {
+            block_size: 0,
+            percent: 0,
+            files: vec![],
+            data: vec![],
+            parity: vec![],
+        };
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').ok_or_else(|| invalid(&line))?;
+            match key {
+                "block-size" => manifest.block_size = value.parse().map_err(|_| invalid(&line))?,
+                "percent" => manifest.percent = value.parse().map_err(|_| invalid(&line))?,
+                "file" => {
+                    let (size, file) = value.split_once(' ').ok_or_else(|| invalid(&line))?;
+                    let size = size.parse().map_err(|_| invalid(&line))?;
+                    manifest.files.push((file.to_string(), size));
+                }
+                "data" => manifest.data.push(value.to_string()),
+                "parity" => manifest.parity.push(value.to_string()),
+                _ => return Err(invalid(&line)),
+            }
+        }
+        let expected: usize = manifest.stripes().iter().map(|(_, p)| p.len()).sum();
+        if manifest.block_size == 0 || manifest.parity.len() != expected {
+            return Err(invalid("number of blocks"));
+        }
+        Ok(manifest)
+    }
+}
+
+/// Stored files read and written as one stream of bytes.
+struct Stream<'a> {
+    snapshot: &'a Path,
+    files: &'a [(String, u64)],
+}
+
+impl Stream<'_> {
+    /// Parts of files covered by the range of the stream, with offsets
+    /// in the file and in the range.
+    fn parts(&self, range: Range<u64>) -> Vec<(PathBuf, u64, Range<usize>)> {
+        let mut parts = vec![];
+        let mut start = 0;
+        for (file, size) in self.files {
+            let end = start + size;
+            if end > range.start && start < range.end {
+                let from = range.start.max(start);
+                let to = range.end.min(end);
+                let in_range = (from - range.start) as usize..(to - range.start) as usize;
+                parts.push((self.snapshot.join(file), from - start, in_range));
+            }
+            start = end;
+            if start >= range.end {
+                break;
+            }
+        }
+        parts
+    }
+
+    /// Missing or unreadable parts are zeros, so their blocks are damaged.
+    fn read_block(&self, index: usize, block_size: usize) -> Vec<u8> {
+        let offset = (index * block_size) as u64;
+        let mut block = vec![0; block_size];
+        for (file, position, range) in self.parts(offset..offset + block_size as u64) {
+            let read = File::open(&file).and_then(|mut file| {
+                file.seek(SeekFrom::Start(position))?;
+                read_up_to(&mut file, &mut block[range])
+            });
+            if let Err(e) = read {
+                trace!("Cannot read {} ({})", file.display(), e);
+            }
+        }
+        block
+    }
+
+    fn write_block(&self, index: usize, block: &[u8]) -> io::Result<()> {
+        let offset = (index * block.len()) as u64;
+        for (file, position, range) in self.parts(offset..offset + block.len() as u64) {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&file)?;
+            file.seek(SeekFrom::Start(position))?;
+            file.write_all(&block[range])?;
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    pub fn has_parity(&self) -> bool {
+        self.location
+            .join(PARITY_FOLDER_NAME)
+            .join(MANIFEST_FILE_NAME)
+            .exists()
+    }
+
+    /// Writes parity blocks taking about `percent` of the size of stored
+    /// files. Existing parity data is replaced.
+    pub fn add_parity(&self, percent: u8) -> Result<ParitySummary, String> {
+        if !(1..=100).contains(&percent) {
+            return Err(format!("Invalid amount of parity data: {}%", percent));
+        }
+        let folder = self.location.join(PARITY_FOLDER_NAME);
+        let error =
+            |e: io::Error| format!("Cannot write parity data of {} ({})", self.timestamp, e);
+        let files = self.stored_files().map_err(error)?;
+        let total: u64 = files.iter().map(|(_, size)| size).sum();
+        let block_size = (total / STRIPE_BLOCKS as u64)
+            .next_power_of_two()
+            .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE) as usize;
+        let mut manifest = Manifest {
+            block_size,
+            percent,
+            data: vec![],
+            parity: vec![],
+            files,
+        };
+        let block_count = total.div_ceil(block_size as u64) as usize;
+        manifest.data = vec![String::new(); block_count];
+        debug!(
+            "Adding parity to {}: {} block(s) of {} bytes",
+            self.timestamp, block_count, block_size
+        );
+
+        // Old parity data doesn't match anymore.
+        if folder.exists() {
+            fs::remove_dir_all(&folder).map_err(error)?;
+        }
+        fs::create_dir_all(&folder).map_err(error)?;
+        let stream = Stream {
+            snapshot: &self.location,
+            files: &manifest.files,
+        };
+        let mut writer =
+            BufWriter::new(File::create(folder.join(BLOCKS_FILE_NAME)).map_err(error)?);
+        let mut parity_checksums = vec![];
+        for (data, parity) in manifest.stripes() {
+            let mut shards: Vec<Vec<u8>> = data
+                .clone()
+                .map(|index| stream.read_block(index, block_size))
+                .collect();
+            for (index, shard) in data.zip(&shards) {
+                manifest.data[index] = to_hex(&Sha256::digest(shard));
+            }
+            shards.resize(shards.len() + parity.len(), vec![0; block_size]);
+            let codec = ReedSolomon::new(shards.len() - parity.len(), parity.len())
+                .map_err(|e| format!("Cannot compute parity ({:?})", e))?;
+            codec
+                .encode(&mut shards)
+                .map_err(|e| format!("Cannot compute parity ({:?})", e))?;
+            for shard in &shards[shards.len() - parity.len()..] {
+                parity_checksums.push(to_hex(&Sha256::digest(shard)));
+                writer.write_all(shard).map_err(error)?;
+            }
+        }
+        writer.flush().map_err(error)?;
+        manifest.parity = parity_checksums;
+        // The manifest is written last, so incomplete parity data is never used.
+        manifest
+            .save(&folder.join(MANIFEST_FILE_NAME))
+            .map_err(error)?;
+
+        Ok(ParitySummary {
+            data_blocks: manifest.data.len(),
+            parity_blocks: manifest.parity.len(),
+            size: (manifest.parity.len() * block_size) as u64,
+        })
+    }
+
+    /// Returns the number of damaged parity blocks.
+    pub fn check_parity(&self) -> io::Result<usize> {
+        let folder = self.location.join(PARITY_FOLDER_NAME);
+        let manifest = Manifest::open(&folder.join(MANIFEST_FILE_NAME))?;
+        let parity = ParityBlocks::open(&folder, manifest.block_size)?;
+        let damaged = (0..manifest.parity.len())
+            .filter(|index| parity.read(*index, &manifest.parity[*index]).is_none())
+            .count();
+        Ok(damaged)
+    }
+
+    /// Rebuilds damaged blocks of stored files from parity data and writes
+    /// them back. Files longer than when parity was made are truncated.
+    /// Returns the number of repaired blocks. Stripes with more damaged
+    /// blocks than intact parity blocks are left as they are.
+    pub fn repair_with_parity(&self) -> io::Result<usize> {
+        // Files unpacked before the repair are outdated.
+        self.unpacked.borrow_mut().remove(&self.timestamp);
+        let folder = self.location.join(PARITY_FOLDER_NAME);
+        let manifest = Manifest::open(&folder.join(MANIFEST_FILE_NAME))?;
+        let parity = ParityBlocks::open(&folder, manifest.block_size)?;
+        for (file, size) in &manifest.files {
+            let file = self.location.join(file);
+            if file.metadata().is_ok_and(|m| m.len() > *size) {
+                debug!("Truncating {} to {} bytes", file.display(), size);
+                OpenOptions::new().write(true).open(&file)?.set_len(*size)?;
+            }
+        }
+        let stream = Stream {
+            snapshot: &self.location,
+            files: &manifest.files,
+        };
+
+        let mut repaired = 0;
+        for (data, parity_range) in manifest.stripes() {
+            let mut shards: Vec<Option<Vec<u8>>> = data
+                .clone()
+                .map(|index| {
+                    let block = stream.read_block(index, manifest.block_size);
+                    let intact = to_hex(&Sha256::digest(&block)) == manifest.data[index];
+                    intact.then_some(block)
+                })
+                .collect();
+            let damaged: Vec<usize> = (0..shards.len()).filter(|i| shards[*i].is_none()).collect();
+            if damaged.is_empty() {
+                continue;
+            }
+            shards.extend(
+                parity_range
+                    .clone()
+                    .map(|index| parity.read(index, &manifest.parity[index])),
+            );
+            let codec = ReedSolomon::new(data.len(), parity_range.len())
+                .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+            if let Err(e) = codec.reconstruct_data(&mut shards) {
+                warn!(
+                    "Cannot repair {} damaged block(s) of {} ({:?})",
+                    damaged.len(),
+                    self.timestamp,
+                    e
+                );
+                continue;
+            }
+            for position in damaged {
+                let index = data.start + position;
+                let block = shards[position].as_deref().unwrap_or_default();
+                if to_hex(&Sha256::digest(block)) != manifest.data[index] {
+                    continue;
+                }
+                trace!("Repaired block {} of {}", index, self.timestamp);
+                stream.write_block(index, block)?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+
+    fn stored_files(&self) -> io::Result<Vec<(String, u64)>> {
+        let mut files = vec![];
+        // Archived snapshots store all files in the archive.
+        let stored = match self.is_archived() {
+            true => self.location.join(ARCHIVE_FILE_NAME),
+            false => self.location.join("files"),
+        };
+        for entry in WalkDir::new(stored).follow_links(false).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let relative = entry
+                    .path()
+                    .strip_prefix(&self.location)
+                    .unwrap_or(entry.path());
+                files.push((to_manifest_path(relative), entry.metadata()?.len()));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Removes parity data that no longer matches stored files.
+pub fn remove_parity(snapshot: &Path) -> io::Result<()> {
+    let folder = snapshot.join(PARITY_FOLDER_NAME);
+    if folder.exists() {
+        debug!("Removing outdated parity data of {}", snapshot.display());
+        fs::remove_dir_all(folder)?;
+    }
+    Ok(())
+}
+
+struct ParityBlocks {
+    file: Option<File>,
+    block_size: usize,
+}
+
+impl ParityBlocks {
+    fn open(folder: &Path, block_size: usize) -> io::Result<Self> {
+        let file = File::open(folder.join(BLOCKS_FILE_NAME));
+        if let Err(e) = &file {
+            warn!("Cannot open parity blocks ({})", e);
+        }
+        Ok(Self {
+            file: file.ok(),
+            block_size,
+        })
+    }
+
+    /// Returns the block only if it matches its checksum.
+    fn read(&self, index: usize, checksum: &str) -> Option<Vec<u8>> {
+        let mut file = self.file.as_ref()?;
+        let mut block = vec![0; self.block_size];
+        file.seek(SeekFrom::Start((index * self.block_size) as u64))
+            .ok()?;
+        file.read_exact(&mut block).ok()?;
+        (to_hex(&Sha256::digest(&block)) == checksum).then_some(block)
+    }
+}
+
+fn parity_count(data_blocks: usize, percent: u8) -> usize {
+    (data_blocks * percent as usize).div_ceil(100).max(1)
+}
+
+fn read_up_to(file: &mut File, buffer: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parity_blocks_of_stripes() {
+        assert_eq!(parity_count(128, 5), 7);
+        assert_eq!(parity_count(3, 5), 1);
+        assert_eq!(parity_count(128, 100), 128);
+
+        let manifest = Manifest {
+            block_size: 512,
+            percent: 10,
+            files: vec![],
+            data: vec![String::new(); 130],
+            parity: vec![],
+        };
+        assert_eq!(
+            manifest.stripes(),
+            vec![(0..128, 0..13), (128..130, 13..14)]
+        );
+    }
+
+    #[test]
+    fn repair_damaged_blocks_with_parity() {
+        let backup = tempfile::tempdir().unwrap();
+        let location = backup.path().join("2021-07-15_18.34");
+        let folder = location.join("files").join("home");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(location.join("index.txt"), "").unwrap();
+        let notes: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(folder.join("notes.txt"), &notes).unwrap();
+        fs::write(folder.join("todo.txt"), b"hello world").unwrap();
+        let snapshot = Snapshot::open(&location).unwrap();
+
+        let summary = snapshot.add_parity(30).unwrap();
+        assert_eq!(summary.data_blocks, 10);
+        assert_eq!(summary.parity_blocks, 3);
+
+        let mut damaged = notes.clone();
+        damaged[100] ^= 0xff;
+        damaged[4000] ^= 0xff;
+        fs::write(folder.join("notes.txt"), &damaged).unwrap();
+        fs::write(folder.join("todo.txt"), b"hellO world and more").unwrap();
+        assert_eq!(snapshot.check_parity().unwrap(), 0);
+
+        assert_eq!(snapshot.repair_with_parity().unwrap(), 3);
+        assert_eq!(fs::read(folder.join("notes.txt")).unwrap(), notes);
+        assert_eq!(fs::read(folder.join("todo.txt")).unwrap(), b"hello world");
+    }
+}
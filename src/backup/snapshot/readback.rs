@@ -0,0 +1,162 @@
+//! Reading back files right after they were copied into a snapshot, to
+//! catch copies that were written wrongly, e.g. by a flaky USB enclosure.
+//! On Linux cached pages of copies are dropped first, so they are read
+//! from the disk and not from memory.
+
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use super::index::{Content, Index, IndexEntry};
+use super::{EntryInfo, Snapshot};
+use crate::parallel;
+
+/// Files copied into a snapshot that were read back.
+#[derive(Default, Debug)]
+pub struct Readback {
+    pub verified: usize,
+    /// Copies that don't match their sources, with the reason.
+    pub mismatched: Vec<(PathBuf, String)>,
+    /// Sources that changed after they were copied, so they couldn't be
+    /// compared with their copies.
+    pub changed: Vec<PathBuf>,
+}
+
+enum Outcome {
+    Matching,
+    Mismatched(String),
+    SourceChanged,
+}
+
+impl Snapshot {
+    /// Reads every file copied into this snapshot and its source, and
+    /// compares both with the checksum recorded while copying. Should be
+    /// called when the index is saved, before files are packed. Encrypted
+    /// copies can't be read without the private key.
+    pub fn read_back_copies(&self) -> io::Result<Readback> {
+        if self.is_encrypted(&self.timestamp) {
+            return Err(io::Error::other(
+                "encrypted files can't be read without the private key",
+            ));
+        }
+        let entries: Cow<[IndexEntry]> = match &self.index_writer {
+            Some(_) => Cow::Owned(Index::read_unfinished(&self.index.location)),
+            None => Cow::Borrowed(&self.index.entries),
+        };
+        // Timestamps read from the index are only as precise as the name.
+        let name = self.name();
+        let copies: Vec<(EntryInfo, Content)> = entries
+            .iter()
+            .filter(|entry| entry.timestamp.to_string() == name)
+            .filter_map(|entry| {
+                let entry = IndexEntry {
+                    timestamp: self.timestamp.clone(),
+                    ..entry.clone()
+                };
+                Some((self.entry_info(&entry), entry.content?))
+            })
+            .collect();
+        debug!("Reading back {} copied file(s)", copies.len());
+
+        let outcomes = parallel::map(copies, |(entry, recorded)| {
+            let outcome = match read_back(&entry) {
+                Err(e) => Outcome::Mismatched(format!("cannot read the copy: {}", e)),
+                Ok(copy) if copy != recorded => {
+                    Outcome::Mismatched("the copy differs from the source".into())
+                }
+                Ok(_) => match Content::of_file(&entry.path) {
+                    Ok(source) if source == recorded => Outcome::Matching,
+                    _ => Outcome::SourceChanged,
+                },
+            };
+            (entry.path, outcome)
+        });
+        let mut readback = Readback::default();
+        for (path, outcome) in outcomes {
+            match outcome {
+                Outcome::Matching => readback.verified += 1,
+                Outcome::Mismatched(reason) => readback.mismatched.push((path, reason)),
+                Outcome::SourceChanged => readback.changed.push(path),
+            }
+        }
+        Ok(readback)
+    }
+}
+
+fn read_back(entry: &EntryInfo) -> io::Result<Content> {
+    match entry.chunks.is_empty() {
+        true => drop_cache(&entry.data),
+        false => entry.chunks.iter().for_each(|chunk| drop_cache(chunk)),
+    }
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut entry.open_data()?, &mut hasher)?;
+    Ok(Content {
+        size,
+        sha256: hasher.finalize().into(),
+    })
+}
+
+/// Writes cached pages of a file to the disk and evicts them from memory.
+#[cfg(target_os = "linux")]
+fn drop_cache(path: &Path) {
+    use std::os::unix::io::AsRawFd;
+
+    if let Ok(file) = std::fs::File::open(path) {
+        file.sync_data().ok();
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cache(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use std::fs;
+
+    #[test]
+    fn read_back_finds_damaged_copies_and_changed_sources() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        let files = files.path().canonicalize().unwrap();
+        for name in ["intact.txt", "damaged.txt", "changed.txt"] {
+            fs::write(files.join(name), name).unwrap();
+        }
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.add_files_to_snapshot(&files);
+        snapshot.save_index().unwrap();
+        let copy = |name| {
+            let files_root = snapshot.location().join("files");
+            super::super::Files::to_snapshot_path_unchecked(&files_root, &files.join(name))
+        };
+        fs::write(copy("damaged.txt"), "bit rot").unwrap();
+        fs::write(files.join("changed.txt"), "new content").unwrap();
+
+        let readback = snapshot.read_back_copies().unwrap();
+
+        assert_eq!(readback.verified, 1);
+        assert_eq!(readback.mismatched.len(), 1);
+        assert_eq!(readback.mismatched[0].0, files.join("damaged.txt"));
+        assert_eq!(readback.changed, vec![files.join("changed.txt")]);
+    }
+
+    #[test]
+    fn read_back_in_bounded_memory_mode() {
+        let root = tempfile::tempdir().unwrap();
+        let files = tempfile::tempdir().unwrap();
+        fs::write(files.path().join("a.txt"), "hello").unwrap();
+
+        let mut snapshot = Snapshot::create(root.path(), &Environment::default()).unwrap();
+        snapshot.set_memory_limit(1024 * 1024).unwrap();
+        snapshot.add_files_to_snapshot(files.path());
+        snapshot.save_index().unwrap();
+
+        assert_eq!(snapshot.read_back_copies().unwrap().verified, 1);
+    }
+}
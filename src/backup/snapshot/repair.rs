@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use super::compression::content_of;
+use super::files::Files;
+use super::index::Content;
+use super::Snapshot;
+use crate::parallel;
+
+/// File stored in a snapshot that doesn't match its checksum in the index.
+pub struct CorruptedFile {
+    /// Original path of the entry.
+    pub path: PathBuf,
+    /// Location of the damaged data in the snapshot.
+    pub data: PathBuf,
+    content: Content,
+    compressed: bool,
+}
+
+impl Snapshot {
+    /// Hashes files stored in this snapshot again and returns those that
+    /// don't match their checksums in the index, together with the number
+    /// of stored files that have no checksum. Encrypted files can't be read
+    /// without the private key, so they aren't hashed.
+    pub fn find_corrupted_files(&self) -> (Vec<CorruptedFile>, usize) {
+        if self.is_encrypted(&self.timestamp) {
+            return (vec![], 0);
+        }
+        let files_root = self.files_root(&self.timestamp);
+        let compressed = self.is_compressed(&self.timestamp);
+        let mut not_recorded = 0;
+        let mut recorded = vec![];
+        for entry in &self.index.entries {
+            // Chunks are verified on their own.
+            if entry.timestamp != self.timestamp || !entry.chunks.is_empty() {
+                continue;
+            }
+            let data = Files::to_snapshot_path_unchecked(&files_root, &entry.path);
+            match &entry.content {
+                Some(content) => recorded.push(CorruptedFile {
+                    path: entry.path.clone(),
+                    data,
+                    content: content.clone(),
+                    compressed,
+                }),
+                None if data.symlink_metadata().is_ok_and(|m| m.is_file()) => not_recorded += 1,
+                None => (),
+            }
+        }
+        debug!("Verifying content of {} file(s)", recorded.len());
+        let corrupted = parallel::map(recorded, |file| match content_of(&file.data, compressed) {
+            Ok(actual) if actual == file.content => None,
+            _ => Some(file),
+        });
+        (corrupted.into_iter().flatten().collect(), not_recorded)
+    }
+
+    /// Location of an intact copy of `file` in this snapshot: data of an
+    /// entry with the same path and checksum, stored in this snapshot and
+    /// still matching the checksum. The copy must be stored the same way,
+    /// compressed or not, and can't be encrypted.
+    pub fn find_intact_copy(&self, file: &CorruptedFile) -> Option<PathBuf> {
+        if self.is_compressed(&self.timestamp) != file.compressed
+            || self.is_encrypted(&self.timestamp)
+        {
+            return None;
+        }
+        let entry = self
+            .index
+            .entries
+            .iter()
+            .find(|e| e.timestamp == self.timestamp && e.path == file.path)?;
+        if entry.content.as_ref() != Some(&file.content) {
+            return None;
+        }
+        let data =
+            Files::to_snapshot_path_unchecked(&self.files_root(&self.timestamp), &entry.path);
+        match content_of(&data, file.compressed) {
+            Ok(actual) if actual == file.content => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Replaces damaged data of `file` with an intact `copy`. The copy is
+    /// written next to the damaged file first and checked again, so the
+    /// damaged file is replaced only by a complete copy.
+    pub fn repair_file(&self, file: &CorruptedFile, copy: &Path) -> io::Result<()> {
+        let mut name = file.data.file_name().unwrap_or_default().to_owned();
+        name.push(".repair");
+        let temporary = file.data.with_file_name(name);
+        fs::copy(copy, &temporary)?;
+        if content_of(&temporary, file.compressed)? != file.content {
+            fs::remove_file(&temporary)?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "copy was changed while it was read",
+            ));
+        }
+        fs::rename(&temporary, &file.data)
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, trace};
+
+use super::files::Files;
+use super::{EntryInfo, EntryKind, Snapshot};
+
+#[derive(Clone, Copy, Default)]
+pub struct RestoreOptions {
+    /// Replace files that already exist in the target.
+    pub overwrite: bool,
+    /// Only report what would be restored, without touching the target.
+    pub dry_run: bool,
+    /// Don't apply permissions, owner and modification time recorded in
+    /// the index.
+    pub ignore_attributes: bool,
+}
+
+#[derive(Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub size: u64, // in bytes
+    /// Files and symlinks that were written, or would be in a dry run.
+    pub written: Vec<EntryInfo>,
+    /// Files that already exist in the target and were left untouched.
+    pub skipped: Vec<PathBuf>,
+    /// Entries whose data is missing in the backup.
+    pub missing: Vec<PathBuf>,
+    /// Special files that were only indexed or couldn't be created.
+    pub not_recreated: Vec<PathBuf>,
+}
+
+pub(super) enum Outcome {
+    Restored(u64),
+    Skipped,
+    Missing,
+    NotRecreated,
+}
+
+impl Snapshot {
+    /// Copies entries indexed under any of `paths`, or all entries when no
+    /// paths are given, into `target`. Absolute paths of entries are
+    /// preserved the same way as in the files folder. Data of entries stored
+    /// in earlier snapshots is read from them, following the incremental
+    /// chain.
+    pub fn restore(
+        &self,
+        paths: &[PathBuf],
+        target: &Path,
+        options: RestoreOptions,
+    ) -> Result<RestoreReport, String> {
+        let selected = |entry: &EntryInfo| {
+            paths.is_empty() || paths.iter().any(|path| entry.path.starts_with(path))
+        };
+        let mut entries: Vec<EntryInfo> = self
+            .resolve_entries()
+            .into_iter()
+            .filter(selected)
+            .collect();
+        for path in paths {
+            if !entries.iter().any(|entry| entry.path.starts_with(path)) {
+                return Err(format!(
+                    "Path '{}' is not indexed in snapshot {}",
+                    path.display(),
+                    self.timestamp
+                ));
+            }
+        }
+        // Folders are restored before their content.
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        debug!(
+            "Restoring {} entries from {} to {}",
+            entries.len(),
+            self.timestamp,
+            target.display()
+        );
+        let links: HashMap<&Path, u32> = self
+            .index
+            .entries
+            .iter()
+            .filter_map(|e| Some((e.path.as_path(), e.link?)))
+            .collect();
+        // Destination of the first restored file of every link group.
+        let mut linked: HashMap<u32, PathBuf> = HashMap::new();
+        let mut report = RestoreReport::default();
+        let mut directories = vec![];
+        for entry in entries {
+            let destination = Files::to_snapshot_path_unchecked(target, &entry.path);
+            trace!(
+                "Restoring {} from {}",
+                entry.path.display(),
+                entry.stored_in
+            );
+            let link = links.get(entry.path.as_path());
+            let outcome = match link.and_then(|link| linked.get(link)) {
+                Some(original) if !options.dry_run && entry.kind == EntryKind::File => {
+                    restore_hardlink(original, &destination, options)
+                        .or_else(|_| restore_entry(&entry, &destination, options))
+                }
+                _ => restore_entry(&entry, &destination, options),
+            }
+            .map_err(|e| format!("Cannot restore '{}' ({})", entry.path.display(), e))?;
+            if let (Some(link), Outcome::Restored(_)) = (link, &outcome) {
+                linked.entry(*link).or_insert_with(|| destination.clone());
+            }
+            match outcome {
+                Outcome::Restored(size) => {
+                    report.restored += 1;
+                    report.size += size;
+                    if entry.kind != EntryKind::Directory {
+                        report.written.push(entry);
+                    } else if !options.dry_run && !options.ignore_attributes {
+                        directories.push((destination, entry.attributes));
+                    }
+                }
+                Outcome::Skipped => report.skipped.push(entry.path),
+                Outcome::Missing => report.missing.push(entry.path),
+                Outcome::NotRecreated => report.not_recreated.push(entry.path),
+            }
+        }
+        // Restoring content of a folder changes its modification time.
+        for (destination, attributes) in directories.iter().rev() {
+            attributes
+                .apply(destination, EntryKind::Directory)
+                .map_err(|e| format!("Cannot restore '{}' ({})", destination.display(), e))?;
+        }
+        Ok(report)
+    }
+}
+
+pub(super) fn restore_entry(
+    entry: &EntryInfo,
+    destination: &Path,
+    options: RestoreOptions,
+) -> io::Result<Outcome> {
+    if entry.kind == EntryKind::Missing {
+        return Ok(Outcome::Missing);
+    }
+    if entry.kind == EntryKind::Directory {
+        if !options.dry_run {
+            fs::create_dir_all(destination)?;
+        }
+        return Ok(Outcome::Restored(0));
+    }
+    let recreatable = entry.special.as_ref().is_some_and(|s| s.node.is_some());
+    if entry.kind == EntryKind::Special && !recreatable {
+        return Ok(Outcome::NotRecreated);
+    }
+    let exists = destination.symlink_metadata().is_ok();
+    if exists && !options.overwrite {
+        return Ok(Outcome::Skipped);
+    }
+    if options.dry_run {
+        return Ok(Outcome::Restored(entry.size));
+    }
+    if exists {
+        fs::remove_file(destination)?;
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let size = if entry.kind == EntryKind::Symlink {
+        restore_symlink(&fs::read_link(&entry.data)?, destination)?;
+        0
+    } else if let Some(special) = &entry.special {
+        if let Err(e) = special.recreate(destination) {
+            debug!("Cannot recreate {} ({})", destination.display(), e);
+            return Ok(Outcome::NotRecreated);
+        }
+        0
+    } else if entry.is_transformed() {
+        let mut file = File::create(destination)?;
+        // A partial file would be skipped when the restore is retried.
+        let copied = entry
+            .open_data()
+            .and_then(|mut data| io::copy(&mut data, &mut file));
+        if copied.is_err() {
+            fs::remove_file(destination).ok();
+        }
+        copied?
+    } else {
+        let size = fs::copy(&entry.data, destination)?;
+        if let Some(modified) = entry.modified {
+            File::options()
+                .write(true)
+                .open(destination)?
+                .set_modified(modified)?;
+        }
+        size
+    };
+    if !options.ignore_attributes {
+        entry.attributes.apply(destination, entry.kind)?;
+    }
+    Ok(Outcome::Restored(size))
+}
+
+/// Restores a file as a hardlink to `original`, another file of the same
+/// link group restored before.
+fn restore_hardlink(
+    original: &Path,
+    destination: &Path,
+    options: RestoreOptions,
+) -> io::Result<Outcome> {
+    if destination.symlink_metadata().is_ok() {
+        if !options.overwrite {
+            return Ok(Outcome::Skipped);
+        }
+        fs::remove_file(destination)?;
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::hard_link(original, destination)?;
+    Ok(Outcome::Restored(0))
+}
+
+#[cfg(unix)]
+fn restore_symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn restore_symlink(original: &Path, link: &Path) -> io::Result<()> {
+    if original.is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
+    }
+}
@@ -0,0 +1,182 @@
+//! Named pipes, sockets and device nodes. They have no data that could be
+//! copied, so depending on the policy they are left out of the snapshot or
+//! only indexed, optionally with what is needed to create them on restore.
+
+use std::fmt::Display;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// What to do with named pipes, sockets and device nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpecialFilesPolicy {
+    /// Leave them out of the snapshot.
+    Skip,
+    /// Index them without any data.
+    IndexOnly,
+    /// Index them with their permissions and device numbers, so they can
+    /// be created again on restore.
+    RecreateOnRestore,
+}
+
+/// Kind of a special file, as written in the index.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpecialKind {
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+impl SpecialKind {
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::Fifo, "fifo"),
+        (Self::Socket, "socket"),
+        (Self::CharDevice, "char"),
+        (Self::BlockDevice, "block"),
+    ];
+
+    fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(kind, _)| *kind == self)
+            .unwrap()
+            .1
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(kind, _)| *kind)
+    }
+}
+
+/// Named pipe, socket or device node, written in the index before the path
+/// as `special:<kind>`. Those that can be recreated on restore also have
+/// their permissions and device number, as `special:<kind>:<mode>:<device>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Special {
+    pub kind: SpecialKind,
+    /// Mode (in octal in the index) and device number.
+    pub node: Option<(u32, u64)>,
+}
+
+impl Special {
+    pub fn parse(field: &str) -> Option<Self> {
+        let mut parts = field.strip_prefix("special:")?.split(':');
+        let kind = SpecialKind::parse(parts.next()?)?;
+        let node = match (parts.next(), parts.next(), parts.next()) {
+            (None, _, _) => None,
+            (Some(mode), Some(device), None) => {
+                Some((u32::from_str_radix(mode, 8).ok()?, device.parse().ok()?))
+            }
+            _ => return None,
+        };
+        Some(Self { kind, node })
+    }
+}
+
+impl Display for Special {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "special:{}", self.kind.name())?;
+        match self.node {
+            Some((mode, device)) => write!(f, ":{:o}:{}", mode, device),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Special {
+    /// Returns `None` for files, folders and symlinks. Permissions and
+    /// the device number are recorded only when `recreatable` is set.
+    #[cfg(unix)]
+    pub fn of(metadata: &Metadata, recreatable: bool) -> Option<Self> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_fifo() {
+            SpecialKind::Fifo
+        } else if file_type.is_socket() {
+            SpecialKind::Socket
+        } else if file_type.is_char_device() {
+            SpecialKind::CharDevice
+        } else if file_type.is_block_device() {
+            SpecialKind::BlockDevice
+        } else {
+            return None;
+        };
+        let node = recreatable.then(|| (metadata.mode() & 0o7777, metadata.rdev()));
+        Some(Self { kind, node })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &Metadata, _recreatable: bool) -> Option<Self> {
+        None
+    }
+
+    /// Creates the special file at `destination`. Fails if the index
+    /// doesn't have what is needed to create it.
+    #[cfg(unix)]
+    pub fn recreate(&self, destination: &Path) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (mode, device) = self.node.ok_or_else(not_recorded)?;
+        let path = CString::new(destination.as_os_str().as_bytes())?;
+        let mode = mode as libc::mode_t;
+        let result = match self.kind {
+            SpecialKind::Fifo => unsafe { libc::mkfifo(path.as_ptr(), mode) },
+            kind => {
+                let file_type = match kind {
+                    SpecialKind::Socket => libc::S_IFSOCK,
+                    SpecialKind::CharDevice => libc::S_IFCHR,
+                    _ => libc::S_IFBLK,
+                };
+                unsafe { libc::mknod(path.as_ptr(), file_type | mode, device as libc::dev_t) }
+            }
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn recreate(&self, _destination: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "special files are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn not_recorded() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "permissions and device number were not recorded",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_special() {
+        let fifo = Special::parse("special:fifo").unwrap();
+        assert_eq!(fifo.kind, SpecialKind::Fifo);
+        assert_eq!(fifo.node, None);
+        assert_eq!(fifo.to_string(), "special:fifo");
+
+        let device = Special::parse("special:char:620:34816").unwrap();
+        assert_eq!(device.kind, SpecialKind::CharDevice);
+        assert_eq!(device.node, Some((0o620, 34816)));
+        assert_eq!(device.to_string(), "special:char:620:34816");
+
+        assert!(Special::parse("special:door").is_none());
+        assert!(Special::parse("special:block:620").is_none());
+        assert!(Special::parse("link:1").is_none());
+    }
+}
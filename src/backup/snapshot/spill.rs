@@ -0,0 +1,307 @@
+//! Bounded-memory mode. The index of a new snapshot is written to the disk
+//! entry by entry and the base index is read as a sorted stream, so memory
+//! doesn't grow with the number of backed up entries.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tracing::{debug, warn};
+
+use super::index::{split_line, Content, IndexEntry};
+use super::timestamp::Timestamp;
+
+/// Memory taken by a line of index in addition to its text.
+const LINE_OVERHEAD: u64 = 64;
+
+/// Index of a new snapshot that is written to the disk immediately.
+pub struct IndexWriter {
+    writer: BufWriter<File>,
+    timestamp: Timestamp,
+    pub indexed: usize,
+    /// Entries whose data is stored in the snapshot being written.
+    pub copied: usize,
+}
+
+impl IndexWriter {
+    pub fn create(location: &Path, timestamp: Timestamp) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(location)?),
+            timestamp,
+            indexed: 0,
+            copied: 0,
+        })
+    }
+
+    pub fn push(&mut self, entry: &IndexEntry) -> io::Result<()> {
+        writeln!(self.writer, "{}", entry)?;
+        self.indexed += 1;
+        if entry.timestamp == self.timestamp {
+            self.copied += 1;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()
+    }
+}
+
+/// Base index read in the order of paths. Entries must be looked up in
+/// ascending order, which is the order of a walk sorted by file names.
+pub struct SortedIndex {
+    lines: Lines<BufReader<File>>,
+    current: Option<(PathBuf, Timestamp, Option<Content>, Option<SystemTime>)>,
+}
+
+impl SortedIndex {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut index = Self {
+            lines: BufReader::new(File::open(path)?).lines(),
+            current: None,
+        };
+        index.advance();
+        Ok(index)
+    }
+
+    fn advance(&mut self) {
+        self.current = match self.lines.next() {
+            Some(Ok(line)) => match parse_line(&line) {
+                Some((timestamp, content, modified, path)) => {
+                    Some((PathBuf::from(path), timestamp, content, modified))
+                }
+                None => {
+                    warn!("Invalid line in base index: {}", line);
+                    None
+                }
+            },
+            _ => None,
+        };
+    }
+
+    /// Entries looked up out of order are not found, so they are copied again.
+    pub fn find(
+        &mut self,
+        absolute_entry: &Path,
+    ) -> Option<(Timestamp, Option<Content>, Option<SystemTime>)> {
+        loop {
+            let (path, timestamp, content, modified) = self.current.as_ref()?;
+            match path.as_path().cmp(absolute_entry) {
+                Ordering::Less => self.advance(),
+                Ordering::Equal => return Some((timestamp.clone(), content.clone(), *modified)),
+                Ordering::Greater => return None,
+            }
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<(Timestamp, Option<Content>, Option<SystemTime>, &str)> {
+    let line = split_line(line)?;
+    Some((
+        Timestamp::parse_from(line.timestamp)?,
+        line.content,
+        line.attributes.modified,
+        line.path,
+    ))
+}
+
+fn path_of(line: &str) -> &Path {
+    Path::new(split_line(line).map_or(line, |line| line.path))
+}
+
+/// Returns a path of the index sorted by paths. If the index is already
+/// sorted it is used as is. Otherwise it is sorted in runs that fit into
+/// `memory_limit` bytes, which are written to `temp` and merged.
+pub fn sort_index(index: &Path, temp: &Path, memory_limit: u64) -> io::Result<PathBuf> {
+    if is_sorted(index)? {
+        debug!("Base index is already sorted");
+        return Ok(index.to_owned());
+    }
+    fs::create_dir_all(temp)?;
+
+    let mut runs = vec![];
+    let mut chunk: Vec<String> = vec![];
+    let mut chunk_size = 0;
+    for line in BufReader::new(File::open(index)?).lines() {
+        let line = line?;
+        chunk_size += line.len() as u64 + LINE_OVERHEAD;
+        chunk.push(line);
+        if chunk_size >= memory_limit {
+            runs.push(write_run(&mut chunk, temp, runs.len())?);
+            chunk_size = 0;
+        }
+    }
+    if !chunk.is_empty() || runs.is_empty() {
+        runs.push(write_run(&mut chunk, temp, runs.len())?);
+    }
+    debug!("Base index sorted in {} run(s)", runs.len());
+
+    let sorted = temp.join("sorted-index.txt");
+    merge_runs(&runs, &sorted)?;
+    for run in runs {
+        fs::remove_file(run)?;
+    }
+    Ok(sorted)
+}
+
+fn is_sorted(index: &Path) -> io::Result<bool> {
+    let mut previous: Option<String> = None;
+    for line in BufReader::new(File::open(index)?).lines() {
+        let line = line?;
+        if let Some(previous) = &previous {
+            if path_of(previous) > path_of(&line) {
+                return Ok(false);
+            }
+        }
+        previous = Some(line);
+    }
+    Ok(true)
+}
+
+fn write_run(chunk: &mut Vec<String>, temp: &Path, number: usize) -> io::Result<PathBuf> {
+    chunk.sort_unstable_by(|a, b| path_of(a).cmp(path_of(b)));
+    let run = temp.join(format!("run-{}.txt", number));
+    let mut writer = BufWriter::new(File::create(&run)?);
+    for line in chunk.drain(..) {
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(run)
+}
+
+struct RunHead {
+    line: String,
+    run: usize,
+}
+
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RunHead {}
+
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        path_of(&self.line)
+            .cmp(path_of(&other.line))
+            .then(self.run.cmp(&other.run))
+    }
+}
+
+fn merge_runs(runs: &[PathBuf], target: &Path) -> io::Result<()> {
+    let mut readers = runs
+        .iter()
+        .map(|run| Ok(BufReader::new(File::open(run)?).lines()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(line) = reader.next() {
+            heap.push(Reverse(RunHead { line: line?, run }));
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(target)?);
+    while let Some(Reverse(head)) = heap.pop() {
+        writeln!(writer, "{}", head.line)?;
+        if let Some(line) = readers[head.run].next() {
+            heap.push(Reverse(RunHead {
+                line: line?,
+                run: head.run,
+            }));
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_unsorted_index_in_many_runs() {
+        let root = tempfile::tempdir().unwrap();
+        let index = root.path().join("index.txt");
+        fs::write(
+            &index,
+            concat!(
+                "2021-07-15_18.34 /b/c\n",
+                "2021-07-15_18.34 /a\n",
+                "2021-07-15_18.34 /b\n",
+                "2021-07-15_18.34 /a/z\n",
+                "2021-07-15_18.34 /a b\n",
+            ),
+        )
+        .unwrap();
+
+        // every line is a separate run
+        let sorted = sort_index(&index, &root.path().join("temp"), 1).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(sorted).unwrap(),
+            concat!(
+                "2021-07-15_18.34 /a\n",
+                "2021-07-15_18.34 /a/z\n",
+                "2021-07-15_18.34 /a b\n",
+                "2021-07-15_18.34 /b\n",
+                "2021-07-15_18.34 /b/c\n",
+            )
+        );
+    }
+
+    #[test]
+    fn sorted_index_is_used_as_is() {
+        let root = tempfile::tempdir().unwrap();
+        let index = root.path().join("index.txt");
+        fs::write(&index, "2021-07-15_18.34 /a\n2021-07-15_18.34 /a/b\n").unwrap();
+
+        let sorted = sort_index(&index, &root.path().join("temp"), 1).unwrap();
+
+        assert_eq!(sorted, index);
+        assert!(!root.path().join("temp").exists());
+    }
+
+    #[test]
+    fn find_entries_in_ascending_order() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().canonicalize().unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(folder.join(name), b"").unwrap();
+        }
+        let index = folder.join("index.txt");
+        fs::write(
+            &index,
+            format!(
+                "2021-07-15_18.34 {}\n2021-07-16_18.34 0:{} {}\n",
+                folder.join("a").display(),
+                "0".repeat(64),
+                folder.join("c").display()
+            ),
+        )
+        .unwrap();
+
+        let mut sorted = SortedIndex::open(&index).unwrap();
+
+        assert_eq!(
+            sorted.find(&folder.join("a")).unwrap().0.to_string(),
+            "2021-07-15_18.34"
+        );
+        assert!(sorted.find(&folder.join("b")).is_none());
+        let (timestamp, content, _) = sorted.find(&folder.join("c")).unwrap();
+        assert_eq!(timestamp.to_string(), "2021-07-16_18.34");
+        assert_eq!(content.unwrap().size, 0);
+        // out of order
+        assert!(sorted.find(&folder.join("a")).is_none());
+    }
+}
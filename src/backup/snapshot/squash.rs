@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+use tracing::{debug, trace};
+
+use super::files::Files;
+use super::index_cache;
+use super::metadata::ChainLink;
+use super::parity::remove_parity;
+use super::restore::{restore_entry, RestoreOptions};
+use super::timestamp::Timestamp;
+use super::{EntryKind, Snapshot};
+
+/// Where entries of a snapshot were stored before it was made full.
+pub struct SquashedSnapshot {
+    timestamp: Timestamp,
+    references: HashMap<PathBuf, Timestamp>,
+}
+
+impl Snapshot {
+    /// Copies data of all entries stored in earlier snapshots into this one
+    /// and indexes them as stored here, so the snapshot no longer depends on
+    /// any other snapshot. Returns where the entries were stored before
+    /// together with the number of copied entries.
+    pub fn make_full(&mut self) -> Result<(SquashedSnapshot, usize), String> {
+        if Snapshot::is_signed(&self.location) {
+            return Err(format!(
+                "Snapshot {} is signed and cannot be rewritten",
+                self.timestamp
+            ));
+        }
+        if self.is_archived() {
+            return Err(format!(
+                "Snapshot {} is archived and cannot be rewritten",
+                self.timestamp
+            ));
+        }
+        // Copies would have to be decrypted, and could end up unencrypted.
+        let mut referenced = self.index.entries.iter().map(|e| &e.timestamp);
+        if let Some(encrypted) = referenced.find(|t| self.is_encrypted(t)) {
+            return Err(format!(
+                "Snapshot {} is encrypted and cannot be squashed",
+                encrypted
+            ));
+        }
+        let squashed = SquashedSnapshot {
+            timestamp: self.timestamp.clone(),
+            references: self
+                .index
+                .entries
+                .iter()
+                .map(|e| (e.path.clone(), e.timestamp.clone()))
+                .collect(),
+        };
+
+        let entries = self.resolve_entries();
+        // Nothing is copied unless the whole snapshot can be made full.
+        if let Some(entry) = entries.iter().find(|e| e.kind == EntryKind::Missing) {
+            return Err(format!(
+                "Data of '{}' is missing in snapshot {}",
+                entry.path.display(),
+                entry.stored_in
+            ));
+        }
+        let files_root = self.location.join("files");
+        let options = RestoreOptions {
+            overwrite: true,
+            dry_run: false,
+            // Copies in the snapshot keep permissions of the snapshot.
+            ignore_attributes: true,
+        };
+        // Copies are stored the same way as files already stored here.
+        let compression = self.metadata.as_ref().and_then(|m| m.compression);
+        let mut copied = 0;
+        for (index_entry, entry) in self.index.entries.iter_mut().zip(entries) {
+            // Chunks may be stored in other snapshots, so chunked files are
+            // copied as a whole.
+            if index_entry.timestamp == self.timestamp && index_entry.chunks.is_empty() {
+                continue;
+            }
+            if index_entry.special.is_some() {
+                // There is no data to copy.
+                index_entry.timestamp = self.timestamp.clone();
+                continue;
+            }
+            trace!("Copying {} from {}", entry.path.display(), entry.stored_in);
+            let destination = Files::to_snapshot_path_unchecked(&files_root, &entry.path);
+            let copy = restore_entry(&entry, &destination, options).and_then(|_| {
+                match (compression, entry.kind) {
+                    (Some(compression), EntryKind::File) => {
+                        compression.compress_in_place(&destination)
+                    }
+                    _ => Ok(()),
+                }
+            });
+            match copy {
+                Ok(_) => (),
+                Err(e) => {
+                    return Err(format!(
+                        "Cannot copy '{}' from {} ({})",
+                        entry.path.display(),
+                        entry.stored_in,
+                        e
+                    ))
+                }
+            }
+            index_entry.timestamp = self.timestamp.clone();
+            index_entry.chunks.clear();
+            copied += 1;
+        }
+        debug!("Copied {} entries into {}", copied, self.timestamp);
+        if copied > 0 {
+            remove_parity(&self.location)
+                .map_err(|e| format!("Cannot remove parity data of {} ({})", self.timestamp, e))?;
+        }
+
+        self.save_rewritten_index()
+            .map_err(|e| format!("Cannot save index of {} ({})", self.timestamp, e))?;
+        Ok((squashed, copied))
+    }
+
+    /// Points entries that reference one of the squashed `originals` to
+    /// the `squashed` snapshot, if it indexed the same data before it was
+    /// made full. Returns the number of redirected entries.
+    pub fn redirect_entries(
+        &mut self,
+        squashed: &SquashedSnapshot,
+        originals: &[String],
+    ) -> io::Result<usize> {
+        let mut redirected = 0;
+        for entry in &mut self.index.entries {
+            let same_data = squashed.references.get(&entry.path) == Some(&entry.timestamp);
+            if same_data && originals.contains(&entry.timestamp.to_string()) {
+                entry.timestamp = squashed.timestamp.clone();
+                redirected += 1;
+            }
+        }
+        if redirected > 0 {
+            debug!(
+                "Redirected {} entries of {} to {}",
+                redirected, self.timestamp, squashed.timestamp
+            );
+            self.save_rewritten_index()?;
+        }
+        Ok(redirected)
+    }
+
+    /// Updates the link to the previous snapshot in the hash chain after
+    /// the previous snapshot was rewritten.
+    pub fn relink(&self, previous: ChainLink) -> io::Result<()> {
+        let path = self.location.join("metadata.txt");
+        let mut metadata = match self.metadata() {
+            Some(metadata) => metadata.clone(),
+            None => return Ok(()),
+        };
+        if metadata.previous.as_ref().map(|link| &link.snapshot) != Some(&previous.snapshot) {
+            return Ok(());
+        }
+        metadata.previous = Some(previous);
+        metadata.save(&path)
+    }
+
+    fn save_rewritten_index(&mut self) -> io::Result<()> {
+        self.index.save()?;
+        let copied = self.copied_count();
+        let size = self.files.size();
+        if let Some(metadata) = &mut self.metadata {
+            metadata.copied = copied;
+            metadata.incremental = copied < metadata.indexed;
+            metadata.size = size;
+            metadata.save(&self.location.join("metadata.txt"))?;
+        }
+        // The next backup reads the cached index instead of index.txt.
+        let cached = index_cache::save(&self.location, &self.index.location, &self.index.entries);
+        if let Err(e) = cached {
+            debug!("Failed to cache index ({})", e);
+        }
+        Ok(())
+    }
+}
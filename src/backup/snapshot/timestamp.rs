@@ -1,8 +1,16 @@
 use std::{fmt::Display, ops::Sub, time::SystemTime};
 
-use log::trace;
+use tracing::trace;
+
+use crate::environment::{Clock, SystemClock};
 use time::format_description::FormatItem;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Period {
+    Day,
+    Month,
+}
+
 #[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Debug)]
 pub struct Timestamp {
     inner: time::PrimitiveDateTime,
@@ -10,7 +18,11 @@ pub struct Timestamp {
 
 impl Timestamp {
     pub fn now() -> Self {
-        Self::from(time::OffsetDateTime::now_local().unwrap_or(time::OffsetDateTime::now_utc()))
+        Self::now_from(&SystemClock)
+    }
+
+    pub fn now_from(clock: &dyn Clock) -> Self {
+        Self::from(clock.now())
     }
 
     pub fn parse_from(str: &str) -> Option<Self> {
@@ -40,6 +52,21 @@ impl Timestamp {
         now.inner - self.inner
     }
 
+    /// Name of the day or month this timestamp belongs to,
+    /// e.g. "2021-07-15" or "2021-07".
+    pub fn get_period(&self, period: Period) -> String {
+        let date = self.inner.date();
+        match period {
+            Period::Day => format!(
+                "{}-{:02}-{:02}",
+                date.year(),
+                date.month() as u8,
+                date.day()
+            ),
+            Period::Month => format!("{}-{:02}", date.year(), date.month() as u8),
+        }
+    }
+
     fn get_format<'a>() -> Vec<FormatItem<'a>> {
         // Format: yyyy-mm-dd_hh.mm
         time::format_description::parse_borrowed::<1>("[year]-[month]-[day]_[hour].[minute]")
@@ -146,6 +173,14 @@ mod tests {
         assert_eq!(ts_system_now.inner.minute(), ts_now.inner.minute());
     }
 
+    #[test]
+    fn timestamp_period() {
+        let ts = Timestamp::parse_from("2021-07-05_18.34").unwrap();
+
+        assert_eq!(ts.get_period(Period::Day), "2021-07-05");
+        assert_eq!(ts.get_period(Period::Month), "2021-07");
+    }
+
     #[test]
     fn next_timestamp() {
         let ts = Timestamp::parse_from("2021-07-15_18.34").unwrap();
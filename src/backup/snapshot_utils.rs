@@ -1,11 +1,61 @@
-use log::{info, trace, warn};
-use std::path::Path;
-
-use super::snapshot::{Snapshot, SnapshotPreview};
-
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tracing::{info, trace, warn};
+
+use super::fingerprints::{APPEND_ONLY_FILE_NAME, FINGERPRINTS_FILE_NAME};
+use super::lock::LOCK_FILE_NAME;
+use super::prune::PRUNED_FILE_NAME;
+use super::settings::SETTINGS_FILE_NAME;
+use super::snapshot::{Snapshot, SnapshotPreview, FILES_CACHE_FILE_NAME, PARTIAL_SUFFIX};
+use super::trash::TRASH_FOLDER_NAME;
+use super::verification::{SCRUBBED_FILE_NAME, VERIFIED_FILE_NAME};
+
+/// Files and folders in the backup folder that belong to mizeria, but
+/// aren't snapshots.
+const BACKUP_FILE_NAMES: [&str; 9] = [
+    SETTINGS_FILE_NAME,
+    FILES_CACHE_FILE_NAME,
+    FINGERPRINTS_FILE_NAME,
+    APPEND_ONLY_FILE_NAME,
+    PRUNED_FILE_NAME,
+    VERIFIED_FILE_NAME,
+    SCRUBBED_FILE_NAME,
+    TRASH_FOLDER_NAME,
+    LOCK_FILE_NAME,
+];
+
+/// Names of snapshots have a fixed width, so the latest snapshot has the
+/// greatest name. Only that folder is checked to be a valid snapshot,
+/// unless it isn't one.
 pub fn get_latest_snapshot_preview(root: &Path) -> Option<SnapshotPreview> {
-    let snapshot_previews = load_all_snapshot_previews(root);
-    snapshot_previews.last().cloned()
+    trace!("Looking for the latest snapshot at: {:?}", root);
+    let mut names: Vec<OsString> = root
+        .read_dir()
+        .ok()?
+        .filter_map(std::result::Result::ok)
+        .map(|entry| entry.file_name())
+        .collect();
+    names.sort_unstable_by(|a, b| b.cmp(a));
+    names
+        .iter()
+        .find_map(|name| SnapshotPreview::new(&root.join(name)))
+}
+
+/// Name and folder of the newest snapshot that wasn't finished.
+pub fn find_unfinished_snapshot(root: &Path) -> Option<(String, PathBuf)> {
+    root.read_dir()
+        .ok()?
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry
+                .file_name()
+                .to_str()?
+                .strip_suffix(PARTIAL_SUFFIX)?
+                .to_owned();
+            Some((name, entry.path()))
+        })
+        .max()
 }
 
 pub fn load_all_snapshot_previews(root: &Path) -> Vec<SnapshotPreview> {
@@ -18,6 +68,33 @@ pub fn load_all_snapshots(root: &Path) -> Vec<Snapshot> {
     load_all(root, Snapshot::open)
 }
 
+pub fn load_all_snapshots_without_index(root: &Path) -> Vec<Snapshot> {
+    trace!("Loading all snapshots without index at: {:?}", root);
+    load_all(root, Snapshot::open_without_index)
+}
+
+/// Entries in the backup folder that aren't snapshots.
+pub fn load_foreign_entries(root: &Path) -> Vec<PathBuf> {
+    trace!("Loading foreign entries at: {:?}", root);
+    let backup_root = match root.read_dir() {
+        Ok(backup_root) => backup_root,
+        Err(_) => return vec![],
+    };
+
+    let mut entries: Vec<PathBuf> = backup_root
+        .filter_map(std::result::Result::ok)
+        .filter(|entry| {
+            !BACKUP_FILE_NAMES
+                .iter()
+                .any(|name| entry.file_name() == *name)
+        })
+        .map(|entry| entry.path())
+        .filter(|path| !path.is_dir() || SnapshotPreview::new(path).is_none())
+        .collect();
+    entries.sort_unstable();
+    entries
+}
+
 fn load_all<F, T>(backup_root: &Path, get_snapshot: F) -> Vec<T>
 where
     F: Fn(&Path) -> Option<T>,
@@ -35,6 +112,11 @@ where
         }
 
         let entry_file_name = entry.file_name().to_string_lossy().as_ref().to_owned();
+        // Another backup is creating it, or was interrupted.
+        if entry_file_name.ends_with(PARTIAL_SUFFIX) {
+            warn!("Skipped unfinished snapshot: \"{}\"", entry_file_name);
+            continue;
+        }
 
         info!("Loading snapshot: {}", entry_file_name);
         let preview = match get_snapshot(entry.path().as_path()) {
@@ -56,7 +138,6 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile;
 
     #[test]
     fn return_empty_vec_when_directory_is_empty() {
@@ -87,6 +168,44 @@ mod tests {
         assert_eq!(snapshots.len(), 0);
     }
 
+    #[test]
+    fn return_foreign_entries_without_snapshots() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::File::create(root.join("some_file")).unwrap();
+        std::fs::create_dir(root.join("some_dir")).unwrap();
+        std::fs::File::create(root.join(FINGERPRINTS_FILE_NAME)).unwrap();
+
+        let snapshot = root.join("2023-06-25_19.49");
+        std::fs::create_dir(&snapshot).unwrap();
+        std::fs::create_dir(snapshot.join("files")).unwrap();
+        std::fs::File::create(snapshot.join("index.txt")).unwrap();
+
+        let entries = load_foreign_entries(root);
+
+        assert_eq!(entries, vec![root.join("some_dir"), root.join("some_file")]);
+    }
+
+    #[test]
+    fn return_latest_valid_snapshot_preview() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        for name in ["2023-06-25_19.49", "2023-06-26_19.49", "2023-06-24_19.49"] {
+            let snapshot = root.join(name);
+            std::fs::create_dir(&snapshot).unwrap();
+            std::fs::create_dir(snapshot.join("files")).unwrap();
+            std::fs::File::create(snapshot.join("index.txt")).unwrap();
+        }
+        // newer, but not a snapshot
+        std::fs::create_dir(root.join("2023-06-27_19.49")).unwrap();
+        std::fs::create_dir(root.join("some_dir")).unwrap();
+
+        let latest = get_latest_snapshot_preview(root).unwrap();
+
+        assert_eq!(latest.name(), "2023-06-26_19.49");
+        assert!(Some(latest) == load_all_snapshot_previews(root).pop());
+    }
+
     #[test]
     fn return_snapshot_preview_from_directory() {
         let root = tempfile::tempdir().unwrap();
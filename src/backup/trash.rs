@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::debug;
+
+/// Folder in the backup where pruned snapshots are kept for some time
+/// before they are removed for good.
+pub const TRASH_FOLDER_NAME: &str = ".trash";
+/// Time every snapshot was moved to the trash. Stored in the trash folder
+/// as `<snapshot> <RFC 3339 time>` lines.
+const TRASHED_FILE_NAME: &str = "trashed.txt";
+
+/// What happens to snapshots removed by prune.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Removal {
+    /// Moved to the trash and removed after the retention time.
+    Trash(Duration),
+    Delete,
+    /// Content of files is overwritten with zeros before they are deleted.
+    Shred,
+}
+
+pub struct Trash {
+    location: PathBuf,
+    entries: BTreeMap<String, OffsetDateTime>,
+}
+
+impl Trash {
+    /// Snapshots in the trash folder without a recorded time are treated
+    /// as trashed just now, so they are never removed too early.
+    pub fn open(backup: &Path, now: OffsetDateTime) -> io::Result<Self> {
+        let location = backup.join(TRASH_FOLDER_NAME);
+        let mut recorded = BTreeMap::new();
+        let log = location.join(TRASHED_FILE_NAME);
+        if log.exists() {
+            for line in fs::read_to_string(&log)?.lines() {
+                let entry = line.split_once(' ').and_then(|(snapshot, time)| {
+                    Some((snapshot, OffsetDateTime::parse(time, &Rfc3339).ok()?))
+                });
+                if let Some((snapshot, time)) = entry {
+                    recorded.insert(snapshot.to_string(), time);
+                }
+            }
+        }
+
+        let mut entries = BTreeMap::new();
+        if location.is_dir() {
+            for entry in location.read_dir()? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                let time = recorded.get(&name).copied().unwrap_or(now);
+                entries.insert(name, time);
+            }
+        }
+        Ok(Self { location, entries })
+    }
+
+    /// Snapshots in the trash with the time they were moved there.
+    pub fn snapshots(&self) -> impl Iterator<Item = (&String, &OffsetDateTime)> {
+        self.entries.iter()
+    }
+
+    /// Moves a snapshot folder to the trash. A snapshot with the same name
+    /// that is already in the trash is replaced.
+    pub fn put(&mut self, snapshot: &Path, name: &str, now: OffsetDateTime) -> io::Result<()> {
+        fs::create_dir_all(&self.location)?;
+        let target = self.location.join(name);
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(snapshot, &target)?;
+        debug!("Moved snapshot to trash: {}", snapshot.display());
+        self.entries.insert(name.to_string(), now);
+        self.save()
+    }
+
+    /// Moves a snapshot from the trash back into the backup.
+    pub fn restore(&mut self, name: &str, backup: &Path) -> io::Result<()> {
+        if !self.entries.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Snapshot {} is not in the trash", name),
+            ));
+        }
+        let target = backup.join(name);
+        if target.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Snapshot {} already exists in the backup", name),
+            ));
+        }
+        fs::rename(self.location.join(name), target)?;
+        self.entries.remove(name);
+        self.save()
+    }
+
+    /// Removes snapshots that were in the trash longer than `retention`
+    /// and returns their names.
+    pub fn remove_expired(
+        &mut self,
+        now: OffsetDateTime,
+        retention: Duration,
+    ) -> io::Result<Vec<String>> {
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, trashed)| now - **trashed >= retention)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &expired {
+            fs::remove_dir_all(self.location.join(name))?;
+            debug!("Removed snapshot from trash: {}", name);
+            self.entries.remove(name);
+        }
+        if !expired.is_empty() {
+            self.save()?;
+        }
+        Ok(expired)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (snapshot, time) in &self.entries {
+            let time = time
+                .format(&Rfc3339)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            content.push_str(&format!("{} {}\n", snapshot, time));
+        }
+        fs::write(self.location.join(TRASHED_FILE_NAME), content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn create_snapshot(backup: &Path, name: &str) -> PathBuf {
+        let snapshot = backup.join(name);
+        fs::create_dir_all(snapshot.join("files")).unwrap();
+        snapshot
+    }
+
+    #[test]
+    fn put_and_restore_snapshot() {
+        let backup = tempfile::tempdir().unwrap();
+        let now = OffsetDateTime::from_unix_timestamp(1626366840).unwrap();
+        let snapshot = create_snapshot(backup.path(), "2021-07-15_18.34");
+        let mut trash = Trash::open(backup.path(), now).unwrap();
+
+        trash.put(&snapshot, "2021-07-15_18.34", now).unwrap();
+        assert!(!snapshot.exists());
+
+        let mut trash = Trash::open(backup.path(), now + DAY).unwrap();
+        let snapshots: Vec<_> = trash.snapshots().collect();
+        assert_eq!(snapshots, [(&"2021-07-15_18.34".to_string(), &now)]);
+
+        trash.restore("2021-07-15_18.34", backup.path()).unwrap();
+        assert!(snapshot.join("files").is_dir());
+        assert_eq!(trash.snapshots().count(), 0);
+        assert!(trash.restore("2021-07-15_18.34", backup.path()).is_err());
+    }
+
+    #[test]
+    fn remove_only_expired_snapshots() {
+        let backup = tempfile::tempdir().unwrap();
+        let now = OffsetDateTime::from_unix_timestamp(1626366840).unwrap();
+        let older = create_snapshot(backup.path(), "2021-07-15_18.34");
+        let newer = create_snapshot(backup.path(), "2021-07-16_18.34");
+        let mut trash = Trash::open(backup.path(), now).unwrap();
+        trash.put(&older, "2021-07-15_18.34", now).unwrap();
+        trash.put(&newer, "2021-07-16_18.34", now + DAY).unwrap();
+
+        let removed = trash.remove_expired(now + 7 * DAY, 7 * DAY).unwrap();
+
+        assert_eq!(removed, ["2021-07-15_18.34"]);
+        let trash = Trash::open(backup.path(), now).unwrap();
+        let names: Vec<_> = trash.snapshots().map(|(name, _)| name.clone()).collect();
+        assert_eq!(names, ["2021-07-16_18.34"]);
+    }
+}
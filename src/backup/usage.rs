@@ -0,0 +1,154 @@
+//! Space taken by a backup: how it grew with every snapshot and which files
+//! and folders take most of it.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use super::snapshot::Snapshot;
+use super::{Backup, EntryKind};
+
+/// File whose data is stored in a snapshot.
+#[derive(PartialEq, Eq, Debug)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    pub snapshot: String,
+    pub size: u64, // in bytes
+}
+
+pub struct Usage {
+    /// Size of everything in the backup folder, including indexes, counted
+    /// the same way as sizes of snapshots.
+    pub total_size: u64,
+    /// Size of files stored in every snapshot, from the oldest.
+    pub snapshots: Vec<(String, u64)>,
+    /// The largest files stored in the backup, the largest first.
+    pub largest_files: Vec<StoredFile>,
+    /// Folders by the size of files stored directly in them, in all
+    /// snapshots together, the largest first.
+    pub largest_folders: Vec<(PathBuf, u64)>,
+}
+
+impl Usage {
+    pub fn average_snapshot_size(&self) -> u64 {
+        match self.snapshots.len() {
+            0 => 0,
+            count => self.snapshots.iter().map(|(_, size)| size).sum::<u64>() / count as u64,
+        }
+    }
+}
+
+impl Backup {
+    /// Measures the backup, listing `top` largest files and folders. Indexes
+    /// of snapshots are read one at a time.
+    pub fn usage(&self, top: usize) -> Usage {
+        let mut snapshots = vec![];
+        let mut largest_files: Vec<StoredFile> = vec![];
+        let mut folders: HashMap<PathBuf, u64> = HashMap::new();
+        for preview in &self.snapshots {
+            let name = preview.name();
+            let snapshot = match Snapshot::open(&self.location.join(&name)) {
+                Some(snapshot) => snapshot,
+                None => continue,
+            };
+            snapshots.push((name.clone(), snapshot.size()));
+            for entry in snapshot.stored_entries() {
+                if entry.kind != EntryKind::File {
+                    continue;
+                }
+                if let Some(parent) = entry.path.parent() {
+                    *folders.entry(parent.to_owned()).or_default() += entry.size;
+                }
+                largest_files.push(StoredFile {
+                    path: entry.path,
+                    snapshot: name.clone(),
+                    size: entry.size,
+                });
+            }
+            largest_files.sort_by_key(|file| Reverse(file.size));
+            largest_files.truncate(top);
+        }
+        let mut largest_folders: Vec<(PathBuf, u64)> = folders.into_iter().collect();
+        largest_folders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        largest_folders.truncate(top);
+        Usage {
+            total_size: folder_size(&self.location),
+            snapshots,
+            largest_files,
+            largest_folders,
+        }
+    }
+}
+
+fn folder_size(location: &Path) -> u64 {
+    WalkDir::new(location)
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn largest_files_and_folders_of_all_snapshots() {
+        let root = tempfile::tempdir().unwrap();
+        let write_snapshot = |name: &str, files: &[(&str, &str)]| {
+            let snapshot = root.path().join(name);
+            let mut index = String::new();
+            for (path, content) in files {
+                let data = snapshot.join("files").join(path.trim_start_matches('/'));
+                fs::create_dir_all(data.parent().unwrap()).unwrap();
+                fs::write(data, content).unwrap();
+                index.push_str(&format!("{} {}\n", name, path));
+            }
+            fs::write(snapshot.join("index.txt"), index).unwrap();
+        };
+        write_snapshot(
+            "2021-07-15_18.34",
+            &[("/docs/a.txt", "aaaa"), ("/docs/b.txt", "bb")],
+        );
+        write_snapshot("2021-07-16_18.34", &[("/music/c.mp3", "ccccccc")]);
+
+        let usage = Backup::open(root.path()).unwrap().usage(2);
+
+        let names: Vec<&str> = usage
+            .snapshots
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["2021-07-15_18.34", "2021-07-16_18.34"]);
+        assert_eq!(
+            usage.average_snapshot_size(),
+            (usage.snapshots[0].1 + usage.snapshots[1].1) / 2
+        );
+        assert_eq!(
+            usage.largest_files,
+            vec![
+                StoredFile {
+                    path: "/music/c.mp3".into(),
+                    snapshot: "2021-07-16_18.34".into(),
+                    size: 7
+                },
+                StoredFile {
+                    path: "/docs/a.txt".into(),
+                    snapshot: "2021-07-15_18.34".into(),
+                    size: 4
+                },
+            ]
+        );
+        assert_eq!(
+            usage.largest_folders,
+            vec![("/music".into(), 7), ("/docs".into(), 6)]
+        );
+        assert!(usage.total_size > 13);
+    }
+}
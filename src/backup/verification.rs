@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub const VERIFIED_FILE_NAME: &str = "verified.txt";
+pub const SCRUBBED_FILE_NAME: &str = "scrubbed.txt";
+
+/// How much of a backup is verified in a single run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VerificationBudget {
+    /// No snapshot is started after the time has passed.
+    Time(Duration),
+    Snapshots(usize),
+    All,
+}
+
+/// Parses durations like "90s", "15min", "2h" or "7d".
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let split = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = duration.split_at(split);
+    let seconds = match unit {
+        "s" | "sec" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(
+        number.parse::<u64>().ok()?.checked_mul(seconds)?,
+    ))
+}
+
+/// Time of the last successful integrity check, or scrub, of every
+/// snapshot. Stored in the backup folder as `<snapshot> <RFC 3339 time>`
+/// lines.
+pub struct VerificationLog {
+    location: PathBuf,
+    entries: BTreeMap<String, OffsetDateTime>,
+}
+
+impl VerificationLog {
+    /// Invalid lines are ignored, so the snapshot is verified again.
+    pub fn open(backup: &Path) -> io::Result<Self> {
+        Self::open_file(backup.join(VERIFIED_FILE_NAME))
+    }
+
+    /// Log of scrubs, which verify content of stored files.
+    pub fn open_scrubbed(backup: &Path) -> io::Result<Self> {
+        Self::open_file(backup.join(SCRUBBED_FILE_NAME))
+    }
+
+    fn open_file(location: PathBuf) -> io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        if location.exists() {
+            for line in fs::read_to_string(&location)?.lines() {
+                let entry = line
+                    .split_once(' ')
+                    .and_then(|(snapshot, time)| Some((snapshot, parse_time(time)?)));
+                if let Some((snapshot, time)) = entry {
+                    entries.insert(snapshot.to_string(), time);
+                }
+            }
+        }
+        Ok(Self { location, entries })
+    }
+
+    pub fn last_verified(&self, snapshot: &str) -> Option<OffsetDateTime> {
+        self.entries.get(snapshot).copied()
+    }
+
+    pub fn record(&mut self, snapshot: &str, time: OffsetDateTime) {
+        self.entries.insert(snapshot.to_string(), time);
+    }
+
+    /// Forgets snapshots that don't exist anymore.
+    pub fn retain(&mut self, snapshots: &[String]) {
+        self.entries.retain(|name, _| snapshots.contains(name));
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut content = String::new();
+        for (snapshot, time) in &self.entries {
+            let time = time
+                .format(&Rfc3339)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            content.push_str(&format!("{} {}\n", snapshot, time));
+        }
+        fs::write(&self.location, content)
+    }
+}
+
+fn parse_time(time: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(time, &Rfc3339).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_with_units() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15min"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(604800)));
+        assert_eq!(parse_duration("15"), None);
+        assert_eq!(parse_duration("min"), None);
+        assert_eq!(parse_duration("1.5h"), None);
+    }
+
+    #[test]
+    fn save_and_open_verification_log() {
+        let backup = tempfile::tempdir().unwrap();
+        let time = OffsetDateTime::from_unix_timestamp(1626366840).unwrap();
+        let mut log = VerificationLog::open(backup.path()).unwrap();
+        assert!(log.last_verified("2021-07-15_18.34").is_none());
+
+        log.record("2021-07-15_18.34", time);
+        log.record("2021-07-16_18.34", time);
+        log.retain(&["2021-07-15_18.34".to_string()]);
+        log.save().unwrap();
+
+        let log = VerificationLog::open(backup.path()).unwrap();
+        assert_eq!(log.last_verified("2021-07-15_18.34"), Some(time));
+        assert!(log.last_verified("2021-07-16_18.34").is_none());
+    }
+
+    #[test]
+    fn invalid_lines_are_ignored() {
+        let backup = tempfile::tempdir().unwrap();
+        fs::write(
+            backup.path().join(VERIFIED_FILE_NAME),
+            "2021-07-15_18.34 yesterday\ninvalid\n2021-07-16_18.34 2021-07-16T18:40:00Z\n",
+        )
+        .unwrap();
+
+        let log = VerificationLog::open(backup.path()).unwrap();
+
+        assert!(log.last_verified("2021-07-15_18.34").is_none());
+        assert!(log.last_verified("2021-07-16_18.34").is_some());
+    }
+}
@@ -0,0 +1,271 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::backup::{Destination, Filesystem};
+use crate::messages::t;
+
+/// Staging area created in the backup folder, so the destination itself
+/// is measured. It is removed afterwards.
+pub const BENCH_FOLDER_NAME: &str = ".mizeria-bench";
+
+/// Buffer sizes compared while writing a large file.
+const BUFFER_SIZES: [usize; 3] = [64 << 10, 1 << 20, 8 << 20];
+const SMALL_FILE_SIZE: usize = 4 << 10;
+/// Slower destinations benefit from compressing archives, because data
+/// can be compressed faster than it is written.
+const COMPRESSION_THRESHOLD: f64 = 50.0 * (1 << 20) as f64; // bytes per second
+/// Destinations with slower metadata operations, e.g. network shares,
+/// spend most of the time waiting, so more threads than CPUs help.
+const SLOW_METADATA_LATENCY: Duration = Duration::from_millis(1);
+
+pub struct BenchOptions {
+    /// Size of the file written sequentially with every buffer size.
+    pub size: u64,
+    /// Number of small files written.
+    pub files: usize,
+}
+
+pub struct BenchReport {
+    pub filesystem: Filesystem,
+    /// Buffer size with the throughput in bytes per second.
+    pub sequential: Vec<(usize, f64)>,
+    /// Small files written per second.
+    pub small_files: f64,
+    /// Average time of renaming a file or reading its metadata.
+    pub metadata_latency: Duration,
+}
+
+pub struct Recommendation {
+    pub setting: String,
+    pub reason: String,
+}
+
+/// Measures how fast data and metadata are written into `backup`.
+pub fn run(backup: &Path, options: &BenchOptions) -> io::Result<BenchReport> {
+    let filesystem = Destination::probe(backup)?.filesystem;
+    let staging = backup.join(BENCH_FOLDER_NAME);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir(&staging)?;
+
+    let result = measure(&staging, options);
+    debug!("Removing staging area: {}", staging.display());
+    fs::remove_dir_all(&staging)?;
+    let (sequential, small_files, metadata_latency) = result?;
+
+    Ok(BenchReport {
+        filesystem,
+        sequential,
+        small_files,
+        metadata_latency,
+    })
+}
+
+type Measurements = (Vec<(usize, f64)>, f64, Duration);
+
+fn measure(staging: &Path, options: &BenchOptions) -> io::Result<Measurements> {
+    let sequential = BUFFER_SIZES
+        .iter()
+        .map(|&buffer_size| {
+            let throughput = write_sequentially(staging, options.size, buffer_size)?;
+            debug!(
+                "Sequential write with {} byte buffer: {:.0} B/s",
+                buffer_size, throughput
+            );
+            Ok((buffer_size, throughput))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let folder = staging.join("small");
+    fs::create_dir(&folder)?;
+    let content = pseudo_random_bytes(SMALL_FILE_SIZE);
+    let start = Instant::now();
+    for i in 0..options.files {
+        File::create(folder.join(format!("{}.bin", i)))?.write_all(&content)?;
+    }
+    let small_files = options.files as f64 / start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    let start = Instant::now();
+    for i in 0..options.files {
+        let renamed = folder.join(format!("{}.renamed", i));
+        fs::rename(folder.join(format!("{}.bin", i)), &renamed)?;
+        fs::metadata(&renamed)?;
+    }
+    let operations = (2 * options.files.max(1)) as u32;
+    let metadata_latency = start.elapsed() / operations;
+
+    Ok((sequential, small_files, metadata_latency))
+}
+
+/// Returns throughput in bytes per second, including the time of flushing
+/// the file to the disk.
+fn write_sequentially(staging: &Path, size: u64, buffer_size: usize) -> io::Result<f64> {
+    let path = staging.join(format!("sequential-{}.bin", buffer_size));
+    let buffer = pseudo_random_bytes(buffer_size);
+    let start = Instant::now();
+    let mut file = File::create(&path)?;
+    let mut written = 0;
+    while written < size {
+        let length = buffer_size.min((size - written) as usize);
+        file.write_all(&buffer[..length])?;
+        written += length as u64;
+    }
+    file.sync_all()?;
+    let elapsed = start.elapsed().as_secs_f64();
+    fs::remove_file(&path)?;
+    Ok(size as f64 / elapsed.max(f64::EPSILON))
+}
+
+impl BenchReport {
+    pub fn recommendations(&self, cpus: usize) -> Vec<Recommendation> {
+        let mut recommendations = vec![];
+
+        let (_, default_throughput) = self.sequential[0];
+        let (best, best_throughput) = self
+            .sequential
+            .iter()
+            .copied()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or(self.sequential[0]);
+        recommendations.push(if best_throughput > default_throughput * 1.1 {
+            Recommendation {
+                setting: format!("--copy-buffer {}", format_buffer_size(best)),
+                reason: t!(
+                    "{}% faster sequential writes",
+                    format!(
+                        "{:.0}",
+                        (best_throughput / default_throughput - 1.0) * 100.0
+                    )
+                ),
+            }
+        } else {
+            Recommendation {
+                setting: t!("default copy buffer"),
+                reason: t!("larger buffers are not faster"),
+            }
+        });
+
+        recommendations.push(if self.metadata_latency >= SLOW_METADATA_LATENCY {
+            Recommendation {
+                setting: format!("--threads {}", 2 * cpus),
+                reason: t!("slow metadata operations, threads mostly wait for the destination"),
+            }
+        } else {
+            Recommendation {
+                setting: format!("--threads {}", cpus),
+                reason: t!("one thread per CPU (default)"),
+            }
+        });
+
+        recommendations.push(if best_throughput < COMPRESSION_THRESHOLD {
+            Recommendation {
+                setting: "archive --compress zstd".into(),
+                reason: t!("data is compressed faster than it is written"),
+            }
+        } else {
+            Recommendation {
+                setting: t!("compression off"),
+                reason: t!("compressing would be slower than writing"),
+            }
+        });
+
+        recommendations
+    }
+}
+
+pub fn format_buffer_size(size: usize) -> String {
+    if size >= 1 << 20 && size.is_multiple_of(1 << 20) {
+        format!("{}M", size >> 20)
+    } else {
+        format!("{}K", size >> 10)
+    }
+}
+
+/// Content that doesn't compress, so filesystems with transparent
+/// compression are measured fairly.
+fn pseudo_random_bytes(length: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: f64 = (1 << 20) as f64;
+
+    fn report(sequential: [f64; 3], metadata_latency: Duration) -> BenchReport {
+        BenchReport {
+            filesystem: Filesystem::Other,
+            sequential: BUFFER_SIZES.iter().copied().zip(sequential).collect(),
+            small_files: 1000.0,
+            metadata_latency,
+        }
+    }
+
+    #[test]
+    fn recommend_settings_for_slow_network_share() {
+        let report = report(
+            [10.0 * MIB, 20.0 * MIB, 15.0 * MIB],
+            Duration::from_millis(5),
+        );
+
+        let settings: Vec<String> = report
+            .recommendations(4)
+            .into_iter()
+            .map(|r| r.setting)
+            .collect();
+
+        assert_eq!(
+            settings,
+            ["--copy-buffer 1M", "--threads 8", "archive --compress zstd"]
+        );
+    }
+
+    #[test]
+    fn recommend_defaults_for_fast_disk() {
+        let report = report(
+            [500.0 * MIB, 510.0 * MIB, 505.0 * MIB],
+            Duration::from_micros(20),
+        );
+
+        let settings: Vec<String> = report
+            .recommendations(4)
+            .into_iter()
+            .map(|r| r.setting)
+            .collect();
+
+        assert_eq!(
+            settings,
+            ["default copy buffer", "--threads 4", "compression off"]
+        );
+    }
+
+    #[test]
+    fn measure_destination() {
+        let backup = tempfile::tempdir().unwrap();
+        let options = BenchOptions {
+            size: 1 << 20,
+            files: 10,
+        };
+
+        let report = run(backup.path(), &options).unwrap();
+
+        assert_eq!(report.sequential.len(), BUFFER_SIZES.len());
+        assert!(report.sequential.iter().all(|(_, t)| *t > 0.0));
+        assert!(report.small_files > 0.0);
+        assert!(!backup.path().join(BENCH_FOLDER_NAME).exists());
+    }
+}
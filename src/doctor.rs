@@ -0,0 +1,536 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::backup::{
+    Backup, Destination, Filesystem, ForeignEntry, Lock, LockState, PARTIAL_SUFFIX,
+};
+use crate::messages::t;
+use crate::result::Severity;
+
+#[cfg(unix)]
+const MAX_PATH_LENGTH: usize = libc::PATH_MAX as usize;
+#[cfg(not(unix))]
+const MAX_PATH_LENGTH: usize = 260;
+
+// Example of what is appended to the backup path: "/2021-07-15_18.34/files"
+const SNAPSHOT_PATH_OVERHEAD: usize = 23;
+
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: String) -> Self {
+        Self { severity, message }
+    }
+}
+
+#[derive(Default)]
+struct InputStats {
+    entries: u64,
+    size: u64, // in bytes
+    largest_file: Option<(PathBuf, u64)>,
+    longest_name: Option<(PathBuf, usize)>,
+    longest_path: Option<(PathBuf, usize)>,
+    unreadable: Vec<PathBuf>,
+}
+
+/// Checks whether a backup of `inputs` can be safely made into `backup`
+/// and whether failed backups left anything behind in it. With `fix` the
+/// leftovers are removed. Inputs aren't checked when none are given.
+pub fn diagnose(backup: &Path, inputs: &[PathBuf], fix: bool) -> Vec<Finding> {
+    let mut findings = vec![];
+
+    if !backup.is_dir() {
+        findings.push(Finding::new(
+            Severity::Error,
+            t!(
+                "Backup folder '{}' doesn't exist or isn't a folder.",
+                backup.display()
+            ),
+        ));
+        return findings;
+    }
+
+    let destination = match Destination::probe(backup) {
+        Ok(destination) => destination,
+        Err(e) => {
+            findings.push(Finding::new(
+                Severity::Error,
+                t!("Backup folder isn't writable ({}).", e),
+            ));
+            return findings;
+        }
+    };
+    findings.push(Finding::new(
+        Severity::Info,
+        t!("Backup folder is writable ({}).", destination.filesystem),
+    ));
+    check_destination(&destination, &mut findings);
+    check_clock(backup, &mut findings);
+    let locked = check_lock(backup, fix, &mut findings);
+    check_leftovers(backup, fix, locked, &mut findings);
+
+    if !inputs.is_empty() {
+        let stats = check_inputs(inputs, &mut findings);
+        check_capacity(backup, &destination, &stats, &mut findings);
+    }
+
+    findings
+}
+
+fn check_destination(destination: &Destination, findings: &mut Vec<Finding>) {
+    if destination.filesystem == Filesystem::NetworkShare {
+        findings.push(Finding::new(
+            Severity::Warning,
+            t!("Backup folder is on a network share. Backups may be slow and interrupted by connection problems."),
+        ));
+    }
+    if destination.filesystem.has_coarse_timestamps() {
+        findings.push(Finding::new(
+            Severity::Warning,
+            t!(
+                "{} stores modification times with 2 second resolution. Incremental backups may copy unchanged files.",
+                destination.filesystem
+            ),
+        ));
+    }
+    if !destination.supports_symlinks {
+        findings.push(Finding::new(
+            Severity::Warning,
+            t!("Backup folder doesn't support symbolic links. Symlinks won't be backed up."),
+        ));
+    }
+}
+
+fn check_clock(backup: &Path, findings: &mut Vec<Finding>) {
+    let backup = match Backup::open(backup) {
+        Ok(backup) => backup,
+        Err(e) => {
+            findings.push(Finding::new(Severity::Error, e.to_string()));
+            return;
+        }
+    };
+
+    match backup.latest_snapshot() {
+        Some(latest) if latest.is_from_the_future() => findings.push(Finding::new(
+            Severity::Warning,
+            t!(
+                "Latest snapshot {} is from the future. Check the system clock.",
+                latest.name()
+            ),
+        )),
+        Some(_) => findings.push(Finding::new(
+            Severity::Info,
+            t!(
+                "System clock is consistent with {} existing snapshot(s).",
+                backup.snapshots().len()
+            ),
+        )),
+        None => findings.push(Finding::new(
+            Severity::Info,
+            t!("Backup folder has no snapshots yet."),
+        )),
+    }
+}
+
+/// Stale locks can be removed. Returns whether a backup may still hold
+/// the lock, so its unfinished snapshot mustn't be removed.
+fn check_lock(backup: &Path, fix: bool, findings: &mut Vec<Finding>) -> bool {
+    if !Lock::is_locked(backup) {
+        return false;
+    }
+    let holder = match Lock::holder(backup) {
+        Some(holder) => holder,
+        None => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!("Backup folder is locked, but the lock file can't be read."),
+            ));
+            return true;
+        }
+    };
+    match holder.state() {
+        LockState::Held => {
+            findings.push(Finding::new(
+                Severity::Info,
+                t!("A backup is running, the folder is locked by {}.", holder),
+            ));
+            true
+        }
+        LockState::Unknown => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!(
+                    "Backup folder is locked by {}, which can't be checked from this host.",
+                    holder
+                ),
+            ));
+            true
+        }
+        LockState::Stale if fix => {
+            match Lock::remove(backup) {
+                Ok(()) => findings.push(Finding::new(
+                    Severity::Info,
+                    t!("Removed stale lock of {}.", holder),
+                )),
+                Err(e) => findings.push(Finding::new(
+                    Severity::Error,
+                    t!("Cannot remove stale lock ({}).", e),
+                )),
+            }
+            false
+        }
+        LockState::Stale => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!(
+                    "Lock of {} is stale, the process isn't running anymore. Remove it with --fix.",
+                    holder
+                ),
+            ));
+            false
+        }
+    }
+}
+
+/// Unfinished snapshots and snapshots without any data can be removed,
+/// unless a backup holding the lock may still be creating them. Other
+/// foreign entries may be files of the user, so they are only reported.
+fn check_leftovers(backup: &Path, fix: bool, locked: bool, findings: &mut Vec<Finding>) {
+    let entries = Backup::get_foreign_entries(backup);
+    for entry in &entries {
+        let name = entry.name();
+        let leftover = if name.ends_with(PARTIAL_SUFFIX) && locked {
+            findings.push(Finding::new(
+                Severity::Info,
+                t!(
+                    "Snapshot '{}' may be being created by the backup holding the lock.",
+                    name
+                ),
+            ));
+            continue;
+        } else if name.ends_with(PARTIAL_SUFFIX) {
+            t!(
+                "Snapshot '{}' was left unfinished by an interrupted backup.",
+                name
+            )
+        } else if entry.is_damaged_snapshot() && is_empty_snapshot(&entry.path) {
+            t!(
+                "Folder '{}' is named like a snapshot, but has no index and no files.",
+                name
+            )
+        } else if entry.is_damaged_snapshot() {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!(
+                    "Folder '{}' looks like a damaged snapshot with {} bytes of files. It is never removed automatically.",
+                    name, entry.size
+                ),
+            ));
+            continue;
+        } else {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!(
+                    "'{}' ({} bytes) isn't part of the backup. Remove it with 'list --foreign --clean'.",
+                    name, entry.size
+                ),
+            ));
+            continue;
+        };
+        match fix {
+            true => findings.push(remove_leftover(entry)),
+            false => findings.push(Finding::new(
+                Severity::Warning,
+                t!("{} Remove it with --fix.", leftover),
+            )),
+        }
+    }
+    if entries.is_empty() {
+        findings.push(Finding::new(
+            Severity::Info,
+            t!("Backup folder has no unfinished snapshots or foreign entries."),
+        ));
+    }
+}
+
+/// A folder named like a snapshot with nothing but an empty `files` folder
+/// is left when a backup fails before anything is copied.
+fn is_empty_snapshot(path: &Path) -> bool {
+    WalkDir::new(path)
+        .min_depth(1)
+        .into_iter()
+        .all(|entry| match entry {
+            Ok(entry) => {
+                entry.depth() == 1 && entry.file_type().is_dir() && entry.file_name() == "files"
+            }
+            Err(_) => false,
+        })
+}
+
+fn remove_leftover(entry: &ForeignEntry) -> Finding {
+    match fs::remove_dir_all(&entry.path) {
+        Ok(()) => {
+            debug!("Removed leftover: {}", entry.path.display());
+            Finding::new(
+                Severity::Info,
+                t!("Removed '{}' ({} bytes).", entry.name(), entry.size),
+            )
+        }
+        Err(e) => Finding::new(
+            Severity::Error,
+            t!("Cannot remove '{}' ({}).", entry.name(), e),
+        ),
+    }
+}
+
+fn check_inputs(inputs: &[PathBuf], findings: &mut Vec<Finding>) -> InputStats {
+    let mut stats = InputStats::default();
+
+    for input in inputs {
+        if !input.exists() {
+            findings.push(Finding::new(
+                Severity::Error,
+                t!("Input '{}' doesn't exist.", input.display()),
+            ));
+            continue;
+        }
+        debug!("Scanning input: {}", input.display());
+        scan_input(input, &mut stats);
+    }
+
+    if let Some(first) = stats.unreadable.first() {
+        findings.push(Finding::new(
+            Severity::Error,
+            t!(
+                "{} input entries aren't readable, e.g. '{}'.",
+                stats.unreadable.len(),
+                first.display()
+            ),
+        ));
+    }
+    findings.push(Finding::new(
+        Severity::Info,
+        t!(
+            "Inputs have {} entries and take {} bytes.",
+            stats.entries,
+            stats.size
+        ),
+    ));
+
+    stats
+}
+
+fn scan_input(input: &Path, stats: &mut InputStats) {
+    for entry in WalkDir::new(input).follow_links(false) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(input).to_owned();
+                stats.unreadable.push(path);
+                continue;
+            }
+        };
+        stats.entries += 1;
+
+        let path = entry.path();
+        let absolute_path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let path_length = absolute_path.as_os_str().len();
+        if stats.longest_path.as_ref().map_or(0, |(_, l)| *l) < path_length {
+            stats.longest_path = Some((absolute_path, path_length));
+        }
+        let name_length = entry.file_name().len();
+        if stats.longest_name.as_ref().map_or(0, |(_, l)| *l) < name_length {
+            stats.longest_name = Some((path.to_owned(), name_length));
+        }
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if File::open(path).is_err() {
+            stats.unreadable.push(path.to_owned());
+            continue;
+        }
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        stats.size += size;
+        if stats.largest_file.as_ref().map_or(0, |(_, s)| *s) < size {
+            stats.largest_file = Some((path.to_owned(), size));
+        }
+    }
+}
+
+fn check_capacity(
+    backup: &Path,
+    destination: &Destination,
+    stats: &InputStats,
+    findings: &mut Vec<Finding>,
+) {
+    match destination.free_space {
+        Some(free_space) if free_space < stats.size => findings.push(Finding::new(
+            Severity::Error,
+            t!(
+                "Only {} bytes are free in backup folder, but a full snapshot needs {} bytes.",
+                free_space,
+                stats.size
+            ),
+        )),
+        Some(free_space) => findings.push(Finding::new(
+            Severity::Info,
+            t!("{} bytes are free in backup folder.", free_space),
+        )),
+        None => findings.push(Finding::new(
+            Severity::Warning,
+            t!("Cannot determine free space in backup folder."),
+        )),
+    }
+
+    if let (Some(max_size), Some((file, size))) = (
+        destination.filesystem.max_file_size(),
+        stats.largest_file.as_ref(),
+    ) {
+        if *size > max_size {
+            findings.push(Finding::new(
+                Severity::Error,
+                t!(
+                    "File '{}' ({} bytes) exceeds the maximum file size of {}.",
+                    file.display(),
+                    size,
+                    destination.filesystem
+                ),
+            ));
+        }
+    }
+
+    if let (Some(max_length), Some((entry, length))) =
+        (destination.max_name_length, stats.longest_name.as_ref())
+    {
+        if *length as u64 > max_length {
+            findings.push(Finding::new(
+                Severity::Error,
+                t!(
+                    "Name of '{}' is longer than {} bytes allowed in backup folder.",
+                    entry.display(),
+                    max_length
+                ),
+            ));
+        }
+    }
+
+    if let Some((entry, length)) = stats.longest_path.as_ref() {
+        let backup = backup.canonicalize().unwrap_or_else(|_| backup.to_owned());
+        let snapshot_path_length = backup.as_os_str().len() + SNAPSHOT_PATH_OVERHEAD + length;
+        if snapshot_path_length > MAX_PATH_LENGTH {
+            findings.push(Finding::new(
+                Severity::Warning,
+                t!(
+                    "Path '{}' will be {} characters long inside a snapshot, which exceeds the limit of {}.",
+                    entry.display(),
+                    snapshot_path_length,
+                    MAX_PATH_LENGTH
+                ),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(findings: &[Finding], severity: Severity) -> usize {
+        findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    #[test]
+    fn diagnose_healthy_backup() {
+        let backup = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        File::create(input.path().join("file.txt")).unwrap();
+
+        let findings = diagnose(backup.path(), &[input.path().to_owned()], false);
+
+        assert_eq!(count(&findings, Severity::Error), 0);
+    }
+
+    #[test]
+    fn diagnose_nonexistent_backup() {
+        let backup = tempfile::tempdir().unwrap();
+        let backup = backup.path().join("nonexistent");
+
+        let findings = diagnose(&backup, &[], false);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn diagnose_nonexistent_input() {
+        let backup = tempfile::tempdir().unwrap();
+        let input = backup.path().join("nonexistent");
+
+        let findings = diagnose(backup.path(), &[input], false);
+
+        assert_eq!(count(&findings, Severity::Error), 1);
+    }
+
+    #[test]
+    fn diagnose_snapshot_from_the_future() {
+        let backup = tempfile::tempdir().unwrap();
+        let snapshot = backup.path().join("2999-01-01_00.00");
+        std::fs::create_dir_all(snapshot.join("files")).unwrap();
+        File::create(snapshot.join("index.txt")).unwrap();
+
+        let findings = diagnose(backup.path(), &[], false);
+
+        assert!(findings
+            .iter()
+            .any(|f| f.severity == Severity::Warning && f.message.contains("2999-01-01_00.00")));
+    }
+
+    #[test]
+    fn fix_removes_only_leftovers_of_failed_backups() {
+        let backup = tempfile::tempdir().unwrap();
+        let unfinished = backup.path().join("2021-07-15_18.34.partial");
+        fs::create_dir_all(unfinished.join("files")).unwrap();
+        fs::write(unfinished.join("files").join("a.txt"), "hello").unwrap();
+        let empty = backup.path().join("2021-07-16_18.34");
+        fs::create_dir_all(empty.join("files")).unwrap();
+        let damaged = backup.path().join("2021-07-17_18.34");
+        fs::create_dir_all(damaged.join("files")).unwrap();
+        fs::write(damaged.join("files").join("b.txt"), "world").unwrap();
+        fs::write(backup.path().join("notes.txt"), "mine").unwrap();
+
+        let findings = diagnose(backup.path(), &[], false);
+
+        assert_eq!(count(&findings, Severity::Warning), 4);
+        assert!(unfinished.exists() && empty.exists());
+
+        let findings = diagnose(backup.path(), &[], true);
+
+        assert_eq!(count(&findings, Severity::Error), 0);
+        assert_eq!(count(&findings, Severity::Warning), 2);
+        assert!(!unfinished.exists());
+        assert!(!empty.exists());
+        assert!(damaged.exists());
+        assert!(backup.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn fix_keeps_snapshot_of_running_backup() {
+        let backup = tempfile::tempdir().unwrap();
+        let unfinished = backup.path().join("2021-07-15_18.34.partial");
+        fs::create_dir_all(unfinished.join("files")).unwrap();
+        let _lock = Lock::acquire(backup.path(), false).unwrap();
+
+        let findings = diagnose(backup.path(), &[], true);
+
+        assert_eq!(count(&findings, Severity::Warning), 0);
+        assert!(unfinished.exists());
+        assert!(Lock::is_locked(backup.path()));
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Source of the current time. Snapshot names are derived from it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::OffsetDateTime;
+}
+
+/// How contents of files are copied into a snapshot.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CopyOptions {
+    /// Size of the copy buffer in bytes. By default the operating system
+    /// picks the fastest way to copy a file.
+    pub buffer_size: Option<usize>,
+    /// Read files bypassing the page cache. Supported only on Linux.
+    pub direct_io: bool,
+}
+
+/// File operations used to write data into a snapshot.
+pub trait FileSystem: Send + Sync {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64>;
+    /// Filesystems that can't tune copying ignore the options.
+    fn copy_file_with(&self, from: &Path, to: &Path, _options: &CopyOptions) -> io::Result<u64> {
+        self.copy_file(from, to)
+    }
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()>;
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+}
+
+/// Local time of the operating system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::now_local().unwrap_or(time::OffsetDateTime::now_utc())
+    }
+}
+
+/// Filesystem of the operating system.
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    /// Modification time is preserved, so it can be queried in a snapshot.
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let size = fs::copy(from, to)?;
+        let modified = fs::metadata(from)?.modified()?;
+        fs::File::options()
+            .write(true)
+            .open(to)?
+            .set_modified(modified)?;
+        Ok(size)
+    }
+
+    fn copy_file_with(&self, from: &Path, to: &Path, options: &CopyOptions) -> io::Result<u64> {
+        if *options == CopyOptions::default() {
+            return self.copy_file(from, to);
+        }
+        let mut source = open_source(from, options.direct_io)?;
+        let metadata = source.metadata()?;
+        let mut target = fs::File::create(to)?;
+
+        let buffer_size = options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        // Direct I/O needs a buffer aligned to the block size of the disk
+        let buffer_size = buffer_size.div_ceil(DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT;
+        let mut buffer = vec![0; buffer_size + DIRECT_IO_ALIGNMENT];
+        let offset = buffer.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        let buffer = &mut buffer[offset..offset + buffer_size];
+
+        let mut size = 0;
+        loop {
+            let read = match source.read(buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            target.write_all(&buffer[..read])?;
+            size += read as u64;
+        }
+        target.set_permissions(metadata.permissions())?;
+        target.set_modified(metadata.modified()?)?;
+        Ok(size)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(original, link)
+    }
+
+    #[cfg(not(unix))]
+    fn symlink(&self, _original: &Path, _link: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Copying symlinks is not supported on this platform.",
+        ))
+    }
+}
+
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+#[cfg(target_os = "linux")]
+fn open_source(path: &Path, direct_io: bool) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if direct_io {
+        match fs::File::options()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+        {
+            Ok(file) => return Ok(file),
+            // e.g. tmpfs doesn't support direct I/O
+            Err(e) => tracing::debug!("Cannot use direct I/O for {} ({})", path.display(), e),
+        }
+    }
+    fs::File::open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_source(path: &Path, _direct_io: bool) -> io::Result<fs::File> {
+    fs::File::open(path)
+}
+
+/// Everything that the backup process takes from the outside world.
+/// Replace any part of it to simulate clock skew or I/O failures.
+#[derive(Clone)]
+pub struct Environment {
+    pub clock: Arc<dyn Clock>,
+    pub fs: Arc<dyn FileSystem>,
+    /// Set when the user asks to stop, e.g. with Ctrl-C. The backup stops
+    /// before the next entry.
+    pub interrupted: Arc<AtomicBool>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            fs: Arc::new(RealFileSystem),
+            interrupted: Arc::default(),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use regex::Regex;
+use tracing::{debug, warn};
+
+use crate::backup::{Backup, EntryInfo, EntryKind};
+
+/// Pattern that an original path of an entry must match.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Part of the path.
+    Part(String),
+    /// Glob with `*`, `?` and `[...]`, which don't match a path separator,
+    /// and `**`, which does. It's matched against the file name, or the
+    /// whole path if it contains a separator.
+    Glob { regex: Regex, whole_path: bool },
+    /// Regular expression found anywhere in the path.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// A pattern without wildcards is a part of the path, any other is
+    /// a glob.
+    pub fn search(pattern: &str) -> Result<Self, String> {
+        match pattern.contains(['*', '?', '[']) {
+            true => Self::glob(pattern),
+            false => Ok(Self::Part(pattern.to_string())),
+        }
+    }
+
+    /// A glob without wildcards matches the file name, or the whole path,
+    /// exactly.
+    pub fn glob(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(&glob_to_regex(pattern))
+            .map_err(|e| format!("Invalid pattern '{}' ({})", pattern, e))?;
+        Ok(Self::Glob {
+            regex,
+            whole_path: pattern.contains(['/', std::path::MAIN_SEPARATOR]),
+        })
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(Self::Regex)
+            .map_err(|e| format!("Invalid regular expression '{}' ({})", pattern, e))
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let whole = path.to_string_lossy();
+        match self {
+            Self::Part(part) => whole.contains(part.as_str()),
+            Self::Glob {
+                regex,
+                whole_path: true,
+            } => regex.is_match(&whole),
+            Self::Glob { regex, .. } => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                regex.is_match(&name)
+            }
+            Self::Regex(regex) => regex.is_match(&whole),
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let separators = regex::escape(&format!("/{}", std::path::MAIN_SEPARATOR));
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str(&format!("[^{}]*", separators)),
+            '?' => regex.push_str(&format!("[^{}]", separators)),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => break,
+                        '\\' | '[' | '^' | '&' | '~' => {
+                            regex.push_str(&regex::escape(&c.to_string()))
+                        }
+                        _ => regex.push(c),
+                    }
+                }
+                regex.push(']');
+            }
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Conditions that an entry stored in a snapshot must meet to be found.
+/// Every condition that is set must be met.
+#[derive(Default)]
+pub struct Filter {
+    pub pattern: Option<Pattern>,
+    pub kind: Option<EntryKind>,
+    pub larger_than: Option<u64>, // in bytes
+    pub modified_before: Option<SystemTime>,
+    pub modified_after: Option<SystemTime>,
+}
+
+impl Filter {
+    /// Checked before data of the entry is looked up.
+    fn matches_path(&self, path: &Path) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.matches(path))
+    }
+
+    fn matches(&self, entry: &EntryInfo) -> bool {
+        if self.kind.is_some_and(|kind| kind != entry.kind) {
+            return false;
+        }
+        if self.larger_than.is_some_and(|size| entry.size <= size) {
+            return false;
+        }
+        if self.modified_before.is_some() || self.modified_after.is_some() {
+            let modified = match entry.modified {
+                Some(modified) => modified,
+                None => return false,
+            };
+            if self.modified_before.is_some_and(|time| modified >= time) {
+                return false;
+            }
+            if self.modified_after.is_some_and(|time| modified < time) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Searches all snapshots of a backup, from the oldest. Every entry is
+/// found only in the snapshot that stores its data, not in snapshots that
+/// refer to it. Indexes are read line by line, one snapshot at a time.
+pub fn find(backup: &Path, filter: &Filter) -> Vec<EntryInfo> {
+    let mut found = vec![];
+    for snapshot in Backup::get_all_snapshots_without_index(backup) {
+        debug!("Searching snapshot: {}", snapshot.name());
+        let scanned = snapshot.scan_stored_entries(
+            |path| filter.matches_path(path),
+            |entry| {
+                if filter.matches(&entry) {
+                    found.push(entry);
+                }
+            },
+        );
+        if let Err(e) = scanned {
+            warn!("Cannot search snapshot {} ({})", snapshot.name(), e);
+        }
+    }
+    found
+}
+
+/// Parses sizes like "100", "512K", "100M" or "1G".
+pub fn parse_size(size: &str) -> Option<u64> {
+    let (number, multiplier) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1 << 10),
+        'M' | 'm' => (&size[..size.len() - 1], 1 << 20),
+        'G' | 'g' => (&size[..size.len() - 1], 1 << 30),
+        'T' | 't' => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a date in "yyyy-mm-dd" format as a local midnight.
+pub fn parse_date(date: &str) -> Option<SystemTime> {
+    let format = time::format_description::parse("[year]-[month]-[day]").ok()?;
+    let date = time::Date::parse(date, &format).ok()?;
+    let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    Some(date.midnight().assume_offset(offset).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_size_with_units() {
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("2K"), Some(2048));
+        assert_eq!(parse_size("100M"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1.5G"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn parse_date_in_valid_format() {
+        assert!(parse_date("2021-07-15").is_some());
+        assert!(parse_date("2021-07-15_18.34").is_none());
+        assert!(parse_date("15.07.2021").is_none());
+    }
+
+    #[test]
+    fn match_glob_and_regex_patterns() {
+        let path = Path::new("/home/user/photos/2021/beach.JPG");
+        let matches = |pattern: &str| Pattern::search(pattern).unwrap().matches(path);
+
+        assert!(matches("photos/20"));
+        assert!(matches("*.JPG"));
+        assert!(matches("b?ach.[JP]PG"));
+        assert!(!matches("*.jpg"));
+        assert!(!matches("/home/*/beach.JPG"));
+        assert!(matches("/home/**/beach.JPG"));
+        assert!(matches("/home/*/photos/*/*"));
+        assert!(!matches("photos/*"));
+        assert!(Pattern::regex(r"(?i)/photos/\d{4}/.*\.jpg$")
+            .unwrap()
+            .matches(path));
+        assert!(Pattern::regex("photos(").is_err());
+        assert!(Pattern::glob("beach.JPG").unwrap().matches(path));
+        assert!(!Pattern::glob("beach").unwrap().matches(path));
+        assert!(!Pattern::glob("/home/user/photos").unwrap().matches(path));
+    }
+
+    #[test]
+    fn find_entries_matching_filter() {
+        let backup = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("small.txt"), b"small").unwrap();
+        fs::write(input.path().join("large.mp4"), vec![0; 2048]).unwrap();
+        fs::create_dir(input.path().join("videos")).unwrap();
+
+        let mut backup_handle = Backup::open(backup.path()).unwrap();
+        backup_handle
+            .add_snapshot(&[input.path().to_owned()], &Default::default())
+            .unwrap();
+
+        let filter = Filter {
+            kind: Some(EntryKind::File),
+            larger_than: parse_size("1K"),
+            ..Default::default()
+        };
+        let found = find(backup.path(), &filter);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("large.mp4"));
+
+        let filter = Filter {
+            pattern: Some(Pattern::search("video").unwrap()),
+            ..Default::default()
+        };
+        let found = find(backup.path(), &filter);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, EntryKind::Directory);
+
+        let filter = Filter {
+            pattern: Some(Pattern::search("*.mp4").unwrap()),
+            ..Default::default()
+        };
+        let found = find(backup.path(), &filter);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("large.mp4"));
+
+        let filter = Filter {
+            kind: Some(EntryKind::File),
+            modified_after: Some(SystemTime::now() + Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        assert!(find(backup.path(), &filter).is_empty());
+    }
+}
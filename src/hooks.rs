@@ -0,0 +1,68 @@
+//! Commands run by the shell before and after a backup, e.g. to quiesce
+//! a database or to sync the backup elsewhere. They learn about the backup
+//! from environment variables.
+
+use std::process::{Command, Stdio};
+
+use tracing::debug;
+
+pub const HOOK_VARIABLE: &str = "MIZERIA_HOOK";
+pub const BACKUP_VARIABLE: &str = "MIZERIA_BACKUP";
+pub const SNAPSHOT_VARIABLE: &str = "MIZERIA_SNAPSHOT";
+pub const RESULT_VARIABLE: &str = "MIZERIA_RESULT";
+pub const ERROR_VARIABLE: &str = "MIZERIA_ERROR";
+
+/// Runs `command` with the shell and the given environment variables
+/// set. It fails when the command can't be run or exits with an error.
+pub fn run(command: &str, variables: &[(&str, &str)]) -> Result<(), String> {
+    debug!("Running hook: {}", command);
+    let status = shell(command)
+        .envs(variables.iter().copied())
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| format!("cannot run '{}' ({})", command, e))?;
+    if !status.success() {
+        return Err(format!("'{}' failed ({})", command, status));
+    }
+    Ok(())
+}
+
+/// `command` run by the shell of the system.
+#[cfg(windows)]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hook_receives_variables_and_its_failure_is_reported() {
+        let root = tempfile::tempdir().unwrap();
+        let output = root.path().join("output");
+        let command = format!(
+            "echo \"$MIZERIA_HOOK $MIZERIA_BACKUP\" > '{}'",
+            output.display()
+        );
+
+        run(
+            &command,
+            &[(HOOK_VARIABLE, "pre"), (BACKUP_VARIABLE, "/backup")],
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "pre /backup\n");
+
+        let error = run("exit 3", &[]).err().unwrap();
+        assert!(error.contains("exit 3"), "{}", error);
+    }
+}
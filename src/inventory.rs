@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use time::format_description::well_known::Rfc3339;
+
+use crate::backup::Backup;
+
+const CSV_HEADER: &str = "snapshot,path,size,modified,stored_in";
+
+/// Writes one row for every entry indexed in every snapshot of a backup.
+/// Modification time is written in RFC 3339 format in UTC.
+pub fn write_csv(backup: &Path, writer: &mut dyn Write) -> io::Result<usize> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+    let mut rows = 0;
+    for snapshot in Backup::get_all_snapshots(backup) {
+        let name = snapshot.name();
+        for entry in snapshot.entries() {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                name,
+                escape_csv(&entry.path.to_string_lossy()),
+                entry.size,
+                entry.modified.map(format_time).unwrap_or_default(),
+                entry.stored_in
+            )?;
+            rows += 1;
+        }
+    }
+    Ok(rows)
+}
+
+fn format_time(time: SystemTime) -> String {
+    time::OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn escape_csv_fields() {
+        assert_eq!(escape_csv("/home/user"), "/home/user");
+        assert_eq!(escape_csv("/home/a,b"), "\"/home/a,b\"");
+        assert_eq!(escape_csv("/home/\"a\""), "\"/home/\"\"a\"\"\"");
+    }
+
+    #[test]
+    fn write_csv_of_backup() {
+        let backup = tempfile::tempdir().unwrap();
+        let input = tempfile::tempdir().unwrap();
+        fs::write(input.path().join("file,1.txt"), b"hello").unwrap();
+
+        let mut backup_handle = Backup::open(backup.path()).unwrap();
+        let report = backup_handle
+            .add_snapshot(&[input.path().to_owned()], &Default::default())
+            .unwrap();
+
+        let mut output = vec![];
+        let rows = write_csv(backup.path(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(rows, 2);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        let file_row = lines.iter().find(|l| l.contains("file,1.txt")).unwrap();
+        assert!(file_row.starts_with(&format!("{},\"", report.snapshot)));
+        assert!(file_row.contains("file,1.txt\",5,"));
+        assert!(file_row.ends_with(&format!("Z,{}", report.snapshot)));
+    }
+}
@@ -1,21 +1,100 @@
-use backup::Backup;
+use archive::{ArchiveOptions, Compression};
+use backup::{
+    parse_duration, Backup, BackupConfig, Change, Encryption, EntryKind, FileCompression,
+    FingerprintStatus, LargeFilesPolicy, LimitsPolicy, Lock, Period, PermissionsPolicy, Removal,
+    RestoreOptions, Settings, SignaturePolicy, SigningKey, SigningTool, SnapshotFormat,
+    SpecialFilesPolicy, VerificationBudget, VerifyingKey, ERRORS_FILE_NAME,
+};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use env_logger::{Builder, WriteStyle};
-use log::LevelFilter;
-use result::{IntegrityCheckError, IntegrityCheckResult};
-use std::ffi::OsStr;
+use environment::{CopyOptions, Environment};
+use hooks::{BACKUP_VARIABLE, ERROR_VARIABLE, HOOK_VARIABLE, RESULT_VARIABLE, SNAPSHOT_VARIABLE};
+use messages::{t, Locale};
+use notify::{Notification, Summary};
+use progress::{Event, ProgressStyle};
+use result::{
+    BackupReport, BackupStats, Completion, IntegrityCheckError, IntegrityFailed, IntegrityReport,
+    Interrupted, Severity,
+};
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
-use std::io::Write;
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
 use std::slice::Iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, warn};
 
+mod archive;
 mod backup;
+mod bench;
+mod doctor;
+pub mod environment;
+mod find;
+mod hooks;
+mod inventory;
+mod logging;
+mod manual;
+mod messages;
+mod mirror;
+mod notify;
+mod parallel;
+mod profiles;
+mod progress;
+mod prompt;
+mod remotes;
 pub mod result;
+mod selftest;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 type Writer<'a> = &'a mut dyn Write;
 
-pub fn run_program<C: IntoIterator>(args: C, writer: Writer) -> Result<()>
+/// Exit code of a run that completed with warnings, e.g. a backup that
+/// skipped some files.
+pub const WARNINGS_EXIT_CODE: i32 = 1;
+/// Exit code of a run that failed, including invalid arguments.
+pub const FATAL_EXIT_CODE: i32 = 2;
+/// Exit code of a check that found damaged, altered or missing data.
+pub const INTEGRITY_EXIT_CODE: i32 = 3;
+/// Exit code of a run interrupted with Ctrl-C, as the shell reports
+/// programs killed by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Exit code of the program for the result of [`run_program`].
+pub fn exit_code(result: &Result<Completion>) -> i32 {
+    match result {
+        Ok(Completion::Success) => 0,
+        Ok(Completion::Warnings) => WARNINGS_EXIT_CODE,
+        Err(e) if e.is::<Interrupted>() => INTERRUPTED_EXIT_CODE,
+        Err(e) if e.is::<IntegrityFailed>() => INTEGRITY_EXIT_CODE,
+        Err(_) => FATAL_EXIT_CODE,
+    }
+}
+
+/// Runs mizeria with command line `args`, writing its output to `writer`.
+///
+/// Logs are `tracing` events. Programs embedding mizeria can install their
+/// own subscriber with `tracing::subscriber::set_global_default` before
+/// the first run; it is then used instead of the one printing on standard
+/// error. Events of a backup are inside a `snapshot` span with the name of
+/// the snapshot and, once it's finished, the numbers of indexed and copied
+/// entries, and files of each input inside an `input` span with its path.
+pub fn run_program<C: IntoIterator>(args: C, writer: Writer) -> Result<Completion>
+where
+    C::Item: AsRef<OsStr>,
+{
+    run_program_with_environment(args, writer, &Environment::default())
+}
+
+/// Same as `run_program`, but the clock and filesystem used while
+/// creating snapshots can be replaced, e.g. to simulate failures.
+pub fn run_program_with_environment<C: IntoIterator>(
+    args: C,
+    writer: Writer,
+    environment: &Environment,
+) -> Result<Completion>
 where
     C::Item: AsRef<OsStr>,
 {
@@ -25,16 +104,71 @@ where
         .collect();
 
     let matches = parse_args(&args);
-    execute_subcommand(matches, writer)
+    if let Some(backup_args) = matches.subcommand_matches("backup") {
+        if backup_args.is_present("profile") {
+            let args =
+                profiles::expand(args.clone()).inspect_err(|_| set_verbosity(backup_args))?;
+            return run_program_with_environment(args, writer, environment);
+        }
+    }
+    let threads = matches
+        .value_of("threads")
+        .or_else(|| matches.subcommand().1.and_then(|a| a.value_of("threads")));
+    if let Some(threads) = threads {
+        parallel::set_threads(threads.parse()?);
+    }
+    let lang = matches
+        .value_of("lang")
+        .or_else(|| matches.subcommand().1.and_then(|a| a.value_of("lang")));
+    messages::set_locale(lang.map_or_else(Locale::from_environment, Locale::parse));
+    let quiet = matches
+        .subcommand()
+        .1
+        .is_some_and(|args| args.is_present("quiet"));
+    let mut sink = std::io::sink();
+    let writer: Writer = if quiet { &mut sink } else { writer };
+    execute_subcommand(matches, writer, environment)
 }
 
-fn execute_subcommand(matches: ArgMatches, writer: Writer) -> Result<()> {
-    return match matches.subcommand() {
-        ("backup", Some(args)) => handle_backup(args, writer),
+fn execute_subcommand(
+    matches: ArgMatches,
+    writer: Writer,
+    environment: &Environment,
+) -> Result<Completion> {
+    let result = match matches.subcommand() {
+        ("backup", Some(args)) => return handle_backup(args, writer, environment),
         ("list", Some(args)) => handle_list_snapshots(args, writer),
-        ("snapshot", Some(args)) => handle_manage_snapshot(args, writer),
+        ("latest", Some(args)) => handle_latest(args, writer),
+        ("snapshot", Some(args)) => return handle_manage_snapshot(args, writer),
+        ("browse", Some(args)) => handle_browse(args, writer),
+        ("restore", Some(args)) => handle_restore(args, writer),
+        ("estimate", Some(args)) => handle_estimate(args, writer),
+        ("doctor", Some(args)) => handle_doctor(args, writer),
+        ("stats", Some(args)) => handle_stats(args, writer),
+        ("selftest", Some(args)) => handle_selftest(args, writer, environment),
+        ("bench", Some(args)) => handle_bench(args, writer),
+        ("which", Some(args)) => handle_which(args, writer),
+        ("diff", Some(args)) => handle_diff(args, writer),
+        ("verify-mirror", Some(args)) => handle_verify_mirror(args, writer),
+        ("archive", Some(args)) => handle_archive(args, writer),
+        ("unarchive", Some(args)) => handle_unarchive(args, writer),
+        ("find", Some(args)) => handle_find(args, writer),
+        ("export-inventory", Some(args)) => handle_export_inventory(args, writer),
+        ("verify-fingerprints", Some(args)) => handle_verify_fingerprints(args, writer),
+        ("prune", Some(args)) => handle_prune(args, writer, environment),
+        ("squash", Some(args)) => handle_squash(args, writer),
+        ("repair", Some(args)) => handle_repair(args, writer),
+        ("undelete", Some(args)) => handle_undelete(args, writer, environment),
+        ("import-rsync", Some(args)) => handle_import_rsync(args, writer, environment),
+        ("import", Some(args)) => handle_import(args, writer, environment),
+        ("check", Some(args)) => handle_check(args, writer),
+        ("scrub", Some(args)) => handle_scrub(args, writer),
+        ("remote", Some(args)) => handle_remote(args, writer),
+        ("topic", Some(args)) => handle_topic(args, writer),
+        ("gen-man", Some(args)) => handle_gen_man(args, writer),
         _ => Ok(()),
     };
+    result.map(|_| Completion::Success)
 }
 
 fn get_verbosity_arg<'a>() -> Arg<'a, 'a> {
@@ -49,27 +183,193 @@ fn get_verbosity_arg<'a>() -> Arg<'a, 'a> {
         ))
 }
 
-fn parse_args(args: &[String]) -> ArgMatches {
+fn get_quiet_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("quiet")
+        .short("q")
+        .long("quiet")
+        .help("Prints only errors")
+        .long_help(concat!(
+            "Suppresses all output except errors, e.g. for backups run by cron that\n",
+            "should send mail only when they fail. Warnings are not logged either.\n",
+            "With -v debug logs are printed anyway."
+        ))
+}
+
+fn get_break_lock_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("break-lock")
+        .long("break-lock")
+        .help("Take over a lock left by a backup that isn't running anymore")
+        .long_help(concat!(
+            "The backup folder is locked while snapshots are created, removed or\n",
+            "changed, so two commands never change it at once. A lock left by a\n",
+            "process that died, or held by a process on another host, is taken\n",
+            "over with this flag. A lock of a process still running on this host\n",
+            "is never taken over."
+        ))
+}
+
+fn get_verify_args<'a>() -> [Arg<'a, 'a>; 3] {
+    [
+        Arg::with_name("verify-with")
+            .long("verify-with")
+            .takes_value(true)
+            .possible_values(&["minisign", "gpg"])
+            .requires("verify-key")
+            .help("Verify signatures of the snapshot with minisign or GPG"),
+        Arg::with_name("verify-key")
+            .long("verify-key")
+            .value_name("KEY")
+            .takes_value(true)
+            .requires("verify-with")
+            .help("Public key file for minisign or keyring file for GPG"),
+        Arg::with_name("on-bad-signature")
+            .long("on-bad-signature")
+            .takes_value(true)
+            .possible_values(&["fail", "warn"])
+            .default_value("fail")
+            .help("Whether a missing or invalid signature fails the check"),
+    ]
+}
+
+fn parse_args(args: &[String]) -> ArgMatches<'_> {
+    build_app().get_matches_from_safe(args).unwrap_or_else(|e| {
+        // Help and version are printed on standard output with code 0.
+        if e.use_stderr() {
+            eprintln!("{}", e.message);
+            std::process::exit(FATAL_EXIT_CODE);
+        }
+        e.exit()
+    })
+}
+
+fn build_app() -> App<'static, 'static> {
     App::new("mizeria")
         .version(clap::crate_version!())
         .about("Simple backup software")
+        .after_help("Run 'mizeria topic' to list extended help topics.")
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::NoBinaryName)
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .takes_value(true)
+                .global(true)
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("expected a positive number")),
+                })
+                .help("Number of threads used for hashing and checking snapshots")
+                .long_help(concat!(
+                    "Number of threads used for hashing and checking snapshots.\n",
+                    "By default there is one thread per CPU. Files are always walked\n",
+                    "and copied in a single thread. Builds without the 'parallel'\n",
+                    "feature always use a single thread."
+                ))
+        )
+        .arg(
+            Arg::with_name("lang")
+                .long("lang")
+                .value_name("LANGUAGE")
+                .takes_value(true)
+                .global(true)
+                .help("Language of the output, e.g. 'en' or 'pl'")
+                .long_help(concat!(
+                    "Language of summaries, errors and integrity check results.\n",
+                    "By default it's taken from the MIZERIA_LANG environment variable.\n",
+                    "Logs are always in English.\n",
+                    "Supported languages: en, pl."
+                ))
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true)
+                .help("Append logs to FILE at the level of --log-level")
+                .long_help(concat!(
+                    "Appends logs to FILE with the time of every line, independently of\n",
+                    "the logs printed on the console, e.g. to keep debug logs of backups\n",
+                    "run by cron with --quiet."
+                ))
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["error", "warn", "info", "debug", "trace"])
+                .help("Level of logs written to --log-file [default: debug]")
+        )
         .subcommand(SubCommand::with_name("backup")
             .about("Make a backup of your files")
             .arg(
                 Arg::with_name("BACKUP")
-                    .help("A folder where snapshot will be stored")
-                    .required(true)
+                    .help("A folder where snapshot will be stored, by default $MIZERIA_BACKUP")
                     .index(1),
             )
             .arg(
                 Arg::with_name("INPUT")
-                    .help("Files or folders to be backed up")
-                    .required(true)
+                    .help("Files or folders to be backed up, by default inputs set in mizeria.toml")
                     .multiple(true)
                     .index(2),
             )
+            .arg(
+                Arg::with_name("profile")
+                    .long("profile")
+                    .value_name("NAME")
+                    .takes_value(true)
+                    .help("Use BACKUP, INPUT and options of a profile from the user's config file")
+                    .long_help(concat!(
+                        "Uses the backup folder, inputs and options of profile NAME defined in\n",
+                        "~/.config/mizeria/config.toml ($XDG_CONFIG_HOME or $MIZERIA_CONFIG\n",
+                        "change the location). BACKUP is then not given, INPUT is backed up\n",
+                        "in addition to inputs of the profile, and options given on the\n",
+                        "command line take precedence."
+                    ))
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("exclude")
+                    .value_name("GLOB")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|glob| find::Pattern::glob(&glob).map(|_| ()))
+                    .help("Don't back up entries matching GLOB, e.g. \"*.tmp\"")
+                    .long_help(concat!(
+                        "Doesn't back up entries matching GLOB. It's matched against the file\n",
+                        "name, or against the whole path if it contains a separator, e.g.\n",
+                        "\"/home/*/.cache\". * and ? don't match a separator, ** does. Matching\n",
+                        "folders are skipped with all their content. Can be given many times,\n",
+                        "in addition to exclude patterns in mizeria.toml."
+                    ))
+            )
+            .arg(
+                Arg::with_name("files-from")
+                    .long("files-from")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .help("Read files or folders to be backed up from FILE, one per line")
+                    .long_help(concat!(
+                        "Reads paths of files or folders to be backed up from FILE, one per\n",
+                        "line, in addition to INPUT. Empty lines are ignored. Use '-' to read\n",
+                        "them from the standard input, e.g. from the output of find."
+                    ))
+            )
+            .arg(
+                Arg::with_name("from0")
+                    .long("from0")
+                    .requires("files-from")
+                    .help("Paths in the --files-from list are separated with NUL characters")
+                    .long_help(concat!(
+                        "Paths in the --files-from list are separated with NUL characters\n",
+                        "instead of new lines, so they may contain new lines themselves.\n",
+                        "Use it with 'find -print0'."
+                    ))
+            )
             .arg(
                 Arg::with_name("full")
                     .long("full")
@@ -82,15 +382,411 @@ fn parse_args(args: &[String]) -> ArgMatches {
                         "present in other snapshots."
                     ))
             )
+            .arg(
+                Arg::with_name("checksum")
+                    .long("checksum")
+                    .conflicts_with("full")
+                    .help("Find changed files by their checksums instead of modification times")
+                    .long_help(concat!(
+                        "Reads every file found in the base snapshot and compares its size and\n",
+                        "SHA-256 checksum with the ones recorded in the index, instead of\n",
+                        "comparing its size and modification time with the recorded ones. Finds\n",
+                        "files whose content changed while their modification time didn't, e.g.\n",
+                        "restored files or files touched with an older date, but reads all\n",
+                        "files on every backup. Files indexed without a checksum are copied."
+                    ))
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Fail if any file was skipped or any other warning occurred")
+                    .long_help(concat!(
+                        "The snapshot is always completed, but when any warning occurs\n",
+                        "(e.g. a file couldn't be copied) the program lists all of them\n",
+                        "and exits with an error."
+                    ))
+            )
+            .arg(
+                Arg::with_name("fail-fast")
+                    .long("fail-fast")
+                    .help("Stop at the first file that can't be backed up")
+                    .long_help(concat!(
+                        "By default files that can't be backed up are skipped, the rest is\n",
+                        "backed up and the skipped ones are listed at the end and in errors.txt\n",
+                        "of the snapshot. With this flag the backup stops at the first one and\n",
+                        "the unfinished snapshot is kept, so it can be continued with --resume."
+                    ))
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .help("Continue the snapshot of a backup that didn't finish")
+                    .long_help(concat!(
+                        "Continues the newest unfinished snapshot, e.g. of a backup that\n",
+                        "crashed or lost its connection, reusing files that were already\n",
+                        "copied. When there is none, a new snapshot is created."
+                    ))
+            )
+            .arg(
+                Arg::with_name("verify")
+                    .long("verify")
+                    .conflicts_with("encrypt-to")
+                    .help("Read back copied files and compare them with their sources")
+                    .long_help(concat!(
+                        "Reads every file copied into the new snapshot back, as soon as the\n",
+                        "index is saved, and compares it with its source, so copies written\n",
+                        "wrongly, e.g. by a flaky USB enclosure, are found right away. On Linux\n",
+                        "copies are read from the disk, not from memory. Sources that changed\n",
+                        "after they were copied are reported as warnings."
+                    ))
+            )
+            .arg(
+                Arg::with_name("remove-partial")
+                    .long("remove-partial")
+                    .help("Remove the snapshot if the backup is interrupted with Ctrl-C")
+                    .long_help(concat!(
+                        "By default a backup interrupted with Ctrl-C keeps the files copied\n",
+                        "so far and saves their index, so the snapshot is a valid base for\n",
+                        "the next one. With this flag the unfinished snapshot is removed."
+                    ))
+            )
+            .arg(get_break_lock_arg())
+            .arg(
+                Arg::with_name("on-fs-limits")
+                    .long("on-fs-limits")
+                    .takes_value(true)
+                    .possible_values(&["adapt", "abort"])
+                    .default_value("adapt")
+                    .help("What to do when the backup folder cannot store some files")
+                    .long_help(concat!(
+                        "Some filesystems (e.g. FAT32, exFAT) cannot store symlinks or files\n",
+                        "larger than 4 GiB. With 'adapt' such files are skipped and reported as\n",
+                        "warnings. With 'abort' the backup is not started at all."
+                    ))
+            )
+            .arg(
+                Arg::with_name("sign-with")
+                    .long("sign-with")
+                    .takes_value(true)
+                    .possible_values(&["minisign", "gpg"])
+                    .requires("sign-key")
+                    .help("Sign the snapshot with minisign or GPG")
+                    .long_help(concat!(
+                        "Signs index.txt and a manifest with SHA-256 checksums of stored files\n",
+                        "(checksums.sha256). Detached signatures are stored in the snapshot,\n",
+                        "so tampering with the backup can be detected. The program must be\n",
+                        "installed and available in PATH."
+                    ))
+            )
+            .arg(
+                Arg::with_name("sign-key")
+                    .long("sign-key")
+                    .value_name("KEY")
+                    .takes_value(true)
+                    .requires("sign-with")
+                    .help("Secret key file for minisign or key ID for GPG")
+            )
+            .arg(
+                Arg::with_name("umask")
+                    .long("umask")
+                    .value_name("MASK")
+                    .takes_value(true)
+                    .default_value("077")
+                    .validator(|mask| match mask.as_str() {
+                        "inherit" => Ok(()),
+                        _ => PermissionsPolicy::parse_umask(&mask)
+                            .map(|_| ())
+                            .ok_or_else(|| String::from("expected an octal mask like 077 or 'inherit'")),
+                    })
+                    .help("Permission bits removed from everything written into the snapshot")
+                    .long_help(concat!(
+                        "Permission bits removed from files and folders written into the\n",
+                        "snapshot, e.g. 027 to let the group read the backup. By default\n",
+                        "snapshots are accessible only by their owner. Use 'inherit' to keep\n",
+                        "defaults of the backup folder and permissions of backed up files.\n",
+                        "Has no effect on Windows, where ACLs of the backup folder are inherited."
+                    ))
+            )
+            .arg(
+                Arg::with_name("memory-limit")
+                    .long("memory-limit")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(size) if size > 0 => Ok(()),
+                        _ => Err(String::from("expected a size like 512M or 2G")),
+                    })
+                    .help("Keep memory usage under SIZE, e.g. 256M, for trees with millions of files")
+                    .long_help(concat!(
+                        "Bounded-memory mode for trees with millions of entries. The index is\n",
+                        "written to the disk while files are backed up and the index of the\n",
+                        "base snapshot is sorted on the disk in chunks of at most SIZE and\n",
+                        "compared as a stream. Slower than the default mode."
+                    ))
+            )
+            .arg(
+                Arg::with_name("max-file-size")
+                    .long("max-file-size")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(_) => Ok(()),
+                        None => Err(String::from("expected a size like 512M or 2G")),
+                    })
+                    .help("Don't copy files larger than SIZE, e.g. 1G")
+                    .long_help(concat!(
+                        "Files larger than SIZE, e.g. VM images or videos, are not copied into\n",
+                        "the snapshot. What happens to them is set with --on-large-file."
+                    ))
+            )
+            .arg(
+                Arg::with_name("on-large-file")
+                    .long("on-large-file")
+                    .takes_value(true)
+                    .possible_values(&["record", "skip"])
+                    .default_value("record")
+                    .help("What to do with files larger than --max-file-size")
+                    .long_help(concat!(
+                        "With 'record' files larger than --max-file-size are listed with their\n",
+                        "sizes in metadata.txt of the snapshot and reported as warnings. With\n",
+                        "'skip' they are left out without a trace."
+                    ))
+            )
+            .arg(
+                Arg::with_name("chunk-larger-than")
+                    .long("chunk-larger-than")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(_) => Ok(()),
+                        None => Err(String::from("expected a size like 64M or 1G")),
+                    })
+                    .help("Store files larger than SIZE as chunks, e.g. 64M")
+                    .long_help(concat!(
+                        "Files larger than SIZE, e.g. mailboxes, databases or VM images, are\n",
+                        "split into chunks of about 1 MiB. Only chunks that aren't stored in\n",
+                        "the backup yet are stored, so a small change of a large file doesn't\n",
+                        "copy the whole file again."
+                    ))
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .value_name("zstd[:LEVEL]")
+                    .takes_value(true)
+                    .validator(|value| match FileCompression::parse(&value) {
+                        Some(_) => Ok(()),
+                        None => Err(String::from("expected zstd or zstd:LEVEL with LEVEL from 1 to 22")),
+                    })
+                    .help("Compress files copied into the snapshot")
+                    .long_help(concat!(
+                        "Every file copied into the snapshot is compressed with zstd, at level 3\n",
+                        "by default. Files keep their names and metadata.txt records the\n",
+                        "compression, so restore and integrity checks decompress them\n",
+                        "transparently. Chunks of files stored with --chunk-larger-than aren't\n",
+                        "compressed."
+                    ))
+            )
+            .arg(
+                Arg::with_name("encrypt-to")
+                    .long("encrypt-to")
+                    .value_name("RECIPIENT")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .conflicts_with("chunk-larger-than")
+                    .help("Encrypt files copied into the snapshot to a public key")
+                    .long_help(concat!(
+                        "Every file copied into the snapshot is encrypted to the public key of\n",
+                        "RECIPIENT, after compression. Recipients starting with age1 or ssh- are\n",
+                        "encrypted with age, others are GPG key IDs. Can be given many times;\n",
+                        "any of the recipients can decrypt the files. Only public keys are\n",
+                        "needed to make backups, the private key is needed to restore them.\n",
+                        "Paths, sizes and checksums of files in the index aren't encrypted."
+                    ))
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .takes_value(true)
+                    .possible_values(&["loose", "archive"])
+                    .default_value("loose")
+                    .help("How files are stored in the snapshot")
+                    .long_help(concat!(
+                        "With 'loose' every file is stored as a file in the files folder of the\n",
+                        "snapshot. With 'archive' the folder is packed into files.tar.zst when\n",
+                        "the backup is finished, which is faster to store and list on NTFS or\n",
+                        "network shares. Files of an archived snapshot are unpacked into a\n",
+                        "temporary folder when they are read."
+                    ))
+            )
+            .arg(
+                Arg::with_name("special-files")
+                    .long("special-files")
+                    .takes_value(true)
+                    .possible_values(&["skip", "index-only", "recreate-on-restore"])
+                    .default_value("skip")
+                    .help("What to do with named pipes, sockets and device nodes")
+                    .long_help(concat!(
+                        "Special files have no data to copy. With 'skip' they are left out of\n",
+                        "the snapshot. With 'index-only' they are listed in the index. With\n",
+                        "'recreate-on-restore' their permissions and device numbers are indexed\n",
+                        "too, so restore can create them again."
+                    ))
+            )
+            .arg(
+                Arg::with_name("no-preserve")
+                    .long("no-preserve")
+                    .help("Don't record permissions, owner and modification time of entries")
+            )
+            .arg(
+                Arg::with_name("max-depth")
+                    .long("max-depth")
+                    .value_name("N")
+                    .takes_value(true)
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Back up only entries at most N levels below every input")
+                    .long_help(concat!(
+                        "Backs up only entries at most N levels below every input. With 0 only\n",
+                        "the inputs themselves are backed up, with 1 also their direct content,\n",
+                        "and so on. Folders at the last level are backed up empty."
+                    ))
+            )
+            .arg(
+                Arg::with_name("copy-buffer")
+                    .long("copy-buffer")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(size) if size > 0 && size <= 1 << 30 => Ok(()),
+                        _ => Err(String::from("expected a size between 1 and 1G, like 4M")),
+                    })
+                    .help("Copy files with a buffer of SIZE, e.g. 8M")
+                    .long_help(concat!(
+                        "Copy files with a buffer of SIZE, e.g. 8M. Larger buffers help with\n",
+                        "large files on USB disks and network shares. By default the operating\n",
+                        "system picks the way files are copied."
+                    ))
+            )
+            .arg(
+                Arg::with_name("direct-io")
+                    .long("direct-io")
+                    .help("Read files bypassing the page cache (Linux only)")
+                    .long_help(concat!(
+                        "Read files bypassing the page cache, so copying very large files\n",
+                        "doesn't evict everything else from memory. Supported only on Linux;\n",
+                        "files on filesystems without direct I/O are read as usual."
+                    ))
+            )
+            .arg(
+                Arg::with_name("append-only")
+                    .long("append-only")
+                    .help("Switch the backup to append-only mode")
+                    .long_help(concat!(
+                        "In append-only mode mizeria never modifies or removes existing\n",
+                        "snapshots, and a fingerprint of every new snapshot is recorded in\n",
+                        "fingerprints.txt. The mode stays enabled for all next backups.\n",
+                        "Use verify-fingerprints to check that snapshots weren't changed."
+                    ))
+            )
+            .arg(
+                Arg::with_name("notify")
+                    .long("notify")
+                    .help("Show a desktop notification with the result of the backup")
+                    .long_help(concat!(
+                        "Shows a desktop notification when the backup is completed or fails,\n",
+                        "so backups run by a scheduler are visible. Uses notify-send on Linux,\n",
+                        "osascript on macOS and PowerShell on Windows. The result of the backup\n",
+                        "doesn't depend on whether the notification could be shown."
+                    ))
+            )
+            .arg(
+                Arg::with_name("notify-url")
+                    .long("notify-url")
+                    .value_name("URL")
+                    .takes_value(true)
+                    .help("POST a JSON summary of the backup to URL when it finishes or fails")
+                    .long_help(concat!(
+                        "Posts a JSON summary to URL with curl when the backup finishes or\n",
+                        "fails, e.g. to a monitoring service, so a failing backup run by cron\n",
+                        "isn't missed. The summary has backup, snapshot, status (success,\n",
+                        "warnings or failure), bytes, entries, copied, warnings and error\n",
+                        "fields. The result of the backup doesn't depend on whether it was sent."
+                    ))
+            )
+            .arg(
+                Arg::with_name("notify-command")
+                    .long("notify-command")
+                    .value_name("COMMAND")
+                    .takes_value(true)
+                    .help("Pipe a JSON summary of the backup to COMMAND when it finishes or fails")
+                    .long_help(concat!(
+                        "Runs COMMAND with the shell when the backup finishes or fails and\n",
+                        "writes the JSON summary described at --notify-url to its standard\n",
+                        "input, e.g. \"mail -s 'Backup' admin@example.com\". The result of\n",
+                        "the backup doesn't depend on whether the command succeeded."
+                    ))
+            )
+            .arg(
+                Arg::with_name("pre-hook")
+                    .long("pre-hook")
+                    .value_name("COMMAND")
+                    .takes_value(true)
+                    .help("Run COMMAND with the shell before files are scanned")
+                    .long_help(concat!(
+                        "Runs COMMAND with the shell before files are scanned, e.g. to dump\n",
+                        "or quiesce a database. $MIZERIA_HOOK is 'pre' and $MIZERIA_BACKUP is\n",
+                        "the backup folder. When the command fails no snapshot is made.\n",
+                        "Overrides pre-hook in mizeria.toml."
+                    ))
+            )
+            .arg(
+                Arg::with_name("post-hook")
+                    .long("post-hook")
+                    .value_name("COMMAND")
+                    .takes_value(true)
+                    .help("Run COMMAND with the shell after the backup, even if it failed")
+                    .long_help(concat!(
+                        "Runs COMMAND with the shell after the snapshot is finished, e.g. to\n",
+                        "resume a database or to sync the backup elsewhere. It runs even if the\n",
+                        "backup failed. $MIZERIA_HOOK is 'post', $MIZERIA_BACKUP is the backup\n",
+                        "folder, $MIZERIA_SNAPSHOT the name of the snapshot, $MIZERIA_RESULT is\n",
+                        "success, warnings or failure and $MIZERIA_ERROR the error of a failed\n",
+                        "backup. When the command fails the backup fails too. Overrides\n",
+                        "post-hook in mizeria.toml."
+                    ))
+            )
+            .arg(
+                Arg::with_name("progress")
+                    .long("progress")
+                    .value_name("STYLE")
+                    .takes_value(true)
+                    .min_values(0)
+                    .max_values(1)
+                    .possible_values(&["bar", "json"])
+                    .help("Show progress of copying files with the time left")
+                    .long_help(concat!(
+                        "Shows entries processed, bytes copied, throughput and the estimated\n",
+                        "time left on a single line of standard error. Inputs are walked once\n",
+                        "before the backup to count their entries. The bar is ignored when\n",
+                        "standard error isn't a terminal.\n",
+                        "With 'json' a JSON object is printed to standard output for every\n",
+                        "event instead: scan_started, scan_finished, file_copied, file_skipped,\n",
+                        "error and summary, which replaces the usual output."
+                    ))
+            )
             .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
         )
         .subcommand(SubCommand::with_name("list")
             .about("List all snapshots")
             .visible_alias("ls")
             .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
             .arg(
                 Arg::with_name("BACKUP")
-                    .help("A folder where snapshots are stored. Defaults to current directory")
+                    .help("A folder where snapshots are stored. Defaults to $MIZERIA_BACKUP or current directory")
                     .required(false)
                     .index(1),
             )
@@ -100,82 +796,1536 @@ fn parse_args(args: &[String]) -> ArgMatches {
                     .short("s")
                     .help("Print only basic information about snapshots in a short format")
             )
+            .arg(
+                Arg::with_name("group-by")
+                    .long("group-by")
+                    .takes_value(true)
+                    .possible_values(&["day", "month"])
+                    .help("Group snapshots by day or month showing their count and size")
+            )
+            .arg(
+                Arg::with_name("foreign")
+                    .long("foreign")
+                    .conflicts_with_all(&["short", "group-by"])
+                    .help("List files and folders in the backup folder that aren't snapshots")
+            )
+            .arg(
+                Arg::with_name("clean")
+                    .long("clean")
+                    .requires("foreign")
+                    .help("Remove listed foreign entries. Damaged snapshots are never removed")
+            )
+            .arg(get_break_lock_arg())
+        )
+        .subcommand(SubCommand::with_name("latest")
+            .about("Print the path of the newest snapshot")
+            .long_about(concat!(
+                "Prints only the path of the newest valid snapshot, so it can be used\n",
+                "in scripts, e.g. cp \"$(mizeria latest /mnt/backup)\"/files/... .\n",
+                "Fails if the backup has no snapshots."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots are stored. Defaults to $MIZERIA_BACKUP or current directory")
+                    .required(false)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("name")
+                    .long("name")
+                    .help("Print only the name of the snapshot")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
         )
         .subcommand(SubCommand::with_name("snapshot")
             .about("View or edit snapshots")
             .arg(
                 Arg::with_name("SNAPSHOT")
-                    .help("A snapshot to be selected")
+                    .help("A snapshot to be selected, e.g. backup/latest or backup/latest~1")
                     .required(true)
                     .index(1)
             )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .help("Treat integrity check warnings as errors")
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .value_name("FORMAT")
+                    .takes_value(true)
+                    .possible_values(&["text", "json"])
+                    .default_value("text")
+                    .help("Format of integrity check results")
+                    .long_help(concat!(
+                        "Format of integrity check results. With json a single object is\n",
+                        "printed with the name of the snapshot, its status (passed or failed)\n",
+                        "and a list of errors. Every error has kind, code, severity, message,\n",
+                        "path, line and snapshot fields; fields that don't apply are null."
+                    ))
+            )
+            .arg(
+                Arg::with_name("only-new")
+                    .long("only-new")
+                    .help("Check only data added by this snapshot and trust the previous ones")
+                    .long_help(concat!(
+                        "Checks only entries stored in this snapshot and its link to the\n",
+                        "previous snapshot in the hash chain. Older snapshots are trusted, so\n",
+                        "the check takes time proportional to the size of changes rather than\n",
+                        "to the size of the whole history. Useful for nightly checks of\n",
+                        "the latest snapshot."
+                    ))
+            )
+            .arg(
+                Arg::with_name("verify")
+                    .long("verify")
+                    .help("Hash stored files again and compare them with checksums in the index")
+                    .long_help(concat!(
+                        "Reads every file stored in the snapshot and compares its size and\n",
+                        "SHA-256 checksum with the ones recorded in index.txt when the file\n",
+                        "was backed up. Detects files silently corrupted on the backup drive.\n",
+                        "Files indexed by older versions of mizeria have no checksums and are\n",
+                        "reported with a warning."
+                    ))
+            )
+            .args(&get_verify_args())
+            .arg(
+                Arg::with_name("info")
+                    .long("info")
+                    .conflicts_with_all(&["ls", "export-checksums", "delete"])
+                    .help("Show how the snapshot was made instead of checking integrity")
+            )
+            .arg(
+                Arg::with_name("ls")
+                    .long("ls")
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .min_values(0)
+                    .help("List entries indexed under the original PATH instead of checking integrity")
+                    .long_help(concat!(
+                        "Lists entries that were indexed directly under the original PATH with\n",
+                        "their type, size and the snapshot where their data is stored.\n",
+                        "Without PATH the inputs of the backup are listed."
+                    ))
+            )
+            .arg(
+                Arg::with_name("du")
+                    .long("du")
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .min_values(0)
+                    .conflicts_with_all(&["ls", "info", "delete"])
+                    .help("Show sizes of folders stored in the snapshot instead of checking integrity")
+                    .long_help(concat!(
+                        "Prints the total size of files stored in the snapshot under every\n",
+                        "folder, by its original path. With PATH only the original PATH and\n",
+                        "folders inside it are shown. Files stored in earlier snapshots of an\n",
+                        "incremental backup are not counted."
+                    ))
+            )
+            .arg(
+                Arg::with_name("export-checksums")
+                    .long("export-checksums")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .conflicts_with_all(&["ls", "delete"])
+                    .help("Write checksums of stored files into FILE instead of checking integrity")
+                    .long_help(concat!(
+                        "Writes SHA-256 checksums of all files stored in the snapshot into FILE\n",
+                        "in the format of sha256sum. Paths are relative to the snapshot folder,\n",
+                        "so a copy of the snapshot can be verified without mizeria by running\n",
+                        "'sha256sum -c FILE' inside it. Files stored in older snapshots of\n",
+                        "an incremental backup are not included."
+                    ))
+            )
+            .arg(
+                Arg::with_name("add-parity")
+                    .long("add-parity")
+                    .value_name("PERCENT")
+                    .takes_value(true)
+                    .conflicts_with_all(&["ls", "info", "export-checksums", "delete"])
+                    .validator(|percent| match parse_percent(&percent) {
+                        Some(_) => Ok(()),
+                        None => Err(format!("Invalid percentage: '{}'", percent)),
+                    })
+                    .help("Add parity data taking PERCENT of the snapshot size instead of checking integrity")
+                    .long_help(concat!(
+                        "Generates Reed-Solomon parity blocks for files stored in the snapshot\n",
+                        "and saves them in the parity folder next to files. The parity takes\n",
+                        "about PERCENT (e.g. 5%) of the size of stored files, and a stripe of\n",
+                        "files can be repaired as long as no more of its blocks are damaged\n",
+                        "than it has parity blocks. Damaged parity is reported by --verify and\n",
+                        "used by the repair command."
+                    ))
+            )
+            .arg(
+                Arg::with_name("delete")
+                    .long("delete")
+                    .conflicts_with("ls")
+                    .help("Remove the snapshot instead of checking integrity")
+                    .long_help(concat!(
+                        "Removes the snapshot from the backup. Indexes of all newer snapshots\n",
+                        "are scanned first, and a snapshot whose data they reference is not\n",
+                        "removed unless --force is given, because that would break their\n",
+                        "incremental chain."
+                    ))
+            )
+            .arg(
+                Arg::with_name("force")
+                    .long("force")
+                    .requires("delete")
+                    .help("Remove the snapshot even if newer snapshots reference its data")
+            )
+            .arg(get_break_lock_arg())
             .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
         )
-        .get_matches_from(args)
-}
-
-fn print_snapshots(writer: Writer, snapshots: Iter<'_, impl Display>) -> Result<()> {
-    writeln!(writer, "Available snapshots:")?;
-    for (index, snapshot) in snapshots.rev().enumerate() {
-        writeln!(writer, "{}. {}", index + 1, snapshot)?;
-    }
-    Ok(())
-}
-
-fn list_all_snapshots(writer: Writer, path: &Path, short_format: bool) -> Result<()> {
-    if !path.exists() {
-        return Err("Folder with backup doesn't exist or isn't accessible".into());
-    }
-
-    if short_format {
-        let previews = Backup::get_all_snapshot_previews(path);
-        print_snapshots(writer, previews.iter())?;
-    } else {
-        let snapshots = Backup::get_all_snapshots(path);
-        print_snapshots(writer, snapshots.iter())?;
-    };
-
-    Ok(())
-}
-
-fn handle_list_snapshots(args: &ArgMatches, writer: Writer) -> Result<()> {
-    set_verbosity(args);
-    let short_format = args.is_present("short");
-    let path = args.value_of("BACKUP").unwrap_or(".");
-    let path = Path::new(path);
-    list_all_snapshots(writer, path, short_format)
-}
-
-fn handle_manage_snapshot(args: &ArgMatches, writer: Writer) -> Result<()> {
-    set_verbosity(args);
-    let snapshot = args.value_of("SNAPSHOT").unwrap();
-    let snapshot = PathBuf::from(snapshot);
-
-    let result = perform_integrity_check(snapshot);
-    let result_message = match result {
-        Ok(()) => "Snapshot integrity check completed. No problems found.".to_string(),
-        Err(error) => format!("Snapshot integrity check failed. {}", error),
-    };
-
-    writeln!(writer, "{}", result_message)?;
-
-    Ok(())
-}
-
-fn perform_integrity_check(snapshot_path: PathBuf) -> IntegrityCheckResult {
-    if !snapshot_path.exists() {
-        Err(IntegrityCheckError::SnapshotDoesntExist)?;
-    }
-    let canonicalized_snapshot_path =
-        snapshot_path
-            .canonicalize()
-            .or(Err(IntegrityCheckError::UnexpectedError(format!(
+        .subcommand(SubCommand::with_name("browse")
+            .about("List a folder as it was captured in a snapshot")
+            .long_about(concat!(
+                "Lists entries indexed directly under the original PATH with their type,\n",
+                "size and the snapshot where their data is stored. Data of unchanged\n",
+                "entries is found in earlier snapshots of an incremental backup.\n",
+                "Without PATH the inputs of the backup are listed. Same as\n",
+                "'snapshot SNAPSHOT --ls PATH'."
+            ))
+            .arg(
+                Arg::with_name("SNAPSHOT")
+                    .help("A snapshot to be browsed, e.g. backup/latest or backup/latest~1")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("PATH")
+                    .help("Original path of a backed up folder")
+                    .index(2),
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("restore")
+            .about("Restore backed up files from a snapshot")
+            .long_about(concat!(
+                "Copies entries indexed in the snapshot into TARGET. Their absolute paths\n",
+                "are preserved under TARGET, e.g. /home/me/notes.txt is restored as\n",
+                "TARGET/home/me/notes.txt. Data stored in earlier snapshots is read from\n",
+                "them. Existing files in TARGET are not overwritten unless --overwrite\n",
+                "is given. With --dry-run nothing is written; files that would be\n",
+                "restored are listed with the snapshot storing their data.\n",
+                "\n",
+                "With --at, SNAPSHOT is a backup folder instead and the newest snapshot\n",
+                "made at or before TIME is restored, e.g. --at 2021-07-15_18.34. A date\n",
+                "alone, e.g. --at 2021-07-15, means the end of that day.\n",
+                "\n",
+                "With --verify-with and --verify-key, signatures of the snapshot are\n",
+                "verified first and nothing is restored if they don't match."
+            ))
+            .arg(
+                Arg::with_name("SNAPSHOT")
+                    .help("A snapshot to restore files from, e.g. backup/latest, or a backup folder or its registered name with --at")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("TARGET")
+                    .help("A folder where files will be restored")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("path")
+                    .long("path")
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Restore only the original PATH and entries under it")
+            )
+            .arg(
+                Arg::with_name("overwrite")
+                    .long("overwrite")
+                    .help("Replace files that already exist in TARGET")
+            )
+            .arg(
+                Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only list files that would be restored, without touching TARGET")
+            )
+            .arg(
+                Arg::with_name("no-preserve")
+                    .long("no-preserve")
+                    .help("Don't apply permissions, owner and modification time from the index")
+            )
+            .arg(
+                Arg::with_name("at")
+                    .long("at")
+                    .value_name("TIME")
+                    .takes_value(true)
+                    .help("Restore the state of the backup at TIME")
+            )
+            .arg(
+                Arg::with_name("key-file")
+                    .long("key-file")
+                    .alias("identity")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .help("Private key to decrypt files of encrypted snapshots")
+                    .long_help(concat!(
+                        "Private key of a recipient of encrypted snapshots: an age identity\n",
+                        "file, or GPG secret keys exported with `gpg --export-secret-keys`.\n",
+                        "Without it, files encrypted with GPG are decrypted with the private\n",
+                        "key in the keyring. The passphrase of a GPG key is asked for on the\n",
+                        "terminal, or read from MIZERIA_PASSPHRASE when there is no terminal,\n",
+                        "e.g. in cron. age asks for passphrases of identity files itself."
+                    ))
+            )
+            .args(&get_verify_args())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("estimate")
+            .about("Estimate what a new snapshot would copy and how long it would take")
+            .long_about(concat!(
+                "Compares the inputs with the latest snapshot the same way a backup does\n",
+                "and reports how many files and bytes a new snapshot would copy. The\n",
+                "duration is predicted from the throughput of the backup folder, which\n",
+                "can be measured with the bench command. Nothing is written."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots are stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Files or folders to be backed up")
+                    .required(true)
+                    .multiple(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("throughput")
+                    .long("throughput")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .default_value("50M")
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(size) if size > 0 => Ok(()),
+                        _ => Err(String::from("expected a size like 50M or 1G")),
+                    })
+                    .help("Bytes copied per second, e.g. 100M")
+            )
+            .arg(
+                Arg::with_name("full")
+                    .long("full")
+                    .help("Estimate a full snapshot that doesn't refer to earlier ones")
+            )
+            .arg(
+                Arg::with_name("max-depth")
+                    .long("max-depth")
+                    .value_name("N")
+                    .takes_value(true)
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Count only entries at most N levels below every input")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("stats")
+            .about("Show how much space a backup takes and what takes most of it")
+            .long_about(concat!(
+                "Reports the size of the backup folder, the number of snapshots and\n",
+                "their average size, how much every snapshot added to the backup and\n",
+                "the largest files and folders stored in it. Nothing is written."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots are stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("top")
+                    .long("top")
+                    .value_name("N")
+                    .takes_value(true)
+                    .default_value("10")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Number of the largest files and folders to show")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Check if a backup can be safely made and clean up failed backups")
+            .long_about(concat!(
+                "Checks if the backup folder is writable and has enough free space,\n",
+                "what its filesystem supports, whether the system clock is consistent\n",
+                "with existing snapshots and whether all inputs can be read.\n",
+                "Unfinished snapshots, stale locks, folders named like snapshots and\n",
+                "entries that aren't part of the backup are reported as well."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots will be stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("INPUT")
+                    .help("Files or folders to be backed up. Without them only the backup folder is checked")
+                    .multiple(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("fix")
+                    .long("fix")
+                    .help("Remove unfinished snapshots, snapshot folders without data and stale locks")
+                    .long_help(concat!(
+                        "Removes snapshots left unfinished by interrupted backups, folders named\n",
+                        "like snapshots that have no index and no files, and a lock left by a\n",
+                        "backup that isn't running anymore. Damaged snapshots with files and\n",
+                        "other foreign entries are never removed. Unfinished snapshots are kept\n",
+                        "while a backup holds the lock."
+                    ))
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("selftest")
+            .about("Check that backups can be made and restored in the backup folder")
+            .long_about(concat!(
+                "Creates a small synthetic dataset in a temporary folder inside BACKUP,\n",
+                "makes a full and an incremental snapshot of it, checks their integrity,\n",
+                "restores the latest snapshot and compares it byte by byte with the\n",
+                "dataset. The temporary folder is removed afterwards. Existing snapshots\n",
+                "are not touched."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("keep")
+                    .long("keep")
+                    .help("Keep the temporary folder for inspection")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("bench")
+            .about("Measure performance of the backup folder and recommend settings")
+            .long_about(concat!(
+                "Writes a large file with different buffer sizes and many small files\n",
+                "into a temporary folder inside BACKUP, and measures how long renaming\n",
+                "files and reading their metadata takes. Based on the results settings\n",
+                "of backups and archives are recommended. The temporary folder is\n",
+                "removed afterwards."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots are stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("size")
+                    .long("size")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .default_value("64M")
+                    .validator(|size| match find::parse_size(&size) {
+                        Some(size) if size > 0 => Ok(()),
+                        _ => Err(String::from("expected a size, e.g. 64M")),
+                    })
+                    .help("Size of the large file written with every buffer size")
+            )
+            .arg(
+                Arg::with_name("files")
+                    .long("files")
+                    .value_name("N")
+                    .takes_value(true)
+                    .default_value("500")
+                    .validator(|n| match n.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(String::from("expected a positive number")),
+                    })
+                    .help("Number of small files written")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("which")
+            .about("Show where the data of a backed up file is stored")
+            .long_about(concat!(
+                "Shows which snapshot holds the data of a backed up file and where\n",
+                "it is located on disk. Incremental snapshots store only changed files,\n",
+                "so the data may live in one of the earlier snapshots."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("PATH")
+                    .help("Original path of a backed up file or folder")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("snapshot")
+                    .long("snapshot")
+                    .value_name("SNAPSHOT")
+                    .takes_value(true)
+                    .help("Name of a snapshot to search in, e.g. latest~1 (the latest one by default)")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("diff")
+            .about("List paths added, removed or modified between two snapshots")
+            .long_about(concat!(
+                "Compares indexes of two snapshots and lists paths that were added,\n",
+                "removed or modified in the second one. Files are compared by checksums\n",
+                "recorded in the indexes, so nothing has to be restored. Files indexed\n",
+                "by older versions of mizeria have no checksums, and are reported as\n",
+                "modified whenever they were copied again."
+            ))
+            .arg(
+                Arg::with_name("SNAPSHOT_A")
+                    .help("The older snapshot, e.g. backup/latest~1")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("SNAPSHOT_B")
+                    .help("The newer snapshot, e.g. backup/latest")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("verify-mirror")
+            .about("Check if two backups contain the same snapshots")
+            .long_about(concat!(
+                "Compares snapshots, their indexes and stored files between a primary\n",
+                "backup and its copy, e.g. an offsite one, and reports every difference.\n",
+                "Stored files are compared by size unless --hash is used."
+            ))
+            .arg(
+                Arg::with_name("PRIMARY")
+                    .help("A folder with the primary backup")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("MIRROR")
+                    .help("A folder with a copy of the backup")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("hash")
+                    .long("hash")
+                    .help("Compare hashes of stored files. Slower, because every file is read")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("archive")
+            .about("Pack the whole backup into a single file")
+            .long_about(concat!(
+                "Packs all snapshots and metadata of the backup into a single file that\n",
+                "can be kept in cold storage or moved to another machine. The archive\n",
+                "can be compressed with gzip, zstd or xz and encrypted with GPG. These\n",
+                "programs must be installed. Use 'unarchive' to unpack it."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Path of the archive, e.g. backup.mza")
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .value_name("PROGRAM")
+                    .takes_value(true)
+                    .possible_values(&["gzip", "zstd", "xz"])
+                    .help("Compress the archive with an external program")
+            )
+            .arg(
+                Arg::with_name("encrypt-to")
+                    .long("encrypt-to")
+                    .value_name("KEY")
+                    .takes_value(true)
+                    .help("Encrypt the archive with GPG for a recipient's key ID")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("unarchive")
+            .about("Unpack an archive made by 'archive' into a new backup folder")
+            .long_about(concat!(
+                "Unpacks an archive into a new backup folder. Compressed and encrypted\n",
+                "archives are recognized automatically. The archive is verified with\n",
+                "its hash and nothing is unpacked if it is corrupted."
+            ))
+            .arg(
+                Arg::with_name("ARCHIVE")
+                    .help("An archive made by 'archive'")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A new folder for the backup. It must not exist or be empty")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("verify-fingerprints")
+            .about("Check that snapshots didn't change since they were created")
+            .long_about(concat!(
+                "Compares every snapshot with the fingerprint recorded when it was created\n",
+                "in append-only mode. Snapshots that were changed or removed are reported\n",
+                "as errors."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("check")
+            .about("Check integrity of snapshots that weren't verified for the longest time")
+            .long_about(concat!(
+                "Checks snapshots that were never verified first, and then those verified\n",
+                "the longest time ago, until the budget is spent. Time of every passed\n",
+                "check is recorded in verified.txt, so the whole backup gets covered\n",
+                "over successive runs, e.g. scheduled every night."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("budget")
+                    .long("budget")
+                    .value_name("DURATION")
+                    .takes_value(true)
+                    .validator(|duration| {
+                        parse_duration(&duration)
+                            .map(|_| ())
+                            .ok_or_else(|| String::from("expected a duration like 90s, 15min or 2h"))
+                    })
+                    .help("Don't start checking another snapshot after DURATION, e.g. 15min")
+            )
+            .arg(
+                Arg::with_name("snapshots")
+                    .long("snapshots")
+                    .value_name("N")
+                    .takes_value(true)
+                    .conflicts_with("budget")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Check at most N snapshots")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("scrub")
+            .about("Verify content of files stored in all snapshots to detect bit rot")
+            .long_about(concat!(
+                "Reads every file stored in every snapshot and compares it with the size\n",
+                "and checksum recorded in the index when it was backed up. Time of every\n",
+                "passed scrub is recorded in scrubbed.txt, so with --since snapshots that\n",
+                "were scrubbed recently are skipped."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("since")
+                    .long("since")
+                    .value_name("DAYS")
+                    .takes_value(true)
+                    .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Scrub only snapshots that weren't scrubbed in the last DAYS days")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("prune")
+            .about("Remove old snapshots")
+            .long_about(concat!(
+                "Removes all snapshots except the newest ones. Snapshots that store data\n",
+                "of files still indexed by a kept snapshot are kept as well."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("keep-last")
+                    .long("keep-last")
+                    .value_name("N")
+                    .takes_value(true)
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Number of the newest snapshots to keep, by default keep-last in mizeria.toml")
+            )
+            .arg(
+                Arg::with_name("keep-trash")
+                    .long("keep-trash")
+                    .value_name("DURATION")
+                    .takes_value(true)
+                    .default_value("7d")
+                    .validator(|duration| {
+                        parse_duration(&duration)
+                            .map(|_| ())
+                            .ok_or_else(|| String::from("expected a duration like 12h or 7d"))
+                    })
+                    .help("How long removed snapshots are kept in the trash")
+                    .long_help(concat!(
+                        "Removed snapshots are moved to the .trash folder in the backup, where\n",
+                        "they can be restored from with 'undelete'. Snapshots that are in the\n",
+                        "trash for longer than DURATION, e.g. 12h or 7d, are removed for good."
+                    ))
+            )
+            .arg(
+                Arg::with_name("no-trash")
+                    .long("no-trash")
+                    .help("Remove snapshots immediately instead of moving them to the trash")
+            )
+            .arg(
+                Arg::with_name("shred")
+                    .long("shred")
+                    .help("Overwrite content of files with zeros before removing them")
+                    .long_help(concat!(
+                        "Overwrites content of every removed file with zeros and flushes it\n",
+                        "to the disk before the file is removed. It is meaningful only when\n",
+                        "data is written in place, e.g. on HDDs with ext4 or NTFS. SSDs,\n",
+                        "copy-on-write filesystems (btrfs, ZFS, APFS) and network shares may\n",
+                        "keep the original data elsewhere. Shredded snapshots are never moved\n",
+                        "to the trash."
+                    ))
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("squash")
+            .about("Squash a range of incremental snapshots into one full snapshot")
+            .long_about(concat!(
+                "Copies data of all entries that snapshot TO references in older snapshots\n",
+                "into TO, so it becomes a full snapshot. Entries of newer snapshots that\n",
+                "reference the same data in snapshots from FROM up to TO are pointed to\n",
+                "TO, and the hash chain of newer snapshots is updated. Squashed snapshots\n",
+                "that are no longer referenced can then be removed with --remove-originals,\n",
+                "'snapshot --delete' or prune. Snapshots are given by name or as latest~N."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("FROM")
+                    .help("The oldest snapshot to squash, e.g. 2021-07-01_18.34")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("TO")
+                    .help("The snapshot that becomes a full snapshot")
+                    .required(true)
+                    .index(3),
+            )
+            .arg(
+                Arg::with_name("remove-originals")
+                    .long("remove-originals")
+                    .help("Remove squashed snapshots that are no longer referenced")
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("repair")
+            .about("Replace corrupted files of a snapshot with intact copies from other snapshots")
+            .long_about(concat!(
+                "Finds files stored in SNAPSHOT that don't match their checksums in the\n",
+                "index and replaces them with copies of the same entries, with the same\n",
+                "path and checksum, stored in other snapshots. Copies are verified before\n",
+                "they replace damaged files. Fails if some file has no intact copy."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("SNAPSHOT")
+                    .help("The snapshot to repair, e.g. 2021-07-01_18.34 or latest~1")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("undelete")
+            .about("Restore a snapshot removed by prune from the trash")
+            .long_about(concat!(
+                "Moves a snapshot removed by prune from the .trash folder back into the\n",
+                "backup. Without SNAPSHOT lists snapshots that are in the trash."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("SNAPSHOT")
+                    .help("Name of the snapshot to restore, e.g. 2021-07-15_18.34")
+                    .index(2),
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("import-rsync")
+            .about("Import dated folders made by rsync or other backup tools")
+            .long_about(concat!(
+                "Imports a folder with dated copies of the backed up folder, e.g. made\n",
+                "by rsync --link-dest or robocopy, as snapshots. Every subfolder with a\n",
+                "date in its name like 2021-07-15, 2021-07-15_18.34 or 20210715-1834\n",
+                "becomes a snapshot named after that date. Files that didn't change\n",
+                "since the previous folder (same size and modification time) are stored\n",
+                "only once. Folders that aren't newer than the latest snapshot are skipped."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots will be stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("FOLDER")
+                    .help("A folder with dated copies")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("original")
+                    .long("original")
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Absolute path of the folder that was copied, e.g. /home/user")
+                    .long_help(concat!(
+                        "Absolute path of the folder that was copied into every dated folder,\n",
+                        "e.g. /home/user. Imported files are indexed under this path, so later\n",
+                        "backups of that folder are incremental to the imported snapshots."
+                    ))
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("import")
+            .about("Import a tar archive as a snapshot")
+            .long_about(concat!(
+                "Unpacks a tar archive, e.g. an old tarball of a folder, into a new\n",
+                "snapshot and indexes its content, so a backup can be seeded from\n",
+                "historical archives and continued incrementally. Archives compressed\n",
+                "with gzip, zstd or xz are recognized by their content. The snapshot is\n",
+                "named after the date in the name of the archive, e.g. home-2021-07-15.tar,\n",
+                "or its modification time, and has to be newer than the latest snapshot."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder where snapshots will be stored")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("ARCHIVE")
+                    .help("A tar archive to import")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("original")
+                    .long("original")
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .default_value("/")
+                    .help("Absolute path of the folder that was archived")
+                    .long_help(concat!(
+                        "Absolute path of the folder whose content is in the archive. Paths in\n",
+                        "tar archives are relative, e.g. 'tar -cf home.tar /home/user' stores\n",
+                        "home/user/..., which is imported under / by default. For an archive\n",
+                        "made with 'tar -C /home/user -cf home.tar .' use --original /home/user."
+                    ))
+            )
+            .arg(get_break_lock_arg())
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("find")
+            .about("Find backed up files in all snapshots")
+            .long_about(concat!(
+                "Finds entries stored in snapshots of a backup. Every entry is listed\n",
+                "together with the snapshot that stores its data. Entries that weren't\n",
+                "changed since an earlier snapshot are listed only once."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("PATTERN")
+                    .help("Part of an original path or a glob, e.g. \"*.jpg\", that must be matched")
+                    .long_help(concat!(
+                        "Part of an original path that must be matched. A pattern with *, ?\n",
+                        "or [...] is a glob. It's matched against the file name, or against\n",
+                        "the whole path if it contains a separator, e.g. \"/home/*/notes/**\".\n",
+                        "* doesn't match a separator, ** does."
+                    ))
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("regex")
+                    .long("regex")
+                    .requires("PATTERN")
+                    .help("Treat PATTERN as a regular expression searched in the whole path")
+            )
+            .arg(
+                Arg::with_name("type")
+                    .long("type")
+                    .takes_value(true)
+                    .possible_values(&["file", "dir", "symlink"])
+                    .help("Find only entries of the given type")
+            )
+            .arg(
+                Arg::with_name("larger-than")
+                    .long("larger-than")
+                    .value_name("SIZE")
+                    .takes_value(true)
+                    .help("Find only entries larger than SIZE, e.g. 512K, 100M or 1G")
+            )
+            .arg(
+                Arg::with_name("modified-before")
+                    .long("modified-before")
+                    .value_name("DATE")
+                    .takes_value(true)
+                    .help("Find only entries modified before DATE (yyyy-mm-dd)")
+            )
+            .arg(
+                Arg::with_name("modified-after")
+                    .long("modified-after")
+                    .value_name("DATE")
+                    .takes_value(true)
+                    .help("Find only entries modified on or after DATE (yyyy-mm-dd)")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("export-inventory")
+            .about("Export a list of all entries in all snapshots")
+            .long_about(concat!(
+                "Exports one row for every entry indexed in every snapshot with its size,\n",
+                "modification time and the snapshot that stores its data. The output\n",
+                "can be loaded into a spreadsheet or a database."
+            ))
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("A folder with snapshots")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .possible_values(&["csv"])
+                    .default_value("csv")
+                    .help("Format of the exported inventory")
+            )
+            .arg(get_verbosity_arg())
+            .arg(get_quiet_arg())
+        )
+        .subcommand(SubCommand::with_name("remote")
+            .about("Register backups under a name")
+            .long_about(concat!(
+                "Registers backups in the user's config file, so 'mizeria backup nas ~/docs'\n",
+                "or 'mizeria list nas' can be used instead of the path of the backup. The\n",
+                "name is used wherever BACKUP is expected, unless a folder of that name\n",
+                "exists in the current folder."
+            ))
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("add")
+                .about("Register a backup")
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("Name of the backup, letters, digits, '-' and '_'")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("LOCATION")
+                        .help("A folder with the backup")
+                        .required(true)
+                        .index(2),
+                )
+            )
+            .subcommand(SubCommand::with_name("remove")
+                .about("Forget a registered backup, the backup itself is kept")
+                .arg(
+                    Arg::with_name("NAME")
+                        .help("Name of the backup")
+                        .required(true)
+                        .index(1),
+                )
+            )
+            .subcommand(SubCommand::with_name("list")
+                .about("List registered backups")
+            )
+        )
+        .subcommand(SubCommand::with_name("topic")
+            .about("Show extended help about a topic")
+            .long_about(concat!(
+                "Shows extended help about how mizeria works, e.g. 'mizeria topic incremental'.\n",
+                "Without a topic all available topics are listed."
+            ))
+            .arg(
+                Arg::with_name("TOPIC")
+                    .help("Name of the topic")
+                    .possible_values(&manual::TOPICS.iter().map(|t| t.name).collect::<Vec<_>>())
+                    .index(1),
+            )
+        )
+        .subcommand(SubCommand::with_name("gen-man")
+            .about("Generate man pages")
+            .setting(AppSettings::Hidden)
+            .long_about(concat!(
+                "Generates roff man pages from the help of every command. The page of\n",
+                "the program also contains extended help topics. Without --out the page\n",
+                "of COMMAND, or of the whole program, is printed."
+            ))
+            .arg(
+                Arg::with_name("COMMAND")
+                    .help("Command to print the page of")
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("out")
+                    .long("out")
+                    .value_name("DIR")
+                    .takes_value(true)
+                    .conflicts_with("COMMAND")
+                    .help("Write pages of all commands into DIR")
+            )
+        )
+}
+
+fn print_snapshots(writer: Writer, snapshots: Iter<'_, impl Display>) -> Result<()> {
+    writeln!(writer, "{}", t!("Available snapshots:"))?;
+    for (index, snapshot) in snapshots.rev().enumerate() {
+        writeln!(writer, "{}. {}", index + 1, snapshot)?;
+    }
+    Ok(())
+}
+
+fn print_grouped_snapshots(writer: Writer, path: &Path, period: Period) -> Result<()> {
+    // (period, count, size) in chronological order
+    let mut groups: Vec<(String, usize, u64)> = vec![];
+    for snapshot in Backup::get_all_snapshots(path) {
+        let key = snapshot.period(period);
+        match groups.last_mut() {
+            Some((last_key, count, size)) if *last_key == key => {
+                *count += 1;
+                *size += snapshot.size();
+            }
+            _ => groups.push((key, 1, snapshot.size())),
+        }
+    }
+
+    writeln!(writer, "{}", t!("Available snapshots:"))?;
+    for (key, count, size) in groups.iter().rev() {
+        let count = t!("{} snapshot(s)", count);
+        let size = t!("{} bytes", size);
+        writeln!(writer, "{}  {}  {}", key, count, size)?;
+    }
+    Ok(())
+}
+
+fn list_all_snapshots(
+    writer: Writer,
+    path: &Path,
+    short_format: bool,
+    group_by: Option<Period>,
+) -> Result<()> {
+    if !path.exists() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
+
+    if let Some(period) = group_by {
+        print_grouped_snapshots(writer, path, period)?;
+    } else if short_format {
+        let previews = Backup::get_all_snapshot_previews(path);
+        print_snapshots(writer, previews.iter())?;
+    } else {
+        let snapshots = Backup::get_all_snapshots(path);
+        print_snapshots(writer, snapshots.iter())?;
+    };
+
+    Ok(())
+}
+
+fn handle_list_snapshots(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let short_format = args.is_present("short");
+    let path = &optional_backup_path(args)?.unwrap_or_else(|| PathBuf::from("."));
+    let group_by = match args.value_of("group-by") {
+        Some("day") => Some(Period::Day),
+        Some("month") => Some(Period::Month),
+        _ => None,
+    };
+    if args.is_present("foreign") {
+        if args.is_present("clean") {
+            let _lock = Lock::acquire(path, args.is_present("break-lock"))?;
+            return list_foreign_entries(writer, path, true);
+        }
+        return list_foreign_entries(writer, path, false);
+    }
+    list_all_snapshots(writer, path, short_format, group_by)
+}
+
+fn handle_latest(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let path = &optional_backup_path(args)?.unwrap_or_else(|| PathBuf::from("."));
+    let backup = Backup::open(path)?;
+    let latest = backup
+        .latest_snapshot()
+        .ok_or_else(|| t!("Backup has no snapshots"))?
+        .name();
+
+    if args.is_present("name") {
+        writeln!(writer, "{}", latest)?;
+    } else {
+        writeln!(writer, "{}", path.join(latest).display())?;
+    }
+    Ok(())
+}
+
+fn list_foreign_entries(writer: Writer, path: &Path, clean: bool) -> Result<()> {
+    if !path.exists() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
+
+    let entries = Backup::get_foreign_entries(path);
+    if entries.is_empty() {
+        writeln!(writer, "{}", t!("No foreign entries found."))?;
+        return Ok(());
+    }
+
+    writeln!(writer, "{}", t!("Foreign entries:"))?;
+    let mut removed = 0;
+    let mut removed_size = 0;
+    for entry in &entries {
+        let note = if entry.is_damaged_snapshot() {
+            format!("  {}", t!("(damaged snapshot, kept)"))
+        } else if clean {
+            entry.remove()?;
+            removed += 1;
+            removed_size += entry.size;
+            format!("  {}", t!("(removed)"))
+        } else {
+            String::new()
+        };
+        writeln!(
+            writer,
+            "{:>12} {}  {}{}",
+            entry.size,
+            t!("bytes"),
+            entry.name(),
+            note
+        )?;
+    }
+
+    let total_size: u64 = entries.iter().map(|e| e.size).sum();
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Found {} foreign entries taking {} bytes.",
+            entries.len(),
+            total_size
+        )
+    )?;
+    if clean {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Removed {} entries freeing {} bytes.",
+                removed,
+                removed_size
+            )
+        )?;
+    }
+    Ok(())
+}
+
+fn handle_manage_snapshot(args: &ArgMatches, writer: Writer) -> Result<Completion> {
+    set_verbosity(args);
+    let snapshot = snapshot_path(args.value_of("SNAPSHOT").unwrap())?;
+
+    let result = if args.is_present("ls") {
+        list_snapshot_entries(writer, &snapshot, args.value_of("ls").map(Path::new))
+    } else if args.is_present("du") {
+        print_disk_usage(writer, &snapshot, args.value_of("du").map(Path::new))
+    } else if args.is_present("info") {
+        print_snapshot_info(writer, &snapshot)
+    } else if args.is_present("delete") {
+        delete_snapshot(writer, &snapshot, args)
+    } else if let Some(percent) = args.value_of("add-parity").and_then(parse_percent) {
+        add_parity(writer, &snapshot, percent, args)
+    } else {
+        return check_snapshot(args, writer, &snapshot);
+    };
+    result.map(|_| Completion::Success)
+}
+
+/// Checks integrity of the snapshot, or exports its checksums.
+fn check_snapshot(args: &ArgMatches, writer: Writer, snapshot: &Path) -> Result<Completion> {
+    if let Some(output) = args.value_of("export-checksums") {
+        let count = Backup::get_snapshot(snapshot)?.export_checksums(Path::new(output))?;
+        writeln!(
+            writer,
+            "{}",
+            t!("Exported checksums of {} file(s) to {}", count, output)
+        )?;
+        return Ok(Completion::Success);
+    }
+
+    let strict = args.is_present("strict");
+    let name = snapshot
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut report = perform_integrity_check(
+        snapshot.to_owned(),
+        get_verifying_key(args).as_ref(),
+        args.is_present("only-new"),
+    );
+    if args.is_present("verify") && report.passed(false) {
+        Backup::get_snapshot(snapshot)?.verify_content(&mut report);
+    }
+    if args.value_of("output") == Some("json") {
+        let status = if report.passed(strict) {
+            "passed"
+        } else {
+            "failed"
+        };
+        let result = serde_json::json!({
+            "snapshot": name,
+            "status": status,
+            "errors": report.findings(),
+        });
+        writeln!(writer, "{}", result)?;
+        return integrity_completion(&report, strict);
+    }
+    for finding in report.findings() {
+        writeln!(
+            writer,
+            "{}[{}]: {}",
+            finding.severity(),
+            finding.code(),
+            finding
+        )?;
+    }
+
+    let warnings = report.count(Severity::Warning);
+    let result_message = if !report.passed(false) {
+        t!("Snapshot integrity check failed.")
+    } else if !report.passed(strict) {
+        t!("Snapshot integrity check failed. Warnings are treated as errors in strict mode.")
+    } else if warnings > 0 {
+        t!(
+            "Snapshot integrity check completed with {} warning(s).",
+            warnings
+        )
+    } else {
+        t!("Snapshot integrity check completed. No problems found.")
+    };
+
+    writeln!(writer, "{}", result_message)?;
+    integrity_completion(&report, strict)
+}
+
+fn integrity_completion(report: &IntegrityReport, strict: bool) -> Result<Completion> {
+    if !report.passed(strict) {
+        Err(IntegrityFailed(t!("Snapshot integrity check failed")).into())
+    } else if report.count(Severity::Warning) > 0 {
+        Ok(Completion::Warnings)
+    } else {
+        Ok(Completion::Success)
+    }
+}
+
+fn delete_snapshot(writer: Writer, snapshot: &Path, args: &ArgMatches) -> Result<()> {
+    let name = snapshot
+        .file_name()
+        .ok_or_else(|| format!("Cannot open snapshot: '{}'", snapshot.display()))?
+        .to_string_lossy();
+    let backup = match snapshot.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let mut backup = Backup::open(backup)?;
+    backup.set_break_lock(args.is_present("break-lock"));
+    let dependents = backup.delete_snapshot(&name, args.is_present("force"))?;
+    writeln!(writer, "{}", t!("Removed snapshot: {}", name))?;
+    if !dependents.is_empty() {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Data referenced by these snapshots is now missing: {}",
+                dependents.join(", ")
+            )
+        )?;
+    }
+    Ok(())
+}
+
+fn add_parity(writer: Writer, snapshot: &Path, percent: u8, args: &ArgMatches) -> Result<()> {
+    let name = snapshot
+        .file_name()
+        .ok_or_else(|| format!("Cannot open snapshot: '{}'", snapshot.display()))?
+        .to_string_lossy();
+    let backup = match snapshot.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let mut backup = Backup::open(backup)?;
+    backup.set_break_lock(args.is_present("break-lock"));
+    let summary = backup.add_parity(&name, percent)?;
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Added {} parity block(s) ({} bytes) protecting {} block(s) of snapshot {}",
+            summary.parity_blocks,
+            summary.size,
+            summary.data_blocks,
+            name
+        )
+    )?;
+    Ok(())
+}
+
+/// Parses a percentage from 1 to 100, with or without the % sign.
+fn parse_percent(percent: &str) -> Option<u8> {
+    let percent = percent.strip_suffix('%').unwrap_or(percent);
+    percent.parse().ok().filter(|p| (1..=100).contains(p))
+}
+
+fn print_snapshot_info(writer: Writer, snapshot: &Path) -> Result<()> {
+    let snapshot = Backup::get_snapshot(snapshot)?;
+    let metadata = match snapshot.metadata() {
+        Some(metadata) => metadata,
+        None => {
+            print_fields(writer, &[(t!("Snapshot:"), snapshot.name())])?;
+            writeln!(writer, "{}", t!("Snapshot has no metadata."))?;
+            return Ok(());
+        }
+    };
+
+    let mut fields = vec![
+        (t!("Snapshot:"), snapshot.name()),
+        (t!("Version:"), metadata.version.clone()),
+        (t!("Host:"), metadata.host.clone()),
+        (t!("User:"), metadata.user.clone()),
+        (
+            t!("Type:"),
+            if metadata.incremental {
+                t!("incremental")
+            } else {
+                t!("full")
+            },
+        ),
+        (
+            t!("Duration:"),
+            format!("{:.3}s", metadata.duration.as_secs_f64()),
+        ),
+    ];
+    for (i, input) in metadata.inputs.iter().enumerate() {
+        let label = if i == 0 { t!("Inputs:") } else { String::new() };
+        fields.push((label, input.display().to_string()));
+    }
+    fields.push((
+        t!("Entries:"),
+        t!("{} indexed, {} copied", metadata.indexed, metadata.copied),
+    ));
+    fields.push((t!("Size:"), t!("{} bytes", metadata.size)));
+    fields.push((t!("Warnings:"), metadata.warnings.to_string()));
+    for (i, (path, size)) in metadata.not_copied.iter().enumerate() {
+        let label = if i == 0 {
+            t!("Not copied:")
+        } else {
+            String::new()
+        };
+        fields.push((label, t!("{} ({} bytes)", path.display(), size)));
+    }
+    for (i, path) in metadata.unstable.iter().enumerate() {
+        let label = if i == 0 {
+            t!("Unstable:")
+        } else {
+            String::new()
+        };
+        fields.push((label, path.display().to_string()));
+    }
+    print_fields(writer, &fields)
+}
+
+/// Prints `label value` lines with values aligned, whatever the length
+/// of the translated labels.
+fn print_fields(writer: Writer, fields: &[(String, String)]) -> Result<()> {
+    let width = fields
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or_default()
+        .max(10);
+    for (label, value) in fields {
+        writeln!(writer, "{:<width$} {}", label, value, width = width)?;
+    }
+    Ok(())
+}
+
+fn list_snapshot_entries(writer: Writer, snapshot: &Path, parent: Option<&Path>) -> Result<()> {
+    let snapshot = Backup::get_snapshot(snapshot)?;
+    let parent = match parent {
+        Some(parent) => Some(to_absolute_path(parent)?),
+        None => None,
+    };
+
+    let mut entries = snapshot.list_entries(parent.as_deref()).ok_or_else(|| {
+        t!(
+            "Path '{}' is not indexed in this snapshot",
+            parent.as_deref().unwrap_or_else(|| Path::new("")).display()
+        )
+    })?;
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in entries {
+        let name = match parent {
+            Some(_) => entry.path.file_name().unwrap_or_default().to_string_lossy(),
+            None => entry.path.to_string_lossy(),
+        };
+        writeln!(
+            writer,
+            "{:<7} {:>12}  {}  {}",
+            entry.kind.to_string(),
+            entry.size,
+            entry.stored_in,
+            name
+        )?;
+    }
+    Ok(())
+}
+
+fn print_disk_usage(writer: Writer, snapshot: &Path, under: Option<&Path>) -> Result<()> {
+    let snapshot = Backup::get_snapshot(snapshot)?;
+    let under = match under {
+        Some(under) => Some(to_absolute_path(under)?),
+        None => None,
+    };
+
+    let folders = snapshot.disk_usage(under.as_deref());
+    if let (Some(under), true) = (&under, folders.is_empty()) {
+        return Err(t!(
+            "No files are stored under '{}' in this snapshot",
+            under.display()
+        )
+        .into());
+    }
+    for (folder, size) in folders {
+        writeln!(
+            writer,
+            "{:>10}  {}",
+            progress::format_size(size),
+            folder.display()
+        )?;
+    }
+    Ok(())
+}
+
+fn perform_integrity_check(
+    snapshot_path: PathBuf,
+    key: Option<&VerifyingKey>,
+    only_new: bool,
+) -> IntegrityReport {
+    match open_backup_of_snapshot(snapshot_path) {
+        Ok((backup, snapshot_name)) => backup.check_integrity(&snapshot_name, key, only_new),
+        Err(error) => IntegrityReport::from(Err(error)),
+    }
+}
+
+fn open_backup_of_snapshot(
+    snapshot_path: PathBuf,
+) -> std::result::Result<(Backup, OsString), IntegrityCheckError> {
+    if !snapshot_path.exists() {
+        Err(IntegrityCheckError::SnapshotDoesntExist)?;
+    }
+    let canonicalized_snapshot_path =
+        snapshot_path
+            .canonicalize()
+            .or(Err(IntegrityCheckError::UnexpectedError(format!(
                 "Cannot determine an absolute path for: '{}'",
                 snapshot_path.display()
             ))))?;
     let snapshot_name = canonicalized_snapshot_path
         .file_name()
-        .ok_or(IntegrityCheckError::SnapshotDoesntExist)?;
+        .ok_or(IntegrityCheckError::SnapshotDoesntExist)?
+        .to_owned();
     let backup_path = snapshot_path
         .parent()
         .ok_or(IntegrityCheckError::UnexpectedError(
@@ -185,54 +2335,1528 @@ fn perform_integrity_check(snapshot_path: PathBuf) -> IntegrityCheckResult {
     let backup_path = if backup_path == Path::new("") {
         Path::new(".")
     } else {
-        backup_path
+        backup_path
+    };
+
+    let backup = match Backup::open(backup_path) {
+        Ok(backup) => backup,
+        Err(error) => Err(IntegrityCheckError::UnexpectedError(format!("{}", error)))?,
+    };
+    Ok((backup, snapshot_name))
+}
+
+fn handle_backup(
+    args: &ArgMatches,
+    writer: Writer,
+    environment: &Environment,
+) -> Result<Completion> {
+    let result = create_snapshot(args, writer, environment);
+    let result = run_post_hook(args, result);
+    send_summary(args, &result);
+    if args.is_present("notify") {
+        let backup = optional_backup_path(args).ok().flatten();
+        let backup = backup.unwrap_or_default().display().to_string();
+        let notification = match &result {
+            Ok(report) if report.warnings.is_empty() => Notification {
+                title: t!("Backup completed"),
+                body: t!("Snapshot {} created in {}", report.snapshot, backup),
+                failure: false,
+            },
+            Ok(report) => Notification {
+                title: t!("Backup completed"),
+                body: t!(
+                    "Snapshot {} created in {} with {} warning(s)",
+                    report.snapshot,
+                    backup,
+                    report.warnings.len()
+                ),
+                failure: false,
+            },
+            Err(e) => Notification {
+                title: t!("Backup failed"),
+                body: e.to_string(),
+                failure: true,
+            },
+        };
+        if let Err(e) = notification.show() {
+            warn!("Cannot show notification: {}", e);
+        }
+    }
+    result.map(|report| match report.warnings.is_empty() {
+        true => Completion::Success,
+        false => Completion::Warnings,
+    })
+}
+
+/// Result of the backup as told to hooks and notifications.
+fn outcome(result: &Result<BackupReport>) -> &'static str {
+    match result {
+        Ok(report) if report.warnings.is_empty() => "success",
+        Ok(_) => "warnings",
+        Err(_) => "failure",
+    }
+}
+
+/// Sends a summary of the backup to --notify-url or --notify-command.
+/// A failure to send it is only logged.
+fn send_summary(args: &ArgMatches, result: &Result<BackupReport>) {
+    let (url, command) = (args.value_of("notify-url"), args.value_of("notify-command"));
+    if url.is_none() && command.is_none() {
+        return;
+    }
+    let backup = optional_backup_path(args).ok().flatten();
+    let mut summary = Summary {
+        backup: backup.unwrap_or_default().display().to_string(),
+        snapshot: None,
+        status: outcome(result),
+        bytes: 0,
+        entries: 0,
+        copied: 0,
+        warnings: vec![],
+        error: None,
+    };
+    match result {
+        Ok(report) => {
+            summary.snapshot = Some(report.snapshot.clone());
+            summary.bytes = report.stats.written;
+            summary.entries = report.stats.scanned;
+            summary.copied = report.stats.copied;
+            summary.warnings = report.warnings.clone();
+        }
+        Err(e) => summary.error = Some(e.to_string()),
+    }
+    let sent = url
+        .map(|url| summary.post(url))
+        .into_iter()
+        .chain(command.map(|command| summary.pipe_to(command)));
+    for error in sent.filter_map(|sent| sent.err()) {
+        warn!("Cannot send backup summary: {}", error);
+    }
+}
+
+/// Runs the post-backup hook with the result of the backup. Its failure
+/// fails a backup that succeeded.
+fn run_post_hook(args: &ArgMatches, result: Result<BackupReport>) -> Result<BackupReport> {
+    let location = match optional_backup_path(args) {
+        Ok(Some(location)) => location,
+        _ => return result,
+    };
+    let command = match args.value_of("post-hook") {
+        Some(command) => command.to_string(),
+        None => match Settings::load(&location).ok().and_then(|s| s.post_hook) {
+            Some(command) => command,
+            None => return result,
+        },
+    };
+    let (snapshot, message) = match &result {
+        Ok(report) => (report.snapshot.clone(), String::new()),
+        Err(e) => (String::new(), e.to_string()),
+    };
+    let location = location.display().to_string();
+    let hooked = hooks::run(
+        &command,
+        &[
+            (HOOK_VARIABLE, "post"),
+            (BACKUP_VARIABLE, &location),
+            (SNAPSHOT_VARIABLE, &snapshot),
+            (RESULT_VARIABLE, outcome(&result)),
+            (ERROR_VARIABLE, &message),
+        ],
+    );
+    match (result, hooked) {
+        (Ok(_), Err(e)) => Err(t!("Post-backup hook failed: {}", e).into()),
+        (Err(failure), Err(e)) => {
+            error!("{}", t!("Post-backup hook failed: {}", e));
+            Err(failure)
+        }
+        (result, Ok(())) => result,
+    }
+}
+
+fn create_snapshot(
+    args: &ArgMatches,
+    writer: Writer,
+    environment: &Environment,
+) -> Result<BackupReport> {
+    let mut files: Vec<PathBuf> = args
+        .values_of("INPUT")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(list) = args.value_of("files-from") {
+        files.extend(read_input_list(list, args.is_present("from0"))?);
+    }
+
+    set_verbosity(args);
+    let location = backup_path(args)?;
+
+    let mut backup = Backup::open_with_environment(&location, environment.clone())?;
+    let settings = backup.settings().clone();
+    if files.is_empty() && args.value_of("files-from").is_none() {
+        files = settings.inputs;
+    }
+    if files.is_empty() {
+        return Err(t!("No files to back up. Give INPUT or set inputs in mizeria.toml").into());
+    }
+    let mut exclude = settings.exclude;
+    for glob in args.values_of("exclude").into_iter().flatten() {
+        exclude.push(find::Pattern::glob(glob)?);
+    }
+
+    let strict = args.is_present("strict");
+    let config = BackupConfig {
+        incremental: settings.incremental && !args.is_present("full"),
+        limits_policy: match args.value_of("on-fs-limits") {
+            Some("abort") => LimitsPolicy::Abort,
+            _ => LimitsPolicy::Adapt,
+        },
+        signing_key: get_signing_key(args),
+        permissions: match args.value_of("umask") {
+            Some("inherit") => PermissionsPolicy::Inherit,
+            mask => mask
+                .and_then(PermissionsPolicy::parse_umask)
+                .unwrap_or_default(),
+        },
+        memory_limit: args.value_of("memory-limit").and_then(find::parse_size),
+        max_depth: args.value_of("max-depth").and_then(|n| n.parse().ok()),
+        exclude,
+        max_file_size: args.value_of("max-file-size").and_then(find::parse_size),
+        chunk_threshold: args
+            .value_of("chunk-larger-than")
+            .and_then(find::parse_size),
+        compression: args
+            .value_of("compress")
+            .and_then(FileCompression::parse)
+            .or(settings.compression),
+        encryption: match args.values_of("encrypt-to") {
+            Some(recipients) => Some(Encryption::new(recipients.map(String::from).collect())?),
+            None => None,
+        },
+        format: match args.value_of("format") {
+            Some("archive") => SnapshotFormat::Archive,
+            _ => SnapshotFormat::Loose,
+        },
+        large_files: match args.value_of("on-large-file") {
+            Some("skip") => LargeFilesPolicy::Skip,
+            _ => LargeFilesPolicy::Record,
+        },
+        special_files: match args.value_of("special-files") {
+            Some("index-only") => SpecialFilesPolicy::IndexOnly,
+            Some("recreate-on-restore") => SpecialFilesPolicy::RecreateOnRestore,
+            _ => SpecialFilesPolicy::Skip,
+        },
+        preserve_attributes: !args.is_present("no-preserve"),
+        fail_fast: args.is_present("fail-fast"),
+        progress: match args.value_of("progress") {
+            Some("json") => Some(ProgressStyle::Json),
+            _ if args.is_present("progress") && std::io::stderr().is_terminal() => {
+                Some(ProgressStyle::Bar)
+            }
+            _ => None,
+        },
+        copy_options: CopyOptions {
+            buffer_size: args
+                .value_of("copy-buffer")
+                .and_then(find::parse_size)
+                .map(|size| size as usize),
+            direct_io: args.is_present("direct-io"),
+        },
+        remove_partial: args.is_present("remove-partial"),
+        resume: args.is_present("resume"),
+        break_lock: args.is_present("break-lock"),
+        verify: args.is_present("verify"),
+        checksum: args.is_present("checksum"),
+    };
+    if args.is_present("append-only") {
+        backup.enable_append_only()?;
+    }
+
+    if let Some(command) = args
+        .value_of("pre-hook")
+        .map(String::from)
+        .or(settings.pre_hook)
+    {
+        let location = location.display().to_string();
+        hooks::run(
+            &command,
+            &[(HOOK_VARIABLE, "pre"), (BACKUP_VARIABLE, &location)],
+        )
+        .map_err(|e| t!("Pre-backup hook failed: {}", e))?;
+    }
+
+    catch_interrupt(&environment.interrupted);
+    let report = backup.add_snapshot(files.as_slice(), &config)?;
+    let interrupted = || -> Result<()> {
+        match report.interrupted {
+            true => Err(Interrupted(t!(
+                "Backup was interrupted, snapshot {} has only files copied so far",
+                report.snapshot
+            ))
+            .into()),
+            false => Ok(()),
+        }
+    };
+    let mismatched = || -> Result<()> {
+        match report.mismatched.len() {
+            0 => Ok(()),
+            count => Err(IntegrityFailed(t!(
+                "Verification found {} copied file(s) that don't match their source",
+                count
+            ))
+            .into()),
+        }
+    };
+    if config.progress == Some(ProgressStyle::Json) {
+        write_backup_summary(&report, &location, writer)?;
+        interrupted()?;
+        mismatched()?;
+        if strict && !report.warnings.is_empty() {
+            return Err(t!(
+                "Backup failed in strict mode with {} warning(s)",
+                report.warnings.len()
+            )
+            .into());
+        }
+        return Ok(report);
+    }
+    writeln!(writer, "{}", t!("Created snapshot: {}", report.snapshot))?;
+    print_backup_stats(writer, &report.stats)?;
+    if !report.not_copied.is_empty() {
+        let size: u64 = report.not_copied.iter().map(|(_, size)| size).sum();
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "{} file(s) larger than the maximum file size were not copied ({} bytes)",
+                report.not_copied.len(),
+                size
+            )
+        )?;
+    }
+    if !report.warnings.is_empty() {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Snapshot created with {} warning(s) (run with -v for details)",
+                report.warnings.len()
+            )
+        )?;
+    }
+    if !report.failures.is_empty() {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "{} file(s) could not be backed up (listed in {}):",
+                report.failures.len(),
+                ERRORS_FILE_NAME
+            )
+        )?;
+        for (_, message) in &report.failures {
+            writeln!(writer, "  {}", message)?;
+        }
+    }
+    interrupted()?;
+    if let Some(verified) = report.verified {
+        writeln!(writer, "{}", t!("Verified {} copied file(s)", verified))?;
+    }
+    for (path, reason) in &report.mismatched {
+        writeln!(writer, "  {} ({})", path.display(), reason)?;
+    }
+    mismatched()?;
+
+    if strict && !report.warnings.is_empty() {
+        writeln!(writer, "{}", t!("Problems found in strict mode:"))?;
+        for warning in &report.warnings {
+            writeln!(writer, "  {}", warning)?;
+        }
+        return Err(t!(
+            "Backup failed in strict mode with {} warning(s)",
+            report.warnings.len()
+        )
+        .into());
+    }
+
+    Ok(report)
+}
+
+fn print_backup_stats(writer: Writer, stats: &BackupStats) -> Result<()> {
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Entries: {} scanned, {} copied, {} reused, {} error(s)",
+            stats.scanned,
+            stats.copied,
+            stats.reused,
+            stats.errors
+        )
+    )?;
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Written: {} in {} ({}/s)",
+            progress::format_size(stats.written),
+            progress::format_duration(stats.duration),
+            progress::format_size(stats.throughput())
+        )
+    )?;
+    Ok(())
+}
+
+/// Last event of a backup with `--progress json`.
+fn write_backup_summary(report: &BackupReport, backup: &Path, writer: Writer) -> Result<()> {
+    let snapshot = Backup::get_snapshot(&backup.join(&report.snapshot))?;
+    let (indexed, copied, size, duration) = match snapshot.metadata() {
+        Some(metadata) => (
+            metadata.indexed,
+            metadata.copied,
+            metadata.size,
+            metadata.duration.as_secs_f64(),
+        ),
+        None => (0, 0, snapshot.size(), 0.0),
+    };
+    let summary = Event::Summary {
+        snapshot: &report.snapshot,
+        indexed,
+        copied,
+        size,
+        duration,
+        warnings: &report.warnings,
+        not_copied: &report.not_copied,
+    };
+    writeln!(writer, "{}", summary.to_json())?;
+    Ok(())
+}
+
+fn handle_browse(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let snapshot = snapshot_path(args.value_of("SNAPSHOT").unwrap())?;
+    list_snapshot_entries(writer, &snapshot, args.value_of("PATH").map(Path::new))
+}
+
+/// Passphrase of the GPG private key from MIZERIA_PASSPHRASE, or asked
+/// for on the terminal if `needed`. Without either, gpg-agent asks for it.
+fn get_passphrase(needed: bool) -> Result<Option<String>> {
+    if let Ok(passphrase) = std::env::var("MIZERIA_PASSPHRASE") {
+        return Ok(Some(passphrase));
+    }
+    if !needed || !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let passphrase = prompt::read_passphrase(&t!("Passphrase of the GPG key: "))?;
+    Ok(Some(passphrase).filter(|p| !p.is_empty()))
+}
+
+fn handle_restore(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let location = args.value_of("SNAPSHOT").unwrap();
+    let mut snapshot = match args.value_of("at") {
+        Some(moment) => {
+            let backup = remotes::resolve(location)?;
+            let snapshot = Backup::open(&backup)?.snapshot_at(moment)?;
+            writeln!(
+                writer,
+                "{}",
+                t!("Restoring from snapshot {}", snapshot.name())
+            )?;
+            snapshot
+        }
+        None => Backup::get_snapshot(&snapshot_path(location)?)?,
+    };
+    let target = Path::new(args.value_of("TARGET").unwrap());
+    let paths = args
+        .values_of("path")
+        .unwrap_or_default()
+        .map(|path| to_absolute_path(Path::new(path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let options = RestoreOptions {
+        overwrite: args.is_present("overwrite"),
+        dry_run: args.is_present("dry-run"),
+        ignore_attributes: args.is_present("no-preserve"),
+    };
+    let signatures =
+        Backup::check_signatures(snapshot.location(), get_verifying_key(args).as_ref());
+    for finding in signatures.findings() {
+        writeln!(
+            writer,
+            "{}[{}]: {}",
+            finding.severity(),
+            finding.code(),
+            finding
+        )?;
+    }
+    if !signatures.passed(false) {
+        return Err(t!(
+            "Signatures of snapshot {} don't match, nothing was restored",
+            snapshot.name()
+        )
+        .into());
+    }
+    snapshot.set_key_file(args.value_of("key-file").map(PathBuf::from))?;
+    let needs_passphrase = !options.dry_run && snapshot.has_gpg_encrypted_data();
+    snapshot.set_passphrase(get_passphrase(needs_passphrase)?);
+    let report = snapshot.restore(&paths, target, options)?;
+    if options.dry_run {
+        for entry in &report.written {
+            writeln!(
+                writer,
+                "{}  {:<7} {:>12}  {}",
+                entry.stored_in,
+                entry.kind,
+                entry.size,
+                entry.path.display()
+            )?;
+        }
+    }
+    for path in &report.skipped {
+        writeln!(
+            writer,
+            "{}",
+            t!("Skipped existing file: {}", path.display())
+        )?;
+    }
+    for path in &report.missing {
+        writeln!(writer, "{}", t!("Data of '{}' is missing", path.display()))?;
+    }
+    for path in &report.not_recreated {
+        writeln!(
+            writer,
+            "{}",
+            t!("Special file not recreated: {}", path.display())
+        )?;
+    }
+    let summary = if options.dry_run {
+        t!(
+            "Would restore {} entries ({} bytes) to {}",
+            report.restored,
+            report.size,
+            target.display()
+        )
+    } else {
+        t!(
+            "Restored {} entries ({} bytes) to {}",
+            report.restored,
+            report.size,
+            target.display()
+        )
     };
+    writeln!(writer, "{}", summary)?;
 
-    let backup = match Backup::open(backup_path) {
-        Ok(backup) => backup,
-        Err(error) => Err(IntegrityCheckError::UnexpectedError(format!("{}", error)))?,
-    };
-    backup.check_integrity(snapshot_name)
+    if !report.missing.is_empty() {
+        return Err(t!(
+            "Data of {} entries is missing in the backup",
+            report.missing.len()
+        )
+        .into());
+    }
+    Ok(())
 }
 
-fn handle_backup(args: &ArgMatches, writer: Writer) -> Result<()> {
-    let backup = args.value_of("BACKUP").unwrap();
-    let files: Vec<PathBuf> = args
+fn handle_estimate(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    let inputs: Vec<PathBuf> = args
         .values_of("INPUT")
         .unwrap()
         .map(PathBuf::from)
         .collect();
+    let throughput = find::parse_size(args.value_of("throughput").unwrap()).unwrap();
+    let max_depth = args.value_of("max-depth").map(|n| n.parse().unwrap());
+
+    let backup = Backup::open(backup)?;
+    let estimate = backup.estimate(&inputs, !args.is_present("full"), max_depth)?;
+    match &estimate.base {
+        Some(base) => writeln!(writer, "{}", t!("Base snapshot: {}", base))?,
+        None => writeln!(
+            writer,
+            "{}",
+            t!("No base snapshot, all files would be copied.")
+        )?,
+    }
+    writeln!(writer, "{}", t!("Entries to index: {}", estimate.entries))?;
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Files to copy: {} ({})",
+            estimate.copied,
+            progress::format_size(estimate.bytes)
+        )
+    )?;
+    writeln!(writer, "{}", t!("Unchanged files: {}", estimate.unchanged))?;
+    if estimate.unreadable > 0 {
+        writeln!(
+            writer,
+            "{}",
+            t!("Unreadable entries: {}", estimate.unreadable)
+        )?;
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Estimated duration: {} at {}/s",
+            progress::format_duration(estimate.duration(throughput)),
+            progress::format_size(throughput)
+        )
+    )?;
+    Ok(())
+}
+
+fn handle_stats(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = Backup::open(&backup_path(args)?)?;
+    let usage = backup.usage(args.value_of("top").unwrap().parse()?);
+
+    writeln!(writer, "{}", t!("Snapshots: {}", usage.snapshots.len()))?;
+    writeln!(
+        writer,
+        "{}",
+        t!("Size on disk: {}", progress::format_size(usage.total_size))
+    )?;
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Average snapshot size: {}",
+            progress::format_size(usage.average_snapshot_size())
+        )
+    )?;
+    if !usage.snapshots.is_empty() {
+        writeln!(writer, "{}", t!("Growth:"))?;
+        let mut total = 0;
+        for (name, size) in &usage.snapshots {
+            total += size;
+            writeln!(
+                writer,
+                "  {}  +{:<10} {}",
+                name,
+                progress::format_size(*size),
+                progress::format_size(total)
+            )?;
+        }
+    }
+    if !usage.largest_files.is_empty() {
+        writeln!(writer, "{}", t!("Largest files:"))?;
+        for file in &usage.largest_files {
+            writeln!(
+                writer,
+                "  {:>10}  {} ({})",
+                progress::format_size(file.size),
+                file.path.display(),
+                file.snapshot
+            )?;
+        }
+    }
+    if !usage.largest_folders.is_empty() {
+        writeln!(writer, "{}", t!("Largest folders:"))?;
+        for (path, size) in &usage.largest_folders {
+            writeln!(
+                writer,
+                "  {:>10}  {}",
+                progress::format_size(*size),
+                path.display()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_doctor(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = backup_path(args)?;
+    let inputs: Vec<PathBuf> = args
+        .values_of("INPUT")
+        .unwrap_or_default()
+        .map(PathBuf::from)
+        .collect();
+
+    let findings = doctor::diagnose(&backup, &inputs, args.is_present("fix"));
+    for finding in &findings {
+        writeln!(writer, "{}: {}", finding.severity, finding.message)?;
+    }
+
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let errors = count(Severity::Error);
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Found {} error(s) and {} warning(s).",
+            errors,
+            count(Severity::Warning)
+        )
+    )?;
+
+    if errors > 0 {
+        return Err(t!("Backup cannot be safely made. See the errors above").into());
+    }
+    Ok(())
+}
+
+fn handle_selftest(args: &ArgMatches, writer: Writer, environment: &Environment) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    if !backup.is_dir() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
+
+    let steps = selftest::run(backup, environment, args.is_present("keep"));
+    for step in &steps {
+        match &step.result {
+            Ok(details) => writeln!(writer, "ok      {} ({})", step.description, details)?,
+            Err(e) => writeln!(writer, "{}", t!("FAILED  {}: {}", step.description, e))?,
+        }
+    }
+
+    if steps.iter().any(|step| step.result.is_err()) {
+        return Err(t!("Self-test failed. Backups may not work in this folder").into());
+    }
+    writeln!(writer, "{}", t!("Self-test passed."))?;
+    Ok(())
+}
+
+fn handle_bench(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    if !backup.is_dir() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
+    let options = bench::BenchOptions {
+        size: find::parse_size(args.value_of("size").unwrap()).unwrap(),
+        files: args.value_of("files").unwrap().parse()?,
+    };
+
+    let report = bench::run(backup, &options)?;
+    let mib = |bytes_per_second: f64| bytes_per_second / (1 << 20) as f64;
+    writeln!(writer, "{}", t!("Destination:      {}", report.filesystem))?;
+    for (buffer_size, throughput) in &report.sequential {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Sequential write: {} MiB/s ({} buffer)",
+                format!("{:.1}", mib(*throughput)),
+                bench::format_buffer_size(*buffer_size)
+            )
+        )?;
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Small files:      {} files/s",
+            format!("{:.0}", report.small_files)
+        )
+    )?;
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Metadata latency: {} ms",
+            format!("{:.3}", report.metadata_latency.as_secs_f64() * 1000.0)
+        )
+    )?;
+
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    writeln!(writer, "{}", t!("Recommended settings:"))?;
+    for recommendation in report.recommendations(cpus) {
+        writeln!(
+            writer,
+            "  {:<24} {}",
+            recommendation.setting, recommendation.reason
+        )?;
+    }
+    Ok(())
+}
+
+fn handle_which(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let path = to_absolute_path(Path::new(args.value_of("PATH").unwrap()))?;
+
+    let backup = Backup::open(&backup_path(args)?)?;
+    let (snapshot, entry) = backup.locate(&path, args.value_of("snapshot"))?;
+
+    print_fields(
+        writer,
+        &[
+            (t!("Snapshot:"), snapshot.to_string()),
+            (t!("Stored in:"), entry.stored_in.to_string()),
+            (t!("Location:"), entry.data.display().to_string()),
+            (t!("Type:"), entry.kind.to_string()),
+        ],
+    )?;
+
+    if entry.kind == EntryKind::Missing {
+        return Err(t!(
+            "Data of '{}' is missing in snapshot {}",
+            path.display(),
+            entry.stored_in
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn handle_diff(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let open = |name| {
+        snapshot_path(args.value_of(name).unwrap()).and_then(|path| Backup::get_snapshot(&path))
+    };
+    let (older, newer) = (open("SNAPSHOT_A")?, open("SNAPSHOT_B")?);
+
+    let differences = older.diff(&newer);
+    for difference in &differences {
+        let change = match difference.change {
+            Change::Added => t!("added"),
+            Change::Removed => t!("removed"),
+            Change::Modified => t!("modified"),
+        };
+        writeln!(writer, "{:<8}  {}", change, difference.path.display())?;
+    }
+    let count = |change| differences.iter().filter(|d| d.change == change).count();
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "{} added, {} removed, {} modified.",
+            count(Change::Added),
+            count(Change::Removed),
+            count(Change::Modified)
+        )
+    )?;
+    Ok(())
+}
+
+fn handle_verify_mirror(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let primary = Path::new(args.value_of("PRIMARY").unwrap());
+    let mirror = Path::new(args.value_of("MIRROR").unwrap());
+    for backup in [primary, mirror] {
+        if !backup.is_dir() {
+            return Err(t!(
+                "Folder with backup '{}' doesn't exist or isn't accessible",
+                backup.display()
+            )
+            .into());
+        }
+    }
+
+    let differences = mirror::compare(primary, mirror, args.is_present("hash"));
+    for difference in &differences {
+        writeln!(writer, "{}", difference)?;
+    }
+
+    if !differences.is_empty() {
+        return Err(IntegrityFailed(t!(
+            "Found {} difference(s) between backups",
+            differences.len()
+        ))
+        .into());
+    }
+    writeln!(writer, "{}", t!("Backups are equivalent."))?;
+    Ok(())
+}
+
+fn handle_archive(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let location = &backup_path(args)?;
+    let backup = Backup::open(location)?;
+    let output = Path::new(args.value_of("output").unwrap());
+    let options = ArchiveOptions {
+        compression: args.value_of("compress").and_then(Compression::parse),
+        recipient: args.value_of("encrypt-to").map(String::from),
+    };
+
+    let report = archive::create(location, output, &options)?;
+    writeln!(
+        writer,
+        "Archived {} entries ({} bytes of files) of {} snapshot(s) to {}",
+        report.entries,
+        report.size,
+        backup.snapshots().len(),
+        output.display()
+    )?;
+    Ok(())
+}
+
+fn handle_unarchive(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let archive = Path::new(args.value_of("ARCHIVE").unwrap());
+    let target = &backup_path(args)?;
+
+    let report = archive::extract(archive, target)?;
+    let backup = Backup::open(target)?;
+    writeln!(
+        writer,
+        "Unpacked {} entries ({} bytes of files) of {} snapshot(s) to {}",
+        report.entries,
+        report.size,
+        backup.snapshots().len(),
+        target.display()
+    )?;
+    Ok(())
+}
+
+fn handle_verify_fingerprints(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = Backup::open(&backup_path(args)?)?;
+    if !backup.is_append_only() {
+        writeln!(
+            writer,
+            "{}",
+            t!("Warning: backup is not in append-only mode.")
+        )?;
+    }
+
+    let mut problems = 0;
+    for (snapshot, status) in backup.verify_fingerprints()? {
+        let (status, is_problem) = match status {
+            FingerprintStatus::Unchanged => ("ok".to_string(), false),
+            FingerprintStatus::NotRecorded => (t!("not fingerprinted"), false),
+            FingerprintStatus::Changed => (t!("CHANGED"), true),
+            FingerprintStatus::Removed => (t!("REMOVED"), true),
+        };
+        if is_problem {
+            problems += 1;
+        }
+        writeln!(writer, "{}  {}", snapshot, status)?;
+    }
+
+    if problems > 0 {
+        return Err(IntegrityFailed(t!(
+            "{} snapshot(s) changed or removed since creation",
+            problems
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn handle_check(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = Backup::open(&backup_path(args)?)?;
+    let budget = if let Some(duration) = args.value_of("budget") {
+        VerificationBudget::Time(parse_duration(duration).unwrap_or_default())
+    } else if let Some(snapshots) = args.value_of("snapshots") {
+        VerificationBudget::Snapshots(snapshots.parse()?)
+    } else {
+        VerificationBudget::All
+    };
+
+    let results = backup.verify_rolling(budget)?;
+    let mut failed = 0;
+    for (snapshot, report) in &results {
+        for finding in report.findings() {
+            writeln!(
+                writer,
+                "{}: {}[{}]: {}",
+                snapshot,
+                finding.severity(),
+                finding.code(),
+                finding
+            )?;
+        }
+        let status = if report.passed(false) {
+            "ok".to_string()
+        } else {
+            failed += 1;
+            t!("FAILED")
+        };
+        writeln!(writer, "{}  {}", snapshot, status)?;
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Checked {} of {} snapshot(s).",
+            results.len(),
+            backup.snapshots().len()
+        )
+    )?;
+
+    if failed > 0 {
+        return Err(IntegrityFailed(t!("Integrity check of {} snapshot(s) failed", failed)).into());
+    }
+    Ok(())
+}
+
+fn handle_scrub(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = Backup::open(&backup_path(args)?)?;
+    let since = match args.value_of("since") {
+        Some(days) => Some(Duration::from_secs(days.parse::<u64>()? * 24 * 60 * 60)),
+        None => None,
+    };
+
+    let results = backup.scrub(since)?;
+    let mut failed = 0;
+    for (snapshot, report) in &results {
+        for finding in report.findings() {
+            writeln!(
+                writer,
+                "{}: {}[{}]: {}",
+                snapshot,
+                finding.severity(),
+                finding.code(),
+                finding
+            )?;
+        }
+        let status = if report.passed(false) {
+            "ok".to_string()
+        } else {
+            failed += 1;
+            t!("FAILED")
+        };
+        writeln!(writer, "{}  {}", snapshot, status)?;
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!(
+            "Scrubbed {} of {} snapshot(s).",
+            results.len(),
+            backup.snapshots().len()
+        )
+    )?;
 
+    if failed > 0 {
+        return Err(
+            IntegrityFailed(t!("Scrub found corrupted data in {} snapshot(s)", failed)).into(),
+        );
+    }
+    Ok(())
+}
+
+fn handle_prune(args: &ArgMatches, writer: Writer, environment: &Environment) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    let mut backup = Backup::open_with_environment(backup, environment.clone())?;
+    backup.set_break_lock(args.is_present("break-lock"));
+    let keep_last = match args.value_of("keep-last") {
+        Some(keep_last) => keep_last.parse()?,
+        None => backup.settings().keep_last.ok_or_else(|| {
+            t!("Number of snapshots to keep is needed. Give --keep-last or set keep-last in mizeria.toml")
+        })?,
+    };
+
+    let removal = if args.is_present("shred") {
+        Removal::Shred
+    } else if args.is_present("no-trash") {
+        Removal::Delete
+    } else {
+        Removal::Trash(parse_duration(args.value_of("keep-trash").unwrap()).unwrap())
+    };
+
+    let report = backup.prune(keep_last, removal)?;
+    for snapshot in &report.expired {
+        writeln!(
+            writer,
+            "{}",
+            t!("Removed snapshot from trash: {}", snapshot)
+        )?;
+    }
+    for snapshot in &report.removed {
+        let message = match removal {
+            Removal::Trash(_) => t!("Moved snapshot to trash: {}", snapshot),
+            _ => t!("Removed snapshot: {}", snapshot),
+        };
+        writeln!(writer, "{}", message)?;
+    }
+    for (snapshot, dependent) in &report.kept {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Kept snapshot: {} (data is referenced by {})",
+                snapshot,
+                dependent
+            )
+        )?;
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!("Removed {} snapshot(s).", report.removed.len())
+    )?;
+    Ok(())
+}
+
+fn handle_squash(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let mut backup = Backup::open(&backup_path(args)?)?;
+    backup.set_break_lock(args.is_present("break-lock"));
+    let remove_originals = args.is_present("remove-originals");
+    let report = backup.squash(
+        args.value_of("FROM").unwrap(),
+        args.value_of("TO").unwrap(),
+        remove_originals,
+    )?;
+
+    writeln!(
+        writer,
+        "{}",
+        t!("Copied {} entries into {}", report.copied, report.snapshot)
+    )?;
+    if report.redirected > 0 {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Pointed {} entries of newer snapshots to {}",
+                report.redirected,
+                report.snapshot
+            )
+        )?;
+    }
+    if remove_originals {
+        for snapshot in &report.unreferenced {
+            writeln!(writer, "{}", t!("Removed snapshot: {}", snapshot))?;
+        }
+    } else if !report.unreferenced.is_empty() {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Snapshots that are no longer needed: {}",
+                report.unreferenced.join(", ")
+            )
+        )?;
+    }
+    Ok(())
+}
+
+fn handle_repair(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let mut backup = Backup::open(&backup_path(args)?)?;
+    backup.set_break_lock(args.is_present("break-lock"));
+    let report = backup.repair(args.value_of("SNAPSHOT").unwrap())?;
+
+    if report.from_parity.is_empty() && report.repaired.is_empty() && report.unrepaired.is_empty() {
+        writeln!(writer, "{}", t!("No corrupted files found."))?;
+        return Ok(());
+    }
+    for path in &report.from_parity {
+        writeln!(
+            writer,
+            "{}",
+            t!("Repaired '{}' with parity data", path.display())
+        )?;
+    }
+    for (path, snapshot) in &report.repaired {
+        writeln!(
+            writer,
+            "{}",
+            t!("Repaired '{}' from snapshot {}", path.display(), snapshot)
+        )?;
+    }
+    for path in &report.unrepaired {
+        writeln!(
+            writer,
+            "{}",
+            t!("No intact copy of '{}' was found", path.display())
+        )?;
+    }
+    if !report.unrepaired.is_empty() {
+        return Err(t!(
+            "{} corrupted file(s) could not be repaired",
+            report.unrepaired.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn handle_undelete(args: &ArgMatches, writer: Writer, environment: &Environment) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    let mut backup = Backup::open_with_environment(backup, environment.clone())?;
+    backup.set_break_lock(args.is_present("break-lock"));
+
+    let snapshot = match args.value_of("SNAPSHOT") {
+        Some(snapshot) => snapshot,
+        None => {
+            let trashed = backup.trashed_snapshots()?;
+            if trashed.is_empty() {
+                writeln!(writer, "{}", t!("Trash is empty."))?;
+            }
+            for (snapshot, time) in trashed {
+                let time = format!("{} {:02}:{:02}", time.date(), time.hour(), time.minute());
+                writeln!(writer, "{}", t!("{}  (removed on {})", snapshot, time))?;
+            }
+            return Ok(());
+        }
+    };
+    backup.undelete(snapshot)?;
+    writeln!(writer, "{}", t!("Restored snapshot: {}", snapshot))?;
+    Ok(())
+}
+
+fn handle_import_rsync(args: &ArgMatches, writer: Writer, environment: &Environment) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    let folder = Path::new(args.value_of("FOLDER").unwrap());
+    let original = Path::new(args.value_of("original").unwrap());
+
+    let config = BackupConfig {
+        break_lock: args.is_present("break-lock"),
+        ..Default::default()
+    };
+
+    let mut backup = Backup::open_with_environment(backup, environment.clone())?;
+    let report = backup.import_dated_folders(folder, original, &config)?;
+    for (path, reason) in &report.skipped {
+        writeln!(writer, "{}", t!("Skipped: {} ({})", path.display(), reason))?;
+    }
+    let mut warnings = 0;
+    for (path, snapshot) in &report.imported {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Imported snapshot: {} (from {})",
+                snapshot.snapshot,
+                path.display()
+            )
+        )?;
+        warnings += snapshot.warnings.len();
+    }
+    writeln!(
+        writer,
+        "{}",
+        t!("Imported {} snapshot(s).", report.imported.len())
+    )?;
+    if warnings > 0 {
+        writeln!(
+            writer,
+            "{}",
+            t!(
+                "Snapshots imported with {} warning(s) (run with -v for details)",
+                warnings
+            )
+        )?;
+    }
+    Ok(())
+}
+
+fn handle_import(args: &ArgMatches, writer: Writer, environment: &Environment) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    let archive = Path::new(args.value_of("ARCHIVE").unwrap());
+    let original = Path::new(args.value_of("original").unwrap());
+
+    let config = BackupConfig {
+        break_lock: args.is_present("break-lock"),
+        ..Default::default()
+    };
+
+    let mut backup = Backup::open_with_environment(backup, environment.clone())?;
+    let report = backup.import_archive(archive, original, &config)?;
+    writeln!(
+        writer,
+        "Imported snapshot: {} (from {})",
+        report.snapshot,
+        archive.display()
+    )?;
+    if !report.warnings.is_empty() {
+        writeln!(
+            writer,
+            "Snapshot imported with {} warning(s) (run with -v for details)",
+            report.warnings.len()
+        )?;
+    }
+    Ok(())
+}
+
+fn handle_find(args: &ArgMatches, writer: Writer) -> Result<()> {
     set_verbosity(args);
+    let backup = &backup_path(args)?;
+    if !backup.is_dir() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
 
-    let incremental_snapshot = !args.is_present("full");
-    let mut backup = Backup::open(Path::new(backup))?;
+    let date = |name| -> Result<_> {
+        match args.value_of(name) {
+            Some(date) => Ok(Some(
+                find::parse_date(date).ok_or_else(|| t!("Invalid date: '{}'", date))?,
+            )),
+            None => Ok(None),
+        }
+    };
+    let larger_than = match args.value_of("larger-than") {
+        Some(size) => Some(find::parse_size(size).ok_or_else(|| t!("Invalid size: '{}'", size))?),
+        None => None,
+    };
+    let filter = find::Filter {
+        pattern: match args.value_of("PATTERN") {
+            Some(pattern) if args.is_present("regex") => Some(find::Pattern::regex(pattern)?),
+            Some(pattern) => Some(find::Pattern::search(pattern)?),
+            None => None,
+        },
+        kind: match args.value_of("type") {
+            Some("file") => Some(EntryKind::File),
+            Some("dir") => Some(EntryKind::Directory),
+            Some("symlink") => Some(EntryKind::Symlink),
+            _ => None,
+        },
+        larger_than,
+        modified_before: date("modified-before")?,
+        modified_after: date("modified-after")?,
+    };
+
+    let found = find::find(backup, &filter);
+    let mut snapshot = None;
+    for entry in &found {
+        if snapshot != Some(&entry.stored_in) {
+            snapshot = Some(&entry.stored_in);
+            writeln!(writer, "{}", entry.stored_in)?;
+        }
+        writeln!(
+            writer,
+            "  {:<7} {:>12}  {}",
+            entry.kind.to_string(),
+            entry.size,
+            entry.path.display()
+        )?;
+    }
+    writeln!(writer, "{}", t!("Found {} entries.", found.len()))?;
+    Ok(())
+}
+
+fn handle_remote(args: &ArgMatches, writer: Writer) -> Result<()> {
+    match args.subcommand() {
+        ("add", Some(args)) => {
+            let location = Path::new(args.value_of("LOCATION").unwrap());
+            remotes::add(args.value_of("NAME").unwrap(), &to_absolute_path(location)?)?;
+        }
+        ("remove", Some(args)) => remotes::remove(args.value_of("NAME").unwrap())?,
+        _ => {
+            for (name, location) in remotes::list()? {
+                writeln!(writer, "{:<16} {}", name, location)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_topic(args: &ArgMatches, writer: Writer) -> Result<()> {
+    match args.value_of("TOPIC").and_then(manual::find_topic) {
+        Some(topic) => {
+            writeln!(writer, "{}\n", topic.title.to_uppercase())?;
+            for paragraph in topic.text.split("\n\n") {
+                writeln!(writer, "{}\n", paragraph)?;
+            }
+        }
+        None => {
+            for topic in manual::TOPICS {
+                writeln!(writer, "{:<16} {}", topic.name, topic.title)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_gen_man(args: &ArgMatches, writer: Writer) -> Result<()> {
+    let help = long_help(None)?;
+    let commands = manual::commands(&help);
+    let page = |command: Option<&str>| -> Result<String> {
+        let help = long_help(command)?;
+        let page = match command {
+            Some(command) => manual::man_page(
+                &format!("mizeria-{}", command),
+                clap::crate_version!(),
+                &help,
+                &[String::from("mizeria")],
+                &[],
+            ),
+            None => manual::man_page(
+                "mizeria",
+                clap::crate_version!(),
+                &help,
+                &commands
+                    .iter()
+                    .map(|c| format!("mizeria-{}", c))
+                    .collect::<Vec<_>>(),
+                manual::TOPICS,
+            ),
+        };
+        Ok(page)
+    };
+
+    let out = match args.value_of("out") {
+        Some(out) => Path::new(out),
+        None => {
+            write!(writer, "{}", page(args.value_of("COMMAND"))?)?;
+            return Ok(());
+        }
+    };
+    std::fs::create_dir_all(out)?;
+    let names = std::iter::once(None).chain(commands.iter().map(|c| Some(c.as_str())));
+    for command in names {
+        let file = match command {
+            Some(command) => out.join(format!("mizeria-{}.1", command)),
+            None => out.join("mizeria.1"),
+        };
+        std::fs::write(&file, page(command)?)?;
+        writeln!(writer, "{}", file.display())?;
+    }
+    Ok(())
+}
+
+/// Help of the program or of a command, as printed with `--help`,
+/// without wrapping lines.
+fn long_help(command: Option<&str>) -> Result<String> {
+    let args = command.into_iter().chain(std::iter::once("--help"));
+    match build_app().set_term_width(0).get_matches_from_safe(args) {
+        Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => Ok(e.message),
+        Err(e) => Err(e.message.into()),
+        Ok(_) => Err(format!("No help for command: '{}'", command.unwrap_or_default()).into()),
+    }
+}
 
-    let timestamp = backup.add_snapshot(files.as_slice(), incremental_snapshot)?;
-    writeln!(writer, "Created snapshot: {}", timestamp)?;
+fn handle_export_inventory(args: &ArgMatches, writer: Writer) -> Result<()> {
+    set_verbosity(args);
+    let backup = &backup_path(args)?;
+    if !backup.is_dir() {
+        return Err(t!("Folder with backup doesn't exist or isn't accessible").into());
+    }
 
+    inventory::write_csv(backup, writer)?;
     Ok(())
 }
 
+fn get_signing_key(args: &ArgMatches) -> Option<SigningKey> {
+    let tool = match args.value_of("sign-with")? {
+        "gpg" => SigningTool::Gpg,
+        _ => SigningTool::Minisign,
+    };
+    let key = args.value_of("sign-key")?.to_string();
+    Some(SigningKey { tool, key })
+}
+
+fn get_verifying_key(args: &ArgMatches) -> Option<VerifyingKey> {
+    let tool = match args.value_of("verify-with")? {
+        "gpg" => SigningTool::Gpg,
+        _ => SigningTool::Minisign,
+    };
+    let key = args.value_of("verify-key")?.to_string();
+    let policy = match args.value_of("on-bad-signature") {
+        Some("warn") => SignaturePolicy::Warn,
+        _ => SignaturePolicy::Fail,
+    };
+    Some(VerifyingKey { tool, key, policy })
+}
+
+/// Reads paths from `source`, or from the standard input if it's `-`.
+fn read_input_list(source: &str, nul_separated: bool) -> Result<Vec<PathBuf>> {
+    let mut content = vec![];
+    let read = if source == "-" {
+        std::io::stdin().read_to_end(&mut content)
+    } else {
+        File::open(source).and_then(|mut file| file.read_to_end(&mut content))
+    };
+    read.map_err(|e| format!("Cannot read list of inputs from '{}' ({})", source, e))?;
+
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    let paths = content
+        .split(|&byte| byte == separator)
+        .map(|line| match line {
+            [path @ .., b'\r'] if !nul_separated => path,
+            path => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect();
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Paths in a backup are absolute, but the original file may no longer
+/// exist, so the path cannot be canonicalized.
+fn to_absolute_path(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_owned())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Ctrl-C sets `interrupted`, so the backup stops before the next entry.
+/// Pressing it again stops the program at once.
+fn catch_interrupt(interrupted: &Arc<AtomicBool>) {
+    let flag = interrupted.clone();
+    let handler = move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    };
+    // Only one handler can be set in a process, e.g. running tests.
+    if let Err(e) = ctrlc::set_handler(handler) {
+        tracing::debug!("Cannot catch Ctrl-C ({})", e);
+    }
+}
+
+/// The backup folder given as BACKUP, or the default one when it's not
+/// given. A name registered with `mizeria remote add` is resolved.
+fn backup_path(args: &ArgMatches) -> Result<PathBuf> {
+    optional_backup_path(args)?.ok_or_else(|| {
+        t!("No backup given. Give BACKUP or set MIZERIA_BACKUP or default-backup in the config file")
+            .into()
+    })
+}
+
+fn optional_backup_path(args: &ArgMatches) -> Result<Option<PathBuf>> {
+    let backup = match args.value_of("BACKUP") {
+        Some(backup) => Some(backup.to_string()),
+        None => remotes::default_backup()?,
+    };
+    Ok(backup.map(|backup| remotes::resolve(&backup)).transpose()?)
+}
+
+/// A snapshot given only by name or reference, e.g. latest~1, is in the
+/// default backup when one is set, otherwise in the current folder.
+fn snapshot_path(snapshot: &str) -> Result<PathBuf> {
+    let path = Path::new(snapshot);
+    let path = match remotes::default_backup()? {
+        Some(backup) if path.components().count() == 1 && !path.exists() => {
+            remotes::resolve(&backup)?.join(path)
+        }
+        _ => path.to_owned(),
+    };
+    Backup::resolve_snapshot_path(&path)
+}
+
 fn set_verbosity(args: &ArgMatches) {
-    let log_level = match args.occurrences_of("v") {
-        0 => LevelFilter::Warn,
-        1 => LevelFilter::Debug,
-        2 => LevelFilter::Trace,
-        _ => LevelFilter::Trace,
-    };
-
-    init_logger(log_level);
-}
-
-fn init_logger(log_level: LevelFilter) {
-    let mut builder = Builder::new();
-    builder
-        .filter(Some("mizeria"), log_level)
-        .write_style(WriteStyle::Auto)
-        .format_module_path(false)
-        .format_timestamp(None)
-        .target(env_logger::Target::Stderr)
-        .try_init()
-        .ok();
+    // Debug logs asked for with -v are printed even in quiet mode.
+    let log_level = match (args.occurrences_of("v"), args.is_present("quiet")) {
+        (0, true) => LevelFilter::ERROR,
+        (0, false) => LevelFilter::WARN,
+        (1, _) => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    let log_file = args.value_of("log-file").map(|path| {
+        let level = args.value_of("log-level").and_then(|l| l.parse().ok());
+        (Path::new(path), level.unwrap_or(LevelFilter::DEBUG))
+    });
+
+    if let Err(e) = logging::init(log_level, log_file) {
+        // Logs asked for in a file would be lost, so it's visible even in quiet mode.
+        error!("Cannot open log file ({})", e);
+    }
 }
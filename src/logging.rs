@@ -0,0 +1,140 @@
+//! Logs printed on standard error and, with --log-file, appended to a file
+//! at their own level, so the console stays terse while the file keeps
+//! every step of the backup. Lines in the file carry their time and the
+//! spans they were logged in, e.g. the snapshot and the input, so logs of
+//! repeated runs can be told apart and filtered.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Installs the subscriber for the whole program, unless one is already
+/// installed, e.g. by a program embedding mizeria. If the log file can't be
+/// opened, logs are printed only on the console and the error is returned.
+pub fn init(console_level: LevelFilter, log_file: Option<(&Path, LevelFilter)>) -> io::Result<()> {
+    let (subscriber, result) = match subscriber(console_level, log_file) {
+        Ok(subscriber) => (subscriber, Ok(())),
+        Err(e) => (subscriber(console_level, None)?, Err(e)),
+    };
+    tracing::subscriber::set_global_default(subscriber).ok();
+    result
+}
+
+fn subscriber(
+    console_level: LevelFilter,
+    log_file: Option<(&Path, LevelFilter)>,
+) -> io::Result<impl Subscriber + Send + Sync> {
+    let console = tracing_subscriber::fmt::layer()
+        .with_writer(io::stderr)
+        .event_format(Format { in_file: false })
+        .with_filter(Targets::new().with_target("mizeria", console_level));
+    let file = match log_file {
+        Some((path, level)) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .event_format(Format { in_file: true })
+                .with_filter(Targets::new().with_target("mizeria", level));
+            Some(layer)
+        }
+        None => None,
+    };
+    Ok(tracing_subscriber::registry().with(console).with(file))
+}
+
+/// `[LEVEL] message` on the console. In the file the time and spans
+/// are added, e.g. `[2021-07-15T18:34:02Z DEBUG snapshot{name=...}] message`.
+struct Format {
+    in_file: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = event.metadata().level();
+        if !self.in_file {
+            write!(writer, "[{:<5}] ", level)?;
+        } else {
+            let now = OffsetDateTime::now_utc();
+            let now = now
+                .replace_nanosecond(0)
+                .unwrap_or(now)
+                .format(&Rfc3339)
+                .unwrap_or_default();
+            write!(writer, "[{} {:<5}", now, level)?;
+            for span in ctx
+                .event_scope()
+                .into_iter()
+                .flat_map(|scope| scope.from_root())
+            {
+                write!(writer, " {}", span.name())?;
+                let extensions = span.extensions();
+                match extensions.get::<FormattedFields<N>>() {
+                    Some(fields) if !fields.is_empty() => write!(writer, "{{{}}}", fields)?,
+                    _ => (),
+                }
+            }
+            write!(writer, "] ")?;
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{debug, field, info_span, trace};
+
+    #[test]
+    fn file_has_its_own_level_and_spans() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("mizeria.log");
+        let subscriber = subscriber(LevelFilter::OFF, Some((&path, LevelFilter::DEBUG))).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            debug!("Started backup process");
+            let span = info_span!(
+                "snapshot",
+                name = "2021-07-15_18.34",
+                indexed = field::Empty
+            );
+            let _entered = span.enter();
+            trace!("Indexed: a.txt");
+            span.record("indexed", 3);
+            debug!("Finished backup process");
+        });
+
+        let logs = std::fs::read_to_string(&path).unwrap();
+        assert!(logs.contains("DEBUG] Started backup process\n"), "{}", logs);
+        assert!(!logs.contains("Indexed"), "{}", logs);
+        assert!(
+            logs.contains(
+                "DEBUG snapshot{name=\"2021-07-15_18.34\" indexed=3}] Finished backup process\n"
+            ),
+            "{}",
+            logs
+        );
+    }
+}
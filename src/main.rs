@@ -1,20 +1,17 @@
-use log::error;
-use mizeria::run_program;
+use mizeria::{exit_code, run_program};
 use std::env;
+use tracing::error;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let result_code = match run_program(&args[1..], &mut std::io::stdout()) {
-        Ok(_) => 0,
-        Err(msg) => {
-            if let Some(source) = msg.source() {
-                error!("{}", source);
-            }
-            error!("{}", msg);
-            1
+    let result = run_program(&args[1..], &mut std::io::stdout());
+    if let Err(msg) = &result {
+        if let Some(source) = msg.source() {
+            error!("{}", source);
         }
-    };
+        error!("{}", msg);
+    }
 
-    std::process::exit(result_code);
+    std::process::exit(exit_code(&result));
 }
@@ -0,0 +1,304 @@
+//! Man pages generated from the help of commands, so they never get out
+//! of sync with the argument definitions, and extended help topics.
+
+pub struct Topic {
+    pub name: &'static str,
+    pub title: &'static str,
+    /// Paragraphs are separated by empty lines.
+    pub text: &'static str,
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "incremental",
+        title: "Incremental semantics",
+        text: concat!(
+            "Every snapshot except the first one is incremental, unless --full is given. ",
+            "The index of the latest snapshot is its base.\n",
+            "\n",
+            "Every entry found in the base index is compared with the modification time ",
+            "recorded there and, for files, with the recorded size. The entry is unchanged ",
+            "when both are the same. Entries indexed without a modification time, e.g. with ",
+            "--no-preserve, are compared with the time of the snapshot that stores their data ",
+            "instead: they are unchanged when both their modification time and their creation ",
+            "time are older than that time minus one minute. ",
+            "Unchanged entries are not copied: the new index points to the snapshot ",
+            "that already stores them. New and changed entries are copied into the new ",
+            "snapshot. Entries that no longer exist are not indexed.\n",
+            "\n",
+            "The index of every snapshot therefore lists all entries that existed when it ",
+            "was made, whatever snapshot stores their data. Snapshots with data referenced ",
+            "by newer snapshots are kept by prune.\n",
+            "\n",
+            "files-cache.bin in the backup folder holds the size, modification time, ",
+            "change time and inode of every file and folder of the latest snapshot. ",
+            "When it was written for the base snapshot, an entry with exactly the same ",
+            "metadata is unchanged and one with different metadata is changed, without a ",
+            "lookup in the base index. Entries not in the cache are compared as above.\n",
+            "\n",
+            "With --checksum files are compared by the size and SHA-256 checksum in the ",
+            "base index instead, so every file is read.\n",
+            "\n",
+            "Symbolic links are followed and indexed as their targets."
+        ),
+    },
+    Topic {
+        name: "snapshot-format",
+        title: "Snapshot format",
+        text: concat!(
+            "A backup is a folder with snapshots. Every snapshot is a folder named after ",
+            "the time it was made, e.g. 2021-07-26_13.45.\n",
+            "\n",
+            "index.txt lists every backed up entry, one per line, as the name of the ",
+            "snapshot that stores its data followed by a space and the absolute path ",
+            "of the entry. Lines of files have the size and SHA-256 checksum of the ",
+            "content between them, as <size>:<checksum>. Files that were hardlinks of ",
+            "each other share a link group, written as link:<group> before the path. ",
+            "Named pipes, sockets and device nodes have no data and are written as ",
+            "special:<kind> or, when they can be recreated, special:<kind>:<mode>:<device>. ",
+            "Files stored as chunks list them as chunks:<snapshot>/<checksum>,... ",
+            "Permissions, owner and modification time of the original entry follow as ",
+            "mode:<octal>, owner:<uid>:<gid> and mtime:<seconds>.<nanoseconds>.\n",
+            "\n",
+            "files is a folder with entries copied into this snapshot. Their absolute ",
+            "paths are preserved, e.g. C:\\my_folder\\file.txt is stored as ",
+            "files/C/my_folder/file.txt. Snapshots made with --format archive have ",
+            "the folder packed into files.tar.zst instead.\n",
+            "\n",
+            "chunks is a folder with chunks of files larger than --chunk-larger-than, ",
+            "named by their SHA-256 checksums, e.g. chunks/d6/d6164f.... Every chunk is ",
+            "stored once in the backup, in the first snapshot that needed it.\n",
+            "\n",
+            "metadata.txt records the version of mizeria, host, user, type (full or ",
+            "incremental), duration, inputs, counts of entries, size, files not copied ",
+            "because of --max-file-size, the compression of stored files and the hash of ",
+            "the previous snapshot. Files of a snapshot made with --compress are stored ",
+            "as zstd frames under their original names. With --encrypt-to, metadata.txt ",
+            "records the recipients as encryption: <age|gpg>:<recipient>,... and every ",
+            "stored file is encrypted to them.\n",
+            "\n",
+            "index-cache.bin is a binary copy of the index read by the next incremental ",
+            "backup. It can be safely removed.\n",
+            "\n",
+            "parity is a folder with Reed-Solomon parity blocks of stored files, present ",
+            "when the snapshot was made with --add-parity.\n",
+            "\n",
+            "checksums.sha256 with its signature is present in signed snapshots. ",
+            "Encrypted snapshots have it too, to verify files without decrypting them."
+        ),
+    },
+    Topic {
+        name: "exit-codes",
+        title: "Exit codes",
+        text: concat!(
+            "0 means success.\n",
+            "\n",
+            "1 means the command completed with warnings, e.g. some files couldn't be ",
+            "backed up or the integrity check found only warnings.\n",
+            "\n",
+            "2 means the command failed, including invalid arguments.\n",
+            "\n",
+            "3 means an integrity check found damaged, altered or missing data: snapshot, ",
+            "check, scrub, verify-fingerprints, verify-mirror or backup --verify.\n",
+            "\n",
+            "130 means the command was interrupted with Ctrl-C.\n",
+            "\n",
+            "With --strict warnings fail the backup or the check, so they exit with 2 or 3 ",
+            "instead of 1."
+        ),
+    },
+];
+
+pub fn find_topic(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|topic| topic.name == name)
+}
+
+/// Names of commands listed in the help of the program.
+pub fn commands(help: &str) -> Vec<String> {
+    help.lines()
+        .skip_while(|line| *line != "SUBCOMMANDS:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '))
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|command| *command != "help")
+        .map(String::from)
+        .collect()
+}
+
+/// Converts the long help of a command, as printed with `--help`, into
+/// a roff man page. `topics` are added as sections at the end.
+pub fn man_page(
+    name: &str,
+    version: &str,
+    help: &str,
+    see_also: &[String],
+    topics: &[Topic],
+) -> String {
+    let mut page = format!(
+        ".TH \"{}\" \"1\" \"\" \"mizeria {}\" \"User Commands\"\n",
+        escape(&name.to_uppercase()),
+        escape(version)
+    );
+    // The first line repeats the name of the command.
+    let mut lines = help.lines().skip(1);
+    let description: Vec<&str> = lines
+        .by_ref()
+        .take_while(|line| *line != "USAGE:")
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    page.push_str(".SH NAME\n");
+    page.push_str(&format!(
+        "{} \\- {}\n",
+        escape(name),
+        escape(description.first().copied().unwrap_or_default())
+    ));
+
+    page.push_str(".SH SYNOPSIS\n");
+    for line in lines.by_ref().take_while(|line| !line.trim().is_empty()) {
+        // Commands are parsed without the name of the program.
+        let usage = line.trim();
+        let usage = if usage.starts_with("mizeria") {
+            usage.to_string()
+        } else {
+            format!("mizeria {}", usage)
+        };
+        page.push_str(&format!(".B {}\n.br\n", escape(&usage)));
+    }
+    if description.len() > 1 {
+        page.push_str(".SH DESCRIPTION\n");
+        for line in &description {
+            page.push_str(&format!("{}\n", escape(line.trim())));
+        }
+    }
+
+    let mut section = "";
+    for line in lines {
+        let indent = line.len() - line.trim_start().len();
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if indent == 0 && text.ends_with(':') && text == text.to_uppercase() {
+            let title = match text.trim_end_matches(':') {
+                "FLAGS" | "OPTIONS" => "OPTIONS",
+                "ARGS" => "ARGUMENTS",
+                "SUBCOMMANDS" => "COMMANDS",
+                other => other,
+            };
+            if title != section {
+                page.push_str(&format!(".SH {}\n", escape(title)));
+                section = title;
+            }
+        } else if indent == 0 {
+            page.push_str(&format!(".PP\n{}\n", escape(text)));
+        } else if indent >= 12 {
+            page.push_str(&format!("{}\n", escape(text)));
+        } else {
+            // Short help is printed on the same line, after the name.
+            let (item, help) = text.split_once("  ").unwrap_or((text, ""));
+            page.push_str(&format!(".TP\n\\fB{}\\fR\n", escape(item)));
+            if !help.trim().is_empty() {
+                page.push_str(&format!("{}\n", escape(help.trim())));
+            }
+        }
+    }
+
+    for topic in topics {
+        page.push_str(&format!(".SH {}\n", escape(&topic.title.to_uppercase())));
+        for paragraph in topic.text.split("\n\n") {
+            page.push_str(&format!(".PP\n{}\n", escape(paragraph)));
+        }
+    }
+    if !see_also.is_empty() {
+        let pages: Vec<String> = see_also
+            .iter()
+            .map(|page| format!("\\fB{}\\fR(1)", escape(page)))
+            .collect();
+        page.push_str(&format!(".SH \"SEE ALSO\"\n{}\n", pages.join(", ")));
+    }
+    page
+}
+
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str = concat!(
+        "mizeria-list \n",
+        "List all snapshots\n",
+        "\n",
+        "USAGE:\n",
+        "    mizeria list [FLAGS] [BACKUP]\n",
+        "\n",
+        "FLAGS:\n",
+        "        --short\n",
+        "            Show only names of snapshots\n",
+        "\n",
+        "    -v\n",
+        "            Use -v to turn on debug logs.\n",
+        "\n",
+        "ARGS:\n",
+        "    <BACKUP>\n",
+        "            A folder with snapshots\n",
+    );
+
+    #[test]
+    fn convert_help_to_man_page() {
+        let see_also = vec!["mizeria".to_string()];
+        let page = man_page("mizeria-list", "1.0.0", HELP, &see_also, &[]);
+
+        let expected = concat!(
+            ".TH \"MIZERIA\\-LIST\" \"1\" \"\" \"mizeria 1.0.0\" \"User Commands\"\n",
+            ".SH NAME\n",
+            "mizeria\\-list \\- List all snapshots\n",
+            ".SH SYNOPSIS\n",
+            ".B mizeria list [FLAGS] [BACKUP]\n",
+            ".br\n",
+            ".SH OPTIONS\n",
+            ".TP\n",
+            "\\fB\\-\\-short\\fR\n",
+            "Show only names of snapshots\n",
+            ".TP\n",
+            "\\fB\\-v\\fR\n",
+            "Use \\-v to turn on debug logs.\n",
+            ".SH ARGUMENTS\n",
+            ".TP\n",
+            "\\fB<BACKUP>\\fR\n",
+            "A folder with snapshots\n",
+            ".SH \"SEE ALSO\"\n",
+            "\\fBmizeria\\fR(1)\n",
+        );
+        assert_eq!(page, expected);
+    }
+
+    #[test]
+    fn list_commands_from_help() {
+        let help = concat!(
+            "mizeria 1.0.0\n",
+            "Simple backup software\n",
+            "\n",
+            "SUBCOMMANDS:\n",
+            "    backup    Make a backup of your files\n",
+            "    help      Prints this message or the help of the given subcommand(s)\n",
+            "    list      List all snapshots\n",
+            "\n",
+            "Run 'mizeria topic' to list help topics.\n",
+        );
+        assert_eq!(commands(help), ["backup", "list"]);
+    }
+
+    #[test]
+    fn escape_roff_control_characters() {
+        assert_eq!(escape(".hidden"), "\\&.hidden");
+        assert_eq!(escape("C:\\files"), "C:\\efiles");
+    }
+}
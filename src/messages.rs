@@ -0,0 +1,344 @@
+//! Translations of user-facing output: summaries of commands, their errors
+//! and findings of integrity checks. Messages are looked up by their English
+//! text, so untranslated ones are printed in English. Log messages are not
+//! translated.
+
+use std::cell::Cell;
+use std::env;
+use std::fmt::Display;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Locale {
+    English,
+    Polish,
+}
+
+impl Locale {
+    /// Parses names like "pl", "pl_PL.UTF-8" or "en-US". Unknown languages
+    /// fall back to English.
+    pub fn parse(name: &str) -> Self {
+        let language = name.split(['_', '-', '.', '@']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "pl" => Self::Polish,
+            _ => Self::English,
+        }
+    }
+
+    /// Locale chosen with MIZERIA_LANG. The system locale (LANG) is not
+    /// used on purpose, so scripts parsing the output keep working.
+    pub fn from_environment() -> Self {
+        env::var("MIZERIA_LANG").map_or(Self::English, |value| Self::parse(&value))
+    }
+
+    fn catalog(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::English => &[],
+            Self::Polish => POLISH,
+        }
+    }
+}
+
+thread_local! {
+    // Every run of the program, e.g. in tests, can use a different locale.
+    static LOCALE: Cell<Locale> = const { Cell::new(Locale::English) };
+}
+
+pub fn set_locale(locale: Locale) {
+    LOCALE.with(|current| current.set(locale));
+}
+
+/// Translates a message and fills its `{}` placeholders in order.
+pub fn translate(message: &'static str, args: &[&dyn Display]) -> String {
+    let catalog = LOCALE.with(Cell::get).catalog();
+    let template = catalog
+        .iter()
+        .find(|(english, _)| *english == message)
+        .map_or(message, |(_, translated)| translated);
+    fill(template, args)
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut parts = template.split("{}");
+    let mut message = parts.next().unwrap_or_default().to_string();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            message.push_str(&arg.to_string());
+        }
+        message.push_str(part);
+    }
+    message
+}
+
+/// Translated message, e.g. `t!("Removed {} snapshot(s).", count)`.
+macro_rules! t {
+    ($message:literal) => {
+        $crate::messages::translate($message, &[])
+    };
+    ($message:literal, $($arg:expr),+ $(,)?) => {
+        $crate::messages::translate($message, &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
+pub(crate) use t;
+
+const POLISH: &[(&str, &str)] = &[
+    // summaries
+    ("Available snapshots:", "Dostępne snapshoty:"),
+    ("{} snapshot(s)", "snapshoty: {}"),
+    ("bytes", "bajtów"),
+    ("{} bytes", "{} bajtów"),
+    ("Folder with backup doesn't exist or isn't accessible", "Folder z kopią zapasową nie istnieje lub jest niedostępny"),
+    ("Backup has no snapshots", "Kopia zapasowa nie zawiera żadnych snapshotów"),
+    ("No foreign entries found.", "Nie znaleziono obcych wpisów."),
+    ("Foreign entries:", "Obce wpisy:"),
+    ("(damaged snapshot, kept)", "(uszkodzony snapshot, zachowany)"),
+    ("(removed)", "(usunięty)"),
+    ("Found {} foreign entries taking {} bytes.", "Znalezione obce wpisy: {}, zajmujące {} bajtów."),
+    ("Removed {} entries freeing {} bytes.", "Usunięte wpisy: {}, zwolniono {} bajtów."),
+    ("Snapshot integrity check failed.", "Sprawdzanie integralności snapshotu nie powiodło się."),
+    ("Snapshot integrity check failed. Warnings are treated as errors in strict mode.", "Sprawdzanie integralności snapshotu nie powiodło się. W trybie ścisłym ostrzeżenia są traktowane jak błędy."),
+    ("Snapshot integrity check completed with {} warning(s).", "Sprawdzanie integralności snapshotu zakończone. Liczba ostrzeżeń: {}."),
+    ("Snapshot integrity check completed. No problems found.", "Sprawdzanie integralności snapshotu zakończone. Nie znaleziono problemów."),
+    ("Snapshot integrity check failed", "Sprawdzanie integralności snapshotu nie powiodło się"),
+    ("Snapshot:", "Snapshot:"),
+    ("Snapshot has no metadata.", "Snapshot nie ma metadanych."),
+    ("Version:", "Wersja:"),
+    ("Host:", "Komputer:"),
+    ("User:", "Użytkownik:"),
+    ("full", "pełny"),
+    ("incremental", "przyrostowy"),
+    ("Duration:", "Czas trwania:"),
+    ("Inputs:", "Źródła:"),
+    ("Entries:", "Wpisy:"),
+    ("{} indexed, {} copied", "{} w indeksie, {} skopiowanych"),
+    ("Size:", "Rozmiar:"),
+    ("Warnings:", "Ostrzeżenia:"),
+    ("Not copied:", "Nieskopiowane:"),
+    ("Unstable:", "Niestabilne:"),
+    ("{} ({} bytes)", "{} ({} bajtów)"),
+    ("Stored in:", "Dane w:"),
+    ("Location:", "Położenie:"),
+    ("Type:", "Typ:"),
+    ("Path '{}' is not indexed in this snapshot", "Ścieżki '{}' nie ma w indeksie tego snapshotu"),
+    ("No files are stored under '{}' in this snapshot", "W tym snapshocie nie ma plików zapisanych w '{}'"),
+    ("No files to back up. Give INPUT or set inputs in mizeria.toml", "Brak plików do zarchiwizowania. Podaj INPUT lub ustaw inputs w mizeria.toml"),
+    ("Pre-backup hook failed: {}", "Skrypt przed kopią zapasową nie powiódł się: {}"),
+    ("Post-backup hook failed: {}", "Skrypt po kopii zapasowej nie powiódł się: {}"),
+    ("No backup given. Give BACKUP or set MIZERIA_BACKUP or default-backup in the config file", "Nie podano kopii zapasowej. Podaj BACKUP lub ustaw MIZERIA_BACKUP albo default-backup w pliku konfiguracyjnym"),
+    ("Number of snapshots to keep is needed. Give --keep-last or set keep-last in mizeria.toml", "Potrzebna jest liczba snapshotów do zachowania. Podaj --keep-last lub ustaw keep-last w mizeria.toml"),
+    ("Data of '{}' is missing in snapshot {}", "Brakuje danych '{}' w snapshocie {}"),
+    ("{} file(s) larger than the maximum file size were not copied ({} bytes)", "Nie skopiowano plików większych niż maksymalny rozmiar: {} ({} bajtów)"),
+    ("Created snapshot: {}", "Utworzono snapshot: {}"),
+    ("Backup was interrupted, snapshot {} has only files copied so far", "Kopia została przerwana, snapshot {} zawiera tylko pliki skopiowane do tej pory"),
+    ("Verified {} copied file(s)", "Zweryfikowane skopiowane pliki: {}"),
+    ("Verification found {} copied file(s) that don't match their source", "Weryfikacja wykryła skopiowane pliki niezgodne ze źródłem: {}"),
+    ("Entries: {} scanned, {} copied, {} reused, {} error(s)", "Wpisy: przeskanowane {}, skopiowane {}, ponownie użyte {}, błędy {}"),
+    ("Written: {} in {} ({}/s)", "Zapisano: {} w {} ({}/s)"),
+    ("Snapshot created with {} warning(s) (run with -v for details)", "Snapshot utworzony, liczba ostrzeżeń: {} (uruchom z -v, aby zobaczyć szczegóły)"),
+    ("{} file(s) could not be backed up (listed in {}):", "Pliki, których nie udało się skopiować do kopii zapasowej: {} (lista w {}):"),
+    ("Problems found in strict mode:", "Problemy znalezione w trybie ścisłym:"),
+    ("Backup failed in strict mode with {} warning(s)", "Kopia zapasowa nie powiodła się w trybie ścisłym, liczba ostrzeżeń: {}"),
+    ("Backup completed", "Kopia zapasowa zakończona"),
+    ("Backup failed", "Kopia zapasowa nie powiodła się"),
+    ("Snapshot {} created in {}", "Utworzono snapshot {} w {}"),
+    ("Snapshot {} created in {} with {} warning(s)", "Utworzono snapshot {} w {}, liczba ostrzeżeń: {}"),
+    ("FAILED", "BŁĄD"),
+    ("Checked {} of {} snapshot(s).", "Sprawdzone snapshoty: {} z {}."),
+    ("Integrity check of {} snapshot(s) failed", "Sprawdzanie integralności nie powiodło się dla snapshotów: {}"),
+    ("Scrubbed {} of {} snapshot(s).", "Przeskanowane snapshoty: {} z {}."),
+    ("Scrub found corrupted data in {} snapshot(s)", "Skanowanie wykryło uszkodzone dane w snapshotach: {}"),
+    ("Removed snapshot from trash: {}", "Usunięto snapshot z kosza: {}"),
+    ("Moved snapshot to trash: {}", "Przeniesiono snapshot do kosza: {}"),
+    ("Removed snapshot: {}", "Usunięto snapshot: {}"),
+    ("Kept snapshot: {} (data is referenced by {})", "Zachowano snapshot: {} (jego dane są potrzebne snapshotowi {})"),
+    ("Removed {} snapshot(s).", "Usunięte snapshoty: {}."),
+    ("Data referenced by these snapshots is now missing: {}", "Brakuje teraz danych, do których odwołują się snapshoty: {}"),
+    ("Copied {} entries into {}", "Skopiowane wpisy: {} do {}"),
+    ("Pointed {} entries of newer snapshots to {}", "Przekierowane wpisy nowszych snapshotów: {} (na {})"),
+    ("Snapshots that are no longer needed: {}", "Snapshoty, które nie są już potrzebne: {}"),
+    ("No corrupted files found.", "Nie znaleziono uszkodzonych plików."),
+    ("Repaired '{}' with parity data", "Naprawiono '{}' na podstawie danych parzystości"),
+    ("Added {} parity block(s) ({} bytes) protecting {} block(s) of snapshot {}", "Dodane bloki parzystości: {} ({} bajtów), chroniące bloki: {} snapshotu {}"),
+    ("Repaired '{}' from snapshot {}", "Naprawiono '{}' na podstawie snapshotu {}"),
+    ("No intact copy of '{}' was found", "Nie znaleziono nieuszkodzonej kopii '{}'"),
+    ("{} corrupted file(s) could not be repaired", "Nie udało się naprawić uszkodzonych plików: {}"),
+    ("Trash is empty.", "Kosz jest pusty."),
+    ("{}  (removed on {})", "{}  (usunięty {})"),
+    ("Restored snapshot: {}", "Przywrócono snapshot: {}"),
+    ("Exported checksums of {} file(s) to {}", "Wyeksportowano sumy kontrolne plików: {} do {}"),
+    ("Restoring from snapshot {}", "Przywracanie ze snapshotu {}"),
+    ("Passphrase of the GPG key: ", "Hasło klucza GPG: "),
+    ("Signatures of snapshot {} don't match, nothing was restored", "Podpisy snapshotu {} się nie zgadzają, nic nie zostało przywrócone"),
+    ("Skipped existing file: {}", "Pominięto istniejący plik: {}"),
+    ("Special file not recreated: {}", "Nie odtworzono pliku specjalnego: {}"),
+    ("Data of '{}' is missing", "Brakuje danych '{}'"),
+    ("Restored {} entries ({} bytes) to {}", "Przywrócone wpisy: {} ({} bajtów) w {}"),
+    ("Would restore {} entries ({} bytes) to {}", "Zostałyby przywrócone wpisy: {} ({} bajtów) w {}"),
+    ("Data of {} entries is missing in the backup", "W kopii zapasowej brakuje danych wpisów: {}"),
+    ("Invalid date: '{}'", "Niepoprawna data: '{}'"),
+    ("Invalid size: '{}'", "Niepoprawny rozmiar: '{}'"),
+    ("Found {} entries.", "Znalezione wpisy: {}."),
+    ("Counting entries...", "Liczenie wpisów..."),
+    ("{}/{} entries", "wpisy: {}/{}"),
+    ("{} copied", "skopiowano {}"),
+    ("ETA {}", "pozostało {}"),
+    ("Base snapshot: {}", "Snapshot bazowy: {}"),
+    ("No base snapshot, all files would be copied.", "Brak snapshotu bazowego, wszystkie pliki zostałyby skopiowane."),
+    ("Entries to index: {}", "Wpisy do zaindeksowania: {}"),
+    ("Files to copy: {} ({})", "Pliki do skopiowania: {} ({})"),
+    ("Unchanged files: {}", "Niezmienione pliki: {}"),
+    ("Unreadable entries: {}", "Nieczytelne wpisy: {}"),
+    ("Estimated duration: {} at {}/s", "Szacowany czas: {} przy {}/s"),
+    ("Snapshots: {}", "Snapshoty: {}"),
+    ("Size on disk: {}", "Rozmiar na dysku: {}"),
+    ("Average snapshot size: {}", "Średni rozmiar snapshotu: {}"),
+    ("Growth:", "Przyrost:"),
+    ("Largest files:", "Największe pliki:"),
+    ("Largest folders:", "Największe foldery:"),
+    ("added", "dodany"),
+    ("removed", "usunięty"),
+    ("modified", "zmieniony"),
+    ("{} added, {} removed, {} modified.", "Dodane: {}, usunięte: {}, zmienione: {}."),
+    ("Destination:      {}", "Miejsce docelowe:      {}"),
+    ("Sequential write: {} MiB/s ({} buffer)", "Zapis sekwencyjny:     {} MiB/s (bufor {})"),
+    ("Small files:      {} files/s", "Małe pliki:            {} plików/s"),
+    ("Metadata latency: {} ms", "Opóźnienie metadanych: {} ms"),
+    ("Recommended settings:", "Zalecane ustawienia:"),
+    ("{}% faster sequential writes", "zapis sekwencyjny szybszy o {}%"),
+    ("default copy buffer", "domyślny bufor kopiowania"),
+    ("larger buffers are not faster", "większe bufory nie są szybsze"),
+    ("slow metadata operations, threads mostly wait for the destination", "wolne operacje na metadanych, wątki głównie czekają na miejsce docelowe"),
+    ("one thread per CPU (default)", "jeden wątek na procesor (domyślnie)"),
+    ("data is compressed faster than it is written", "dane są kompresowane szybciej, niż są zapisywane"),
+    ("compression off", "kompresja wyłączona"),
+    ("compressing would be slower than writing", "kompresja byłaby wolniejsza niż zapis"),
+    ("Found {} error(s) and {} warning(s).", "Znalezione błędy: {}, ostrzeżenia: {}."),
+    ("Backup cannot be safely made. See the errors above", "Nie można bezpiecznie wykonać kopii zapasowej. Zobacz błędy powyżej"),
+    ("Backup folder '{}' doesn't exist or isn't a folder.", "Folder z kopią zapasową '{}' nie istnieje lub nie jest folderem."),
+    ("Backup folder isn't writable ({}).", "Nie można zapisywać w folderze z kopią zapasową ({})."),
+    ("Backup folder is writable ({}).", "Można zapisywać w folderze z kopią zapasową ({})."),
+    ("Backup folder is on a network share. Backups may be slow and interrupted by connection problems.", "Folder z kopią zapasową jest na udziale sieciowym. Kopie mogą być wolne i przerywane przez problemy z połączeniem."),
+    ("{} stores modification times with 2 second resolution. Incremental backups may copy unchanged files.", "{} zapisuje czas modyfikacji z dokładnością do 2 sekund. Kopie przyrostowe mogą kopiować niezmienione pliki."),
+    ("Backup folder doesn't support symbolic links. Symlinks won't be backed up.", "Folder z kopią zapasową nie obsługuje dowiązań symbolicznych. Dowiązania nie zostaną skopiowane."),
+    ("Latest snapshot {} is from the future. Check the system clock.", "Najnowszy snapshot {} pochodzi z przyszłości. Sprawdź zegar systemowy."),
+    ("System clock is consistent with {} existing snapshot(s).", "Zegar systemowy jest zgodny z istniejącymi snapshotami: {}."),
+    ("Backup folder has no snapshots yet.", "Folder z kopią zapasową nie zawiera jeszcze snapshotów."),
+    ("Backup folder is locked, but the lock file can't be read.", "Folder z kopią zapasową jest zablokowany, ale nie można odczytać pliku blokady."),
+    ("A backup is running, the folder is locked by {}.", "Trwa tworzenie kopii zapasowej, folder jest zablokowany przez {}."),
+    ("Backup folder is locked by {}, which can't be checked from this host.", "Folder z kopią zapasową jest zablokowany przez {}, czego nie można sprawdzić z tego komputera."),
+    ("Removed stale lock of {}.", "Usunięto nieaktualną blokadę {}."),
+    ("Cannot remove stale lock ({}).", "Nie można usunąć nieaktualnej blokady ({})."),
+    ("Lock of {} is stale, the process isn't running anymore. Remove it with --fix.", "Blokada {} jest nieaktualna, proces już nie działa. Usuń ją z --fix."),
+    ("Snapshot '{}' may be being created by the backup holding the lock.", "Snapshot '{}' może być właśnie tworzony przez kopię zapasową, która trzyma blokadę."),
+    ("Snapshot '{}' was left unfinished by an interrupted backup.", "Snapshot '{}' został niedokończony przez przerwaną kopię zapasową."),
+    ("Folder '{}' is named like a snapshot, but has no index and no files.", "Folder '{}' jest nazwany jak snapshot, ale nie ma indeksu ani plików."),
+    ("Folder '{}' looks like a damaged snapshot with {} bytes of files. It is never removed automatically.", "Folder '{}' wygląda na uszkodzony snapshot z plikami o rozmiarze {} bajtów. Nigdy nie jest usuwany automatycznie."),
+    ("'{}' ({} bytes) isn't part of the backup. Remove it with 'list --foreign --clean'.", "'{}' ({} bajtów) nie należy do kopii zapasowej. Usuń to poleceniem 'list --foreign --clean'."),
+    ("{} Remove it with --fix.", "{} Usuń go z --fix."),
+    ("Backup folder has no unfinished snapshots or foreign entries.", "Folder z kopią zapasową nie zawiera niedokończonych snapshotów ani obcych wpisów."),
+    ("Removed '{}' ({} bytes).", "Usunięto '{}' ({} bajtów)."),
+    ("Cannot remove '{}' ({}).", "Nie można usunąć '{}' ({})."),
+    ("Input '{}' doesn't exist.", "Wejście '{}' nie istnieje."),
+    ("{} input entries aren't readable, e.g. '{}'.", "Nie można odczytać wpisów wejściowych: {}, np. '{}'."),
+    ("Inputs have {} entries and take {} bytes.", "Wejścia zawierają {} wpisów i zajmują {} bajtów."),
+    ("Only {} bytes are free in backup folder, but a full snapshot needs {} bytes.", "W folderze z kopią zapasową wolne jest tylko {} bajtów, a pełny snapshot potrzebuje {} bajtów."),
+    ("{} bytes are free in backup folder.", "W folderze z kopią zapasową wolne jest {} bajtów."),
+    ("Cannot determine free space in backup folder.", "Nie można ustalić wolnego miejsca w folderze z kopią zapasową."),
+    ("File '{}' ({} bytes) exceeds the maximum file size of {}.", "Plik '{}' ({} bajtów) przekracza maksymalny rozmiar pliku w {}."),
+    ("Name of '{}' is longer than {} bytes allowed in backup folder.", "Nazwa '{}' jest dłuższa niż {} bajtów dozwolonych w folderze z kopią zapasową."),
+    ("Path '{}' will be {} characters long inside a snapshot, which exceeds the limit of {}.", "Ścieżka '{}' będzie miała w snapshocie {} znaków, co przekracza limit {}."),
+    ("Create synthetic dataset", "Tworzenie syntetycznych danych"),
+    ("Create full snapshot", "Tworzenie pełnego snapshotu"),
+    ("Create incremental snapshot", "Tworzenie przyrostowego snapshotu"),
+    ("Check integrity of snapshots", "Sprawdzanie integralności snapshotów"),
+    ("Restore latest snapshot", "Przywracanie najnowszego snapshotu"),
+    ("Compare restored files", "Porównanie przywróconych plików"),
+    ("{} files", "pliki: {}"),
+    ("Unchanged file was stored again in snapshot {}", "Niezmieniony plik został ponownie zapisany w snapshocie {}"),
+    ("{} with {} warning(s), e.g. {}", "{}, liczba ostrzeżeń: {}, np. {}"),
+    ("{} entries", "wpisy: {}"),
+    ("{} entries are identical", "identyczne wpisy: {}"),
+    ("FAILED  {}: {}", "BŁĄD    {}: {}"),
+    ("Self-test failed. Backups may not work in this folder", "Autotest nie powiódł się. Kopie zapasowe mogą nie działać w tym folderze"),
+    ("Self-test passed.", "Autotest zakończony powodzeniem."),
+    ("Folder with backup '{}' doesn't exist or isn't accessible", "Folder z kopią zapasową '{}' nie istnieje lub jest niedostępny"),
+    ("Snapshot {} is missing in mirror", "Brakuje snapshotu {} w kopii lustrzanej"),
+    ("Snapshot {} is missing in primary", "Brakuje snapshotu {} w kopii głównej"),
+    ("Index of snapshot {} differs", "Indeks snapshotu {} się różni"),
+    ("Snapshot {}: '{}' is missing in mirror", "Snapshot {}: brakuje '{}' w kopii lustrzanej"),
+    ("Snapshot {}: '{}' is missing in primary", "Snapshot {}: brakuje '{}' w kopii głównej"),
+    ("Snapshot {}: '{}' is {} in primary, but {} in mirror", "Snapshot {}: '{}' to {} w kopii głównej, ale {} w kopii lustrzanej"),
+    ("Snapshot {}: contents of '{}' differ", "Snapshot {}: zawartość '{}' się różni"),
+    ("Snapshot {}: cannot read '{}' ({})", "Snapshot {}: nie można odczytać '{}' ({})"),
+    ("file of {} bytes", "plik o rozmiarze {} bajtów"),
+    ("symlink to '{}'", "dowiązanie do '{}'"),
+    ("Found {} difference(s) between backups", "Znalezione różnice między kopiami zapasowymi: {}"),
+    ("Backups are equivalent.", "Kopie zapasowe są równoważne."),
+    ("Warning: backup is not in append-only mode.", "Ostrzeżenie: kopia zapasowa nie jest w trybie tylko do dopisywania."),
+    ("not fingerprinted", "bez odcisku"),
+    ("CHANGED", "ZMIENIONY"),
+    ("REMOVED", "USUNIĘTY"),
+    ("{} snapshot(s) changed or removed since creation", "Snapshoty zmienione lub usunięte od utworzenia: {}"),
+    ("no date in its name", "brak daty w nazwie"),
+    ("not newer than snapshot {}", "nie jest nowszy niż snapshot {}"),
+    ("Skipped: {} ({})", "Pominięto: {} ({})"),
+    ("Imported snapshot: {} (from {})", "Zaimportowano snapshot: {} (z {})"),
+    ("Imported {} snapshot(s).", "Zaimportowane snapshoty: {}."),
+    ("Snapshots imported with {} warning(s) (run with -v for details)", "Snapshoty zaimportowane, liczba ostrzeżeń: {} (uruchom z -v, aby zobaczyć szczegóły)"),
+    // integrity checks
+    ("Snapshot doesn't exist.", "Snapshot nie istnieje."),
+    ("Snapshot's name '{}' is not a correct timestamp.", "Nazwa snapshotu '{}' nie jest poprawną datą."),
+    ("Files index.txt is missing.", "Brakuje pliku index.txt."),
+    ("Folder files is missing.", "Brakuje folderu files."),
+    ("Invalid timestamp in line {} of index.txt.", "Niepoprawna data w linii {} pliku index.txt."),
+    ("Invalid path in line {} of index.txt.", "Niepoprawna ścieżka w linii {} pliku index.txt."),
+    ("Entry '{}' is indexed, but is missing in snapshot.", "Wpis '{}' jest w indeksie, ale brakuje go w snapshocie."),
+    ("Entry '{}' is present in snapshot, but is not indexed.", "Wpis '{}' jest w snapshocie, ale nie ma go w indeksie."),
+    ("Directory '{}' is present in snapshot, but is not indexed.", "Folder '{}' jest w snapshocie, ale nie ma go w indeksie."),
+    ("Signature of '{}' is missing or invalid.", "Podpisu '{}' brakuje lub jest niepoprawny."),
+    ("Entry '{}' doesn't match its signed checksum.", "Wpis '{}' nie zgadza się z podpisaną sumą kontrolną."),
+    ("Snapshot is signed, but no key was given to verify it.", "Snapshot jest podpisany, ale nie podano klucza do sprawdzenia podpisu."),
+    ("Snapshot '{}' is linked in the hash chain, but is missing.", "Snapshot '{}' jest w łańcuchu skrótów, ale go brakuje."),
+    ("Snapshot '{}' was altered after the next snapshot was made.", "Snapshot '{}' został zmieniony po utworzeniu następnego snapshotu."),
+    ("Entry '{}' doesn't match its checksum in index.txt.", "Wpis '{}' nie zgadza się ze swoją sumą kontrolną w pliku index.txt."),
+    ("{} file(s) have no checksum in index.txt and weren't verified.", "Pliki bez sumy kontrolnej w pliku index.txt, które nie zostały sprawdzone: {}."),
+    ("{} parity block(s) are damaged.", "Uszkodzone bloki parzystości: {}."),
+    ("Unexpected error occured: {}", "Wystąpił nieoczekiwany błąd: {}"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_locale_names() {
+        assert_eq!(Locale::parse("pl"), Locale::Polish);
+        assert_eq!(Locale::parse("pl_PL.UTF-8"), Locale::Polish);
+        assert_eq!(Locale::parse("PL-pl"), Locale::Polish);
+        assert_eq!(Locale::parse("en_US.UTF-8"), Locale::English);
+        assert_eq!(Locale::parse("C"), Locale::English);
+        assert_eq!(Locale::parse(""), Locale::English);
+    }
+
+    #[test]
+    fn translate_and_fill_placeholders() {
+        set_locale(Locale::Polish);
+        assert_eq!(t!("Removed {} snapshot(s).", 2), "Usunięte snapshoty: 2.");
+        assert_eq!(t!("Not translated {}", "yet"), "Not translated yet");
+        set_locale(Locale::English);
+        assert_eq!(t!("Removed {} snapshot(s).", 2), "Removed 2 snapshot(s).");
+    }
+
+    #[test]
+    fn translations_have_the_same_placeholders() {
+        for (english, translated) in POLISH {
+            assert_eq!(
+                english.matches("{}").count(),
+                translated.matches("{}").count(),
+                "{}",
+                english
+            );
+            let duplicates = POLISH.iter().filter(|(e, _)| e == english).count();
+            assert_eq!(duplicates, 1, "{}", english);
+        }
+    }
+}
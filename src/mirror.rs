@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::backup::{sha256_file, Backup, ARCHIVE_FILE_NAME};
+use crate::messages::t;
+use crate::parallel;
+
+#[derive(PartialEq, Eq, Debug)]
+enum StoredEntry {
+    File(u64), // size in bytes
+    Directory,
+    Symlink(PathBuf),
+}
+
+impl Display for StoredEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(size) => write!(f, "{}", t!("file of {} bytes", size)),
+            Self::Directory => write!(f, "{}", t!("folder")),
+            Self::Symlink(target) => write!(f, "{}", t!("symlink to '{}'", target.display())),
+        }
+    }
+}
+
+/// Compares snapshots of two backups and returns descriptions of every
+/// difference found. Contents of stored files are compared only when
+/// `compare_hashes` is set, otherwise only their sizes are checked.
+pub fn compare(primary: &Path, mirror: &Path, compare_hashes: bool) -> Vec<String> {
+    let primary_snapshots = snapshot_names(primary);
+    let mirror_snapshots = snapshot_names(mirror);
+    let mut differences = vec![];
+
+    for name in &primary_snapshots {
+        if !mirror_snapshots.contains(name) {
+            differences.push(t!("Snapshot {} is missing in mirror", name));
+        }
+    }
+    for name in &mirror_snapshots {
+        if !primary_snapshots.contains(name) {
+            differences.push(t!("Snapshot {} is missing in primary", name));
+        }
+    }
+
+    let common: Vec<&String> = primary_snapshots
+        .iter()
+        .filter(|n| mirror_snapshots.contains(n))
+        .collect();
+    let compared = parallel::map(common, |name| {
+        debug!("Comparing snapshot: {}", name);
+        let mut differences = vec![];
+        compare_snapshot(
+            &primary.join(name),
+            &mirror.join(name),
+            compare_hashes,
+            &mut differences,
+        );
+        differences
+    });
+    differences.extend(compared.into_iter().flatten());
+    differences
+}
+
+fn snapshot_names(backup: &Path) -> Vec<String> {
+    Backup::get_all_snapshot_previews(backup)
+        .iter()
+        .map(|s| s.name())
+        .collect()
+}
+
+fn compare_snapshot(
+    primary: &Path,
+    mirror: &Path,
+    compare_hashes: bool,
+    differences: &mut Vec<String>,
+) {
+    let name = primary.file_name().unwrap_or_default().to_string_lossy();
+
+    let primary_index = fs::read(primary.join("index.txt")).ok();
+    let mirror_index = fs::read(mirror.join("index.txt")).ok();
+    if primary_index != mirror_index {
+        differences.push(t!("Index of snapshot {} differs", name));
+    }
+
+    let primary_files = stored_entries(primary);
+    let mirror_files = stored_entries(mirror);
+
+    for (path, entry) in &primary_files {
+        let mirror_entry = match mirror_files.get(path) {
+            Some(mirror_entry) => mirror_entry,
+            None => {
+                differences.push(t!(
+                    "Snapshot {}: '{}' is missing in mirror",
+                    name,
+                    path.display()
+                ));
+                continue;
+            }
+        };
+        if entry != mirror_entry {
+            differences.push(t!(
+                "Snapshot {}: '{}' is {} in primary, but {} in mirror",
+                name,
+                path.display(),
+                entry,
+                mirror_entry
+            ));
+        } else if compare_hashes && matches!(entry, StoredEntry::File(_)) {
+            let hashes = (
+                sha256_file(&primary.join(path)),
+                sha256_file(&mirror.join(path)),
+            );
+            match hashes {
+                (Ok(primary_hash), Ok(mirror_hash)) if primary_hash == mirror_hash => {}
+                (Ok(_), Ok(_)) => differences.push(t!(
+                    "Snapshot {}: contents of '{}' differ",
+                    name,
+                    path.display()
+                )),
+                (Err(e), _) | (_, Err(e)) => differences.push(t!(
+                    "Snapshot {}: cannot read '{}' ({})",
+                    name,
+                    path.display(),
+                    e
+                )),
+            }
+        }
+    }
+    for path in mirror_files.keys() {
+        if !primary_files.contains_key(path) {
+            differences.push(t!(
+                "Snapshot {}: '{}' is missing in primary",
+                name,
+                path.display()
+            ));
+        }
+    }
+}
+
+/// Entries stored in `files` folder of a snapshot, or its archive, by
+/// their paths relative to the snapshot.
+fn stored_entries(snapshot: &Path) -> BTreeMap<PathBuf, StoredEntry> {
+    let mut entries = BTreeMap::new();
+    let stored = ["files", ARCHIVE_FILE_NAME]
+        .iter()
+        .flat_map(|name| WalkDir::new(snapshot.join(name)).follow_links(false))
+        .filter_map(Result::ok);
+    for entry in stored {
+        let path = entry.path();
+        let stored_entry = if entry.file_type().is_symlink() {
+            StoredEntry::Symlink(fs::read_link(path).unwrap_or_default())
+        } else if entry.file_type().is_dir() {
+            StoredEntry::Directory
+        } else {
+            StoredEntry::File(entry.metadata().map(|m| m.len()).unwrap_or(0))
+        };
+        let relative_path = path.strip_prefix(snapshot).unwrap_or(path).to_owned();
+        entries.insert(relative_path, stored_entry);
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_snapshot(backup: &Path, name: &str, file_content: &[u8]) {
+        let snapshot = backup.join(name);
+        fs::create_dir_all(snapshot.join("files").join("folder")).unwrap();
+        fs::write(snapshot.join("index.txt"), b"index").unwrap();
+        fs::write(
+            snapshot.join("files").join("folder").join("file"),
+            file_content,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn identical_backups_have_no_differences() {
+        let primary = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+        create_snapshot(primary.path(), "2021-07-15_18.34", b"abc");
+        create_snapshot(mirror.path(), "2021-07-15_18.34", b"abc");
+
+        assert!(compare(primary.path(), mirror.path(), true).is_empty());
+    }
+
+    #[test]
+    fn missing_snapshots_are_reported() {
+        let primary = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+        create_snapshot(primary.path(), "2021-07-15_18.34", b"abc");
+        create_snapshot(mirror.path(), "2021-07-16_18.34", b"abc");
+
+        let differences = compare(primary.path(), mirror.path(), false);
+
+        assert_eq!(
+            differences,
+            vec![
+                "Snapshot 2021-07-15_18.34 is missing in mirror",
+                "Snapshot 2021-07-16_18.34 is missing in primary"
+            ]
+        );
+    }
+
+    #[test]
+    fn different_contents_are_reported_only_with_hashes() {
+        let primary = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+        create_snapshot(primary.path(), "2021-07-15_18.34", b"abc");
+        create_snapshot(mirror.path(), "2021-07-15_18.34", b"abd");
+
+        assert!(compare(primary.path(), mirror.path(), false).is_empty());
+        assert_eq!(compare(primary.path(), mirror.path(), true).len(), 1);
+    }
+
+    #[test]
+    fn different_files_and_indexes_are_reported() {
+        let primary = tempfile::tempdir().unwrap();
+        let mirror = tempfile::tempdir().unwrap();
+        create_snapshot(primary.path(), "2021-07-15_18.34", b"abc");
+        create_snapshot(mirror.path(), "2021-07-15_18.34", b"abcd");
+        let snapshot = mirror.path().join("2021-07-15_18.34");
+        fs::write(snapshot.join("index.txt"), b"other index").unwrap();
+        fs::write(snapshot.join("files").join("extra"), b"").unwrap();
+
+        let differences = compare(primary.path(), mirror.path(), false);
+
+        assert_eq!(differences.len(), 3, "{:?}", differences);
+    }
+}
@@ -0,0 +1,200 @@
+//! Desktop notifications shown with the tools of the operating system:
+//! notify-send on Linux and BSD, osascript on macOS and PowerShell toasts
+//! on Windows. A JSON summary of the backup can be posted to a URL or
+//! piped to a command instead, e.g. for monitoring or mail.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+use tracing::debug;
+
+use crate::hooks;
+
+// Texts are passed in the environment, so they don't need to be escaped
+// for AppleScript or PowerShell.
+const TITLE_VARIABLE: &str = "MIZERIA_NOTIFY_TITLE";
+const BODY_VARIABLE: &str = "MIZERIA_NOTIFY_BODY";
+
+#[cfg(windows)]
+const TOAST_SCRIPT: &str = concat!(
+    "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null;",
+    "$template = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02);",
+    "$texts = $template.GetElementsByTagName('text');",
+    "$texts.Item(0).AppendChild($template.CreateTextNode($env:MIZERIA_NOTIFY_TITLE)) > $null;",
+    "$texts.Item(1).AppendChild($template.CreateTextNode($env:MIZERIA_NOTIFY_BODY)) > $null;",
+    "$toast = [Windows.UI.Notifications.ToastNotification]::new($template);",
+    "[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('mizeria').Show($toast)"
+);
+
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    /// Failures are shown as urgent where it is supported.
+    pub failure: bool,
+}
+
+impl Notification {
+    pub fn show(&self) -> Result<(), String> {
+        debug!("Showing notification: {}: {}", self.title, self.body);
+        let mut command = self.command();
+        let program = command.get_program().to_string_lossy().to_string();
+        let status = command
+            .env(TITLE_VARIABLE, &self.title)
+            .env(BODY_VARIABLE, &self.body)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|e| format!("Cannot run {} ({})", program, e))?;
+        if !status.success() {
+            return Err(format!("{} failed ({})", program, status));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn command(&self) -> Command {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification (system attribute \"{}\") with title (system attribute \"{}\")",
+            BODY_VARIABLE, TITLE_VARIABLE
+        ));
+        command
+    }
+
+    #[cfg(windows)]
+    fn command(&self) -> Command {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-NonInteractive", "-Command", TOAST_SCRIPT]);
+        command
+    }
+
+    #[cfg(not(any(target_os = "macos", windows)))]
+    fn command(&self) -> Command {
+        let urgency = if self.failure { "critical" } else { "normal" };
+        let mut command = Command::new("notify-send");
+        command
+            .arg("--app-name=mizeria")
+            .arg(format!("--urgency={}", urgency))
+            .arg(&self.title)
+            .arg(&self.body);
+        command
+    }
+}
+
+/// Result of a backup as sent to a URL or a command.
+#[derive(Serialize)]
+pub struct Summary {
+    pub backup: String,
+    /// Missing when the backup failed before the snapshot was made.
+    pub snapshot: Option<String>,
+    /// success, warnings or failure.
+    pub status: &'static str,
+    /// Bytes written into the snapshot.
+    pub bytes: u64,
+    pub entries: usize,
+    pub copied: usize,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Posts the summary with curl, so no HTTP client is built in.
+    pub fn post(&self, url: &str) -> Result<(), String> {
+        let mut curl = Command::new("curl");
+        curl.args(["--fail", "--silent", "--show-error", "--max-time", "60"])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-", url]);
+        self.pipe(curl, "curl")
+    }
+
+    /// Writes the summary to the standard input of `command` run with
+    /// the shell, e.g. to mail it.
+    pub fn pipe_to(&self, command: &str) -> Result<(), String> {
+        self.pipe(hooks::shell(command), command)
+    }
+
+    fn pipe(&self, mut command: Command, program: &str) -> Result<(), String> {
+        debug!("Sending backup summary to {}", program);
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Cannot run {} ({})", program, e))?;
+        let written = child
+            .stdin
+            .take()
+            .map(|mut stdin| writeln!(stdin, "{}", self.to_json()));
+        let status = child
+            .wait()
+            .map_err(|e| format!("Cannot run {} ({})", program, e))?;
+        if !status.success() {
+            return Err(format!("{} failed ({})", program, status));
+        }
+        match written {
+            Some(Err(e)) => Err(format!("Cannot write to {} ({})", program, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(any(target_os = "macos", windows)))]
+    fn failure_is_urgent() {
+        let notification = Notification {
+            title: "Backup failed".into(),
+            body: "Folder with backup doesn't exist".into(),
+            failure: true,
+        };
+
+        let command = notification.command();
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(command.get_program(), "notify-send");
+        assert_eq!(
+            args,
+            [
+                "--app-name=mizeria",
+                "--urgency=critical",
+                "Backup failed",
+                "Folder with backup doesn't exist"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn summary_is_piped_to_command() {
+        let root = tempfile::tempdir().unwrap();
+        let output = root.path().join("summary.json");
+        let summary = Summary {
+            backup: "/mnt/backup".into(),
+            snapshot: None,
+            status: "failure",
+            bytes: 0,
+            entries: 0,
+            copied: 0,
+            warnings: vec![],
+            error: Some("Folder with backup doesn't exist".into()),
+        };
+
+        summary
+            .pipe_to(&format!("cat > '{}'", output.display()))
+            .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(json["status"], "failure");
+        assert_eq!(json["snapshot"], serde_json::Value::Null);
+        assert_eq!(json["error"], "Folder with backup doesn't exist");
+        assert!(summary.pipe_to("exit 1").is_err());
+    }
+}
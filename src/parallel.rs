@@ -0,0 +1,60 @@
+//! Work that is spread across threads. Without the `parallel` feature
+//! everything runs on the calling thread.
+
+use tracing::debug;
+
+/// Sets the number of threads used by every parallel task. The thread pool
+/// can be configured only once per process, so later calls are ignored.
+#[cfg(feature = "parallel")]
+pub fn set_threads(threads: usize) {
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+    {
+        Ok(()) => debug!("Using {} thread(s)", threads),
+        Err(e) => debug!("Thread pool is already configured ({})", e),
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn set_threads(threads: usize) {
+    if threads > 1 {
+        tracing::warn!("Built without parallel feature, using a single thread");
+    } else {
+        debug!("Using 1 thread");
+    }
+}
+
+/// Applies `f` to all items and returns results in the order of items.
+#[cfg(feature = "parallel")]
+pub fn map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync + Send,
+{
+    use rayon::prelude::*;
+    items.into_par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    F: Fn(T) -> R,
+{
+    items.into_iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_keeps_order_of_items() {
+        let items: Vec<u32> = (0..1000).collect();
+
+        let squares = map(items, |i| i * i);
+
+        assert_eq!(squares, (0..1000).map(|i| i * i).collect::<Vec<u32>>());
+    }
+}
@@ -0,0 +1,252 @@
+//! Named profiles in the user's config file, e.g.
+//! ~/.config/mizeria/config.toml. Every profile has a backup folder, inputs
+//! and options of the backup command, so `mizeria backup --profile photos`
+//! replaces a wrapper script. The profile is expanded into arguments before
+//! they are parsed.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::Value;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    /// Read by the remotes module.
+    #[serde(default)]
+    #[allow(dead_code)]
+    remotes: BTreeMap<String, String>,
+    #[serde(default, rename = "default-backup")]
+    #[allow(dead_code)]
+    default_backup: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Profile {
+    pub backup: PathBuf,
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    /// Other keys are long options of the backup command, e.g.
+    /// `compress = "zstd"` or `verify = true`.
+    #[serde(flatten)]
+    pub options: BTreeMap<String, Value>,
+}
+
+impl Profile {
+    /// Arguments of the backup command, except options that are `given`
+    /// on the command line, as those take precedence.
+    fn to_args(&self, name: &str, given: &[String]) -> Result<Vec<String>, String> {
+        let mut args = vec![self.backup.to_string_lossy().to_string()];
+        args.extend(self.inputs.iter().map(|i| i.to_string_lossy().to_string()));
+        for (key, value) in &self.options {
+            let option = format!("--{}", key);
+            let is_given =
+                |arg: &String| *arg == option || arg.starts_with(&format!("{}=", option));
+            if given.iter().any(is_given) {
+                continue;
+            }
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    Value::Boolean(true) => args.push(option.clone()),
+                    Value::Boolean(false) => (),
+                    Value::String(value) => args.extend([option.clone(), value.clone()]),
+                    Value::Integer(value) => args.extend([option.clone(), value.to_string()]),
+                    _ => {
+                        return Err(format!(
+                            "Option '{}' of profile '{}' has an unsupported value",
+                            key, name
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Location of the config file: $MIZERIA_CONFIG, or mizeria/config.toml in
+/// the user's config folder.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("MIZERIA_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) => PathBuf::from(config_home),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("mizeria").join("config.toml"))
+}
+
+fn load_profile(config: &Path, name: &str) -> Result<Profile, String> {
+    let text = fs::read_to_string(config)
+        .map_err(|e| format!("Cannot read config file {} ({})", config.display(), e))?;
+    let mut file: ConfigFile = toml::from_str(&text)
+        .map_err(|e| format!("Invalid config file {}: {}", config.display(), e))?;
+    file.profiles.remove(name).ok_or_else(|| {
+        let names: Vec<&str> = file.profiles.keys().map(String::as_str).collect();
+        format!(
+            "Profile '{}' is not defined in {}. Defined profiles: {}",
+            name,
+            config.display(),
+            names.join(", ")
+        )
+    })
+}
+
+/// Replaces `--profile NAME` of the backup command with the backup folder,
+/// inputs and options of the profile. Other arguments are left as they are,
+/// so inputs given on the command line are backed up as well.
+pub fn expand(args: Vec<String>) -> Result<Vec<String>, String> {
+    let config = config_path().ok_or("Cannot find the config folder of the user")?;
+    expand_with(args, &config)
+}
+
+/// Global options taking a value, which can come before the subcommand.
+const GLOBAL_OPTIONS_WITH_VALUE: [&str; 4] = ["--threads", "--lang", "--log-file", "--log-level"];
+
+/// Position of the subcommand, the first argument after global options.
+fn subcommand_position(args: &[String]) -> Option<usize> {
+    let mut position = 0;
+    while let Some(arg) = args.get(position) {
+        if GLOBAL_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            position += 2;
+        } else if arg.starts_with('-') {
+            position += 1;
+        } else {
+            return Some(position);
+        }
+    }
+    None
+}
+
+fn expand_with(args: Vec<String>, config: &Path) -> Result<Vec<String>, String> {
+    let command = match subcommand_position(&args) {
+        Some(command) if args[command] == "backup" => command,
+        _ => return Ok(args),
+    };
+    let (mut before, rest) = (args[..=command].to_vec(), &args[command + 1..]);
+    let mut name = None;
+    let mut given = vec![];
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.strip_prefix("--profile") {
+            Some("") => name = Some(rest.next().ok_or("--profile needs a name")?.clone()),
+            Some(value) if value.starts_with('=') => name = Some(value[1..].to_string()),
+            _ => given.push(arg.clone()),
+        }
+    }
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(args),
+    };
+    let profile = load_profile(config, &name)?;
+    before.extend(profile.to_args(&name, &given)?);
+    before.extend(given);
+    Ok(before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn profile_is_expanded_into_arguments() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config.toml");
+        fs::write(
+            &config,
+            "[profiles.photos]\n\
+             backup = \"/mnt/usb/photos\"\n\
+             inputs = [\"/home/user/photos\"]\n\
+             compress = \"zstd\"\n\
+             exclude = [\"*.tmp\", \"cache\"]\n\
+             verify = true\n\
+             full = false\n\
+             [profiles.documents]\n\
+             backup = \"/mnt/usb/documents\"\n",
+        )
+        .unwrap();
+
+        let expanded = expand_with(
+            args(&[
+                "--threads",
+                "2",
+                "backup",
+                "--profile",
+                "photos",
+                "--compress=zstd:9",
+            ]),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            args(&[
+                "--threads",
+                "2",
+                "backup",
+                "/mnt/usb/photos",
+                "/home/user/photos",
+                "--exclude",
+                "*.tmp",
+                "--exclude",
+                "cache",
+                "--verify",
+                "--compress=zstd:9"
+            ])
+        );
+
+        let unchanged = args(&["backup", "/backup", "/input"]);
+        assert_eq!(expand_with(unchanged.clone(), &config).unwrap(), unchanged);
+        let unchanged = args(&["list", "backup", "--profile", "photos"]);
+        assert_eq!(expand_with(unchanged.clone(), &config).unwrap(), unchanged);
+        let unchanged = args(&["--log-file", "backup", "list", "--profile", "photos"]);
+        assert_eq!(expand_with(unchanged.clone(), &config).unwrap(), unchanged);
+
+        let expanded = expand_with(
+            args(&["--lang", "backup", "backup", "--profile", "documents"]),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            args(&["--lang", "backup", "backup", "/mnt/usb/documents"])
+        );
+
+        let error = expand_with(args(&["backup", "--profile=music"]), &config)
+            .err()
+            .unwrap();
+        assert!(error.contains("documents, photos"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config.toml");
+
+        fs::write(&config, "[profiles.photos]\ninputs = []\n").unwrap();
+        let error = load_profile(&config, "photos").err().unwrap();
+        assert!(error.contains("backup"), "{}", error);
+
+        fs::write(
+            &config,
+            "[profiles.photos]\nbackup = \"/b\"\nmax-depth = 1.5\n",
+        )
+        .unwrap();
+        let profile = load_profile(&config, "photos").unwrap();
+        assert!(profile.to_args("photos", &[]).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Progress of a backup drawn on a single line of the terminal: entries
+//! processed, bytes copied, throughput and the estimated time left. Totals
+//! are counted by walking the inputs before the backup starts, so the time
+//! left is known from the first file. For GUIs and scripts, events of the
+//! backup can be printed instead as JSON objects, one per line.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Serializer};
+use walkdir::WalkDir;
+
+use crate::messages::t;
+
+/// The line isn't redrawn more often than this.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgressStyle {
+    /// Line redrawn on standard error.
+    Bar,
+    /// JSON object for every event on standard output.
+    Json,
+}
+
+/// Something that happened during backup. Serialized with its name in
+/// the `event` field.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ScanStarted {
+        #[serde(serialize_with = "display_paths")]
+        inputs: Vec<&'a Path>,
+    },
+    ScanFinished {
+        entries: u64,
+        bytes: u64,
+    },
+    FileCopied {
+        #[serde(serialize_with = "display_path")]
+        path: &'a Path,
+        size: u64,
+    },
+    FileSkipped {
+        #[serde(serialize_with = "display_path")]
+        path: &'a Path,
+        reason: SkipReason,
+    },
+    Error {
+        message: &'a str,
+    },
+    Summary {
+        snapshot: &'a str,
+        indexed: usize,
+        copied: usize,
+        size: u64,
+        /// In seconds.
+        duration: f64,
+        warnings: &'a [String],
+        #[serde(serialize_with = "display_sizes")]
+        not_copied: &'a [(PathBuf, u64)],
+    },
+}
+
+impl Event<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The file is stored in an earlier snapshot.
+    Unchanged,
+    /// The file is larger than the maximum file size.
+    TooLarge,
+}
+
+// Paths that aren't valid UTF-8 are printed lossily, like in the output.
+fn display_path<P: AsRef<Path>, S: Serializer>(path: &P, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&path.as_ref().display())
+}
+
+fn display_paths<S: Serializer>(paths: &[&Path], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| path.display().to_string()))
+}
+
+fn display_sizes<S: Serializer>(
+    files: &[(PathBuf, u64)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct File<'a> {
+        #[serde(serialize_with = "display_path")]
+        path: &'a PathBuf,
+        size: u64,
+    }
+    serializer.collect_seq(files.iter().map(|(path, size)| File { path, size: *size }))
+}
+
+/// Number of entries and sizes of files in bytes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Totals {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl Totals {
+    /// Counts entries of inputs the same way they are walked during backup.
+    /// Entries that can't be read are counted without size.
+    pub fn count<P: AsRef<Path>>(inputs: &[P], max_depth: Option<usize>) -> Self {
+        let mut totals = Self::default();
+        for input in inputs {
+            let mut walk = WalkDir::new(input).follow_links(false);
+            if let Some(depth) = max_depth {
+                walk = walk.max_depth(depth);
+            }
+            for entry in walk.into_iter().flatten() {
+                totals.entries += 1;
+                match entry.metadata() {
+                    Ok(metadata) if metadata.is_file() => totals.bytes += metadata.len(),
+                    _ => (),
+                }
+            }
+        }
+        totals
+    }
+}
+
+pub struct Progress {
+    style: ProgressStyle,
+    total: Totals,
+    done: Totals,
+    /// Bytes stored in the snapshot so far.
+    copied: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+    /// Length of the drawn line, to clear it.
+    width: usize,
+}
+
+impl Progress {
+    /// Counts entries of inputs, reporting that meanwhile.
+    pub fn start<P: AsRef<Path>>(
+        style: ProgressStyle,
+        inputs: &[P],
+        max_depth: Option<usize>,
+    ) -> Self {
+        let mut progress = Self::new(style, Totals::default());
+        match style {
+            ProgressStyle::Bar => progress.draw(&t!("Counting entries...")),
+            ProgressStyle::Json => progress.emit(Event::ScanStarted {
+                inputs: inputs.iter().map(AsRef::as_ref).collect(),
+            }),
+        }
+        progress.total = Totals::count(inputs, max_depth);
+        progress.emit(Event::ScanFinished {
+            entries: progress.total.entries,
+            bytes: progress.total.bytes,
+        });
+        progress.started = Instant::now();
+        progress
+    }
+
+    fn new(style: ProgressStyle, total: Totals) -> Self {
+        Self {
+            style,
+            total,
+            done: Totals::default(),
+            copied: 0,
+            started: Instant::now(),
+            drawn: None,
+            width: 0,
+        }
+    }
+
+    /// Records a processed entry with `size` bytes of data. `copied` is the
+    /// number of bytes stored in the snapshot so far.
+    pub fn advance(&mut self, size: u64, copied: u64) {
+        self.done.entries += 1;
+        self.done.bytes += size;
+        self.copied = copied;
+        if self.style != ProgressStyle::Bar {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .drawn
+            .is_some_and(|drawn| now - drawn < REFRESH_INTERVAL)
+        {
+            return;
+        }
+        self.drawn = Some(now);
+        let line = self.line(now - self.started);
+        self.draw(&line);
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        match self.style {
+            // Warnings are logged, so they mustn't be printed after the line.
+            ProgressStyle::Bar if matches!(event, Event::Error { .. }) => self.clear(),
+            ProgressStyle::Bar => (),
+            ProgressStyle::Json => {
+                let mut stdout = io::stdout().lock();
+                writeln!(stdout, "{}", event.to_json()).ok();
+                stdout.flush().ok();
+            }
+        }
+    }
+
+    /// Removes the line. It's drawn again with the next entry.
+    fn clear(&mut self) {
+        self.draw("");
+        self.drawn = None;
+    }
+
+    fn draw(&mut self, line: &str) {
+        let width = line.chars().count();
+        let padding = self.width.saturating_sub(width);
+        let mut stderr = io::stderr().lock();
+        // The progress is only informative, so failures are ignored.
+        write!(stderr, "\r{}{}\r{}", line, " ".repeat(padding), line).ok();
+        stderr.flush().ok();
+        self.width = width;
+    }
+
+    fn line(&self, elapsed: Duration) -> String {
+        // Sizes of files tell how long the rest takes better than their number.
+        let (done, total) = match self.total.bytes {
+            0 => (self.done.entries, self.total.entries),
+            _ => (self.done.bytes, self.total.bytes),
+        };
+        let fraction = match total {
+            0 => 1.0,
+            _ => (done as f64 / total as f64).min(1.0),
+        };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let seconds = elapsed.as_secs_f64();
+        let throughput = match seconds > 0.0 {
+            true => self.done.bytes as f64 / seconds,
+            false => 0.0,
+        };
+        let left = match (done, seconds >= 1.0) {
+            (0, _) | (_, false) => "--".to_string(),
+            _ => {
+                let remaining = total.saturating_sub(done) as f64 * seconds / done as f64;
+                format_duration(Duration::from_secs_f64(remaining))
+            }
+        };
+        format!(
+            "[{}{}] {:>3}%  {}  {}  {}/s  {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u32,
+            t!("{}/{} entries", self.done.entries, self.total.entries),
+            t!("{} copied", format_size(self.copied)),
+            format_size(throughput as u64),
+            t!("ETA {}", left)
+        )
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.width > 0 {
+            self.clear();
+        }
+    }
+}
+
+/// Size in binary units with one decimal, e.g. "1.5 GiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn count_entries_and_sizes_of_files() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("folder");
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("a.txt"), "hello").unwrap();
+        fs::create_dir(folder.join("nested")).unwrap();
+        fs::write(folder.join("nested").join("b.txt"), "world!").unwrap();
+
+        assert_eq!(
+            Totals::count(&[&folder], None),
+            Totals {
+                entries: 4,
+                bytes: 11
+            }
+        );
+        assert_eq!(
+            Totals::count(&[&folder], Some(1)),
+            Totals {
+                entries: 3,
+                bytes: 5
+            }
+        );
+    }
+
+    #[test]
+    fn line_shows_progress_and_time_left() {
+        let mut progress = Progress::new(
+            ProgressStyle::Bar,
+            Totals {
+                entries: 10,
+                bytes: 4 * 1024 * 1024,
+            },
+        );
+        progress.done = Totals {
+            entries: 4,
+            bytes: 1024 * 1024,
+        };
+        progress.copied = 512 * 1024;
+
+        assert_eq!(
+            progress.line(Duration::from_secs(10)),
+            "[####------------]  25%  4/10 entries  512.0 KiB copied  102.4 KiB/s  ETA 30s"
+        );
+        assert!(progress.line(Duration::ZERO).ends_with("ETA --"));
+    }
+
+    #[test]
+    fn events_as_json() {
+        let path = Path::new("/home/a.txt");
+        assert_eq!(
+            Event::FileCopied { path, size: 5 }.to_json(),
+            r#"{"event":"file_copied","path":"/home/a.txt","size":5}"#
+        );
+        assert_eq!(
+            Event::FileSkipped {
+                path,
+                reason: SkipReason::TooLarge
+            }
+            .to_json(),
+            r#"{"event":"file_skipped","path":"/home/a.txt","reason":"too_large"}"#
+        );
+        assert_eq!(
+            Event::Summary {
+                snapshot: "2021-07-15_18.34",
+                indexed: 3,
+                copied: 1,
+                size: 5,
+                duration: 0.5,
+                warnings: &["Failed to copy".into()],
+                not_copied: &[(path.into(), 7)],
+            }
+            .to_json(),
+            concat!(
+                r#"{"event":"summary","snapshot":"2021-07-15_18.34","indexed":3,"copied":1,"#,
+                r#""size":5,"duration":0.5,"warnings":["Failed to copy"],"#,
+                r#""not_copied":[{"path":"/home/a.txt","size":7}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn format_sizes_and_durations() {
+        assert_eq!(format_size(1000), "1000 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 3600 + 60)),
+            "3h 01m"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Asking for a passphrase on the terminal, without echoing what is typed.
+
+use std::io::{self, BufRead, Write};
+
+/// Writes `prompt` to stderr and reads a line from stdin, which should be a
+/// terminal. The trailing newline isn't part of the passphrase.
+pub fn read_passphrase(prompt: &str) -> io::Result<String> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{}", prompt)?;
+    stderr.flush()?;
+    let mut line = String::new();
+    without_echo(|| io::stdin().lock().read_line(&mut line))?;
+    let passphrase = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(passphrase
+        .strip_suffix('\r')
+        .unwrap_or(passphrase)
+        .to_owned())
+}
+
+/// Runs `read` with echo of the terminal turned off, except for the newline
+/// ending the input.
+#[cfg(unix)]
+fn without_echo<T>(read: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    let fd = libc::STDIN_FILENO;
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = read();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    result
+}
+
+#[cfg(not(unix))]
+fn without_echo<T>(read: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    read()
+}
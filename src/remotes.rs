@@ -0,0 +1,195 @@
+//! Backups registered under a name in the user's config file, e.g. with
+//! `mizeria remote add nas /mnt/nas/backups`, so the name can be given
+//! wherever a backup folder is expected.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml_edit::{table, value, Document};
+
+use crate::profiles::config_path;
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct ConfigFile {
+    remotes: BTreeMap<String, String>,
+    default_backup: Option<String>,
+}
+
+/// Names look like identifiers, so they are never mistaken for paths.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn read_config(config: &Path) -> Result<Option<String>, String> {
+    match fs::read_to_string(config) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!(
+            "Cannot read config file {} ({})",
+            config.display(),
+            e
+        )),
+    }
+}
+
+fn user_config() -> Result<PathBuf, String> {
+    config_path().ok_or_else(|| "Cannot find the config folder of the user".to_string())
+}
+
+/// All registered backups with their locations.
+pub fn list() -> Result<BTreeMap<String, String>, String> {
+    list_in(&user_config()?)
+}
+
+fn list_in(config: &Path) -> Result<BTreeMap<String, String>, String> {
+    Ok(load(config)?.remotes)
+}
+
+fn load(config: &Path) -> Result<ConfigFile, String> {
+    match read_config(config)? {
+        Some(text) => toml::from_str(&text)
+            .map_err(|e| format!("Invalid config file {}: {}", config.display(), e)),
+        None => Ok(ConfigFile::default()),
+    }
+}
+
+/// Backup used when none is given: $MIZERIA_BACKUP, or `default-backup`
+/// in the user's config file. It can be a registered name as well.
+pub fn default_backup() -> Result<Option<String>, String> {
+    match std::env::var("MIZERIA_BACKUP") {
+        Ok(backup) if !backup.is_empty() => Ok(Some(backup)),
+        _ => match config_path() {
+            Some(config) => Ok(load(&config)?.default_backup),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Location of the backup given on the command line. A registered name
+/// is replaced with its location, unless a folder of that name exists.
+pub fn resolve(backup: &str) -> Result<PathBuf, String> {
+    if !is_valid_name(backup) || Path::new(backup).exists() {
+        return Ok(PathBuf::from(backup));
+    }
+    match config_path() {
+        Some(config) => resolve_in(&config, backup),
+        None => Ok(PathBuf::from(backup)),
+    }
+}
+
+fn resolve_in(config: &Path, backup: &str) -> Result<PathBuf, String> {
+    let location = list_in(config)?.remove(backup);
+    Ok(PathBuf::from(location.as_deref().unwrap_or(backup)))
+}
+
+/// Registers a backup at `location` under `name`. Other content of the
+/// config file, including comments, is kept as it is.
+pub fn add(name: &str, location: &Path) -> Result<(), String> {
+    add_in(&user_config()?, name, location)
+}
+
+fn add_in(config: &Path, name: &str, location: &Path) -> Result<(), String> {
+    if !is_valid_name(name) {
+        return Err(format!(
+            "Invalid name '{}', use only letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    if let Some(existing) = list_in(config)?.get(name) {
+        return Err(format!(
+            "Backup '{}' is already registered at {}",
+            name, existing
+        ));
+    }
+    let mut document = parse_document(config)?;
+    document["remotes"].or_insert(table())[name] = value(location.to_string_lossy().as_ref());
+    write_document(config, &document)
+}
+
+/// Forgets the backup registered under `name`. The backup itself is kept.
+pub fn remove(name: &str) -> Result<(), String> {
+    remove_in(&user_config()?, name)
+}
+
+fn remove_in(config: &Path, name: &str) -> Result<(), String> {
+    let mut document = parse_document(config)?;
+    let removed = document
+        .get_mut("remotes")
+        .and_then(|remotes| remotes.as_table_like_mut())
+        .and_then(|remotes| remotes.remove(name));
+    if removed.is_none() {
+        return Err(format!("No backup is registered as '{}'", name));
+    }
+    write_document(config, &document)
+}
+
+fn parse_document(config: &Path) -> Result<Document, String> {
+    read_config(config)?
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| format!("Invalid config file {}: {}", config.display(), e))
+}
+
+fn write_document(config: &Path, document: &Document) -> Result<(), String> {
+    let written = match config.parent() {
+        Some(folder) => fs::create_dir_all(folder),
+        None => Ok(()),
+    };
+    written
+        .and_then(|_| fs::write(config, document.to_string()))
+        .map_err(|e| format!("Cannot write config file {} ({})", config.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_and_resolve_backups() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("mizeria").join("config.toml");
+
+        assert_eq!(resolve_in(&config, "nas").unwrap(), PathBuf::from("nas"));
+        add_in(&config, "nas", Path::new("/mnt/nas/backups")).unwrap();
+        add_in(&config, "usb", Path::new("/mnt/usb")).unwrap();
+        assert_eq!(
+            resolve_in(&config, "nas").unwrap(),
+            PathBuf::from("/mnt/nas/backups")
+        );
+        assert!(add_in(&config, "nas", Path::new("/mnt/other")).is_err());
+        assert!(add_in(&config, "../nas", Path::new("/mnt/other")).is_err());
+
+        remove_in(&config, "nas").unwrap();
+        assert!(remove_in(&config, "nas").is_err());
+        let remotes = list_in(&config).unwrap();
+        assert_eq!(remotes.keys().collect::<Vec<_>>(), vec!["usb"]);
+    }
+
+    #[test]
+    fn other_content_of_config_is_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config.toml");
+        let profiles = "default-backup = \"nas\"\n\n\
+                        # Photos from the phone\n\
+                        [profiles.photos]\n\
+                        backup = \"nas\"\n";
+        fs::write(&config, profiles).unwrap();
+
+        add_in(&config, "nas", Path::new("/mnt/nas/backups")).unwrap();
+        let text = fs::read_to_string(&config).unwrap();
+        assert!(text.starts_with(profiles), "{}", text);
+        assert!(
+            text.contains("[remotes]\nnas = \"/mnt/nas/backups\""),
+            "{}",
+            text
+        );
+        assert_eq!(load(&config).unwrap().default_backup.unwrap(), "nas");
+    }
+}
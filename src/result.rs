@@ -1,7 +1,109 @@
-use std::{fmt::Display, path::PathBuf};
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::messages::t;
 
 pub type IntegrityCheckResult = std::result::Result<(), IntegrityCheckError>;
 
+/// Summary of a single backup run.
+pub struct BackupReport {
+    /// Name of the created snapshot.
+    pub snapshot: String,
+    /// Problems that didn't stop the backup, e.g. skipped files.
+    pub warnings: Vec<String>,
+    /// Files larger than the size limit that were not copied, with sizes.
+    pub not_copied: Vec<(PathBuf, u64)>,
+    /// Entries that couldn't be backed up, with the reason. Their messages
+    /// are among the warnings too.
+    pub failures: Vec<(PathBuf, String)>,
+    /// The user stopped the backup, so the snapshot has only files copied
+    /// until then.
+    pub interrupted: bool,
+    /// Number of copied files read back and found matching their sources,
+    /// if the backup was verified.
+    pub verified: Option<usize>,
+    /// Copies that don't match their sources, with the reason.
+    pub mismatched: Vec<(PathBuf, String)>,
+    pub stats: BackupStats,
+}
+
+/// Numbers of a backup run, printed when it finishes.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct BackupStats {
+    /// Entries found in inputs and indexed.
+    pub scanned: usize,
+    /// Entries copied into the new snapshot.
+    pub copied: usize,
+    /// Entries unchanged since the base snapshot, stored in earlier ones.
+    pub reused: usize,
+    /// Bytes stored in the new snapshot, after compression.
+    pub written: u64,
+    /// Entries that couldn't be backed up.
+    pub errors: usize,
+    pub duration: Duration,
+}
+
+impl BackupStats {
+    /// Bytes written per second.
+    pub fn throughput(&self) -> u64 {
+        (self.written as f64 / self.duration.as_secs_f64().max(0.001)) as u64
+    }
+}
+
+/// Error of a run stopped by the user, e.g. with Ctrl-C. The program exits
+/// with a distinct code then.
+#[derive(Debug)]
+pub struct Interrupted(pub String);
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Error of a check that found damaged, altered or missing data. The
+/// program exits with a distinct code then.
+#[derive(Debug)]
+pub struct IntegrityFailed(pub String);
+
+impl Display for IntegrityFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for IntegrityFailed {}
+
+/// How a command that didn't fail ended.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Completion {
+    Success,
+    /// The command did its job, but with warnings, e.g. some files
+    /// couldn't be backed up.
+    Warnings,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
 pub enum IntegrityCheckError {
     SnapshotDoesntExist,
     SnapshotNameHasInvalidTimestamp(String),
@@ -11,34 +113,170 @@ pub enum IntegrityCheckError {
     IndexFileContainsInvalidPathInLine(usize),
     EntryIndexedButNotExists(PathBuf),
     EntryExistsButNotIndexed(PathBuf),
+    DirectoryExistsButNotIndexed(PathBuf),
+    /// Severity depends on the policy chosen by the user.
+    InvalidSignature(PathBuf, Severity),
+    ChecksumMismatch(PathBuf),
+    SignatureNotVerified,
+    ChainedSnapshotMissing(String),
+    ChainedSnapshotAltered(String),
+    /// File stored in the snapshot doesn't match its checksum in the index.
+    ContentMismatch(PathBuf),
+    /// Number of stored files without a checksum in the index.
+    ContentNotRecorded(usize),
+    /// Number of parity blocks that don't match their checksums.
+    ParityDamaged(usize),
     UnexpectedError(String),
 }
 
 impl IntegrityCheckError {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::DirectoryExistsButNotIndexed(_) => Severity::Warning,
+            Self::InvalidSignature(_, severity) => *severity,
+            Self::SignatureNotVerified => Severity::Warning,
+            Self::ContentNotRecorded(_) => Severity::Warning,
+            Self::ParityDamaged(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Stable identifier of the finding. Codes are never reused or changed,
+    /// so scripts can match on them instead of on the message wording.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnexpectedError(_) => "MZ0000",
+            Self::SnapshotDoesntExist => "MZ0001",
+            Self::SnapshotNameHasInvalidTimestamp(_) => "MZ0002",
+            Self::IndexFileDoesntExist => "MZ0003",
+            Self::FilesFolderDoesntExist => "MZ0004",
+            Self::IndexFileContainsInvalidTimestampInLine(_) => "MZ0005",
+            Self::IndexFileContainsInvalidPathInLine(_) => "MZ0006",
+            Self::EntryIndexedButNotExists(_) => "MZ0007",
+            Self::EntryExistsButNotIndexed(_) => "MZ0008",
+            Self::DirectoryExistsButNotIndexed(_) => "MZ0009",
+            Self::InvalidSignature(_, _) => "MZ0010",
+            Self::ChecksumMismatch(_) => "MZ0011",
+            Self::SignatureNotVerified => "MZ0012",
+            Self::ChainedSnapshotMissing(_) => "MZ0013",
+            Self::ChainedSnapshotAltered(_) => "MZ0014",
+            Self::ContentMismatch(_) => "MZ0015",
+            Self::ContentNotRecorded(_) => "MZ0016",
+            Self::ParityDamaged(_) => "MZ0017",
+        }
+    }
+
+    /// Name of the kind of finding used in JSON output. Like codes, names
+    /// are never changed.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnexpectedError(_) => "unexpected_error",
+            Self::SnapshotDoesntExist => "snapshot_doesnt_exist",
+            Self::SnapshotNameHasInvalidTimestamp(_) => "invalid_snapshot_name",
+            Self::IndexFileDoesntExist => "index_missing",
+            Self::FilesFolderDoesntExist => "files_missing",
+            Self::IndexFileContainsInvalidTimestampInLine(_) => "invalid_timestamp_in_index",
+            Self::IndexFileContainsInvalidPathInLine(_) => "invalid_path_in_index",
+            Self::EntryIndexedButNotExists(_) => "entry_missing",
+            Self::EntryExistsButNotIndexed(_) => "entry_not_indexed",
+            Self::DirectoryExistsButNotIndexed(_) => "directory_not_indexed",
+            Self::InvalidSignature(_, _) => "invalid_signature",
+            Self::ChecksumMismatch(_) => "checksum_mismatch",
+            Self::SignatureNotVerified => "signature_not_verified",
+            Self::ChainedSnapshotMissing(_) => "chained_snapshot_missing",
+            Self::ChainedSnapshotAltered(_) => "chained_snapshot_altered",
+            Self::ContentMismatch(_) => "content_mismatch",
+            Self::ContentNotRecorded(_) => "content_not_recorded",
+            Self::ParityDamaged(_) => "parity_damaged",
+        }
+    }
+
+    fn path(&self) -> Option<String> {
+        match self {
+            Self::EntryIndexedButNotExists(path)
+            | Self::EntryExistsButNotIndexed(path)
+            | Self::DirectoryExistsButNotIndexed(path)
+            | Self::InvalidSignature(path, _)
+            | Self::ChecksumMismatch(path)
+            | Self::ContentMismatch(path) => Some(path.to_string_lossy().into()),
+            _ => None,
+        }
+    }
+
+    fn line(&self) -> Option<usize> {
+        match self {
+            Self::IndexFileContainsInvalidTimestampInLine(line)
+            | Self::IndexFileContainsInvalidPathInLine(line) => Some(*line),
+            _ => None,
+        }
+    }
+
+    fn snapshot(&self) -> Option<&str> {
+        match self {
+            Self::SnapshotNameHasInvalidTimestamp(name)
+            | Self::ChainedSnapshotMissing(name)
+            | Self::ChainedSnapshotAltered(name) => Some(name),
+            _ => None,
+        }
+    }
+
     pub fn get_message(&self) -> String {
         match self {
-            Self::SnapshotDoesntExist => "Snapshot doesn't exist.".into(),
+            Self::SnapshotDoesntExist => t!("Snapshot doesn't exist."),
             Self::SnapshotNameHasInvalidTimestamp(name) => {
-                format!("Snapshot's name '{}' is not a correct timestamp.", name)
+                t!("Snapshot's name '{}' is not a correct timestamp.", name)
             }
-            Self::IndexFileDoesntExist => "Files index.txt is missing.".into(),
-            Self::FilesFolderDoesntExist => "Folder files is missing.".into(),
+            Self::IndexFileDoesntExist => t!("Files index.txt is missing."),
+            Self::FilesFolderDoesntExist => t!("Folder files is missing."),
             IntegrityCheckError::IndexFileContainsInvalidTimestampInLine(line) => {
-                format!("Invalid timestamp in line {} of index.txt.", line)
+                t!("Invalid timestamp in line {} of index.txt.", line)
             }
             IntegrityCheckError::IndexFileContainsInvalidPathInLine(line) => {
-                format!("Invalid path in line {} of index.txt.", line)
+                t!("Invalid path in line {} of index.txt.", line)
             }
-            IntegrityCheckError::EntryIndexedButNotExists(path) => format!(
+            IntegrityCheckError::EntryIndexedButNotExists(path) => t!(
                 "Entry '{}' is indexed, but is missing in snapshot.",
                 path.display()
             ),
-            IntegrityCheckError::EntryExistsButNotIndexed(path) => format!(
+            IntegrityCheckError::EntryExistsButNotIndexed(path) => t!(
                 "Entry '{}' is present in snapshot, but is not indexed.",
                 path.display()
             ),
+            IntegrityCheckError::DirectoryExistsButNotIndexed(path) => t!(
+                "Directory '{}' is present in snapshot, but is not indexed.",
+                path.display()
+            ),
+            IntegrityCheckError::InvalidSignature(path, _) => {
+                t!("Signature of '{}' is missing or invalid.", path.display())
+            }
+            IntegrityCheckError::ChecksumMismatch(path) => t!(
+                "Entry '{}' doesn't match its signed checksum.",
+                path.display()
+            ),
+            IntegrityCheckError::SignatureNotVerified => {
+                t!("Snapshot is signed, but no key was given to verify it.")
+            }
+            IntegrityCheckError::ChainedSnapshotMissing(name) => t!(
+                "Snapshot '{}' is linked in the hash chain, but is missing.",
+                name
+            ),
+            IntegrityCheckError::ChainedSnapshotAltered(name) => t!(
+                "Snapshot '{}' was altered after the next snapshot was made.",
+                name
+            ),
+            IntegrityCheckError::ContentMismatch(path) => t!(
+                "Entry '{}' doesn't match its checksum in index.txt.",
+                path.display()
+            ),
+            IntegrityCheckError::ContentNotRecorded(count) => t!(
+                "{} file(s) have no checksum in index.txt and weren't verified.",
+                count
+            ),
+            IntegrityCheckError::ParityDamaged(count) => {
+                t!("{} parity block(s) are damaged.", count)
+            }
             IntegrityCheckError::UnexpectedError(message) => {
-                format!("Unexpected error occured: {}", message)
+                t!("Unexpected error occured: {}", message)
             }
         }
     }
@@ -49,3 +287,133 @@ impl Display for IntegrityCheckError {
         write!(f, "{}", self.get_message())
     }
 }
+
+/// Every finding has the same fields, so the schema of JSON output stays
+/// stable. Fields that don't apply to a finding are `null`.
+impl Serialize for IntegrityCheckError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut finding = serializer.serialize_struct("IntegrityCheckError", 7)?;
+        finding.serialize_field("kind", self.kind())?;
+        finding.serialize_field("code", self.code())?;
+        finding.serialize_field("severity", &self.severity())?;
+        finding.serialize_field("message", &self.get_message())?;
+        finding.serialize_field("path", &self.path())?;
+        finding.serialize_field("line", &self.line())?;
+        finding.serialize_field("snapshot", &self.snapshot())?;
+        finding.end()
+    }
+}
+
+/// Findings collected during an integrity check. Only findings with
+/// `Severity::Error` fail the check, unless strict mode escalates warnings.
+#[derive(Default)]
+pub struct IntegrityReport {
+    findings: Vec<IntegrityCheckError>,
+}
+
+impl IntegrityReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, finding: IntegrityCheckError) {
+        self.findings.push(finding);
+    }
+
+    pub fn findings(&self) -> &[IntegrityCheckError] {
+        &self.findings
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity() == severity)
+            .count()
+    }
+
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings
+            .iter()
+            .map(IntegrityCheckError::severity)
+            .max()
+    }
+
+    pub fn passed(&self, strict: bool) -> bool {
+        let threshold = if strict {
+            Severity::Warning
+        } else {
+            Severity::Error
+        };
+        self.findings.iter().all(|f| f.severity() < threshold)
+    }
+}
+
+impl From<IntegrityCheckResult> for IntegrityReport {
+    fn from(result: IntegrityCheckResult) -> Self {
+        let mut report = Self::new();
+        if let Err(error) = result {
+            report.push(error);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_codes_are_unique() {
+        let errors = [
+            IntegrityCheckError::UnexpectedError(String::new()),
+            IntegrityCheckError::SnapshotDoesntExist,
+            IntegrityCheckError::SnapshotNameHasInvalidTimestamp(String::new()),
+            IntegrityCheckError::IndexFileDoesntExist,
+            IntegrityCheckError::FilesFolderDoesntExist,
+            IntegrityCheckError::IndexFileContainsInvalidTimestampInLine(1),
+            IntegrityCheckError::IndexFileContainsInvalidPathInLine(1),
+            IntegrityCheckError::EntryIndexedButNotExists(PathBuf::new()),
+            IntegrityCheckError::EntryExistsButNotIndexed(PathBuf::new()),
+            IntegrityCheckError::DirectoryExistsButNotIndexed(PathBuf::new()),
+            IntegrityCheckError::InvalidSignature(PathBuf::new(), Severity::Error),
+            IntegrityCheckError::ChecksumMismatch(PathBuf::new()),
+            IntegrityCheckError::SignatureNotVerified,
+            IntegrityCheckError::ChainedSnapshotMissing(String::new()),
+            IntegrityCheckError::ChainedSnapshotAltered(String::new()),
+            IntegrityCheckError::ContentMismatch(PathBuf::new()),
+            IntegrityCheckError::ContentNotRecorded(1),
+            IntegrityCheckError::ParityDamaged(1),
+        ];
+        let mut codes: Vec<&str> = errors.iter().map(IntegrityCheckError::code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+
+        assert_eq!(codes.len(), errors.len());
+        let mut kinds: Vec<&str> = errors.iter().map(IntegrityCheckError::kind).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        assert_eq!(kinds.len(), errors.len());
+    }
+
+    #[test]
+    fn serialize_finding_to_json() {
+        let error = IntegrityCheckError::EntryIndexedButNotExists(PathBuf::from("/home/notes.txt"));
+
+        let json = serde_json::to_string(&error).unwrap();
+
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"kind":"entry_missing","code":"MZ0007","severity":"error","#,
+                r#""message":"Entry '/home/notes.txt' is indexed, but is missing in snapshot.","#,
+                r#""path":"/home/notes.txt","line":null,"snapshot":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn diagnostic_code_is_stable() {
+        let error = IntegrityCheckError::EntryIndexedButNotExists(PathBuf::new());
+        assert_eq!(error.code(), "MZ0007");
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::debug;
+use walkdir::WalkDir;
+
+use crate::backup::{Backup, BackupConfig, EntryKind};
+use crate::environment::{Clock, Environment};
+use crate::messages::t;
+
+/// Staging area created in the backup folder, so the test exercises the
+/// same destination as real backups.
+pub const SELFTEST_FOLDER_NAME: &str = ".mizeria-selftest";
+
+/// Snapshots are made with a clock shifted into the future, as if the
+/// dataset was created a while before. Otherwise it would be too new to be
+/// treated as unchanged by the incremental snapshot.
+const FULL_SNAPSHOT_SHIFT: Duration = Duration::from_secs(2 * 60);
+const CHANGE_SHIFT: Duration = Duration::from_secs(3 * 60);
+const INCREMENTAL_SNAPSHOT_SHIFT: Duration = Duration::from_secs(4 * 60);
+
+type StepResult = std::result::Result<String, String>;
+type StepFn = fn(&mut SelfTest) -> StepResult;
+
+pub struct Step {
+    pub description: String,
+    pub result: StepResult,
+}
+
+/// Backs up a small synthetic dataset into a staging area in `backup`,
+/// makes an incremental snapshot of it, checks integrity of both snapshots
+/// and restores the latest one. Steps are run until the first one fails.
+/// The staging area is removed unless `keep` is set.
+pub fn run(backup: &Path, environment: &Environment, keep: bool) -> Vec<Step> {
+    let staging = backup.join(SELFTEST_FOLDER_NAME);
+    let mut test = SelfTest {
+        input: staging.join("input"),
+        backup: staging.join("backup"),
+        restored: staging.join("restored"),
+        environment: environment.clone(),
+        snapshots: vec![],
+    };
+
+    let plan: [(String, StepFn); 6] = [
+        (t!("Create synthetic dataset"), SelfTest::create_dataset),
+        (t!("Create full snapshot"), SelfTest::create_full_snapshot),
+        (
+            t!("Create incremental snapshot"),
+            SelfTest::create_incremental_snapshot,
+        ),
+        (
+            t!("Check integrity of snapshots"),
+            SelfTest::check_integrity,
+        ),
+        (t!("Restore latest snapshot"), SelfTest::restore),
+        (t!("Compare restored files"), SelfTest::compare),
+    ];
+    let mut steps = vec![];
+    remove_staging(&staging);
+    for (description, step) in plan {
+        let result = step(&mut test);
+        let failed = result.is_err();
+        steps.push(Step {
+            description,
+            result,
+        });
+        if failed {
+            break;
+        }
+    }
+
+    if keep {
+        debug!("Staging area kept in: {}", staging.display());
+    } else {
+        remove_staging(&staging);
+    }
+    steps
+}
+
+fn remove_staging(staging: &Path) {
+    if staging.exists() {
+        debug!("Removing staging area: {}", staging.display());
+        if let Err(e) = fs::remove_dir_all(staging) {
+            debug!("Cannot remove staging area ({})", e);
+        }
+    }
+}
+
+struct SelfTest {
+    input: PathBuf,
+    backup: PathBuf,
+    restored: PathBuf,
+    environment: Environment,
+    snapshots: Vec<String>,
+}
+
+impl SelfTest {
+    fn create_dataset(&mut self) -> StepResult {
+        let folder = self.input.join("folder").join("nested folder");
+        fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&self.backup).map_err(|e| e.to_string())?;
+
+        let files: [(PathBuf, Vec<u8>); 5] = [
+            (self.input.join("text.txt"), b"mizeria self-test\n".to_vec()),
+            (self.input.join("empty.bin"), vec![]),
+            (self.input.join("changed.txt"), b"before".to_vec()),
+            (folder.join("zażółć gęślą jaźń.txt"), "ünïcödé\n".into()),
+            (
+                folder.join("random.bin"),
+                pseudo_random_bytes(1024 * 1024 + 7),
+            ),
+        ];
+        for (path, content) in &files {
+            fs::write(path, content)
+                .map_err(|e| format!("Cannot create '{}' ({})", path.display(), e))?;
+        }
+
+        self.input = self.input.canonicalize().map_err(|e| e.to_string())?;
+        Ok(t!("{} files", files.len()))
+    }
+
+    fn create_full_snapshot(&mut self) -> StepResult {
+        self.create_snapshot(FULL_SNAPSHOT_SHIFT)
+    }
+
+    /// One file is changed and one is added. The rest must be found
+    /// in the full snapshot.
+    fn create_incremental_snapshot(&mut self) -> StepResult {
+        let changed = self.input.join("changed.txt");
+        let modified = SystemTime::now() + CHANGE_SHIFT;
+        fs::write(&changed, b"after")
+            .and_then(|_| File::options().write(true).open(&changed))
+            .and_then(|file| file.set_modified(modified))
+            .map_err(|e| e.to_string())?;
+        fs::write(self.input.join("added.txt"), b"added").map_err(|e| e.to_string())?;
+        self.create_snapshot(INCREMENTAL_SNAPSHOT_SHIFT)?;
+
+        let backup = Backup::open(&self.backup).map_err(|e| e.to_string())?;
+        let (_, unchanged) = backup
+            .locate(&self.input.join("text.txt"), None)
+            .map_err(|e| e.to_string())?;
+        if unchanged.stored_in != self.snapshots[0] {
+            return Err(t!(
+                "Unchanged file was stored again in snapshot {}",
+                unchanged.stored_in
+            ));
+        }
+        Ok(self.snapshots[1].clone())
+    }
+
+    fn create_snapshot(&mut self, shift: Duration) -> StepResult {
+        let environment = Environment {
+            clock: Arc::new(ShiftedClock {
+                clock: self.environment.clock.clone(),
+                shift,
+            }),
+            fs: self.environment.fs.clone(),
+            interrupted: self.environment.interrupted.clone(),
+        };
+        let mut backup =
+            Backup::open_with_environment(&self.backup, environment).map_err(|e| e.to_string())?;
+        let report = backup
+            .add_snapshot(std::slice::from_ref(&self.input), &BackupConfig::default())
+            .map_err(|e| e.to_string())?;
+        self.snapshots.push(report.snapshot.clone());
+        match report.warnings.first() {
+            Some(warning) => Ok(t!(
+                "{} with {} warning(s), e.g. {}",
+                report.snapshot,
+                report.warnings.len(),
+                warning
+            )),
+            None => Ok(report.snapshot),
+        }
+    }
+
+    fn check_integrity(&mut self) -> StepResult {
+        let backup = Backup::open(&self.backup).map_err(|e| e.to_string())?;
+        for snapshot in &self.snapshots {
+            let report = backup.check_integrity(snapshot.as_ref(), None, false);
+            if !report.passed(false) {
+                let finding = &report.findings()[0];
+                return Err(format!("{}: {}", snapshot, finding));
+            }
+        }
+        Ok(t!("{} snapshot(s)", self.snapshots.len()))
+    }
+
+    /// Every entry of the dataset is copied from where the latest snapshot
+    /// says its data is stored.
+    fn restore(&mut self) -> StepResult {
+        let backup = Backup::open(&self.backup).map_err(|e| e.to_string())?;
+        let mut restored = 0;
+        for entry in WalkDir::new(&self.input).sort_by_file_name() {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let (_, info) = backup
+                .locate(entry.path(), None)
+                .map_err(|e| e.to_string())?;
+            let target = self.restored_path(entry.path());
+            let result = match info.kind {
+                EntryKind::Directory => fs::create_dir_all(&target),
+                EntryKind::File => info
+                    .open_data()
+                    .and_then(|mut data| io::copy(&mut data, &mut File::create(&target)?))
+                    .map(|_| ()),
+                EntryKind::Symlink | EntryKind::Special | EntryKind::Missing => {
+                    return Err(format!(
+                        "Data of '{}' in snapshot {} is missing or isn't a file",
+                        entry.path().display(),
+                        info.stored_in
+                    ))
+                }
+            };
+            result.map_err(|e| format!("Cannot restore '{}' ({})", entry.path().display(), e))?;
+            restored += 1;
+        }
+        Ok(t!("{} entries", restored))
+    }
+
+    fn compare(&mut self) -> StepResult {
+        let mut compared = 0;
+        for entry in WalkDir::new(&self.input) {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let restored = self.restored_path(entry.path());
+            let equal = if entry.file_type().is_file() {
+                fs::read(entry.path()).ok() == fs::read(&restored).ok()
+            } else {
+                restored.is_dir()
+            };
+            if !equal {
+                return Err(format!(
+                    "Restored '{}' differs from the original",
+                    entry.path().display()
+                ));
+            }
+            compared += 1;
+        }
+        Ok(t!("{} entries are identical", compared))
+    }
+
+    fn restored_path(&self, original: &Path) -> PathBuf {
+        let relative = original.strip_prefix(&self.input).unwrap();
+        self.restored.join(relative)
+    }
+}
+
+struct ShiftedClock {
+    clock: Arc<dyn Clock>,
+    shift: Duration,
+}
+
+impl Clock for ShiftedClock {
+    fn now(&self) -> time::OffsetDateTime {
+        self.clock.now() + self.shift
+    }
+}
+
+/// Content that doesn't compress, so the copy is byte for byte.
+fn pseudo_random_bytes(length: usize) -> Vec<u8> {
+    let mut state: u32 = 0x9e37_79b9;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
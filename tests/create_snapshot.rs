@@ -7,12 +7,18 @@ use walkdir::WalkDir;
 mod utils;
 pub use crate::utils::*;
 
+/// Size and SHA-256 checksum of "hello world" as written in the index.
+const HELLO_WORLD_CONTENT: &str =
+    "11:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
 fn init_logger() {
-    let mut builder = env_logger::Builder::new();
-    // builder.filter_level(log::LevelFilter::Trace); // uncomment to see all logs
-    builder.format_timestamp(None);
-    builder.format_module_path(false);
-    builder.target(env_logger::Target::Stdout).try_init().ok();
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::ERROR) // change to TRACE to see all logs
+        .without_time()
+        .with_target(false)
+        .with_test_writer()
+        .try_init()
+        .ok();
 }
 
 fn create_snapshot(backup: &Path, files: &[&Path]) {
@@ -20,6 +26,10 @@ fn create_snapshot(backup: &Path, files: &[&Path]) {
 }
 
 fn create_snapshot_with_args(backup: &Path, files: &[&Path], args: &[&str]) {
+    create_snapshot_with_output(backup, files, args);
+}
+
+fn create_snapshot_with_output(backup: &Path, files: &[&Path], args: &[&str]) -> String {
     let mut program_args = vec![String::from("backup"), backup.to_string_lossy().to_string()];
 
     for arg in args {
@@ -30,11 +40,13 @@ fn create_snapshot_with_args(backup: &Path, files: &[&Path], args: &[&str]) {
         program_args.push(file.to_string_lossy().to_string());
     }
     init_logger();
-    mizeria::run_program(program_args, &mut std::io::sink()).expect("program failed");
+    let mut output = Vec::new();
+    mizeria::run_program(program_args, &mut output).expect("program failed");
+    String::from_utf8(output).expect("Invalid UTF-8")
 }
 
 fn get_entry_from(folder: &Path) -> PathBuf {
-    folder.read_dir().unwrap().next().unwrap().unwrap().path()
+    backup_entries(folder).remove(0)
 }
 
 #[test]
@@ -44,11 +56,11 @@ fn create_snapshot_with_empty_folder() {
     let files = files.path().join("dummy_dir");
     fs::create_dir(&files).unwrap();
 
-    create_snapshot(backup.path(), &[files.as_path()]);
+    create_snapshot_with_args(backup.path(), &[files.as_path()], &["--no-preserve"]);
 
     // backup should have one entry (snapshot)
     assert_eq!(
-        backup.path().read_dir().unwrap().count(),
+        backup_entries(backup.path()).len(),
         1,
         "backup folder should have only one entry (the snapshot)"
     );
@@ -85,7 +97,7 @@ fn create_snapshot_with_one_file() {
 
     // run program
     let snapshot_name = utils::generate_snapshot_name();
-    create_snapshot(backup.path(), &[files.path()]);
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--no-preserve"]);
 
     // snapshot
     let snapshot = get_entry_from(backup.path());
@@ -102,7 +114,7 @@ fn create_snapshot_with_one_file() {
     assert_eq!(
         snapshot_index_content,
         format!(
-            "{snap} {}\n{snap} {}\n",
+            "{snap} {}\n{snap} {HELLO_WORLD_CONTENT} {}\n",
             files.path().canonicalize().unwrap().display(),
             dummy_file.canonicalize().unwrap().display(),
             snap = snapshot_name,
@@ -119,14 +131,13 @@ fn create_three_snapshots_one_after_another() {
     create_snapshot(backup.path(), &[files.path()]);
     create_snapshot(backup.path(), &[files.path()]);
 
-    let backup = backup.path().read_dir().unwrap();
-    let snapshots: Vec<fs::DirEntry> = backup.filter_map(Result::ok).collect();
+    let snapshots = backup_entries(backup.path());
 
     assert_eq!(snapshots.len(), 3);
 
-    utils::assert_snapshot_exists(snapshots[0].path().borrow());
-    utils::assert_snapshot_exists(snapshots[1].path().borrow());
-    utils::assert_snapshot_exists(snapshots[2].path().borrow());
+    utils::assert_snapshot_exists(snapshots[0].borrow());
+    utils::assert_snapshot_exists(snapshots[1].borrow());
+    utils::assert_snapshot_exists(snapshots[2].borrow());
 }
 
 #[test]
@@ -140,7 +151,11 @@ fn create_snapshot_from_two_paths() {
         .write_all(b"hello world")
         .unwrap();
 
-    create_snapshot(backup.path(), &[path_1.path(), path_2.path()]);
+    create_snapshot_with_args(
+        backup.path(),
+        &[path_1.path(), path_2.path()],
+        &["--no-preserve"],
+    );
 
     let snapshot = get_entry_from(backup.path());
     let snapshot = StubSnapshot::open(snapshot.as_path());
@@ -166,7 +181,7 @@ fn create_snapshot_from_two_paths() {
     );
 
     let expected_index_content = format!(
-        "{timestamp} {}\n{timestamp} {}\n{timestamp} {}\n",
+        "{timestamp} {}\n{timestamp} {}\n{timestamp} {HELLO_WORLD_CONTENT} {}\n",
         path_1.path().canonicalize().unwrap().display(),
         path_2.path().canonicalize().unwrap().display(),
         path_2_file.as_path().canonicalize().unwrap().display(),
@@ -408,7 +423,7 @@ fn create_snapshot_with_symlinks() {
     // create symlinks
     #[cfg(unix)]
     {
-        std::os::unix::fs::symlink(&target_dir, &dir_link).unwrap();
+        std::os::unix::fs::symlink(target_dir, &dir_link).unwrap();
         std::os::unix::fs::symlink(&target_file, &file_link).unwrap();
     }
     #[cfg(windows)]
@@ -439,7 +454,7 @@ fn create_snapshot_with_symlinks() {
                 return Some(entry);
             }
         }
-        return None;
+        None
     }
 
     // links were successfully copied into 'files'
@@ -513,3 +528,870 @@ fn create_snapshot_from_duplicated_and_nonexistent_paths() {
 
     assert!(snapshot.find_file("file.txt").is_some());
 }
+
+#[test]
+fn create_snapshot_reports_number_of_warnings() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let path = tempfile::tempdir().unwrap();
+    let path = path.path();
+    let nonexistent_path = path.join("foobar");
+
+    let output = create_snapshot_with_output(backup, &[path, nonexistent_path.as_path()], &[]);
+    assert!(
+        output.contains("Snapshot created with 1 warning(s)"),
+        "Warning banner not found in: '{}'",
+        output
+    );
+
+    let output = create_snapshot_with_output(backup, &[path], &[]);
+    assert!(
+        !output.contains("warning(s)"),
+        "Unexpected warning banner in: '{}'",
+        output
+    );
+}
+
+#[test]
+fn create_snapshot_with_warnings_has_its_own_exit_code() {
+    let backup = tempfile::tempdir().unwrap();
+    let path = tempfile::tempdir().unwrap();
+    let backup_with = |inputs: &[&Path]| {
+        let mut args = vec![String::from("backup"), backup.path().display().to_string()];
+        args.extend(inputs.iter().map(|input| input.display().to_string()));
+        mizeria::run_program(args, &mut std::io::sink())
+    };
+
+    let result = backup_with(&[path.path(), &path.path().join("foobar")]);
+    assert_eq!(result.unwrap(), mizeria::result::Completion::Warnings);
+    assert_eq!(mizeria::exit_code(&backup_with(&[path.path()])), 0);
+    let args = ["backup", "nonexistent", &path.path().display().to_string()].map(String::from);
+    let result = mizeria::run_program(args, &mut std::io::sink());
+    assert_eq!(mizeria::exit_code(&result), mizeria::FATAL_EXIT_CODE);
+}
+
+#[test]
+fn create_snapshot_quietly() {
+    let backup = tempfile::tempdir().unwrap();
+    let path = tempfile::tempdir().unwrap();
+    let nonexistent_path = path.path().join("foobar");
+
+    let output = create_snapshot_with_output(
+        backup.path(),
+        &[path.path(), nonexistent_path.as_path()],
+        &["--quiet"],
+    );
+
+    assert_eq!(output, "");
+    assert!(get_entry_from(backup.path()).join("index.txt").exists());
+}
+
+#[test]
+fn create_snapshot_in_strict_mode_fails_on_warning() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let path = tempfile::tempdir().unwrap();
+    let path = path.path();
+    let nonexistent_path = path.join("foobar");
+
+    let args = vec![
+        String::from("backup"),
+        backup.to_string_lossy().to_string(),
+        String::from("--strict"),
+        path.to_string_lossy().to_string(),
+        nonexistent_path.to_string_lossy().to_string(),
+    ];
+    let mut output = Vec::new();
+    let result = mizeria::run_program(args, &mut output);
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(result.is_err());
+    assert!(
+        output.contains(&format!("{}", nonexistent_path.display())),
+        "Skipped path not listed in: '{}'",
+        output
+    );
+    // snapshot is still completed
+    let snapshot = get_entry_from(backup);
+    let snapshot = StubSnapshot::open(snapshot.as_path());
+    assert!(snapshot.index_contains(snapshot.timestamp.as_str(), path));
+}
+
+#[test]
+fn append_only_backup_detects_modified_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let input = tempfile::tempdir().unwrap();
+    let input = input.path();
+    fs::write(input.join("file.txt"), b"original").unwrap();
+
+    create_snapshot_with_args(backup, &[input], &["--append-only"]);
+    assert!(backup.join(".append-only").exists());
+    assert!(backup.join("fingerprints.txt").exists());
+
+    let verify = |output: &mut Vec<u8>| {
+        let args = vec![
+            String::from("verify-fingerprints"),
+            backup.to_string_lossy().to_string(),
+        ];
+        mizeria::run_program(args, output)
+    };
+    let mut output = Vec::new();
+    assert!(verify(&mut output).is_ok());
+    assert!(String::from_utf8(output).unwrap().contains("  ok"));
+
+    let stored_file = get_file_by_name(backup, "file.txt").unwrap();
+    fs::write(stored_file, b"tampered").unwrap();
+
+    let mut output = Vec::new();
+    assert!(verify(&mut output).is_err());
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.contains("CHANGED"),
+        "Unexpected output: '{}'",
+        output
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn create_snapshot_accessible_only_by_owner() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let input = tempfile::tempdir().unwrap();
+    let input = input.path();
+    let file = input.join("private.txt");
+    fs::write(&file, b"private").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+    create_snapshot(backup, &[input]);
+
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let snapshot = get_entry_from(backup);
+    assert_eq!(mode(&snapshot), 0o700);
+    assert_eq!(mode(&snapshot.join("index.txt")) & 0o077, 0);
+    assert_eq!(
+        mode(&get_file_by_name(&snapshot, "private.txt").unwrap()),
+        0o600
+    );
+}
+
+#[test]
+fn create_snapshots_in_bounded_memory_mode() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let input = tempfile::tempdir().unwrap();
+    let input = input.path();
+    create_dir(input.join("folder")).unwrap();
+    fs::write(input.join("folder").join("file.txt"), b"file").unwrap();
+
+    create_snapshot_with_args(backup, &[input], &["--memory-limit", "1M"]);
+    create_snapshot_with_args(backup, &[input], &["--memory-limit", "1M"]);
+
+    let mut snapshots: Vec<PathBuf> = backup
+        .read_dir()
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    snapshots.sort();
+    assert_eq!(snapshots.len(), 2);
+
+    let first = StubSnapshot::open(&snapshots[0]);
+    let folder = input.join("folder");
+    let file = folder.join("file.txt");
+    assert!(first.index_contains_all(first.timestamp.as_str(), &[input, &folder, &file]));
+    let second = StubSnapshot::open(&snapshots[1]);
+    assert_eq!(second.index.lines().count(), 3);
+    assert!(!snapshots[1].join(".sort").exists());
+}
+
+#[test]
+fn create_snapshot_with_copy_buffer_and_direct_io() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let input = tempfile::tempdir().unwrap();
+    let input = input.path();
+    // larger than the buffer and not aligned to it
+    let content: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    fs::write(input.join("file.bin"), &content).unwrap();
+
+    create_snapshot_with_args(backup, &[input], &["--copy-buffer", "4K", "--direct-io"]);
+
+    let snapshot = get_entry_from(backup);
+    let snapshot = StubSnapshot::open(&snapshot);
+    let copied = get_file_by_name(&snapshot.files, "file.bin").unwrap();
+    assert_eq!(fs::read(copied).unwrap(), content);
+}
+
+#[test]
+fn import_dated_folders_as_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let rsync = tempfile::tempdir().unwrap();
+    let rsync = rsync.path();
+    let first = rsync.join("2021-07-15_18.34");
+    let second = rsync.join("daily.2021-07-16");
+    for folder in [&first, &second] {
+        create_dir(folder).unwrap();
+        fs::write(folder.join("same.txt"), b"same").unwrap();
+    }
+    fs::write(first.join("changed.txt"), b"old").unwrap();
+    fs::write(second.join("changed.txt"), b"new content").unwrap();
+    let modified = fs::metadata(first.join("same.txt")).unwrap().modified();
+    File::options()
+        .write(true)
+        .open(second.join("same.txt"))
+        .unwrap()
+        .set_modified(modified.unwrap())
+        .unwrap();
+    create_dir(rsync.join("latest")).unwrap();
+
+    // the folder that was copied by rsync
+    let original = tempfile::tempdir().unwrap();
+    let original = original.path().canonicalize().unwrap();
+    fs::write(original.join("same.txt"), b"same").unwrap();
+    fs::write(original.join("changed.txt"), b"new content").unwrap();
+    let original = original.as_path();
+    let args = [
+        "import-rsync",
+        &backup.to_string_lossy(),
+        &rsync.to_string_lossy(),
+        "--original",
+        &original.to_string_lossy(),
+    ]
+    .map(String::from);
+    let mut output = Vec::new();
+    mizeria::run_program(args.to_vec(), &mut output).expect("program failed");
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("Imported 2 snapshot(s)."));
+    assert!(output.contains("Skipped: "));
+    let first = StubSnapshot::open(&backup.join("2021-07-15_18.34"));
+    let second = StubSnapshot::open(&backup.join("2021-07-16_00.00"));
+    let same = original.join("same.txt");
+    let changed = original.join("changed.txt");
+    assert!(first.index_contains_all("2021-07-15_18.34", &[original, &same, &changed]));
+    assert!(second.index_contains("2021-07-15_18.34", &same));
+    assert!(second.index_contains("2021-07-16_00.00", &changed));
+    assert!(second.find_file("same.txt").is_none());
+    let copied = second.find_file("changed.txt").unwrap();
+    assert_eq!(fs::read(copied).unwrap(), b"new content");
+}
+
+#[test]
+fn import_archive_as_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let files = tempfile::tempdir().unwrap();
+    create_dir(files.path().join("docs")).unwrap();
+    fs::write(files.path().join("docs").join("notes.txt"), b"notes").unwrap();
+    let archive = files.path().join("home-2021-07-15.tar");
+    let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+    builder
+        .append_dir_all("home/user/docs", files.path().join("docs"))
+        .unwrap();
+    builder.finish().unwrap();
+
+    let args = [
+        "import",
+        &backup.to_string_lossy(),
+        &archive.to_string_lossy(),
+    ]
+    .map(String::from);
+    let mut output = Vec::new();
+    mizeria::run_program(args.to_vec(), &mut output).expect("program failed");
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("Imported snapshot: 2021-07-15_00.00"));
+    let snapshot = StubSnapshot::open(&backup.join("2021-07-15_00.00"));
+    // the archived paths don't exist, so they aren't canonicalized
+    for path in ["/home", "/home/user", "/home/user/docs/notes.txt"] {
+        assert!(snapshot.index.contains(&format!(" {}\n", path)));
+    }
+    assert!(!snapshot.index.contains(" /\n"));
+    assert_eq!(
+        fs::read(snapshot.find_file("notes.txt").unwrap()).unwrap(),
+        b"notes"
+    );
+
+    // an archive can't be imported before the latest snapshot
+    assert!(mizeria::run_program(args.to_vec(), &mut std::io::sink()).is_err());
+}
+
+#[test]
+fn create_snapshot_of_inputs_listed_in_file() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let notes = files.path().join("notes.txt");
+    let todo = files.path().join("todo.txt");
+    let other = files.path().join("other.txt");
+    for file in [&notes, &todo, &other] {
+        fs::write(file, "hello world").unwrap();
+    }
+    let list = files.path().join("list.txt");
+    fs::write(
+        &list,
+        format!("{}\r\n\n{}\n", notes.display(), todo.display()),
+    )
+    .unwrap();
+
+    create_snapshot_with_args(
+        backup.path(),
+        &[],
+        &["--files-from", list.to_str().unwrap()],
+    );
+
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    assert!(snapshot.index_contains_all(snapshot.timestamp.as_str(), &[&notes, &todo]));
+    assert!(snapshot.find_file("other.txt").is_none());
+}
+
+#[test]
+fn create_snapshot_of_inputs_separated_with_nul() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let notes = files.path().join("my notes.txt");
+    let todo = files.path().join("todo.txt");
+    fs::write(&notes, "hello world").unwrap();
+    fs::write(&todo, "hello world").unwrap();
+    let list = files.path().join("list.txt");
+    fs::write(&list, format!("{}\0{}\0", notes.display(), todo.display())).unwrap();
+
+    create_snapshot_with_args(
+        backup.path(),
+        &[],
+        &["--files-from", list.to_str().unwrap(), "--from0"],
+    );
+
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    assert!(snapshot.find_file("my notes.txt").is_some());
+    assert!(snapshot.find_file("todo.txt").is_some());
+}
+
+#[test]
+fn create_snapshot_with_limited_depth() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let project = files.path().join("project");
+    let nested = project.join("src").join("nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(project.join("README.md"), "hello world").unwrap();
+    fs::write(project.join("src").join("main.rs"), "hello world").unwrap();
+    fs::write(nested.join("lib.rs"), "hello world").unwrap();
+
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--max-depth", "2"]);
+
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    let timestamp = snapshot.timestamp.as_str();
+    assert!(snapshot.index_contains_all(
+        timestamp,
+        &[&project, &project.join("README.md"), &project.join("src")]
+    ));
+    assert_eq!(snapshot.index.lines().count(), 4);
+    assert!(snapshot.find_file("main.rs").is_none());
+    let src = snapshot.find_dir("src").unwrap();
+    assert_eq!(src.read_dir().unwrap().count(), 0);
+}
+
+#[test]
+fn large_files_are_recorded_but_not_copied() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let image = files.path().join("disk.img");
+    fs::write(&image, vec![0u8; 2048]).unwrap();
+    fs::write(files.path().join("notes.txt"), "hello world").unwrap();
+
+    let output =
+        create_snapshot_with_output(backup.path(), &[files.path()], &["--max-file-size", "1K"]);
+
+    assert!(
+        output.contains("1 file(s) larger than the maximum file size were not copied (2048 bytes)"),
+        "unexpected output: '{}'",
+        output
+    );
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    assert!(snapshot.find_file("notes.txt").is_some());
+    assert!(snapshot.find_file("disk.img").is_none());
+    let metadata = fs::read_to_string(snapshot.files.with_file_name("metadata.txt")).unwrap();
+    let expected = format!(
+        "not_copied: 2048 {}\n",
+        image.canonicalize().unwrap().display()
+    );
+    assert!(
+        metadata.contains(&expected),
+        "unexpected metadata: '{}'",
+        metadata
+    );
+}
+
+#[test]
+fn json_progress_ends_with_summary() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("disk.img"), vec![0u8; 2048]).unwrap();
+    fs::write(files.path().join("notes.txt"), "hello world").unwrap();
+
+    let output = create_snapshot_with_output(
+        backup.path(),
+        &[files.path()],
+        &["--progress", "json", "--max-file-size", "1K"],
+    );
+
+    let summary: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let snapshot = get_entry_from(backup.path());
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(
+        summary["snapshot"],
+        snapshot.file_name().unwrap().to_str().unwrap()
+    );
+    assert_eq!(summary["indexed"], 2);
+    assert_eq!(summary["copied"], 2);
+    assert_eq!(summary["not_copied"][0]["size"], 2048);
+    assert_eq!(summary["warnings"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn large_files_are_skipped_without_a_trace() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("disk.img"), vec![0u8; 2048]).unwrap();
+
+    create_snapshot_with_args(
+        backup.path(),
+        &[files.path()],
+        &[
+            "--max-file-size",
+            "1K",
+            "--on-large-file",
+            "skip",
+            "--strict",
+        ],
+    );
+
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    assert!(snapshot.find_file("disk.img").is_none());
+    let metadata = fs::read_to_string(snapshot.files.with_file_name("metadata.txt")).unwrap();
+    assert!(!metadata.contains("not_copied"));
+}
+
+#[test]
+#[cfg(unix)]
+fn hardlinks_are_stored_once_and_restored() {
+    use std::os::unix::fs::MetadataExt;
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let original = files.path().join("original.txt");
+    let link = files.path().join("link.txt");
+    fs::write(&original, "hello world").unwrap();
+    fs::hard_link(&original, &link).unwrap();
+
+    create_snapshot(backup.path(), &[files.path()]);
+
+    let snapshot_path = get_entry_from(backup.path());
+    let snapshot = StubSnapshot::open(&snapshot_path);
+    assert_eq!(snapshot.index.matches(" link:1 ").count(), 2);
+    let stored_original = snapshot.find_file("original.txt").unwrap();
+    let stored_link = snapshot.find_file("link.txt").unwrap();
+    assert_eq!(
+        fs::metadata(stored_original).unwrap().ino(),
+        fs::metadata(stored_link).unwrap().ino()
+    );
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored = Path::new(target.path()).join(
+        files
+            .path()
+            .canonicalize()
+            .unwrap()
+            .strip_prefix("/")
+            .unwrap(),
+    );
+    let restored_original = fs::metadata(restored.join("original.txt")).unwrap();
+    let restored_link = fs::metadata(restored.join("link.txt")).unwrap();
+    assert_eq!(restored_original.ino(), restored_link.ino());
+    assert_eq!(restored_link.nlink(), 2);
+}
+
+#[test]
+#[cfg(unix)]
+fn special_files_are_skipped_or_recreated_on_restore() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let pipe = files.path().join("pipe");
+    let pipe_path = CString::new(pipe.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(pipe_path.as_ptr(), 0o640) }, 0);
+
+    create_snapshot(backup.path(), &[files.path()]);
+
+    let snapshot = StubSnapshot::open(&get_entry_from(backup.path()));
+    assert!(!snapshot.index.contains("pipe"));
+    fs::remove_dir_all(get_entry_from(backup.path())).unwrap();
+
+    create_snapshot_with_args(
+        backup.path(),
+        &[files.path()],
+        &["--special-files", "recreate-on-restore"],
+    );
+
+    let snapshot_path = get_entry_from(backup.path());
+    let snapshot = StubSnapshot::open(&snapshot_path);
+    assert!(snapshot.index.contains(" special:fifo:"));
+    assert!(snapshot.find_file("pipe").is_none());
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored =
+        Path::new(target.path()).join(pipe.canonicalize().unwrap().strip_prefix("/").unwrap());
+    assert!(fs::symlink_metadata(restored)
+        .unwrap()
+        .file_type()
+        .is_fifo());
+}
+
+#[test]
+#[cfg(unix)]
+fn permissions_and_modification_times_are_restored() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, SystemTime};
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let folder = files.path().join("folder");
+    let file = folder.join("script.sh");
+    fs::create_dir(&folder).unwrap();
+    fs::write(&file, "echo hello").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o750)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::new(1_600_000_000, 123);
+    File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    File::open(&folder).unwrap().set_modified(modified).unwrap();
+
+    create_snapshot(backup.path(), &[folder.as_path()]);
+
+    let snapshot_path = get_entry_from(backup.path());
+    let snapshot = StubSnapshot::open(&snapshot_path);
+    assert!(snapshot.index.contains(" mode:750 "));
+    assert!(snapshot.index.contains(" mtime:1600000000.000000123 "));
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored =
+        Path::new(target.path()).join(folder.canonicalize().unwrap().strip_prefix("/").unwrap());
+    let restored_file = fs::metadata(restored.join("script.sh")).unwrap();
+    assert_eq!(restored_file.permissions().mode() & 0o7777, 0o750);
+    assert_eq!(restored_file.modified().unwrap(), modified);
+    assert_eq!(
+        fs::metadata(restored).unwrap().modified().unwrap(),
+        modified
+    );
+}
+
+#[test]
+fn files_are_compared_with_modification_times_in_index() {
+    use std::time::{Duration, SystemTime};
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let (same, changed) = (
+        files.path().join("same.txt"),
+        files.path().join("changed.txt"),
+    );
+    let write = |path: &Path, content: &str, seconds: u64| {
+        fs::write(path, content).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        file.set_modified(modified).unwrap();
+    };
+    // created just now, which was enough to copy them again
+    write(&same, "same", 1_600_000_000);
+    write(&changed, "old", 1_600_000_000);
+
+    create_snapshot(backup.path(), &[files.path()]);
+    write(&changed, "old content", 1_600_000_001);
+    fs::remove_file(backup.path().join("files-cache.bin")).unwrap();
+    create_snapshot(backup.path(), &[files.path()]);
+
+    let snapshots = backup_entries(backup.path());
+    let second = StubSnapshot::open(&snapshots[1]);
+    let copied = get_file_by_name(&second.files, "changed.txt").unwrap();
+    assert_eq!(fs::read_to_string(copied).unwrap(), "old content");
+    assert!(get_file_by_name(&second.files, "same.txt").is_none());
+}
+
+#[test]
+fn checksum_finds_changes_hidden_by_modification_time() {
+    use std::time::{Duration, SystemTime};
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let (same, changed) = (
+        files.path().join("same.txt"),
+        files.path().join("changed.txt"),
+    );
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let write = |path: &Path, content: &str| {
+        fs::write(path, content).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(modified).unwrap();
+    };
+    write(&same, "same");
+    write(&changed, "old content");
+
+    create_snapshot(backup.path(), &[files.path()]);
+    write(&changed, "new content");
+    // without it the change time would give the change away
+    fs::remove_file(backup.path().join("files-cache.bin")).unwrap();
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--checksum"]);
+
+    let snapshots = backup_entries(backup.path());
+    let second = StubSnapshot::open(&snapshots[1]);
+    let copied = get_file_by_name(&second.files, "changed.txt").unwrap();
+    assert_eq!(fs::read_to_string(copied).unwrap(), "new content");
+    assert!(get_file_by_name(&second.files, "same.txt").is_none());
+}
+
+#[test]
+fn large_files_are_stored_as_shared_chunks() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let mut state: u64 = 42;
+    let data: Vec<u8> = (0..6 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let mut changed = data.clone();
+    changed[3 * 1024 * 1024] ^= 0xff;
+    fs::write(files.path().join("mailbox"), &data).unwrap();
+    fs::write(files.path().join("mailbox.old"), &changed).unwrap();
+
+    create_snapshot_with_args(
+        backup.path(),
+        &[files.path()],
+        &["--chunk-larger-than", "1M"],
+    );
+
+    let snapshot_path = get_entry_from(backup.path());
+    let snapshot = StubSnapshot::open(&snapshot_path);
+    assert_eq!(snapshot.index.matches(" chunks:").count(), 2);
+    assert!(snapshot.find_file("mailbox").is_none());
+    let stored: u64 = WalkDir::new(snapshot_path.join("chunks"))
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.metadata().unwrap().len())
+        .sum();
+    assert!(stored > data.len() as u64);
+    assert!(stored < 2 * data.len() as u64);
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored = Path::new(target.path()).join(
+        files
+            .path()
+            .canonicalize()
+            .unwrap()
+            .strip_prefix("/")
+            .unwrap(),
+    );
+    assert_eq!(fs::read(restored.join("mailbox")).unwrap(), data);
+    assert_eq!(fs::read(restored.join("mailbox.old")).unwrap(), changed);
+}
+
+#[test]
+fn files_are_stored_compressed_and_restored() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let text = "hello world\n".repeat(10_000);
+    fs::write(files.path().join("notes.txt"), &text).unwrap();
+
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--compress", "zstd:19"]);
+
+    let snapshot_path = get_entry_from(backup.path());
+    let snapshot = StubSnapshot::open(&snapshot_path);
+    let stored = snapshot.find_file("notes.txt").unwrap();
+    assert!(fs::metadata(stored).unwrap().len() < text.len() as u64 / 10);
+    assert!(snapshot.index.contains(&format!(" {}:", text.len())));
+    let metadata = fs::read_to_string(snapshot_path.join("metadata.txt")).unwrap();
+    assert!(metadata.contains("compression: zstd:19\n"));
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored = Path::new(target.path()).join(
+        files
+            .path()
+            .canonicalize()
+            .unwrap()
+            .strip_prefix("/")
+            .unwrap(),
+    );
+    assert_eq!(
+        fs::read_to_string(restored.join("notes.txt")).unwrap(),
+        text
+    );
+}
+
+#[test]
+fn files_are_stored_in_archive_and_restored() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::create_dir(files.path().join("folder")).unwrap();
+    fs::write(files.path().join("folder").join("a.txt"), "a").unwrap();
+    fs::write(files.path().join("b.txt"), "b").unwrap();
+
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--format", "archive"]);
+
+    let snapshot_path = get_entry_from(backup.path());
+    assert!(snapshot_path.join("files.tar.zst").is_file());
+    assert!(!snapshot_path.join("files").exists());
+
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot_path.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored = Path::new(target.path()).join(
+        files
+            .path()
+            .canonicalize()
+            .unwrap()
+            .strip_prefix("/")
+            .unwrap(),
+    );
+    assert_eq!(
+        fs::read_to_string(restored.join("folder").join("a.txt")).unwrap(),
+        "a"
+    );
+    assert_eq!(fs::read_to_string(restored.join("b.txt")).unwrap(), "b");
+}
+
+#[test]
+fn backup_uses_inputs_and_excludes_from_settings_file() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let files = files.path().canonicalize().unwrap();
+    fs::create_dir(files.join("cache")).unwrap();
+    fs::write(files.join("cache/page.html"), "page").unwrap();
+    fs::write(files.join("notes.txt"), "notes").unwrap();
+    fs::write(files.join("notes.txt.tmp"), "temporary").unwrap();
+    fs::write(files.join("draft.txt"), "draft").unwrap();
+    let settings = format!(
+        "inputs = [{:?}]\nexclude = [\"*.tmp\", {:?}]\n",
+        files.to_string_lossy(),
+        files.join("cache").to_string_lossy()
+    );
+    fs::write(backup.path().join("mizeria.toml"), settings).unwrap();
+
+    create_snapshot_with_args(backup.path(), &[], &["--exclude", "draft.*"]);
+
+    let snapshot = get_dir_by_name(backup.path(), &generate_snapshot_name()).unwrap();
+    let index = fs::read_to_string(snapshot.join("index.txt")).unwrap();
+    assert!(index.contains("notes.txt\n"), "{}", index);
+    assert!(!index.contains("notes.txt.tmp"), "{}", index);
+    assert!(!index.contains("cache"), "{}", index);
+    assert!(!index.contains("draft.txt"), "{}", index);
+}
+
+#[test]
+fn backup_without_inputs_fails() {
+    let backup = tempfile::tempdir().unwrap();
+    let args = vec![
+        String::from("backup"),
+        backup.path().to_string_lossy().to_string(),
+    ];
+
+    let error = mizeria::run_program(args, &mut std::io::sink())
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("mizeria.toml"), "{}", error);
+}
+
+#[test]
+#[cfg(unix)]
+fn hooks_run_before_and_after_backup() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("notes.txt"), "notes").unwrap();
+    let log = files.path().join("hooks.log");
+    let post_hook = format!(
+        "echo \"$MIZERIA_HOOK $MIZERIA_SNAPSHOT $MIZERIA_RESULT\" >> '{}'",
+        log.display()
+    );
+    fs::write(
+        backup.path().join("mizeria.toml"),
+        format!("post-hook = {:?}\n", post_hook),
+    )
+    .unwrap();
+
+    let pre_hook = format!("echo \"$MIZERIA_HOOK\" > '{}'", log.display());
+    create_snapshot_with_args(backup.path(), &[files.path()], &["--pre-hook", &pre_hook]);
+
+    let log = fs::read_to_string(log).unwrap();
+    assert_eq!(
+        log,
+        format!("pre\npost {} success\n", generate_snapshot_name())
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn failed_pre_hook_stops_backup() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    let args = vec![
+        "backup",
+        backup.path().to_str().unwrap(),
+        files.path().to_str().unwrap(),
+        "--pre-hook",
+        "exit 1",
+    ];
+
+    let error = mizeria::run_program(args, &mut std::io::sink())
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("exit 1"), "{}", error);
+    assert_eq!(fs::read_dir(backup.path()).unwrap().count(), 0);
+}
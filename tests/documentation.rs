@@ -0,0 +1,50 @@
+use std::fs;
+
+fn run(args: &[&str]) -> String {
+    let mut output = Vec::new();
+    mizeria::run_program(args, &mut output).expect("program failed");
+    String::from_utf8(output).expect("Invalid UTF-8")
+}
+
+#[test]
+fn generate_man_pages() {
+    let out = tempfile::tempdir().unwrap();
+
+    let output = run(&["gen-man", "--out", out.path().to_str().unwrap()]);
+
+    assert!(
+        output.lines().count() > 10,
+        "unexpected output: '{}'",
+        output
+    );
+    let main_page = fs::read_to_string(out.path().join("mizeria.1")).unwrap();
+    assert!(main_page.starts_with(".TH \"MIZERIA\" \"1\""));
+    assert!(main_page.contains(".SH COMMANDS"));
+    assert!(main_page.contains(".SH SNAPSHOT FORMAT"));
+    assert!(main_page.contains("\\fBmizeria\\-backup\\fR(1)"));
+    assert!(!main_page.contains("gen\\-man"));
+
+    let backup_page = fs::read_to_string(out.path().join("mizeria-backup.1")).unwrap();
+    assert!(backup_page.contains(".B mizeria backup [FLAGS] [OPTIONS] [\\-\\-] [ARGS]"));
+    assert!(backup_page.contains(".TP\n\\fB\\-\\-full\\fR\n"));
+    assert!(!out.path().join("mizeria-help.1").exists());
+}
+
+#[test]
+fn print_man_page_of_command() {
+    let page = run(&["gen-man", "list"]);
+
+    assert!(page.starts_with(".TH \"MIZERIA\\-LIST\" \"1\""));
+    assert!(page.contains("\\fB\\-\\-group\\-by <group\\-by>\\fR"));
+}
+
+#[test]
+fn show_help_topic() {
+    let topics = run(&["topic"]);
+    assert!(topics.contains("incremental"));
+    assert!(topics.contains("snapshot-format"));
+
+    let topic = run(&["topic", "incremental"]);
+    assert!(topic.starts_with("INCREMENTAL SEMANTICS\n"));
+    assert!(topic.contains("minus one minute"));
+}
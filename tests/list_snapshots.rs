@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::Path;
+
+mod utils;
+
+fn list_snapshots(backup: &Path, args: &[&str]) -> String {
+    let mut program_args = vec![String::from("list"), backup.to_string_lossy().to_string()];
+    for arg in args {
+        program_args.push(arg.to_string());
+    }
+
+    let mut output = Vec::new();
+    mizeria::run_program(program_args, &mut output).expect("program failed");
+    String::from_utf8(output).expect("Invalid UTF-8")
+}
+
+#[test]
+fn list_snapshots_with_metadata() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    let snapshot = utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+    fs::write(
+        snapshot.join("metadata.txt"),
+        "host: my-computer\nuser: kyriet\ntype: incremental\ninput: /home\ninput: /etc\n",
+    )
+    .unwrap();
+
+    let output = list_snapshots(backup, &[]);
+
+    assert_eq!(
+        output,
+        concat!(
+            "Available snapshots:\n",
+            "1. Snapshot: 2021-07-16_18.34\n",
+            "  Index: 0 entries\n",
+            "  Files: 0 bytes\n",
+            "  Type: incremental\n",
+            "  Made by: kyriet@my-computer\n",
+            "  Inputs: /home, /etc\n",
+            "\n",
+            "2. Snapshot: 2021-07-15_18.34\n",
+            "  Index: 0 entries\n",
+            "  Files: 0 bytes\n",
+            "\n",
+        )
+    );
+}
+
+#[test]
+fn list_snapshots_grouped_by_day() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    utils::create_empty_snapshot(backup, "2021-07-15_19.34");
+    let snapshot = utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+    fs::write(snapshot.join("files").join("file.txt"), b"hello world").unwrap();
+
+    let output = list_snapshots(backup, &["--group-by", "day"]);
+
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "unexpected output: '{}'", output);
+    assert_eq!(lines[1], "2021-07-16  1 snapshot(s)  11 bytes");
+    assert_eq!(lines[2], "2021-07-15  2 snapshot(s)  0 bytes");
+}
+
+#[test]
+fn list_snapshots_grouped_by_month() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+    utils::create_empty_snapshot(backup, "2021-08-01_18.34");
+
+    let output = list_snapshots(backup, &["--group-by", "month"]);
+
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "unexpected output: '{}'", output);
+    assert_eq!(lines[1], "2021-08  1 snapshot(s)  0 bytes");
+    assert_eq!(lines[2], "2021-07  2 snapshot(s)  0 bytes");
+}
+
+#[test]
+fn list_foreign_entries() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    fs::write(backup.join("notes.txt"), b"hello world").unwrap();
+    fs::create_dir(backup.join("2021-07-16_18.34")).unwrap();
+
+    let output = list_snapshots(backup, &["--foreign"]);
+
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 4, "unexpected output: '{}'", output);
+    assert_eq!(
+        lines[1].trim(),
+        "0 bytes  2021-07-16_18.34  (damaged snapshot, kept)"
+    );
+    assert_eq!(lines[2].trim(), "11 bytes  notes.txt");
+    assert_eq!(lines[3], "Found 2 foreign entries taking 11 bytes.");
+    assert!(backup.join("notes.txt").exists());
+}
+
+#[test]
+fn clean_foreign_entries() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let snapshot = utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    fs::write(backup.join("notes.txt"), b"hello world").unwrap();
+    fs::create_dir_all(backup.join("some_dir").join("nested")).unwrap();
+    fs::create_dir(backup.join("2021-07-16_18.34")).unwrap();
+
+    let output = list_snapshots(backup, &["--foreign", "--clean"]);
+
+    assert!(output.contains("Removed 2 entries freeing 11 bytes."));
+    assert!(!backup.join("notes.txt").exists());
+    assert!(!backup.join("some_dir").exists());
+    assert!(backup.join("2021-07-16_18.34").exists());
+    assert!(snapshot.exists());
+}
+
+#[test]
+fn print_latest_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+    fs::create_dir(backup.join("2021-07-17_18.34")).unwrap(); // not a snapshot
+
+    let latest = |args: &[&str]| {
+        let mut program_args = vec![String::from("latest"), backup.to_string_lossy().to_string()];
+        program_args.extend(args.iter().map(|a| a.to_string()));
+        let mut output = Vec::new();
+        mizeria::run_program(program_args, &mut output).expect("program failed");
+        String::from_utf8(output).expect("Invalid UTF-8")
+    };
+
+    assert_eq!(
+        latest(&[]),
+        format!("{}\n", backup.join("2021-07-16_18.34").display())
+    );
+    assert_eq!(latest(&["--name"]), "2021-07-16_18.34\n");
+}
+
+#[test]
+fn latest_snapshot_of_empty_backup_is_an_error() {
+    let backup = tempfile::tempdir().unwrap();
+    let args = vec![
+        String::from("latest"),
+        backup.path().to_string_lossy().to_string(),
+    ];
+
+    let result = mizeria::run_program(args, &mut std::io::sink());
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn list_snapshots_in_polish() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    fs::write(backup.join("notes.txt"), b"hello world").unwrap();
+
+    let output = list_snapshots(backup, &["--group-by", "day", "--lang", "pl"]);
+    assert_eq!(
+        output,
+        "Dostępne snapshoty:\n2021-07-15  snapshoty: 1  0 bajtów\n"
+    );
+
+    let output = list_snapshots(backup, &["--foreign", "--lang", "pl_PL.UTF-8"]);
+    assert!(output.contains("Znalezione obce wpisy: 1, zajmujące 11 bajtów."));
+
+    // The language is chosen again for every run
+    let output = list_snapshots(backup, &["--group-by", "day"]);
+    assert!(output.starts_with("Available snapshots:"));
+}
@@ -1,8 +1,11 @@
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use mizeria::result::IntegrityCheckError;
+use mizeria::result::{IntegrityCheckError, Severity};
+
+mod utils;
 
 struct ProgramOutput {
     buffer: Vec<u8>,
@@ -21,15 +24,22 @@ impl ProgramOutput {
         ProgramOutput { buffer: Vec::new() }
     }
 }
-impl ToString for ProgramOutput {
-    fn to_string(&self) -> String {
-        String::from_utf8(self.buffer.clone()).expect("Invalid UTF-8")
+
+/// GNUPGHOME is shared by the whole process, so tests using GPG take turns.
+static GNUPG: Mutex<()> = Mutex::new(());
+impl std::fmt::Display for ProgramOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let output = String::from_utf8(self.buffer.clone()).expect("Invalid UTF-8");
+        write!(f, "{}", output)
     }
 }
 
 fn init_logger() {
-    let mut builder = env_logger::Builder::new();
-    builder.target(env_logger::Target::Stdout).try_init().ok();
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::ERROR)
+        .with_test_writer()
+        .try_init()
+        .ok();
 }
 
 fn check_snapshot_integrity(snapshot_path: &Path) -> ProgramOutput {
@@ -49,7 +59,7 @@ fn check_snapshot_integrity_with_args(snapshot_path: &Path, args: &[&str]) -> Pr
 
     let mut output = ProgramOutput::new();
     let _ = mizeria::run_program(program_args, &mut output);
-    return output;
+    output
 }
 
 fn expect_integrity_success(output: ProgramOutput) {
@@ -65,7 +75,7 @@ fn expect_integrity_success(output: ProgramOutput) {
 
 fn expect_integrity_error(output: ProgramOutput, result: IntegrityCheckError) {
     let output = output.to_string();
-    let expected_msg = result.to_string();
+    let expected_msg = format!("error[{}]: {}", result.code(), result);
     assert!(
         output.contains(expected_msg.as_str()),
         "Expected result message: '{}' not found in: '{}'",
@@ -74,6 +84,24 @@ fn expect_integrity_error(output: ProgramOutput, result: IntegrityCheckError) {
     );
 }
 
+fn expect_integrity_warning(output: ProgramOutput, result: IntegrityCheckError) {
+    const INTEGRITY_CHECK_WARNING: &str = "completed with 1 warning(s).";
+    let output = output.to_string();
+    let expected_msg = format!("warning[{}]: {}", result.code(), result);
+    assert!(
+        output.contains(expected_msg.as_str()),
+        "Expected result message: '{}' not found in: '{}'",
+        expected_msg,
+        output
+    );
+    assert!(
+        output.contains(INTEGRITY_CHECK_WARNING),
+        "Expected result message: '{}' not found in: '{}'",
+        INTEGRITY_CHECK_WARNING,
+        output
+    );
+}
+
 #[test]
 fn check_integrity_for_empty_snapshot() {
     let backup = tempfile::tempdir().unwrap();
@@ -137,6 +165,35 @@ fn check_integrity_for_snapshot_without_files_folder() {
     expect_integrity_error(output, IntegrityCheckError::FilesFolderDoesntExist);
 }
 
+#[test]
+fn check_integrity_with_json_output() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    fs::write(
+        snapshot.join("index.txt"),
+        "2021-07-15_18.34 /home/notes.txt\nbroken\n",
+    )
+    .unwrap();
+
+    let output = check_snapshot_integrity_with_args(&snapshot, &["--output", "json"]);
+
+    let result: serde_json::Value = serde_json::from_str(&output.to_string()).unwrap();
+    assert_eq!(result["snapshot"], "2021-07-15_18.34");
+    assert_eq!(result["status"], "failed");
+    assert_eq!(
+        result["errors"][0],
+        serde_json::json!({
+            "kind": "invalid_timestamp_in_index",
+            "code": "MZ0005",
+            "severity": "error",
+            "message": "Invalid timestamp in line 2 of index.txt.",
+            "path": null,
+            "line": 2,
+            "snapshot": null,
+        })
+    );
+}
+
 #[test]
 fn check_integrity_for_snapshot_with_file_present_but_not_indexed() {
     let backup = tempfile::tempdir().unwrap();
@@ -171,13 +228,36 @@ fn check_integrity_for_snapshot_with_folder_present_but_not_indexed() {
     File::create(&index).unwrap();
     let files = snapshot.join("files");
     fs::create_dir(&files).unwrap();
-    let my_file = files.join("my_folder");
-    fs::create_dir(&my_file).unwrap();
+    let my_folder = files.join("my_folder");
+    fs::create_dir(&my_folder).unwrap();
 
     let output = check_snapshot_integrity(snapshot.as_path());
-    expect_integrity_error(
+    expect_integrity_warning(
         output,
-        IntegrityCheckError::EntryExistsButNotIndexed(my_file),
+        IntegrityCheckError::DirectoryExistsButNotIndexed(my_folder),
+    );
+}
+
+#[test]
+fn check_integrity_in_strict_mode_fails_on_warning() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    let snapshot_name = "2021-07-15_18.34";
+    let snapshot = backup.join(snapshot_name);
+    fs::create_dir(&snapshot).unwrap();
+    // empty index
+    let index = snapshot.join("index.txt");
+    File::create(&index).unwrap();
+    let files = snapshot.join("files");
+    fs::create_dir(&files).unwrap();
+    fs::create_dir(files.join("my_folder")).unwrap();
+
+    let output = check_snapshot_integrity_with_args(snapshot.as_path(), &["--strict"]);
+    let output = output.to_string();
+    assert!(
+        output.contains("Warnings are treated as errors in strict mode."),
+        "Strict mode failure not found in: '{}'",
+        output
     );
 }
 
@@ -338,13 +418,1275 @@ fn check_integrity_for_snapshot_created_with_command() {
     mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
     mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
 
-    let backup = backup.path().read_dir().unwrap();
-    let snapshots: Vec<fs::DirEntry> = backup.filter_map(Result::ok).collect();
+    let snapshots = utils::backup_entries(backup.path());
 
     assert_eq!(snapshots.len(), 2);
 
-    let output = check_snapshot_integrity(&snapshots[0].path());
+    let output = check_snapshot_integrity(&snapshots[0]);
     expect_integrity_success(output);
-    let output = check_snapshot_integrity(&snapshots[1].path());
+    let output = check_snapshot_integrity(&snapshots[1]);
+    expect_integrity_success(output);
+}
+
+#[test]
+fn list_entries_of_snapshot_created_with_command() {
+    let backup = tempfile::tempdir().unwrap();
+
+    let files = tempfile::tempdir().unwrap();
+    let files_path = files.path().canonicalize().unwrap();
+    fs::write(files_path.join("dummy_file.txt"), b"dummy").unwrap();
+    fs::create_dir(files_path.join("dummy_folder")).unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files_path.to_string_lossy()),
+    ];
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+
+    let snapshot = utils::backup_entries(backup.path()).remove(0);
+    let snapshot_name = snapshot.file_name().unwrap().to_string_lossy().to_string();
+
+    let output = check_snapshot_integrity_with_args(&snapshot, &["--ls"]).to_string();
+    assert!(output.contains(files_path.to_string_lossy().as_ref()));
+    assert!(!output.contains("dummy_file.txt"));
+
+    let folder = files_path.to_string_lossy();
+    let output = check_snapshot_integrity_with_args(&snapshot, &["--ls", &folder]);
+    let lines: Vec<String> = output.to_string().lines().map(String::from).collect();
+    assert_eq!(lines.len(), 2, "Unexpected output: {:?}", lines);
+    let file = lines
+        .iter()
+        .find(|l| l.ends_with("dummy_file.txt"))
+        .unwrap();
+    assert!(file.starts_with("file"));
+    assert!(file.contains(" 5 "));
+    assert!(file.contains(&snapshot_name));
+    let folder = lines.iter().find(|l| l.ends_with("dummy_folder")).unwrap();
+    assert!(folder.starts_with("dir"));
+}
+
+#[test]
+fn which_shows_where_data_of_file_is_stored() {
+    let backup = tempfile::tempdir().unwrap();
+
+    let files = tempfile::tempdir().unwrap();
+    let file = files.path().canonicalize().unwrap().join("dummy_file.txt");
+    fs::write(&file, b"dummy").unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(file.to_string_lossy()),
+    ];
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+
+    let snapshot = utils::backup_entries(backup.path()).remove(0);
+    let snapshot_name = snapshot.file_name().unwrap().to_string_lossy().to_string();
+
+    let args = vec![
+        String::from("which"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(file.to_string_lossy()),
+    ];
+    let mut output = ProgramOutput::new();
+    mizeria::run_program(&args, &mut output).expect("program failed");
+    let output = output.to_string();
+
+    let location = output
+        .lines()
+        .find_map(|l| l.strip_prefix("Location:"))
+        .unwrap()
+        .trim();
+    assert!(output.contains(&format!("Stored in: {}", snapshot_name)));
+    assert!(location.starts_with(snapshot.to_string_lossy().as_ref()));
+    assert_eq!(fs::read(location).unwrap(), b"dummy");
+
+    let mut args = args;
+    args.extend_from_slice(&["--snapshot".into(), "2021-07-14_18.34".into()]);
+    let result = mizeria::run_program(&args, &mut std::io::sink());
+    assert!(result.is_err());
+}
+
+#[test]
+fn show_info_of_snapshot_created_with_command() {
+    let backup = tempfile::tempdir().unwrap();
+
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("dummy_file.txt"), b"dummy").unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files.path().to_string_lossy()),
+    ];
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+
+    let snapshot = utils::backup_entries(backup.path()).remove(0);
+    let output = check_snapshot_integrity_with_args(&snapshot, &["--info"]).to_string();
+
+    let version = format!("Version:   {}", env!("CARGO_PKG_VERSION"));
+    let inputs = format!("Inputs:    {}", files.path().display());
+    assert!(output.contains(&version), "Unexpected output: {}", output);
+    assert!(output.contains(&inputs), "Unexpected output: {}", output);
+    assert!(output.contains("Entries:   2 indexed, 2 copied"));
+    assert!(
+        output.contains("Type:      full"),
+        "Unexpected output: {}",
+        output
+    );
+    assert!(output.contains("Warnings:  0"));
+
+    // metadata doesn't affect integrity of a snapshot
+    expect_integrity_success(check_snapshot_integrity(&snapshot));
+}
+
+#[test]
+fn check_integrity_for_signed_snapshot_without_key() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = backup.path().join("2021-07-15_18.34");
+    fs::create_dir_all(snapshot.join("files")).unwrap();
+    File::create(snapshot.join("index.txt")).unwrap();
+    File::create(snapshot.join("index.txt.minisig")).unwrap();
+
+    let output = check_snapshot_integrity(snapshot.as_path());
+    expect_integrity_warning(output, IntegrityCheckError::SignatureNotVerified);
+}
+
+#[test]
+fn check_integrity_for_unsigned_snapshot_with_key() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = backup.path().join("2021-07-15_18.34");
+    fs::create_dir_all(snapshot.join("files")).unwrap();
+    File::create(snapshot.join("index.txt")).unwrap();
+    let key_args = ["--verify-with", "minisign", "--verify-key", "minisign.pub"];
+
+    let output = check_snapshot_integrity_with_args(&snapshot, &key_args);
+    expect_integrity_error(
+        output,
+        IntegrityCheckError::InvalidSignature(snapshot.join("index.txt"), Severity::Error),
+    );
+
+    let mut args = key_args.to_vec();
+    args.extend_from_slice(&["--on-bad-signature", "warn"]);
+    let output = check_snapshot_integrity_with_args(&snapshot, &args);
+    expect_integrity_warning(
+        output,
+        IntegrityCheckError::InvalidSignature(snapshot.join("index.txt"), Severity::Warning),
+    );
+}
+
+#[test]
+fn check_integrity_detects_altered_and_removed_previous_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    File::create(files.path().join("dummy_file.txt")).unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files.path().to_string_lossy()),
+    ];
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+
+    let mut snapshots: Vec<_> = backup
+        .path()
+        .read_dir()
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    snapshots.sort();
+    let (first, second) = (&snapshots[0], &snapshots[1]);
+    let first_name = first.file_name().unwrap().to_string_lossy().to_string();
+    expect_integrity_success(check_snapshot_integrity(second));
+
+    let mut index = fs::OpenOptions::new()
+        .append(true)
+        .open(first.join("index.txt"))
+        .unwrap();
+    writeln!(index, "{} /injected/file.txt", first_name).unwrap();
+    let output = check_snapshot_integrity(second);
+    expect_integrity_error(
+        output,
+        IntegrityCheckError::ChainedSnapshotAltered(first_name.clone()),
+    );
+
+    fs::remove_dir_all(first).unwrap();
+    let output = check_snapshot_integrity(second);
+    expect_integrity_error(
+        output,
+        IntegrityCheckError::ChainedSnapshotMissing(first_name),
+    );
+}
+
+#[test]
+fn check_integrity_of_only_new_data_trusts_older_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    File::create(files.path().join("dummy_file.txt")).unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files.path().to_string_lossy()),
+    ];
+    for _ in 0..3 {
+        mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+    }
+    let mut snapshots: Vec<_> = backup
+        .path()
+        .read_dir()
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    snapshots.sort();
+    let first_name = snapshots[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    fs::write(snapshots[0].join("metadata.txt"), "altered").unwrap();
+
+    let output = check_snapshot_integrity(&snapshots[2]);
+    expect_integrity_error(
+        output,
+        IntegrityCheckError::ChainedSnapshotAltered(first_name),
+    );
+
+    let output = check_snapshot_integrity_with_args(&snapshots[2], &["--only-new"]);
     expect_integrity_success(output);
 }
+
+#[test]
+fn check_backup_in_rolling_budget() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    File::create(files.path().join("dummy_file.txt")).unwrap();
+
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files.path().to_string_lossy()),
+    ];
+    for _ in 0..3 {
+        mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+    }
+    let mut snapshots: Vec<String> = backup
+        .path()
+        .read_dir()
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    snapshots.sort();
+
+    let check = |output: &mut ProgramOutput| {
+        let args = vec![
+            String::from("check"),
+            String::from(backup.path().to_string_lossy()),
+            String::from("--snapshots"),
+            String::from("2"),
+        ];
+        mizeria::run_program(args, output)
+    };
+    let mut output = ProgramOutput::new();
+    check(&mut output).expect("check failed");
+    let output = output.to_string();
+    assert!(output.contains(&format!("{}  ok", snapshots[0])));
+    assert!(output.contains(&format!("{}  ok", snapshots[1])));
+    assert!(output.contains("Checked 2 of 3 snapshot(s)."));
+
+    // snapshot that was never verified goes first
+    let mut output = ProgramOutput::new();
+    check(&mut output).expect("check failed");
+    let output = output.to_string();
+    assert!(output.starts_with(&format!("{}  ok", snapshots[2])));
+
+    let verified = fs::read_to_string(backup.path().join("verified.txt")).unwrap();
+    assert_eq!(verified.lines().count(), 3);
+}
+
+#[test]
+fn check_backup_with_limited_threads() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    File::create(files.path().join("dummy_file.txt")).unwrap();
+    let args = vec![
+        String::from("backup"),
+        String::from(backup.path().to_string_lossy()),
+        String::from(files.path().to_string_lossy()),
+    ];
+    mizeria::run_program(&args, &mut std::io::sink()).expect("program failed");
+
+    for args in [
+        vec!["--threads", "2", "check"],
+        vec!["check", "--threads", "1"],
+    ] {
+        let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+        args.push(String::from(backup.path().to_string_lossy()));
+        let mut output = ProgramOutput::new();
+        mizeria::run_program(args, &mut output).expect("check failed");
+        assert!(output.to_string().contains("Checked 1 of 1 snapshot(s)."));
+    }
+}
+
+#[test]
+fn archive_and_unarchive_backup() {
+    let root = tempfile::tempdir().unwrap();
+    let backup = root.path().join("backup");
+    let files = tempfile::tempdir().unwrap();
+    fs::create_dir(&backup).unwrap();
+    fs::write(files.path().join("dummy_file.txt"), b"dummy").unwrap();
+    let run = |args: &[&Path]| {
+        let args: Vec<String> = args.iter().map(|a| a.to_string_lossy().into()).collect();
+        let mut output = ProgramOutput::new();
+        mizeria::run_program(args, &mut output).expect("program failed");
+        output.to_string()
+    };
+    run(&[Path::new("backup"), &backup, files.path()]);
+    let archive = root.path().join("backup.mza");
+    let unpacked = root.path().join("unpacked");
+
+    let output = run(&[Path::new("archive"), &backup, Path::new("-o"), &archive]);
+    assert!(output.contains("of 1 snapshot(s)"), "{}", output);
+    let output = run(&[Path::new("unarchive"), &archive, &unpacked]);
+    assert!(output.contains("of 1 snapshot(s)"), "{}", output);
+
+    let output = run(&[Path::new("check"), &unpacked]);
+    assert!(output.contains("Checked 1 of 1 snapshot(s)."), "{}", output);
+}
+
+#[test]
+fn selftest_leaves_backup_untouched() {
+    let backup = tempfile::tempdir().unwrap();
+    let args = vec![
+        String::from("selftest"),
+        String::from(backup.path().to_string_lossy()),
+    ];
+
+    let mut output = ProgramOutput::new();
+    mizeria::run_program(args, &mut output).expect("selftest failed");
+
+    let output = output.to_string();
+    assert!(!output.contains("FAILED"), "{}", output);
+    assert!(output.contains("Self-test passed."), "{}", output);
+    assert_eq!(backup.path().read_dir().unwrap().count(), 0);
+}
+
+fn run_on_backup(args: &[&str], backup: &Path) -> String {
+    let mut program_args = vec![args[0].to_string(), backup.to_string_lossy().to_string()];
+    program_args.extend(args[1..].iter().map(|a| a.to_string()));
+    let mut output = Vec::new();
+    mizeria::run_program(program_args, &mut output).expect("program failed");
+    String::from_utf8(output).expect("Invalid UTF-8")
+}
+
+#[test]
+fn undelete_pruned_snapshot_from_trash() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+
+    let output = run_on_backup(&["prune", "--keep-last", "1"], backup);
+    assert!(output.contains("Moved snapshot to trash: 2021-07-15_18.34"));
+    assert!(!backup.join("2021-07-15_18.34").exists());
+    assert!(run_on_backup(&["list", "--foreign"], backup).contains("No foreign entries found."));
+    assert!(run_on_backup(&["undelete"], backup).starts_with("2021-07-15_18.34  (removed on "));
+
+    let output = run_on_backup(&["undelete", "2021-07-15_18.34"], backup);
+
+    assert_eq!(output, "Restored snapshot: 2021-07-15_18.34\n");
+    assert!(backup.join("2021-07-15_18.34").is_dir());
+    assert_eq!(run_on_backup(&["undelete"], backup), "Trash is empty.\n");
+}
+
+#[test]
+fn prune_without_trash() {
+    let backup = tempfile::tempdir().unwrap();
+    let backup = backup.path();
+    utils::create_empty_snapshot(backup, "2021-07-15_18.34");
+    utils::create_empty_snapshot(backup, "2021-07-16_18.34");
+
+    let output = run_on_backup(&["prune", "--keep-last", "1", "--no-trash"], backup);
+
+    assert!(output.contains("Removed snapshot: 2021-07-15_18.34"));
+    assert_eq!(run_on_backup(&["undelete"], backup), "Trash is empty.\n");
+}
+
+#[test]
+fn bench_backup_folder() {
+    let backup = tempfile::tempdir().unwrap();
+    let args = [
+        "bench",
+        backup.path().to_str().unwrap(),
+        "--size",
+        "1M",
+        "--files",
+        "10",
+    ];
+
+    let mut output = Vec::new();
+    mizeria::run_program(&args, &mut output).expect("program failed");
+    let output = String::from_utf8(output).unwrap();
+
+    assert_eq!(
+        output.matches("Sequential write: ").count(),
+        3,
+        "{}",
+        output
+    );
+    assert!(output.contains("Recommended settings:"));
+    assert!(output.contains("--threads "));
+    assert_eq!(fs::read_dir(backup.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn export_checksums_of_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let folder = snapshot.join("files").join("home");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("notes.txt"), b"hello world").unwrap();
+    let manifest = backup.path().join("manifest.sha256");
+
+    let output = run_on_backup(
+        &["snapshot", "--export-checksums", manifest.to_str().unwrap()],
+        &snapshot,
+    );
+
+    assert!(output.starts_with("Exported checksums of 1 file(s)"));
+    assert_eq!(
+        fs::read_to_string(&manifest).unwrap(),
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  files/home/notes.txt\n"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_selected_paths_from_older_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let older = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let docs = older.join("files").join("home").join("me").join("docs");
+    fs::create_dir_all(&docs).unwrap();
+    fs::write(docs.join("report.odt"), b"report").unwrap();
+    fs::write(
+        older.join("index.txt"),
+        concat!(
+            "2021-07-15_18.34 /home/me\n",
+            "2021-07-15_18.34 /home/me/docs\n",
+            "2021-07-15_18.34 /home/me/docs/report.odt\n",
+        ),
+    )
+    .unwrap();
+    let newer = utils::create_empty_snapshot(backup.path(), "2021-07-16_18.34");
+    let home = newer.join("files").join("home").join("me");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("notes.txt"), b"notes").unwrap();
+    fs::write(
+        newer.join("index.txt"),
+        concat!(
+            "2021-07-16_18.34 /home/me\n",
+            "2021-07-15_18.34 /home/me/docs\n",
+            "2021-07-15_18.34 /home/me/docs/report.odt\n",
+            "2021-07-16_18.34 /home/me/notes.txt\n",
+        ),
+    )
+    .unwrap();
+    let restore = |args: &[&str]| {
+        let mut program_args = vec![
+            "restore",
+            newer.to_str().unwrap(),
+            target.path().to_str().unwrap(),
+        ];
+        program_args.extend(args);
+        let mut output = Vec::new();
+        mizeria::run_program(program_args, &mut output).expect("program failed");
+        String::from_utf8(output).unwrap()
+    };
+
+    let output = restore(&["--path", "/home/me/docs"]);
+
+    assert_eq!(
+        output,
+        format!(
+            "Restored 2 entries (6 bytes) to {}\n",
+            target.path().display()
+        )
+    );
+    let restored = target.path().join("home").join("me");
+    assert_eq!(
+        fs::read(restored.join("docs").join("report.odt")).unwrap(),
+        b"report"
+    );
+    assert!(!restored.join("notes.txt").exists());
+
+    fs::write(restored.join("docs").join("report.odt"), b"changed").unwrap();
+    let output = restore(&[]);
+
+    assert!(output.starts_with("Skipped existing file: /home/me/docs/report.odt\n"));
+    assert_eq!(
+        fs::read(restored.join("docs").join("report.odt")).unwrap(),
+        b"changed"
+    );
+    assert_eq!(fs::read(restored.join("notes.txt")).unwrap(), b"notes");
+
+    restore(&["--path", "/home/me/docs/report.odt", "--overwrite"]);
+    assert_eq!(
+        fs::read(restored.join("docs").join("report.odt")).unwrap(),
+        b"report"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_dry_run_lists_files_without_writing() {
+    let backup = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let older = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let home = older.join("files").join("home").join("me");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("report.odt"), b"report").unwrap();
+    let newer = utils::create_empty_snapshot(backup.path(), "2021-07-16_18.34");
+    let home = newer.join("files").join("home").join("me");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("notes.txt"), b"notes").unwrap();
+    fs::write(
+        newer.join("index.txt"),
+        concat!(
+            "2021-07-16_18.34 /home/me\n",
+            "2021-07-16_18.34 /home/me/notes.txt\n",
+            "2021-07-15_18.34 /home/me/report.odt\n",
+        ),
+    )
+    .unwrap();
+    let args = [
+        "restore",
+        newer.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+        "--dry-run",
+    ];
+    let mut output = Vec::new();
+
+    mizeria::run_program(args, &mut output).expect("program failed");
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            concat!(
+                "2021-07-16_18.34  file            5  /home/me/notes.txt\n",
+                "2021-07-15_18.34  file            6  /home/me/report.odt\n",
+                "Would restore 3 entries (11 bytes) to {}\n",
+            ),
+            target.path().display()
+        )
+    );
+    assert_eq!(fs::read_dir(target.path()).unwrap().count(), 0);
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_state_at_point_in_time() {
+    let backup = tempfile::tempdir().unwrap();
+    let index =
+        |snapshot: &Path, lines: &str| fs::write(snapshot.join("index.txt"), lines).unwrap();
+    let home = |snapshot: &Path| {
+        let home = snapshot.join("files").join("home").join("me");
+        fs::create_dir_all(&home).unwrap();
+        home
+    };
+    let first = utils::create_empty_snapshot(backup.path(), "2021-07-14_10.00");
+    fs::write(home(&first).join("a.txt"), b"a").unwrap();
+    index(
+        &first,
+        "2021-07-14_10.00 /home/me\n2021-07-14_10.00 /home/me/a.txt\n",
+    );
+    let second = utils::create_empty_snapshot(backup.path(), "2021-07-15_10.00");
+    fs::write(home(&second).join("b.txt"), b"old b").unwrap();
+    index(
+        &second,
+        concat!(
+            "2021-07-15_10.00 /home/me\n",
+            "2021-07-14_10.00 /home/me/a.txt\n",
+            "2021-07-15_10.00 /home/me/b.txt\n",
+        ),
+    );
+    // Data of a.txt isn't in the second snapshot, so its index is followed.
+    let third = utils::create_empty_snapshot(backup.path(), "2021-07-16_10.00");
+    fs::write(home(&third).join("b.txt"), b"new b").unwrap();
+    index(
+        &third,
+        concat!(
+            "2021-07-16_10.00 /home/me\n",
+            "2021-07-15_10.00 /home/me/a.txt\n",
+            "2021-07-16_10.00 /home/me/b.txt\n",
+        ),
+    );
+    let restore_at = |moment: &str| {
+        let target = tempfile::tempdir().unwrap();
+        let args = [
+            "restore",
+            backup.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--at",
+            moment,
+        ];
+        let mut output = Vec::new();
+        mizeria::run_program(args, &mut output).expect("program failed");
+        let restored = target.path().join("home").join("me");
+        let a = fs::read_to_string(restored.join("a.txt")).unwrap();
+        let b = fs::read_to_string(restored.join("b.txt")).unwrap();
+        (String::from_utf8(output).unwrap(), a, b)
+    };
+
+    let (output, a, b) = restore_at("2021-07-15");
+    assert!(output.starts_with("Restoring from snapshot 2021-07-15_10.00\n"));
+    assert_eq!((a.as_str(), b.as_str()), ("a", "old b"));
+
+    let (output, a, b) = restore_at("2021-07-16_12.00");
+    assert!(output.starts_with("Restoring from snapshot 2021-07-16_10.00\n"));
+    assert_eq!((a.as_str(), b.as_str()), ("a", "new b"));
+
+    let mut output = Vec::new();
+    let args = [
+        "restore",
+        backup.path().to_str().unwrap(),
+        "target",
+        "--at",
+        "2021-07-13",
+    ];
+    let result = mizeria::run_program(args, &mut output);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "No snapshot was made at or before 2021-07-13_23.59"
+    );
+}
+
+#[test]
+fn select_snapshot_relative_to_the_latest_one() {
+    let backup = tempfile::tempdir().unwrap();
+    utils::create_empty_snapshot(backup.path(), "2021-07-14_10.00");
+    utils::create_empty_snapshot(backup.path(), "2021-07-15_10.00");
+    utils::create_empty_snapshot(backup.path(), "2021-07-16_10.00");
+    let info = |reference: &str| {
+        let snapshot = backup.path().join(reference);
+        let args = ["snapshot", snapshot.to_str().unwrap(), "--info"];
+        let mut output = Vec::new();
+        mizeria::run_program(args, &mut output).map(|_| String::from_utf8(output).unwrap())
+    };
+
+    assert!(info("latest")
+        .unwrap()
+        .starts_with("Snapshot:  2021-07-16_10.00\n"));
+    assert!(info("latest~2")
+        .unwrap()
+        .starts_with("Snapshot:  2021-07-14_10.00\n"));
+    assert_eq!(
+        info("latest~3").unwrap_err().to_string(),
+        "Snapshot latest~3 doesn't exist, the backup has 3 snapshot(s)"
+    );
+}
+
+#[test]
+fn delete_snapshot_referenced_by_newer_snapshots_only_with_force() {
+    let backup = tempfile::tempdir().unwrap();
+    let older = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let newer = utils::create_empty_snapshot(backup.path(), "2021-07-16_18.34");
+    let home = older.join("files").join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(older.join("index.txt"), "2021-07-15_18.34 /home\n").unwrap();
+    fs::write(newer.join("index.txt"), "2021-07-15_18.34 /home\n").unwrap();
+    let delete = |snapshot: &Path, force: bool| {
+        let mut args = vec!["snapshot", snapshot.to_str().unwrap(), "--delete"];
+        if force {
+            args.push("--force");
+        }
+        let mut output = Vec::new();
+        mizeria::run_program(args, &mut output).map(|_| String::from_utf8(output).unwrap())
+    };
+
+    let result = delete(&older, false);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Snapshot 2021-07-15_18.34 is referenced by newer snapshots: 2021-07-16_18.34"
+    );
+    assert!(older.exists());
+
+    let output = delete(&older, true).unwrap();
+
+    assert_eq!(
+        output,
+        concat!(
+            "Removed snapshot: 2021-07-15_18.34\n",
+            "Data referenced by these snapshots is now missing: 2021-07-16_18.34\n"
+        )
+    );
+    assert!(!older.exists());
+
+    let output = delete(&newer, false).unwrap();
+
+    assert_eq!(output, "Removed snapshot: 2021-07-16_18.34\n");
+    assert!(!newer.exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn squash_incremental_snapshots_into_full_snapshot() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = |name: &str, stored: &[(&str, &str)], index: &str| {
+        let location = utils::create_empty_snapshot(backup.path(), name);
+        let home = location.join("files").join("home");
+        fs::create_dir_all(&home).unwrap();
+        for (file, content) in stored {
+            fs::write(home.join(file), content).unwrap();
+        }
+        fs::write(location.join("index.txt"), index).unwrap();
+        location
+    };
+    let first = snapshot(
+        "2021-07-14_10.00",
+        &[("a.txt", "a")],
+        "2021-07-14_10.00 /home\n2021-07-14_10.00 /home/a.txt\n",
+    );
+    let second = snapshot(
+        "2021-07-15_10.00",
+        &[("b.txt", "b")],
+        concat!(
+            "2021-07-15_10.00 /home\n",
+            "2021-07-14_10.00 /home/a.txt\n",
+            "2021-07-15_10.00 /home/b.txt\n",
+        ),
+    );
+    let third = snapshot(
+        "2021-07-16_10.00",
+        &[],
+        concat!(
+            "2021-07-16_10.00 /home\n",
+            "2021-07-14_10.00 /home/a.txt\n",
+            "2021-07-15_10.00 /home/b.txt\n",
+        ),
+    );
+    let args = [
+        "squash",
+        backup.path().to_str().unwrap(),
+        "2021-07-14_10.00",
+        "latest~1",
+        "--remove-originals",
+    ];
+    let mut output = Vec::new();
+
+    mizeria::run_program(args, &mut output).expect("program failed");
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        concat!(
+            "Copied 1 entries into 2021-07-15_10.00\n",
+            "Pointed 1 entries of newer snapshots to 2021-07-15_10.00\n",
+            "Removed snapshot: 2021-07-14_10.00\n",
+        )
+    );
+    assert!(!first.exists());
+    assert_eq!(
+        fs::read_to_string(second.join("files/home/a.txt")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        fs::read_to_string(second.join("index.txt")).unwrap(),
+        concat!(
+            "2021-07-15_10.00 /home\n",
+            "2021-07-15_10.00 /home/a.txt\n",
+            "2021-07-15_10.00 /home/b.txt\n",
+        )
+    );
+    assert_eq!(
+        fs::read_to_string(third.join("index.txt")).unwrap(),
+        concat!(
+            "2021-07-16_10.00 /home\n",
+            "2021-07-15_10.00 /home/a.txt\n",
+            "2021-07-15_10.00 /home/b.txt\n",
+        )
+    );
+}
+
+#[test]
+fn squash_refuses_to_rewrite_fingerprinted_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("dummy_file.txt"), b"dummy").unwrap();
+    let backup_args = [
+        "backup",
+        backup.path().to_str().unwrap(),
+        files.path().to_str().unwrap(),
+        "--append-only",
+    ];
+    for _ in 0..2 {
+        mizeria::run_program(backup_args, &mut std::io::sink()).expect("program failed");
+    }
+    // append-only mode alone would stop squash
+    fs::remove_file(backup.path().join(".append-only")).unwrap();
+    let snapshots = utils::backup_entries(backup.path());
+    let snapshots: Vec<&Path> = snapshots
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|path| path.is_dir())
+        .collect();
+    assert_eq!(snapshots.len(), 2);
+    let args = [
+        "squash",
+        backup.path().to_str().unwrap(),
+        "latest~1",
+        "latest",
+    ];
+
+    let error = mizeria::run_program(args, &mut std::io::sink())
+        .err()
+        .unwrap();
+
+    assert!(error.to_string().contains("fingerprinted"), "{}", error);
+    let args = ["verify-fingerprints", backup.path().to_str().unwrap()];
+    mizeria::run_program(args, &mut std::io::sink()).expect("fingerprints changed");
+    for snapshot in snapshots {
+        expect_integrity_success(check_snapshot_integrity(snapshot));
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_refuses_snapshot_with_tampered_index() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let _gnupg = GNUPG.lock().unwrap_or_else(|e| e.into_inner());
+    let gnupg_home = tempfile::tempdir().unwrap();
+    fs::set_permissions(gnupg_home.path(), fs::Permissions::from_mode(0o700)).unwrap();
+    std::env::set_var("GNUPGHOME", gnupg_home.path());
+    let user = "mizeria-test@example.com";
+    let gpg = |args: &[&str]| {
+        let output = Command::new("gpg").arg("--batch").args(args).output();
+        let output = output.expect("cannot run gpg");
+        assert!(output.status.success(), "gpg {:?} failed", args);
+        output.stdout
+    };
+    gpg(&[
+        "--passphrase",
+        "",
+        "--quick-gen-key",
+        user,
+        "default",
+        "default",
+        "never",
+    ]);
+    let keyring = gnupg_home.path().join("public.gpg");
+    fs::write(&keyring, gpg(&["--export", user])).unwrap();
+
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("dummy_file.txt"), b"dummy").unwrap();
+    let args = [
+        "backup",
+        backup.path().to_str().unwrap(),
+        files.path().to_str().unwrap(),
+        "--sign-with",
+        "gpg",
+        "--sign-key",
+        user,
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+    let snapshot = utils::backup_entries(backup.path())
+        .into_iter()
+        .find(|path| path.is_dir())
+        .unwrap();
+    let restore = |target: &Path| {
+        let args = [
+            "restore",
+            snapshot.to_str().unwrap(),
+            target.to_str().unwrap(),
+            "--verify-with",
+            "gpg",
+            "--verify-key",
+            keyring.to_str().unwrap(),
+        ];
+        mizeria::run_program(args, &mut std::io::sink())
+    };
+    let target = tempfile::tempdir().unwrap();
+    restore(target.path()).expect("restore of a signed snapshot failed");
+    assert_ne!(utils::backup_entries(target.path()), Vec::<PathBuf>::new());
+
+    // an entry removed from the index still leaves a valid index
+    let index = fs::read_to_string(snapshot.join("index.txt")).unwrap();
+    let lines: Vec<&str> = index.lines().collect();
+    fs::write(
+        snapshot.join("index.txt"),
+        lines[..lines.len() - 1].join("\n") + "\n",
+    )
+    .unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let error = restore(target.path()).err().unwrap();
+
+    assert!(
+        error.to_string().contains("nothing was restored"),
+        "{}",
+        error
+    );
+    assert_eq!(utils::backup_entries(target.path()), Vec::<PathBuf>::new());
+    Command::new("gpgconf")
+        .args(["--kill", "gpg-agent"])
+        .status()
+        .ok();
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_decrypts_files_with_exported_gpg_key() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    let _gnupg = GNUPG.lock().unwrap_or_else(|e| e.into_inner());
+    let gnupg_home = tempfile::tempdir().unwrap();
+    fs::set_permissions(gnupg_home.path(), fs::Permissions::from_mode(0o700)).unwrap();
+    std::env::set_var("GNUPGHOME", gnupg_home.path());
+    let user = "mizeria-key-file@example.com";
+    let gpg = |args: &[&str]| {
+        let output = Command::new("gpg")
+            .args(["--batch", "--pinentry-mode", "loopback"])
+            .args(["--passphrase", "secret"])
+            .args(args)
+            .output();
+        let output = output.expect("cannot run gpg");
+        assert!(output.status.success(), "gpg {:?} failed", args);
+        output.stdout
+    };
+    gpg(&["--quick-gen-key", user, "default", "default", "never"]);
+    let keys = tempfile::tempdir().unwrap();
+    let key_file = keys.path().join("secret.gpg");
+    fs::write(&key_file, gpg(&["--export-secret-keys", user])).unwrap();
+
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("dummy_file.txt"), b"dummy").unwrap();
+    let args = [
+        "backup",
+        backup.path().to_str().unwrap(),
+        files.path().to_str().unwrap(),
+        "--encrypt-to",
+        user,
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+    let snapshot = utils::backup_entries(backup.path())
+        .into_iter()
+        .find(|path| path.is_dir())
+        .unwrap();
+    Command::new("gpgconf")
+        .args(["--kill", "gpg-agent"])
+        .status()
+        .ok();
+
+    // the restoring machine has no keyring, only the exported key
+    let empty_home = tempfile::tempdir().unwrap();
+    fs::set_permissions(empty_home.path(), fs::Permissions::from_mode(0o700)).unwrap();
+    std::env::set_var("GNUPGHOME", empty_home.path());
+    std::env::set_var("MIZERIA_PASSPHRASE", "secret");
+    let target = tempfile::tempdir().unwrap();
+    let args = [
+        "restore",
+        snapshot.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+        "--key-file",
+        key_file.to_str().unwrap(),
+    ];
+    let result = mizeria::run_program(args, &mut std::io::sink());
+    std::env::remove_var("MIZERIA_PASSPHRASE");
+
+    result.expect("restore with a key file failed");
+    let restored = utils::get_file_by_name(target.path(), "dummy_file.txt").unwrap();
+    assert_eq!(fs::read(restored).unwrap(), b"dummy");
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_state_at_point_in_time_from_registered_backup() {
+    let config = tempfile::tempdir().unwrap();
+    std::env::set_var("MIZERIA_CONFIG", config.path().join("config.toml"));
+    let backup = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_10.00");
+    let home = snapshot.join("files").join("home").join("me");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("a.txt"), b"a").unwrap();
+    fs::write(
+        snapshot.join("index.txt"),
+        "2021-07-15_10.00 /home/me\n2021-07-15_10.00 /home/me/a.txt\n",
+    )
+    .unwrap();
+    let args = [
+        "remote",
+        "add",
+        "restore-nas",
+        backup.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let args = [
+        "restore",
+        "restore-nas",
+        target.path().to_str().unwrap(),
+        "--at",
+        "2021-07-15",
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("program failed");
+
+    let restored = target.path().join("home").join("me").join("a.txt");
+    assert_eq!(fs::read_to_string(restored).unwrap(), "a");
+}
+
+#[test]
+#[cfg(unix)]
+fn restore_snapshot_of_default_backup() {
+    let backup = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_10.00");
+    let home = snapshot.join("files").join("home").join("me");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("a.txt"), b"a").unwrap();
+    fs::write(
+        snapshot.join("index.txt"),
+        "2021-07-15_10.00 /home/me\n2021-07-15_10.00 /home/me/a.txt\n",
+    )
+    .unwrap();
+    std::env::set_var("MIZERIA_BACKUP", backup.path());
+
+    let args = ["restore", "latest", target.path().to_str().unwrap()];
+    let result = mizeria::run_program(args, &mut std::io::sink());
+    std::env::remove_var("MIZERIA_BACKUP");
+
+    result.expect("program failed");
+    let restored = target.path().join("home").join("me").join("a.txt");
+    assert_eq!(fs::read_to_string(restored).unwrap(), "a");
+}
+
+#[test]
+fn restore_path_that_is_not_indexed_is_an_error() {
+    let backup = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let args = [
+        "restore",
+        snapshot.to_str().unwrap(),
+        target.path().to_str().unwrap(),
+        "--path",
+        backup.path().to_str().unwrap(),
+    ];
+
+    let result = mizeria::run_program(args, &mut std::io::sink());
+
+    assert!(result.is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn verify_content_of_files_against_checksums_in_index() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let folder = snapshot.join("files").join("home");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("notes.txt"), b"hello world").unwrap();
+    fs::write(folder.join("todo.txt"), b"hellO world").unwrap();
+    fs::write(folder.join("old.txt"), b"indexed by older version").unwrap();
+    let content = "11:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    fs::write(
+        snapshot.join("index.txt"),
+        format!(
+            concat!(
+                "2021-07-15_18.34 /home\n",
+                "2021-07-15_18.34 {0} /home/notes.txt\n",
+                "2021-07-15_18.34 {0} /home/todo.txt\n",
+                "2021-07-15_18.34 /home/old.txt\n",
+            ),
+            content
+        ),
+    )
+    .unwrap();
+
+    // corruption that keeps the size isn't found without --verify
+    expect_integrity_success(check_snapshot_integrity(&snapshot));
+
+    let output = check_snapshot_integrity_with_args(&snapshot, &["--verify"]);
+
+    let corrupted = snapshot.join("files/home/todo.txt");
+    let not_recorded = IntegrityCheckError::ContentNotRecorded(1);
+    assert!(!output.to_string().contains("notes.txt"));
+    assert!(output.to_string().contains(&format!(
+        "warning[{}]: {}",
+        not_recorded.code(),
+        not_recorded
+    )));
+    expect_integrity_error(output, IntegrityCheckError::ContentMismatch(corrupted));
+}
+
+#[test]
+#[cfg(unix)]
+fn scrub_detects_corrupted_files_in_all_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let files = tempfile::tempdir().unwrap();
+    fs::write(files.path().join("notes.txt"), b"hello world").unwrap();
+    let args = [
+        "backup",
+        backup.path().to_str().unwrap(),
+        files.path().to_str().unwrap(),
+    ];
+    mizeria::run_program(args, &mut std::io::sink()).expect("backup failed");
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2000-01-01_00.00");
+    let folder = snapshot.join("files").join("home");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("notes.txt"), b"hellO world").unwrap();
+    fs::write(
+        snapshot.join("index.txt"),
+        "2000-01-01_00.00 11:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9 /home/notes.txt\n",
+    )
+    .unwrap();
+    let scrub = |args: &[&str]| {
+        let mut program_args = vec!["scrub", backup.path().to_str().unwrap()];
+        program_args.extend(args);
+        let mut output = ProgramOutput::new();
+        let result = mizeria::run_program(program_args, &mut output);
+        (result, output.to_string())
+    };
+
+    let (result, output) = scrub(&[]);
+
+    assert_eq!(mizeria::exit_code(&result), mizeria::INTEGRITY_EXIT_CODE);
+    assert!(output.contains("2000-01-01_00.00: error[MZ0015]"));
+    assert!(output.contains("2000-01-01_00.00  FAILED"));
+    assert!(output.contains("Scrubbed 2 of 2 snapshot(s)."));
+    let scrubbed = fs::read_to_string(backup.path().join("scrubbed.txt")).unwrap();
+    assert_eq!(scrubbed.lines().count(), 1);
+
+    // only the corrupted snapshot wasn't scrubbed in the last day
+    let (result, output) = scrub(&["--since", "1"]);
+
+    assert!(result.is_err());
+    assert!(output.contains("Scrubbed 1 of 2 snapshot(s)."));
+}
+
+#[test]
+#[cfg(unix)]
+fn repair_corrupted_files_from_other_snapshots() {
+    let backup = tempfile::tempdir().unwrap();
+    let content = "11:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    for (name, notes, todo) in [
+        ("2021-07-15_18.34", "hello world", "hello world?"),
+        ("2021-07-16_18.34", "hellO world", "hellO world"),
+    ] {
+        let snapshot = utils::create_empty_snapshot(backup.path(), name);
+        let folder = snapshot.join("files").join("home");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("notes.txt"), notes).unwrap();
+        fs::write(folder.join("todo.txt"), todo).unwrap();
+        fs::write(
+            snapshot.join("index.txt"),
+            format!(
+                "{0} {1} /home/notes.txt\n{0} {1} /home/todo.txt\n",
+                name, content
+            ),
+        )
+        .unwrap();
+    }
+    let args = ["repair", backup.path().to_str().unwrap(), "latest"];
+    let mut output = ProgramOutput::new();
+
+    let result = mizeria::run_program(args, &mut output);
+
+    assert!(result.is_err());
+    assert_eq!(
+        output.to_string(),
+        concat!(
+            "Repaired '/home/notes.txt' from snapshot 2021-07-15_18.34\n",
+            "No intact copy of '/home/todo.txt' was found\n",
+        )
+    );
+    let repaired = backup.path().join("2021-07-16_18.34/files/home/notes.txt");
+    assert_eq!(fs::read_to_string(repaired).unwrap(), "hello world");
+}
+
+#[test]
+#[cfg(unix)]
+fn repair_corrupted_files_with_parity_data() {
+    let backup = tempfile::tempdir().unwrap();
+    let snapshot = utils::create_empty_snapshot(backup.path(), "2021-07-15_18.34");
+    let folder = snapshot.join("files").join("home");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("notes.txt"), "hello world").unwrap();
+    fs::write(
+        snapshot.join("index.txt"),
+        "2021-07-15_18.34 11:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9 /home/notes.txt\n",
+    )
+    .unwrap();
+    let add_parity = [
+        "snapshot",
+        snapshot.to_str().unwrap(),
+        "--add-parity",
+        "50%",
+    ];
+    let mut output = ProgramOutput::new();
+    mizeria::run_program(add_parity, &mut output).unwrap();
+    assert_eq!(
+        output.to_string(),
+        "Added 1 parity block(s) (512 bytes) protecting 1 block(s) of snapshot 2021-07-15_18.34\n"
+    );
+    fs::write(folder.join("notes.txt"), "hellO world").unwrap();
+
+    let args = ["repair", backup.path().to_str().unwrap(), "latest"];
+    let mut output = ProgramOutput::new();
+    let result = mizeria::run_program(args, &mut output);
+
+    assert!(result.is_ok());
+    assert_eq!(
+        output.to_string(),
+        "Repaired '/home/notes.txt' with parity data\n"
+    );
+    let repaired = folder.join("notes.txt");
+    assert_eq!(fs::read_to_string(repaired).unwrap(), "hello world");
+}
+
+#[test]
+fn browse_lists_folder_through_incremental_chain() {
+    let backup = tempfile::tempdir().unwrap();
+    let write_snapshot = |name: &str, index: &str, files: &[&str]| {
+        let snapshot = backup.path().join(name);
+        fs::create_dir_all(snapshot.join("files/docs")).unwrap();
+        for file in files {
+            fs::write(snapshot.join("files/docs").join(file), file).unwrap();
+        }
+        fs::write(snapshot.join("index.txt"), index).unwrap();
+    };
+    write_snapshot(
+        "2021-07-15_18.34",
+        "2021-07-15_18.34 /docs\n2021-07-15_18.34 /docs/old.txt\n",
+        &["old.txt"],
+    );
+    write_snapshot(
+        "2021-07-16_18.34",
+        "2021-07-16_18.34 /docs\n2021-07-15_18.34 /docs/old.txt\n2021-07-16_18.34 /docs/new.txt\n",
+        &["new.txt"],
+    );
+
+    let latest = backup.path().join("latest");
+    let args = ["browse", &latest.to_string_lossy(), "/docs"];
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let mut output = ProgramOutput::new();
+    mizeria::run_program(&args, &mut output).expect("program failed");
+
+    let lines: Vec<String> = output.to_string().lines().map(String::from).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "file               7  2021-07-16_18.34  new.txt",
+            "file               7  2021-07-15_18.34  old.txt",
+        ]
+    );
+}